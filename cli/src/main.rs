@@ -1,7 +1,11 @@
-use anansi_core::AnansiFacade;
+use anansi_core::{
+    AnansiFacade, ArpBinding, DhcpAllowList, DhcpLease, DisplayFilter, ExtractOptions, FieldExtractor, Occurrence, OutputFormat,
+    Ipv6Neighbor, MulticastMembership, PacketCapture, PacketInfo, PacketWriter, Quote, SmbEvent, SshSession,
+};
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use log::{info, debug, warn};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use log::{info, warn};
+use std::net::Ipv4Addr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use colored::Colorize;
@@ -31,7 +35,233 @@ enum Commands {
         /// Output file for PCAP capture
         #[arg(short, long)]
         output: Option<String>,
+        /// Display filter applied to decoded packets (e.g. `tls.sni contains "example"`)
+        #[arg(short = 'Y', long)]
+        display_filter: Option<String>,
+        /// NSS key log file (SSLKEYLOGFILE) used to decrypt TLS sessions
+        #[arg(long)]
+        tls_keylog: Option<String>,
+        /// Print the DHCP lease table when the capture stops
+        #[arg(long)]
+        leases: bool,
+        #[command(flatten)]
+        dhcp_allow: DhcpAllowOptions,
+        #[command(flatten)]
+        output_options: OutputOptions,
     },
+    /// Read and decode packets from a capture file
+    Read {
+        /// Capture file to read (pcap or pcapng)
+        #[arg(short, long)]
+        read: String,
+        /// Filter expression (BPF syntax)
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Display filter applied to decoded packets
+        #[arg(short = 'Y', long)]
+        display_filter: Option<String>,
+        /// NSS key log file (SSLKEYLOGFILE) used to decrypt TLS sessions
+        #[arg(long)]
+        tls_keylog: Option<String>,
+        #[command(flatten)]
+        dhcp_allow: DhcpAllowOptions,
+        #[command(flatten)]
+        output_options: OutputOptions,
+    },
+    /// Extract TLS certificates from a capture file
+    ExportCerts {
+        /// Capture file to read (pcap or pcapng)
+        #[arg(short, long)]
+        read: String,
+        /// Directory the certificates are written to
+        #[arg(short, long, default_value = "certs")]
+        output: String,
+        /// Write PEM instead of DER
+        #[arg(long)]
+        pem: bool,
+        /// Filter expression (BPF syntax)
+        #[arg(short, long)]
+        filter: Option<String>,
+    },
+    /// Extract the files transferred in a capture file
+    ExportObjects {
+        /// Protocol the files are extracted from
+        #[arg(value_enum)]
+        protocol: ObjectProtocol,
+        /// Capture file to read (pcap or pcapng)
+        #[arg(short, long)]
+        read: String,
+        /// Directory the files are written to
+        #[arg(short, long, default_value = "objects")]
+        output: String,
+        /// Filter expression (BPF syntax)
+        #[arg(short, long)]
+        filter: Option<String>,
+    },
+    /// Print statistics computed over a capture file
+    Stats {
+        #[command(subcommand)]
+        report: StatsReport,
+    },
+    /// Print the DHCP lease table built from a capture file
+    Leases {
+        /// Capture file to read (pcap or pcapng)
+        #[arg(short, long)]
+        read: String,
+        /// Filter expression (BPF syntax)
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Print the leases as JSON
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        dhcp_allow: DhcpAllowOptions,
+    },
+    /// Print the SSH sessions: software, negotiated algorithms and HASSH fingerprints
+    SshSessions {
+        /// Capture file to read (pcap or pcapng)
+        #[arg(short, long)]
+        read: String,
+        /// Filter expression (BPF syntax)
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Print the sessions as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the IPv4 to MAC bindings learned from ARP, with the spoofing alerts
+    ArpTable {
+        /// Capture file to read (pcap or pcapng)
+        #[arg(short, long)]
+        read: String,
+        /// Filter expression (BPF syntax)
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Print the bindings as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the IPv6 neighbor table (IP to MAC) learned from Neighbor Discovery
+    Neighbors {
+        /// Capture file to read (pcap or pcapng)
+        #[arg(short, long)]
+        read: String,
+        /// Filter expression (BPF syntax)
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Print the neighbors as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the multicast group memberships reported over IGMP
+    Memberships {
+        /// Capture file to read (pcap or pcapng)
+        #[arg(short, long)]
+        read: String,
+        /// Filter expression (BPF syntax)
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Print the memberships as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the SMB2 logons, tree connects and file operations of a capture file
+    SmbTimeline {
+        /// Capture file to read (pcap or pcapng)
+        #[arg(short, long)]
+        read: String,
+        /// Filter expression (BPF syntax)
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Print the events as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ObjectProtocol {
+    /// Files sent with RETR, STOR, STOU and APPE
+    Ftp,
+    /// Files read or written over unencrypted SMB2
+    Smb,
+    /// Messages sent over SMTP or retrieved over POP3 and IMAP in clear text, as .eml files
+    Imf,
+}
+
+#[derive(Subcommand)]
+enum StatsReport {
+    /// DNS resolver performance: latency per server, response codes, top names and query types
+    Dns {
+        /// Capture file to read (pcap or pcapng)
+        #[arg(short, long)]
+        read: String,
+        /// Filter expression (BPF syntax)
+        #[arg(short, long)]
+        filter: Option<String>,
+        /// Number of names and query types listed
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+}
+
+/// Addresses DHCP servers may hand out; anything else raises an alert
+#[derive(Args, Clone)]
+struct DhcpAllowOptions {
+    /// Allowed DHCP servers, comma-separated
+    #[arg(long = "dhcp-allow-server", value_delimiter = ',', value_name = "IP")]
+    servers: Vec<Ipv4Addr>,
+    /// Allowed routers offered by DHCP, comma-separated
+    #[arg(long = "dhcp-allow-router", value_delimiter = ',', value_name = "IP")]
+    routers: Vec<Ipv4Addr>,
+    /// Allowed DNS servers offered by DHCP, comma-separated
+    #[arg(long = "dhcp-allow-dns", value_delimiter = ',', value_name = "IP")]
+    dns_servers: Vec<Ipv4Addr>,
+}
+
+impl DhcpAllowOptions {
+    fn to_allow_list(&self) -> DhcpAllowList {
+        DhcpAllowList {
+            servers: self.servers.clone(),
+            routers: self.routers.clone(),
+            dns_servers: self.dns_servers.clone(),
+        }
+    }
+}
+
+/// How every decoded packet is printed
+#[derive(Args, Clone)]
+struct OutputOptions {
+    /// Output format: text, json, ndjson or ek (Elasticsearch bulk)
+    #[arg(short = 'T', long, default_value = "text")]
+    format: OutputFormat,
+    /// Print the protocol tree of every packet
+    #[arg(short = 'V', long)]
+    verbose: bool,
+    /// Print a hex and ASCII dump of every frame, coloured by layer
+    #[arg(short = 'x', long)]
+    hex: bool,
+    /// Print a hex and ASCII dump of one layer only, e.g. `tcp` or `tls`
+    #[arg(short = 'X', long, value_name = "LAYER")]
+    hex_layer: Option<String>,
+    /// Print only these fields of every packet as separated values, e.g. `ip.src,tcp.dstport,tls.sni`
+    #[arg(short = 'e', long, value_delimiter = ',', conflicts_with = "format")]
+    fields: Vec<String>,
+    /// Separator of the extracted fields: a string, `tab` or `space`
+    #[arg(long, default_value = "tab", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    separator: String,
+    /// Print a header row with the names of the extracted fields
+    #[arg(long)]
+    header: bool,
+    /// Quote extracted values: auto (when needed), always or never
+    #[arg(long, default_value = "auto")]
+    quote: Quote,
+    /// Joins the values of a field that occurs several times in a packet
+    #[arg(long, default_value = ",")]
+    aggregator: String,
+    /// Values printed for a field that occurs several times: first, last or all
+    #[arg(long, default_value = "all")]
+    occurrence: Occurrence,
 }
 
 #[tokio::main]
@@ -49,9 +279,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("{}", interface.bold().green());
             }
         }
-        Commands::Capture { interface, filter, debug: _, output } => {
+        Commands::Capture {
+            interface,
+            filter,
+            debug: _,
+            output,
+            display_filter,
+            tls_keylog,
+            leases,
+            dhcp_allow,
+            output_options,
+        } => {
+            let display_filter = display_filter
+                .as_deref()
+                .map(DisplayFilter::parse)
+                .transpose()?;
+            facade.lock().await.set_dhcp_allow_list(dhcp_allow.to_allow_list());
+            if let Some(keylog) = &tls_keylog {
+                let count = facade.lock().await.load_tls_keylog(keylog)?;
+                info!("Loaded {} TLS secrets from {}", count, keylog);
+            }
+            let printer = Arc::new(PacketPrinter::new(output_options));
             let facade_clone = facade.clone();
-            let observer = Arc::new(PrintObserver::new(facade_clone));
+            let observer = Arc::new(PrintObserver::new(facade_clone, display_filter, printer.clone()));
             facade.lock().await.add_observer(observer).await;
 
             // Set up PCAP output if specified
@@ -70,6 +320,182 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             // Wait for Ctrl+C
             tokio::signal::ctrl_c().await?;
             facade.lock().await.stop_capture().await;
+            printer.finish()?;
+            if leases {
+                print_leases(&facade.lock().await.dhcp_leases());
+            }
+        }
+        Commands::Read { read, filter, display_filter, tls_keylog, dhcp_allow, output_options } => {
+            let display_filter = display_filter
+                .as_deref()
+                .map(DisplayFilter::parse)
+                .transpose()?;
+            let facade = facade.lock().await;
+            facade.set_dhcp_allow_list(dhcp_allow.to_allow_list());
+            if let Some(keylog) = &tls_keylog {
+                let count = facade.load_tls_keylog(keylog)?;
+                info!("Loaded {} TLS secrets from {}", count, keylog);
+            }
+            let count = facade.load_tls_secrets_from_capture(&read)?;
+            if count > 0 {
+                info!("Loaded {} TLS secrets from decryption secrets blocks", count);
+            }
+
+            let printer = PacketPrinter::new(output_options);
+            PacketCapture::read_file(&read, filter.as_deref(), |packet| {
+                let info = facade.process_packet(packet);
+                if display_filter.as_ref().is_none_or(|f| f.matches(&info)) {
+                    printer.print(&facade, &info, packet.data);
+                }
+                printer.print_alerts(&facade);
+            })?;
+            printer.finish()?;
+        }
+        Commands::ExportCerts { read, output, pem, filter } => {
+            let facade = facade.lock().await;
+            PacketCapture::read_file(&read, filter.as_deref(), |packet| {
+                facade.process_packet(packet);
+            })?;
+            print_alerts(&facade);
+
+            std::fs::create_dir_all(&output)?;
+            let certificates = facade.certificates();
+            for cert in &certificates {
+                let path = std::path::Path::new(&output)
+                    .join(format!("{}.{}", cert.sha256, if pem { "pem" } else { "der" }));
+                if pem {
+                    std::fs::write(&path, cert.to_pem())?;
+                } else {
+                    std::fs::write(&path, &cert.der)?;
+                }
+                println!("{} {}", path.display().to_string().green(), cert.format_info());
+            }
+            println!("Exported {} certificate(s) to {}", certificates.len(), output);
+        }
+        Commands::ExportObjects { protocol, read, output, filter } => {
+            let facade = facade.lock().await;
+            PacketCapture::read_file(&read, filter.as_deref(), |packet| {
+                facade.process_packet(packet);
+            })?;
+            print_alerts(&facade);
+
+            std::fs::create_dir_all(&output)?;
+            let objects: Vec<(String, Vec<u8>, String)> = match protocol {
+                ObjectProtocol::Ftp => facade
+                    .ftp_transfers()
+                    .into_iter()
+                    .map(|transfer| (transfer.file_name(), transfer.data.clone(), transfer.format_info()))
+                    .collect(),
+                ObjectProtocol::Smb => facade
+                    .smb_files()
+                    .into_iter()
+                    .map(|file| (file.file_name(), file.data(), file.format_info()))
+                    .collect(),
+                ObjectProtocol::Imf => facade
+                    .mail_messages()
+                    .into_iter()
+                    .filter(|message| !message.content_dropped)
+                    .map(|message| (message.file_name(), message.data.clone(), message.format_info()))
+                    .collect(),
+            };
+            let mut exported = 0;
+            for (name, data, info) in &objects {
+                let path = unique_path(std::path::Path::new(&output), name);
+                if let Err(e) = std::fs::write(&path, data) {
+                    warn!("Cannot write {}: {}", path.display(), e);
+                    continue;
+                }
+                exported += 1;
+                println!("{} {}", path.display().to_string().green(), info);
+            }
+            println!("Exported {} file(s) to {}", exported, output);
+        }
+        Commands::Stats { report: StatsReport::Dns { read, filter, top } } => {
+            let facade = facade.lock().await;
+            PacketCapture::read_file(&read, filter.as_deref(), |packet| {
+                facade.process_packet(packet);
+            })?;
+            print_alerts(&facade);
+            print!("{}", facade.dns_statistics(top));
+        }
+        Commands::Leases { read, filter, json, dhcp_allow } => {
+            let facade = facade.lock().await;
+            facade.set_dhcp_allow_list(dhcp_allow.to_allow_list());
+            PacketCapture::read_file(&read, filter.as_deref(), |packet| {
+                facade.process_packet(packet);
+            })?;
+            let leases = facade.dhcp_leases();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&leases)?);
+            } else {
+                print_alerts(&facade);
+                print_leases(&leases);
+            }
+        }
+        Commands::SshSessions { read, filter, json } => {
+            let facade = facade.lock().await;
+            PacketCapture::read_file(&read, filter.as_deref(), |packet| {
+                facade.process_packet(packet);
+            })?;
+            let sessions = facade.ssh_sessions();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&sessions)?);
+            } else {
+                print_alerts(&facade);
+                print_ssh_sessions(&sessions);
+            }
+        }
+        Commands::ArpTable { read, filter, json } => {
+            let facade = facade.lock().await;
+            PacketCapture::read_file(&read, filter.as_deref(), |packet| {
+                facade.process_packet(packet);
+            })?;
+            let bindings = facade.arp_bindings();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&bindings)?);
+            } else {
+                print_alerts(&facade);
+                print_arp_bindings(&bindings);
+            }
+        }
+        Commands::Neighbors { read, filter, json } => {
+            let facade = facade.lock().await;
+            PacketCapture::read_file(&read, filter.as_deref(), |packet| {
+                facade.process_packet(packet);
+            })?;
+            let neighbors = facade.ipv6_neighbors();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&neighbors)?);
+            } else {
+                print_alerts(&facade);
+                print_neighbors(&neighbors);
+            }
+        }
+        Commands::Memberships { read, filter, json } => {
+            let facade = facade.lock().await;
+            PacketCapture::read_file(&read, filter.as_deref(), |packet| {
+                facade.process_packet(packet);
+            })?;
+            let memberships = facade.multicast_memberships();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&memberships)?);
+            } else {
+                print_alerts(&facade);
+                print_memberships(&memberships);
+            }
+        }
+        Commands::SmbTimeline { read, filter, json } => {
+            let facade = facade.lock().await;
+            PacketCapture::read_file(&read, filter.as_deref(), |packet| {
+                facade.process_packet(packet);
+            })?;
+            let events = facade.smb_events();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&events)?);
+            } else {
+                print_alerts(&facade);
+                print_smb_events(&events);
+            }
         }
     }
 
@@ -78,11 +504,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 struct PrintObserver {
     facade: Arc<Mutex<AnansiFacade>>,
+    display_filter: Option<DisplayFilter>,
+    printer: Arc<PacketPrinter>,
 }
 
 impl PrintObserver {
-    fn new(facade: Arc<Mutex<AnansiFacade>>) -> Self {
-        Self { facade }
+    fn new(facade: Arc<Mutex<AnansiFacade>>, display_filter: Option<DisplayFilter>, printer: Arc<PacketPrinter>) -> Self {
+        Self { facade, display_filter, printer }
     }
 }
 
@@ -91,7 +519,218 @@ impl anansi_core::Observer for PrintObserver {
     async fn update<'a>(&self, packet: &'a pcap::Packet<'a>) {
         let facade = self.facade.lock().await;
         let info = facade.process_packet(packet);
-        println!("{}", facade.format_packet_info(&info));
+        if self.display_filter.as_ref().is_none_or(|filter| filter.matches(&info)) {
+            self.printer.print(&facade, &info, packet.data);
+        }
+        // Alerts are shown even when the packet that raised them is filtered out
+        self.printer.print_alerts(&facade);
+    }
+}
+
+/// Prints decoded packets as coloured text, extracted fields or in one of the
+/// structured formats
+struct PacketPrinter {
+    options: OutputOptions,
+    writer: Option<std::sync::Mutex<PacketWriter<std::io::Stdout>>>,
+    extractor: Option<FieldExtractor>,
+}
+
+impl PacketPrinter {
+    /// Prints the header row right away when fields are extracted with `--header`
+    fn new(options: OutputOptions) -> Self {
+        let writer = (options.format != OutputFormat::Text)
+            .then(|| std::sync::Mutex::new(PacketWriter::new(options.format, std::io::stdout())));
+        let extractor = (!options.fields.is_empty()).then(|| {
+            let separator = match options.separator.as_str() {
+                "tab" | "\\t" => "\t".to_string(),
+                "space" => " ".to_string(),
+                separator => separator.to_string(),
+            };
+            let extract_options = ExtractOptions {
+                separator,
+                aggregator: options.aggregator.clone(),
+                occurrence: options.occurrence,
+                quote: options.quote,
+            };
+            FieldExtractor::new(options.fields.clone(), extract_options)
+        });
+        if let (Some(extractor), true) = (&extractor, options.header) {
+            println!("{}", extractor.header());
+        }
+        Self { options, writer, extractor }
+    }
+
+    fn print(&self, facade: &AnansiFacade, info: &PacketInfo, data: &[u8]) {
+        if let Some(extractor) = &self.extractor {
+            println!("{}", extractor.row(info));
+            return;
+        }
+        if let Some(writer) = &self.writer {
+            if let Err(e) = writer.lock().unwrap().write_packet(info) {
+                warn!("Cannot write packet {}: {}", info.number, e);
+            }
+            return;
+        }
+
+        println!("{}", facade.format_packet_info(info));
+        if self.options.verbose {
+            println!("{}", facade.format_packet_tree(info));
+        }
+        if self.options.hex {
+            if let Some(dump) = facade.format_hex_dump(info, data, None) {
+                println!("{}", dump);
+            }
+        }
+        if let Some(layer) = &self.options.hex_layer {
+            match facade.format_hex_dump(info, data, Some(layer)) {
+                Some(dump) => println!("{}", dump),
+                None => println!("{}\n", format!("No {} bytes in this frame", layer).dimmed()),
+            }
+        }
+    }
+
+    /// Alerts go to stderr with the structured formats so stdout stays parseable
+    fn print_alerts(&self, facade: &AnansiFacade) {
+        for alert in facade.take_alerts() {
+            if self.writer.is_some() || self.extractor.is_some() {
+                eprintln!("{}", alert);
+            } else {
+                println!("{}", alert.to_string().red().bold());
+            }
+        }
+    }
+
+    fn finish(&self) -> anyhow::Result<()> {
+        match &self.writer {
+            Some(writer) => writer.lock().unwrap().finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn print_alerts(facade: &AnansiFacade) {
+    for alert in facade.take_alerts() {
+        println!("{}", alert.to_string().red().bold());
+    }
+}
+
+/// `name` in `directory`, suffixed with a counter if a file of that name exists
+fn unique_path(directory: &std::path::Path, name: &str) -> std::path::PathBuf {
+    let mut path = directory.join(name);
+    // The counter goes before the extension so that `.eml` files still open
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    let mut counter = 1;
+    while path.exists() {
+        path = directory.join(format!("{}.{}{}", stem, counter, extension));
+        counter += 1;
+    }
+    path
+}
+
+fn print_leases(leases: &[DhcpLease]) {
+    println!(
+        "{}",
+        format!(
+            "{:<17} {:<15} {:<8} {:<20} {:>8} {:<15} {}",
+            "MAC", "IP", "State", "Hostname", "Lease", "Server", "Last seen"
+        )
+        .bold()
+        .underline()
+    );
+    for lease in leases {
+        println!("{}", lease.format_info());
+    }
+}
+
+fn print_ssh_sessions(sessions: &[SshSession]) {
+    println!(
+        "{}",
+        format!(
+            "{:<21} {:<21} {:<28} {:<28} {:<30} {:<30} {:<32} {}",
+            "Client", "Server", "Client software", "Server software", "Key exchange", "Cipher", "HASSH", "HASSHServer"
+        )
+        .bold()
+        .underline()
+    );
+    for session in sessions {
+        if session.weak_algorithms.is_empty() {
+            println!("{}", session.format_info());
+        } else {
+            println!("{}", session.format_info().yellow());
+        }
+    }
+}
+
+fn print_arp_bindings(bindings: &[ArpBinding]) {
+    println!(
+        "{}",
+        format!(
+            "{:<15} {:<17} {:>7} {:<19} {:<19} {}",
+            "IP", "MAC", "Packets", "First seen", "Last seen", "Previous MACs"
+        )
+        .bold()
+        .underline()
+    );
+    for binding in bindings {
+        // Addresses that changed MAC are the spoofing candidates
+        if binding.previous_macs.is_empty() {
+            println!("{}", binding.format_info());
+        } else {
+            println!("{}", binding.format_info().red());
+        }
+    }
+}
+
+fn print_neighbors(neighbors: &[Ipv6Neighbor]) {
+    println!(
+        "{}",
+        format!(
+            "{:<39} {:<17} {:<6} {:<4} {:<19} {}",
+            "IP", "MAC", "Router", "From", "First seen", "Last seen"
+        )
+        .bold()
+        .underline()
+    );
+    for neighbor in neighbors {
+        println!("{}", neighbor.format_info());
+    }
+}
+
+fn print_memberships(memberships: &[MulticastMembership]) {
+    println!(
+        "{}",
+        format!(
+            "{:<15} {:<15} {:<3} {:<24} {:<8} {:<19} {}",
+            "Host", "Group", "Ver", "Sources", "State", "Last report", "Expires"
+        )
+        .bold()
+        .underline()
+    );
+    for membership in memberships {
+        println!("{}", membership.format_info());
+    }
+}
+
+fn print_smb_events(events: &[SmbEvent]) {
+    println!(
+        "{}",
+        format!(
+            "{:<23} {:<15} {:<15} {:<20} {:<12} {:<24} {:<32} {:>10} {}",
+            "Time", "Client", "Server", "User", "Operation", "Share", "Path", "Bytes", "Status"
+        )
+        .bold()
+        .underline()
+    );
+    for event in events {
+        let line = event.format_info();
+        if event.status == "STATUS_SUCCESS" {
+            println!("{}", line);
+        } else {
+            println!("{}", line.yellow());
+        }
     }
 }
 
@@ -102,7 +741,17 @@ trait CommandExt {
 impl CommandExt for Commands {
     fn is_debug(&self) -> bool {
         match self {
-            Commands::ListInterfaces => false,
+            Commands::ListInterfaces
+            | Commands::Read { .. }
+            | Commands::ExportCerts { .. }
+            | Commands::ExportObjects { .. }
+            | Commands::Stats { .. }
+            | Commands::Leases { .. }
+            | Commands::Neighbors { .. }
+            | Commands::Memberships { .. }
+            | Commands::ArpTable { .. }
+            | Commands::SshSessions { .. }
+            | Commands::SmbTimeline { .. } => false,
             Commands::Capture { debug, .. } => *debug,
         }
     }
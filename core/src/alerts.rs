@@ -0,0 +1,71 @@
+use serde::Serialize;
+use std::fmt;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for AlertSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertSeverity::Info => write!(f, "INFO"),
+            AlertSeverity::Warning => write!(f, "WARNING"),
+            AlertSeverity::Critical => write!(f, "CRITICAL"),
+        }
+    }
+}
+
+/// Security or health finding produced while analysing traffic
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub timestamp: String,
+    pub severity: AlertSeverity,
+    /// Dotted category, e.g. `tls.certificate.expired`
+    pub category: String,
+    pub message: String,
+}
+
+impl Alert {
+    pub fn new(timestamp: &str, severity: AlertSeverity, category: &str, message: String) -> Self {
+        Self {
+            timestamp: timestamp.to_string(),
+            severity,
+            category: category.to_string(),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}] {}: {}", self.timestamp, self.severity, self.category, self.message)
+    }
+}
+
+/// Common channel through which every analyser reports alerts.
+///
+/// Alerts are queued until a consumer (the CLI printer, the GUI) drains them.
+#[derive(Default)]
+pub struct AlertChannel {
+    pending: Mutex<Vec<Alert>>,
+}
+
+impl AlertChannel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn raise(&self, alert: Alert) {
+        self.pending.lock().unwrap().push(alert);
+    }
+
+    /// Removes and returns all pending alerts
+    pub fn drain(&self) -> Vec<Alert> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
@@ -1,4 +1,4 @@
-use pcap::{Capture, Device, Active, Inactive};
+use pcap::{Capture, Device, Active, Inactive, Offline};
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock};
 use uuid::Uuid;
@@ -112,5 +112,30 @@ impl PacketCapture {
         *self.capture.lock().await = None;
         info!("Stopped capture on interface: {}", self.interface);
     }
+
+    /// Читает пакеты из файла PCAP/PCAPNG и передает каждый обработчику
+    pub fn read_file<F>(path: &str, filter: Option<&str>, mut handler: F) -> Result<()>
+    where
+        F: FnMut(&pcap::Packet),
+    {
+        let mut capture: Capture<Offline> = Capture::from_file(path)
+            .map_err(|e| CaptureError::CaptureFailure(format!("Cannot open {}: {}", path, e)))?;
+
+        if let Some(filter) = filter {
+            capture
+                .filter(filter)
+                .map_err(|e| CaptureError::InvalidFilter(e.to_string()))?;
+        }
+
+        info!("Reading packets from file: {}", path);
+        loop {
+            match capture.next() {
+                Ok(packet) => handler(&packet),
+                Err(pcap::Error::NoMorePackets) => break,
+                Err(e) => return Err(CaptureError::CaptureFailure(e.to_string()).into()),
+            }
+        }
+        Ok(())
+    }
 }
 
@@ -2,6 +2,8 @@ use crate::capture::{PacketCapture, Observer};
 use crate::interface::{list_interfaces, get_interface_names, validate_interface};
 use crate::packets::{PacketProcessor, PacketInfo};
 use crate::observers::PcapWriter;
+use crate::alerts::Alert;
+use crate::packets::{ArpBinding, CertificateInfo, DhcpAllowList, DhcpLease, DnsStatistics, FtpTransfer, Ipv6Neighbor, MailMessage, MulticastMembership, SmbEvent, SmbFile, SshSession, Dissector, DissectorTable, Transport, read_pcapng_secrets};
 use anyhow::Result;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -91,6 +93,16 @@ impl AnansiFacade {
         validate_interface(interface_name)
     }
 
+    /// Регистрирует пользовательский диссектор
+    pub fn register_dissector(&mut self, table: DissectorTable, dissector: Arc<dyn Dissector>) {
+        self.packet_processor.register_dissector(table, dissector);
+    }
+
+    /// Регистрирует эвристический диссектор для TCP- или UDP-потоков
+    pub fn register_heuristic_dissector(&mut self, transport: Transport, dissector: Arc<dyn Dissector>) {
+        self.packet_processor.register_heuristic_dissector(transport, dissector);
+    }
+
     pub fn process_packet(&self, packet: &pcap::Packet) -> PacketInfo {
         self.packet_processor.process_packet(packet)
     }
@@ -98,4 +110,90 @@ impl AnansiFacade {
     pub fn format_packet_info(&self, info: &PacketInfo) -> String {
         self.packet_processor.format_packet_info(info)
     }
+
+    /// Форматирует шестнадцатеричный дамп кадра или одного его уровня
+    pub fn format_hex_dump(&self, info: &PacketInfo, data: &[u8], layer: Option<&str>) -> Option<String> {
+        self.packet_processor.format_hex_dump(info, data, layer)
+    }
+
+    /// Форматирует дерево протоколов пакета
+    pub fn format_packet_tree(&self, info: &PacketInfo) -> String {
+        self.packet_processor.format_packet_tree(info)
+    }
+
+    /// Возвращает и очищает накопленные оповещения
+    pub fn take_alerts(&self) -> Vec<Alert> {
+        self.packet_processor.alerts().drain()
+    }
+
+    /// Загружает ключи TLS-сессий из файла SSLKEYLOGFILE
+    pub fn load_tls_keylog(&self, path: &str) -> Result<usize> {
+        self.packet_processor.load_tls_keylog(std::path::Path::new(path))
+    }
+
+    /// Загружает ключи TLS из блоков Decryption Secrets файла PCAPNG
+    pub fn load_tls_secrets_from_capture(&self, path: &str) -> Result<usize> {
+        let secrets = read_pcapng_secrets(std::path::Path::new(path))?;
+        Ok(self.packet_processor.add_tls_secrets(&secrets))
+    }
+
+    /// Возвращает статистику DNS-транзакций с `top` самыми частыми именами и типами запросов
+    pub fn dns_statistics(&self, top: usize) -> DnsStatistics {
+        self.packet_processor.dns_statistics(top)
+    }
+
+    /// Возвращает таблицу DHCP-аренд
+    pub fn dhcp_leases(&self) -> Vec<DhcpLease> {
+        self.packet_processor.dhcp_leases()
+    }
+
+    /// Задает разрешенные DHCP-серверы, маршрутизаторы и DNS-серверы
+    pub fn set_dhcp_allow_list(&self, allow_list: DhcpAllowList) {
+        self.packet_processor.set_dhcp_allow_list(allow_list);
+    }
+
+    /// Возвращает файлы, переданные по FTP, включая незавершенные передачи
+    pub fn ftp_transfers(&self) -> Vec<FtpTransfer> {
+        self.packet_processor.ftp_transfers()
+    }
+
+    /// Возвращает письма, отправленные по SMTP или полученные по POP3 и IMAP в открытом виде
+    pub fn mail_messages(&self) -> Vec<MailMessage> {
+        self.packet_processor.mail_messages()
+    }
+
+    /// Возвращает хронологию SMB-операций: входы, подключения к ресурсам и действия с файлами
+    pub fn smb_events(&self) -> Vec<SmbEvent> {
+        self.packet_processor.smb_events()
+    }
+
+    /// Возвращает файлы, прочитанные или записанные по SMB2 без шифрования
+    pub fn smb_files(&self) -> Vec<SmbFile> {
+        self.packet_processor.smb_files()
+    }
+
+    /// Возвращает SSH-сессии с ПО клиента и сервера, согласованными алгоритмами и отпечатками HASSH
+    pub fn ssh_sessions(&self) -> Vec<SshSession> {
+        self.packet_processor.ssh_sessions()
+    }
+
+    /// Возвращает таблицу привязок IPv4 -> MAC, построенную по ARP-трафику
+    pub fn arp_bindings(&self) -> Vec<ArpBinding> {
+        self.packet_processor.arp_bindings()
+    }
+
+    /// Возвращает таблицу IPv6-соседей (IP -> MAC), построенную по сообщениям Neighbor Discovery
+    pub fn ipv6_neighbors(&self) -> Vec<Ipv6Neighbor> {
+        self.packet_processor.ipv6_neighbors()
+    }
+
+    /// Возвращает таблицу членства в multicast-группах по отчетам IGMP
+    pub fn multicast_memberships(&self) -> Vec<MulticastMembership> {
+        self.packet_processor.multicast_memberships()
+    }
+
+    /// Возвращает все уникальные TLS-сертификаты, встреченные в трафике
+    pub fn certificates(&self) -> Vec<CertificateInfo> {
+        self.packet_processor.certificates()
+    }
 }
@@ -0,0 +1,275 @@
+use anyhow::Result;
+use crate::packets::{FieldValue, PacketInfo};
+
+/// Display filter evaluated against the decoded fields of a packet.
+///
+/// Supported syntax:
+/// - `tls` — the packet contains the protocol or any field under `tls.`
+/// - `tls.sni` — the field is present
+/// - `tls.sni == "example.com"`, `!=`, `>`, `>=`, `<`, `<=`, `contains`
+/// - `&&` / `and`, `||` / `or`, `!` / `not` and parentheses
+#[derive(Debug, Clone)]
+pub struct DisplayFilter {
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Exists(String),
+    Compare(String, CompareOp, String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(String),
+    Op(CompareOp),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl DisplayFilter {
+    pub fn parse(input: &str) -> Result<Self> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if parser.pos != parser.tokens.len() {
+            return Err(anyhow::anyhow!(
+                "Unexpected token in display filter: {:?}",
+                parser.tokens[parser.pos]
+            ));
+        }
+        Ok(Self { expr })
+    }
+
+    pub fn matches(&self, info: &PacketInfo) -> bool {
+        eval(&self.expr, info)
+    }
+}
+
+fn eval(expr: &Expr, info: &PacketInfo) -> bool {
+    match expr {
+        Expr::Exists(name) => {
+            let prefix = format!("{}.", name);
+            info.protocol.eq_ignore_ascii_case(name)
+                || info.layers.iter().any(|layer| layer.protocol.eq_ignore_ascii_case(name))
+                || info
+                    .fields()
+                    .any(|field| field.name == *name || field.name.starts_with(&prefix))
+        }
+        Expr::Compare(name, op, value) => info
+            .field_values(name)
+            .any(|field_value| compare(field_value, *op, value)),
+        Expr::Not(inner) => !eval(inner, info),
+        Expr::And(left, right) => eval(left, info) && eval(right, info),
+        Expr::Or(left, right) => eval(left, info) || eval(right, info),
+    }
+}
+
+fn compare(field_value: &FieldValue, op: CompareOp, value: &str) -> bool {
+    // Booleans compare equal to `1`/`0` as well as `true`/`false`
+    let field_value = match field_value {
+        FieldValue::Bool(flag) => u8::from(*flag).to_string(),
+        _ => field_value.to_string(),
+    };
+    let value = match value {
+        "true" => "1",
+        "false" => "0",
+        _ => value,
+    };
+    if op == CompareOp::Contains {
+        return field_value.to_lowercase().contains(&value.to_lowercase());
+    }
+
+    let ordering = match (parse_number(&field_value), parse_number(value)) {
+        (Some(a), Some(b)) => a.partial_cmp(&b),
+        _ => Some(field_value.to_lowercase().cmp(&value.to_lowercase())),
+    };
+
+    match ordering {
+        Some(ordering) => match op {
+            CompareOp::Eq => ordering.is_eq(),
+            CompareOp::Ne => ordering.is_ne(),
+            CompareOp::Gt => ordering.is_gt(),
+            CompareOp::Ge => ordering.is_ge(),
+            CompareOp::Lt => ordering.is_lt(),
+            CompareOp::Le => ordering.is_le(),
+            CompareOp::Contains => unreachable!(),
+        },
+        None => false,
+    }
+}
+
+fn parse_number(value: &str) -> Option<f64> {
+    if let Some(hex) = value.strip_prefix("0x") {
+        return u64::from_str_radix(hex, 16).ok().map(|v| v as f64);
+    }
+    value.parse::<f64>().ok()
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ' ' | '\t' | '\n' => i += 1,
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            '"' => {
+                let mut value = String::new();
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' && i + 1 < chars.len() {
+                        i += 1;
+                    }
+                    value.push(chars[i]);
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(anyhow::anyhow!("Unterminated string in display filter"));
+                }
+                i += 1;
+                tokens.push(Token::Literal(value));
+            }
+            '=' | '!' | '>' | '<' | '&' | '|' => {
+                let next = chars.get(i + 1).copied();
+                let (token, len) = match (c, next) {
+                    ('=', Some('=')) => (Token::Op(CompareOp::Eq), 2),
+                    ('!', Some('=')) => (Token::Op(CompareOp::Ne), 2),
+                    ('>', Some('=')) => (Token::Op(CompareOp::Ge), 2),
+                    ('<', Some('=')) => (Token::Op(CompareOp::Le), 2),
+                    ('&', Some('&')) => (Token::And, 2),
+                    ('|', Some('|')) => (Token::Or, 2),
+                    ('>', _) => (Token::Op(CompareOp::Gt), 1),
+                    ('<', _) => (Token::Op(CompareOp::Lt), 1),
+                    ('!', _) => (Token::Not, 1),
+                    _ => return Err(anyhow::anyhow!("Unexpected character '{}' in display filter", c)),
+                };
+                tokens.push(token);
+                i += len;
+            }
+            _ => {
+                let start = i;
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && !"()\"=!<>&|".contains(chars[i])
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "eq" => Token::Op(CompareOp::Eq),
+                    "ne" => Token::Op(CompareOp::Ne),
+                    "gt" => Token::Op(CompareOp::Gt),
+                    "ge" => Token::Op(CompareOp::Ge),
+                    "lt" => Token::Op(CompareOp::Lt),
+                    "le" => Token::Op(CompareOp::Le),
+                    "contains" => Token::Op(CompareOp::Contains),
+                    _ => Token::Ident(word),
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut left = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            let right = self.parse_not()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                if self.next() != Some(Token::RParen) {
+                    return Err(anyhow::anyhow!("Missing ')' in display filter"));
+                }
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                if let Some(Token::Op(op)) = self.peek().cloned() {
+                    self.pos += 1;
+                    let value = match self.next() {
+                        Some(Token::Ident(value)) | Some(Token::Literal(value)) => value,
+                        _ => return Err(anyhow::anyhow!("Expected a value after operator for '{}'", name)),
+                    };
+                    Ok(Expr::Compare(name, op, value))
+                } else {
+                    Ok(Expr::Exists(name))
+                }
+            }
+            Some(token) => Err(anyhow::anyhow!("Unexpected token in display filter: {:?}", token)),
+            None => Err(anyhow::anyhow!("Unexpected end of display filter")),
+        }
+    }
+}
@@ -4,8 +4,18 @@ pub mod facade;
 pub mod packets;
 pub mod errors;
 pub mod observers;
+pub mod filter;
+pub mod alerts;
+pub mod output;
 
 pub use interface::{NetworkInterface, list_interfaces, format_interface_list, validate_interface};
 pub use capture::{PacketCapture, Observer};
 pub use facade::AnansiFacade;
-pub use packets::{PacketProcessor, PacketInfo};
+pub use packets::{PacketProcessor, PacketInfo, CertificateInfo, DnsStatistics, Field, FieldValue, Layer};
+pub use packets::{ArpBinding, DhcpAllowList, DhcpLease, LeaseState, FtpTransfer, Ipv6Neighbor, MailMessage, MulticastMembership, SmbEvent, SmbFile, SmbOperation, SshAlgorithms, SshSession};
+pub use packets::{
+    Dissection, DissectContext, Dissector, DissectorRegistry, DissectorTable, ProbeResult, Transport,
+};
+pub use alerts::{Alert, AlertSeverity, AlertChannel};
+pub use filter::DisplayFilter;
+pub use output::{ExtractOptions, FieldExtractor, Occurrence, OutputFormat, PacketWriter, Quote, SCHEMA_VERSION};
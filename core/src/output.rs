@@ -0,0 +1,506 @@
+use anyhow::Result;
+use chrono::SecondsFormat;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::io::Write;
+use std::str::FromStr;
+use crate::packets::{FieldValue, Layer, PacketInfo};
+
+/// Version of the JSON packet schema; bumped on any incompatible change
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Characters kept of a name taken from the traffic
+const MAX_FILE_NAME_LEN: usize = 64;
+/// Longest extension kept when a name is shortened
+const MAX_EXTENSION_LEN: usize = 16;
+
+/// `name` with the characters unsafe in file names replaced and shortened to
+/// [`MAX_FILE_NAME_LEN`] characters keeping its extension, or `fallback` if
+/// nothing usable is left
+pub fn safe_file_name(name: &str, fallback: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') { '_' } else { c })
+        .collect();
+    let name = match name.trim() {
+        "" | "." | ".." => return fallback.to_string(),
+        name => name,
+    };
+    if name.chars().count() <= MAX_FILE_NAME_LEN {
+        return name.to_string();
+    }
+    let extension = match name.rfind('.') {
+        Some(dot) if dot > 0 && name[dot..].chars().count() <= MAX_EXTENSION_LEN => &name[dot..],
+        _ => "",
+    };
+    let stem: String = name.chars().take(MAX_FILE_NAME_LEN - extension.chars().count()).collect();
+    stem + extension
+}
+
+/// How decoded packets are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One-line summaries
+    Text,
+    /// A JSON array of packets
+    Json,
+    /// One JSON packet per line
+    Ndjson,
+    /// Elasticsearch bulk API lines
+    Ek,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            "ek" => Ok(OutputFormat::Ek),
+            _ => Err(anyhow::anyhow!("Unknown output format '{}', expected text, json, ndjson or ek", s)),
+        }
+    }
+}
+
+/// A packet as written by the `json` and `ndjson` formats
+#[derive(Serialize)]
+struct JsonPacket<'a> {
+    schema_version: u32,
+    frame_number: u64,
+    /// RFC 3339, UTC
+    timestamp: String,
+    time_epoch: f64,
+    source: &'a str,
+    destination: &'a str,
+    protocol: &'a str,
+    length: usize,
+    info: &'a str,
+    layers: &'a [Layer],
+}
+
+impl<'a> JsonPacket<'a> {
+    fn new(info: &'a PacketInfo) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            frame_number: info.number,
+            timestamp: info.captured_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            time_epoch: info.captured_at.timestamp_micros() as f64 / 1e6,
+            source: &info.source_ip,
+            destination: &info.destination_ip,
+            protocol: &info.protocol,
+            length: info.length,
+            info: &info.details,
+            layers: &info.layers,
+        }
+    }
+}
+
+/// Writes decoded packets; `Text` writes the summary line without colours
+pub struct PacketWriter<W: Write> {
+    format: OutputFormat,
+    out: W,
+    written: u64,
+}
+
+impl<W: Write> PacketWriter<W> {
+    pub fn new(format: OutputFormat, out: W) -> Self {
+        Self { format, out, written: 0 }
+    }
+
+    pub fn write_packet(&mut self, info: &PacketInfo) -> Result<()> {
+        match self.format {
+            OutputFormat::Text => writeln!(
+                self.out,
+                "{} {} -> {} {} {} {}",
+                info.timestamp, info.source_ip, info.destination_ip, info.protocol, info.length, info.details
+            )?,
+            OutputFormat::Json => {
+                let separator = if self.written == 0 { "[\n" } else { ",\n" };
+                let packet = serde_json::to_string_pretty(&JsonPacket::new(info))?;
+                write!(self.out, "{}{}", separator, indent(&packet))?;
+            }
+            OutputFormat::Ndjson => writeln!(self.out, "{}", serde_json::to_string(&JsonPacket::new(info))?)?,
+            OutputFormat::Ek => {
+                let index = json!({ "index": { "_index": format!("packets-{}", info.captured_at.format("%Y-%m-%d")) } });
+                writeln!(self.out, "{}", index)?;
+                writeln!(self.out, "{}", ek_document(info))?;
+            }
+        }
+        self.written += 1;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// Closes the JSON array; a no-op for the line based formats
+    pub fn finish(&mut self) -> Result<()> {
+        if self.format == OutputFormat::Json {
+            if self.written == 0 {
+                writeln!(self.out, "[]")?;
+            } else {
+                writeln!(self.out, "\n]")?;
+            }
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+fn indent(text: &str) -> String {
+    text.lines().map(|line| format!("  {}", line)).collect::<Vec<_>>().join("\n")
+}
+
+/// Flattens a packet for Elasticsearch: one object per layer keyed by the
+/// lowercase protocol name, field names with dots replaced by underscores and
+/// repeated fields collected into arrays
+fn ek_document(info: &PacketInfo) -> Value {
+    let mut layers = Map::new();
+    for layer in &info.layers {
+        let fields = layers
+            .entry(layer.protocol.to_ascii_lowercase())
+            .or_insert_with(|| Value::Object(Map::new()));
+        let Value::Object(fields) = fields else {
+            continue;
+        };
+        for field in &layer.fields {
+            let value = plain_value(&field.value);
+            match fields.get_mut(&field.name.replace('.', "_")) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None => {
+                    fields.insert(field.name.replace('.', "_"), value);
+                }
+            }
+        }
+    }
+
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "timestamp": info.captured_at.timestamp_millis().to_string(),
+        "frame_number": info.number,
+        "protocol": info.protocol,
+        "info": info.details,
+        "layers": layers,
+    })
+}
+
+fn plain_value(value: &FieldValue) -> Value {
+    match value {
+        FieldValue::Bool(value) => json!(value),
+        FieldValue::Uint(value) | FieldValue::Hex(value) => json!(value),
+        FieldValue::Int(value) => json!(value),
+        FieldValue::Float(value) => json!(value),
+        _ => json!(value.to_string()),
+    }
+}
+
+/// Which values of a field that occurs several times are extracted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Occurrence {
+    First,
+    Last,
+    All,
+}
+
+impl FromStr for Occurrence {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "f" | "first" => Ok(Occurrence::First),
+            "l" | "last" => Ok(Occurrence::Last),
+            "a" | "all" => Ok(Occurrence::All),
+            _ => Err(anyhow::anyhow!("Unknown occurrence '{}', expected first, last or all", s)),
+        }
+    }
+}
+
+/// When extracted values are enclosed in double quotes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quote {
+    /// Only values containing the separator, a quote or a line break (RFC 4180)
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for Quote {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Quote::Auto),
+            "always" | "d" => Ok(Quote::Always),
+            "never" | "n" => Ok(Quote::Never),
+            _ => Err(anyhow::anyhow!("Unknown quoting '{}', expected auto, always or never", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    pub separator: String,
+    /// Joins the values of a field that occurs several times
+    pub aggregator: String,
+    pub occurrence: Occurrence,
+    pub quote: Quote,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            separator: "\t".to_string(),
+            aggregator: ",".to_string(),
+            occurrence: Occurrence::All,
+            quote: Quote::Auto,
+        }
+    }
+}
+
+/// Extracts selected fields of every packet as separated values.
+///
+/// Besides the decoded fields, the packet list columns are available as
+/// `_ws.col.time`, `_ws.col.src`, `_ws.col.dst`, `_ws.col.protocol`,
+/// `_ws.col.length` and `_ws.col.info`.
+pub struct FieldExtractor {
+    fields: Vec<String>,
+    options: ExtractOptions,
+}
+
+impl FieldExtractor {
+    pub fn new(fields: Vec<String>, options: ExtractOptions) -> Self {
+        Self { fields, options }
+    }
+
+    pub fn header(&self) -> String {
+        let names: Vec<String> = self.fields.iter().map(|name| self.quote(name)).collect();
+        names.join(&self.options.separator)
+    }
+
+    pub fn row(&self, info: &PacketInfo) -> String {
+        let values: Vec<String> = self.fields.iter().map(|name| self.quote(&self.extract(info, name))).collect();
+        values.join(&self.options.separator)
+    }
+
+    fn extract(&self, info: &PacketInfo, name: &str) -> String {
+        let column = match name {
+            "_ws.col.time" => Some(info.timestamp.clone()),
+            "_ws.col.src" => Some(info.source_ip.clone()),
+            "_ws.col.dst" => Some(info.destination_ip.clone()),
+            "_ws.col.protocol" => Some(info.protocol.clone()),
+            "_ws.col.length" => Some(info.length.to_string()),
+            "_ws.col.info" => Some(info.details.clone()),
+            _ => None,
+        };
+        if let Some(column) = column {
+            return column;
+        }
+
+        let values: Vec<String> = info.field_values(name).map(|value| value.to_string()).collect();
+        match self.options.occurrence {
+            Occurrence::First => values.into_iter().next().unwrap_or_default(),
+            Occurrence::Last => values.into_iter().last().unwrap_or_default(),
+            Occurrence::All => values.join(&self.options.aggregator),
+        }
+    }
+
+    fn quote(&self, value: &str) -> String {
+        let needs_quotes = match self.options.quote {
+            Quote::Always => true,
+            Quote::Never => false,
+            Quote::Auto => {
+                // Every string contains the empty separator
+                (!self.options.separator.is_empty() && value.contains(self.options.separator.as_str()))
+                    || value.contains('"')
+                    || value.contains('\n')
+                    || value.contains('\r')
+            }
+        };
+        if needs_quotes {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use crate::packets::Field;
+
+    fn field(name: &str, offset: usize, length: usize, value: impl Into<FieldValue>) -> Field {
+        Field { offset: Some(offset), length, ..Field::new(name, value) }
+    }
+
+    /// TCP segment carrying decrypted HTTP, with every kind of field value
+    fn packet(number: u64) -> PacketInfo {
+        let mut frame = Layer::new("Frame", Some(0), 74);
+        frame.fields = vec![Field::new("frame.number", number), Field::new("frame.time_delta", 0.25)];
+        let mut ethernet = Layer::new("Ethernet", Some(0), 74);
+        ethernet.fields = vec![
+            field("eth.src", 6, 6, FieldValue::Mac([0x00, 0x11, 0x22, 0xaa, 0xbb, 0xcc])),
+            field("eth.type", 12, 2, FieldValue::Hex(0x0800)),
+        ];
+        let mut ipv4 = Layer::new("IPv4", Some(14), 60);
+        ipv4.fields = vec![
+            field("ip.src", 26, 4, "10.0.0.1".parse::<std::net::IpAddr>().unwrap()),
+            field("ip.flags.df", 20, 1, true),
+        ];
+        let mut tcp = Layer::new("TCP", Some(34), 40);
+        tcp.fields = vec![
+            field("tcp.option_kind", 54, 1, 2u8),
+            field("tcp.option_kind", 58, 1, 4u8),
+            Field::new("tcp.analysis.bytes_in_flight_delta", FieldValue::Int(-20)),
+            field("tcp.payload", 74, 0, FieldValue::Bytes(vec![0x17, 0x03, 0x03])),
+        ];
+        let mut http = Layer::new("HTTP", None, 18);
+        http.fields = vec![Field::new("http.request.method", "GET")];
+        PacketInfo {
+            number,
+            captured_at: Utc.timestamp_opt(1_700_000_000, 123_000).unwrap(),
+            timestamp: "22:13:20.000123".to_string(),
+            source_ip: "10.0.0.1".to_string(),
+            destination_ip: "10.0.0.2".to_string(),
+            protocol: "HTTP".to_string(),
+            length: 74,
+            details: "GET / HTTP/1.1".to_string(),
+            layers: vec![frame, ethernet, ipv4, tcp, http],
+        }
+    }
+
+    fn write(format: OutputFormat, count: u64) -> String {
+        let mut out = Vec::new();
+        let mut writer = PacketWriter::new(format, &mut out);
+        for number in 1..=count {
+            writer.write_packet(&packet(number)).unwrap();
+        }
+        writer.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_a_json_array() {
+        assert_eq!(write(OutputFormat::Json, 0), "[]\n");
+        for count in [1, 2] {
+            let packets: Vec<Value> = serde_json::from_str(&write(OutputFormat::Json, count)).unwrap();
+            let numbers: Vec<u64> = packets.iter().map(|packet| packet["frame_number"].as_u64().unwrap()).collect();
+            assert_eq!(numbers, (1..=count).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn writes_one_ndjson_object_per_line() {
+        assert_eq!(write(OutputFormat::Ndjson, 0), "");
+        let output = write(OutputFormat::Ndjson, 2);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for (line, number) in lines.iter().zip(1..) {
+            let packet: Value = serde_json::from_str(line).unwrap();
+            assert_eq!(packet["frame_number"], number);
+            assert_eq!(packet["timestamp"], "2023-11-14T22:13:20.000123Z");
+            assert_eq!(packet["time_epoch"], 1_700_000_000.000123);
+        }
+    }
+
+    #[test]
+    fn writes_ek_index_and_document_lines() {
+        let output = write(OutputFormat::Ek, 1);
+        let lines: Vec<Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let [index, document] = &lines[..] else {
+            panic!("expected an index line and a document");
+        };
+        assert_eq!(index, &json!({ "index": { "_index": "packets-2023-11-14" } }));
+        assert_eq!(document["timestamp"], "1700000000000");
+        assert_eq!(document["frame_number"], 1);
+        assert_eq!(document["protocol"], "HTTP");
+        let layers = &document["layers"];
+        assert_eq!(layers["tcp"]["tcp_option_kind"], json!([2, 4]));
+        assert_eq!(layers["tcp"]["tcp_analysis_bytes_in_flight_delta"], -20);
+        assert_eq!(layers["tcp"]["tcp_payload"], "170303");
+        assert_eq!(layers["ethernet"]["eth_src"], "00:11:22:aa:bb:cc");
+        assert_eq!(layers["ethernet"]["eth_type"], 0x0800);
+        assert_eq!(layers["ipv4"]["ip_src"], "10.0.0.1");
+        assert_eq!(layers["ipv4"]["ip_flags_df"], true);
+        assert_eq!(layers["frame"]["frame_time_delta"], 0.25);
+        assert_eq!(layers["http"]["http_request_method"], "GET");
+    }
+
+    fn extract(fields: &[&str], options: ExtractOptions) -> (String, String) {
+        let extractor = FieldExtractor::new(fields.iter().map(|name| name.to_string()).collect(), options);
+        (extractor.header(), extractor.row(&packet(1)))
+    }
+
+    #[test]
+    fn extracts_packet_list_columns() {
+        let columns = ["_ws.col.time", "_ws.col.src", "_ws.col.dst", "_ws.col.protocol", "_ws.col.length", "_ws.col.info"];
+        let (header, row) = extract(&columns, ExtractOptions::default());
+        assert_eq!(header, columns.join("\t"));
+        assert_eq!(row, "22:13:20.000123\t10.0.0.1\t10.0.0.2\tHTTP\t74\tGET / HTTP/1.1");
+    }
+
+    #[test]
+    fn extracts_repeated_fields() {
+        let fields = ["tcp.option_kind", "ip.src", "http.missing"];
+        let options = |occurrence| ExtractOptions { aggregator: "/".to_string(), occurrence, ..ExtractOptions::default() };
+        assert_eq!(extract(&fields, options(Occurrence::All)).1, "2/4\t10.0.0.1\t");
+        assert_eq!(extract(&fields, options(Occurrence::First)).1, "2\t10.0.0.1\t");
+        assert_eq!(extract(&fields, options(Occurrence::Last)).1, "4\t10.0.0.1\t");
+    }
+
+    #[test]
+    fn quotes_values() {
+        let options = |separator: &str, quote| ExtractOptions { separator: separator.to_string(), quote, ..ExtractOptions::default() };
+        let fields = ["tcp.option_kind", "http.request.method", "_ws.col.info"];
+        // RFC 4180: values holding the separator or a quote are quoted, quotes doubled
+        let extractor = FieldExtractor::new(Vec::new(), options(",", Quote::Auto));
+        assert_eq!(extractor.quote(r#"say "hi""#), r#""say ""hi""""#);
+        assert_eq!(extractor.quote("a\r\nb"), "\"a\r\nb\"");
+        assert_eq!(extract(&fields, options(",", Quote::Auto)).1, "\"2,4\",GET,GET / HTTP/1.1");
+        assert_eq!(extract(&fields, options(" ", Quote::Auto)).1, "2,4 GET \"GET / HTTP/1.1\"");
+        assert_eq!(extract(&fields, options(",", Quote::Always)), (
+            "\"tcp.option_kind\",\"http.request.method\",\"_ws.col.info\"".to_string(),
+            "\"2,4\",\"GET\",\"GET / HTTP/1.1\"".to_string(),
+        ));
+        assert_eq!(extract(&fields, options(",", Quote::Never)).1, "2,4,GET,GET / HTTP/1.1");
+        // The empty separator does not make every value quoted
+        assert_eq!(extract(&fields, options("", Quote::Auto)).1, "2,4GETGET / HTTP/1.1");
+    }
+
+    #[test]
+    fn matches_the_packet_schema() {
+        let schema: Value = serde_json::from_str(include_str!("../../docs/packet-schema-v1.json")).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        let packet = serde_json::to_value(JsonPacket::new(&packet(1))).unwrap();
+        let errors: Vec<String> = validator.iter_errors(&packet).map(|error| error.to_string()).collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        // Every kind of value is checked
+        let kinds: std::collections::BTreeSet<&str> = packet["layers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|layer| layer["fields"].as_array().unwrap())
+            .map(|field| field["value"]["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds.len(), 9);
+        let mut invalid = packet.clone();
+        invalid["layers"][1]["fields"][0]["value"]["value"] = json!("00-11-22-aa-bb-cc");
+        assert!(!validator.is_valid(&invalid));
+    }
+
+    #[test]
+    fn makes_file_names_safe() {
+        assert_eq!(safe_file_name("report.pdf", "object"), "report.pdf");
+        assert_eq!(safe_file_name("../etc/passwd", "object"), ".._etc_passwd");
+        assert_eq!(safe_file_name("C:\\temp\\a?b*.txt", "object"), "C__temp_a_b_.txt");
+        assert_eq!(safe_file_name("line\r\nbreak\0", "object"), "line__break_");
+        assert_eq!(safe_file_name(" .. ", "object"), "object");
+        assert_eq!(safe_file_name("", "object"), "object");
+        let long = format!("{}.pdf", "é".repeat(100));
+        assert_eq!(safe_file_name(&long, "object"), format!("{}.pdf", "é".repeat(60)));
+        assert_eq!(safe_file_name(&"x".repeat(100), "object"), "x".repeat(64));
+    }
+}
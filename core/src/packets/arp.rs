@@ -1,11 +1,26 @@
-use pnet::packet::arp::ArpPacket;
+use anyhow::Result;
+use pnet::packet::arp::{ArpHardwareTypes, ArpPacket};
+use pnet::packet::ethernet::EtherTypes;
 use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use super::arp_watch::{is_gratuitous, is_probe, ArpBinding, ArpWatch};
+use super::dissector::{Dissection, DissectContext, Dissector};
 
-pub struct ArpProcessor;
+/// ARP, with the binding table watched for spoofing
+pub struct ArpProcessor {
+    watch: Mutex<ArpWatch>,
+}
 
 impl ArpProcessor {
     pub fn new() -> Self {
-        Self
+        Self {
+            watch: Mutex::new(ArpWatch::default()),
+        }
+    }
+
+    /// IPv4 to MAC bindings seen so far
+    pub fn bindings(&self) -> Vec<ArpBinding> {
+        self.watch.lock().unwrap().bindings()
     }
 
     pub fn process<'a>(&self, data: &'a [u8]) -> Option<ArpPacket<'a>> {
@@ -20,13 +35,42 @@ impl ArpProcessor {
         let _target_mac = format_mac(&packet.get_target_hw_addr().octets());
 
         match operation.0 {
+            1 if is_probe(packet) => format!("Who has {}? (ARP Probe)", target_ip),
+            1 if is_gratuitous(packet) => format!("ARP Announcement for {}", sender_ip),
             1 => format!("Who has {}? Tell {}", target_ip, sender_ip),
+            2 if is_gratuitous(packet) => format!("Gratuitous ARP for {} (Reply)", sender_ip),
             2 => format!("{} is at {}", sender_ip, sender_mac),
             _ => format!("Unknown ARP operation: {}", operation.0)
         }
     }
 }
 
+impl Dissector for ArpProcessor {
+    fn name(&self) -> &'static str {
+        "ARP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let arp = self.process(data).ok_or_else(|| anyhow::anyhow!("Packet too short for ARP"))?;
+        ctx.add_field_at("arp.opcode", 6, 2, arp.get_operation().0);
+        ctx.add_field_at("arp.src.hw_mac", 8, 6, arp.get_sender_hw_addr());
+        ctx.add_field_at("arp.src.proto_ipv4", 14, 4, arp.get_sender_proto_addr());
+        ctx.add_field_at("arp.dst.hw_mac", 18, 6, arp.get_target_hw_addr());
+        ctx.add_field_at("arp.dst.proto_ipv4", 24, 4, arp.get_target_proto_addr());
+        if arp.get_hardware_type() != ArpHardwareTypes::Ethernet || arp.get_protocol_type() != EtherTypes::Ipv4 {
+            return Ok(Dissection::new("ARP", Self::format_arp_info(&arp)));
+        }
+        ctx.add_field("arp.isgratuitous", is_gratuitous(&arp));
+        ctx.add_field("arp.isprobe", is_probe(&arp));
+
+        let alerts = self.watch.lock().unwrap().update(&arp, ctx.captured_at, &ctx.timestamp);
+        for alert in alerts {
+            ctx.raise(alert);
+        }
+        Ok(Dissection::new("ARP", Self::format_arp_info(&arp)))
+    }
+}
+
 fn format_mac(mac: &[u8]) -> String {
     if mac.iter().all(|&b| b == 0) {
         return "Broadcast".to_string();
@@ -0,0 +1,362 @@
+use chrono::{DateTime, Duration, Utc};
+use pnet::packet::arp::{ArpOperations, ArpPacket};
+use pnet::util::MacAddr;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv4Addr;
+use crate::alerts::{Alert, AlertSeverity};
+use super::flow::FlowTable;
+
+/// ARP packets per second from one MAC above which it is flooding
+const FLOOD_RATE: u32 = 50;
+/// ARP packets per second from all MACs together above which the network is
+/// flooded, e.g. from random source MACs
+const TOTAL_FLOOD_RATE: u32 = 500;
+/// Bindings and the addresses of a MAC not refreshed for this long are forgotten
+const BINDING_TIMEOUT_SECS: i64 = 3600;
+const MAX_BINDINGS: usize = 65536;
+/// Rates of MACs silent for this long are dropped
+const RATE_TIMEOUT_SECS: i64 = 10;
+/// Distinct addresses one MAC may claim before it is reported
+const MAX_ADDRESSES_PER_MAC: usize = 4;
+/// How long a request waits for its reply
+const REPLY_TIMEOUT_SECS: i64 = 5;
+const MAX_PENDING_REQUESTS: usize = 4096;
+
+/// IPv4 address to MAC binding learned from ARP
+#[derive(Debug, Clone, Serialize)]
+pub struct ArpBinding {
+    pub ip: Ipv4Addr,
+    pub mac: String,
+    /// MACs the address was bound to before, oldest first
+    pub previous_macs: Vec<String>,
+    /// ARP packets sent for this binding
+    pub packets: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Time of the last MAC change
+    pub changed_at: Option<DateTime<Utc>>,
+}
+
+impl ArpBinding {
+    pub fn format_info(&self) -> String {
+        format!(
+            "{:<15} {:<17} {:>7} {} {} {}",
+            self.ip,
+            self.mac,
+            self.packets,
+            self.first_seen.format("%Y-%m-%d %H:%M:%S"),
+            self.last_seen.format("%Y-%m-%d %H:%M:%S"),
+            self.previous_macs.join(",")
+        )
+    }
+}
+
+/// ARP packets sent in the current one second window
+struct Rate {
+    window_start: DateTime<Utc>,
+    count: u32,
+    /// Already reported for the running flood
+    flooding: bool,
+}
+
+impl Rate {
+    fn new(at: DateTime<Utc>) -> Self {
+        Self {
+            window_start: at,
+            count: 0,
+            flooding: false,
+        }
+    }
+
+    /// Counts a packet; returns the count once it reaches `limit`, once per flood
+    fn count(&mut self, at: DateTime<Utc>, limit: u32) -> Option<u32> {
+        if at - self.window_start >= Duration::seconds(1) {
+            self.flooding = self.flooding && self.count >= limit;
+            self.window_start = at;
+            self.count = 0;
+        }
+        self.count += 1;
+        if self.count < limit || self.flooding {
+            return None;
+        }
+        self.flooding = true;
+        Some(self.count)
+    }
+}
+
+/// Addresses currently bound to one MAC
+#[derive(Default)]
+struct Claims {
+    addresses: HashSet<Ipv4Addr>,
+    /// Already reported for claiming too many addresses
+    reported: bool,
+}
+
+/// Binding table built from the ARP traffic, watching for spoofing
+pub(crate) struct ArpWatch {
+    bindings: FlowTable<Ipv4Addr, ArpBinding>,
+    claims: FlowTable<MacAddr, Claims>,
+    /// Unanswered requests by (sender, target) address
+    requests: HashMap<(Ipv4Addr, Ipv4Addr), DateTime<Utc>>,
+    rates: FlowTable<MacAddr, Rate>,
+    /// ARP packets from all MACs
+    total: Option<Rate>,
+}
+
+impl Default for ArpWatch {
+    fn default() -> Self {
+        let binding_timeout = Duration::seconds(BINDING_TIMEOUT_SECS);
+        Self {
+            bindings: FlowTable::with_limits(binding_timeout, MAX_BINDINGS),
+            claims: FlowTable::with_limits(binding_timeout, MAX_BINDINGS),
+            requests: HashMap::new(),
+            rates: FlowTable::with_limits(Duration::seconds(RATE_TIMEOUT_SECS), MAX_BINDINGS),
+            total: None,
+        }
+    }
+}
+
+/// Sender and target address are the same: the host announces its own binding
+pub fn is_gratuitous(packet: &ArpPacket) -> bool {
+    let sender = packet.get_sender_proto_addr();
+    !sender.is_unspecified() && sender == packet.get_target_proto_addr()
+}
+
+/// Request for an address without a sender address (RFC 5227)
+pub fn is_probe(packet: &ArpPacket) -> bool {
+    packet.get_operation() == ArpOperations::Request && packet.get_sender_proto_addr().is_unspecified()
+}
+
+impl ArpWatch {
+    /// Learns the sender binding of the packet. Returns alerts for suspicious traffic.
+    pub fn update(&mut self, packet: &ArpPacket, at: DateTime<Utc>, timestamp: &str) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        let operation = packet.get_operation();
+        let mac = packet.get_sender_hw_addr();
+        let sender = packet.get_sender_proto_addr();
+        let target = packet.get_target_proto_addr();
+
+        alerts.extend(self.count(mac, at, timestamp));
+
+        let gratuitous = is_gratuitous(packet);
+        // Hosts repeat their announcements; only a new or changed binding is news
+        let known = self.bindings.get(&sender).is_some_and(|binding| binding.mac == mac.to_string());
+        if gratuitous && !known {
+            alerts.push(Alert::new(
+                timestamp,
+                AlertSeverity::Info,
+                "arp.gratuitous",
+                format!("Gratuitous ARP {} for {} at {}", operation_name(packet), sender, mac),
+            ));
+        }
+
+        if operation == ArpOperations::Request && !sender.is_unspecified() {
+            if self.requests.len() >= MAX_PENDING_REQUESTS {
+                let timeout = Duration::seconds(REPLY_TIMEOUT_SECS);
+                self.requests.retain(|_, asked_at| at - *asked_at <= timeout);
+            }
+            self.requests.insert((sender, target), at);
+        } else if operation == ArpOperations::Reply && !gratuitous {
+            let solicited = self
+                .requests
+                .remove(&(target, sender))
+                .is_some_and(|asked_at| at - asked_at <= Duration::seconds(REPLY_TIMEOUT_SECS));
+            if !solicited {
+                alerts.push(Alert::new(
+                    timestamp,
+                    AlertSeverity::Warning,
+                    "arp.unsolicited_reply",
+                    format!("Unsolicited ARP reply {} is at {} sent to {}", sender, mac, target),
+                ));
+            }
+        }
+
+        if !sender.is_unspecified() {
+            alerts.extend(self.learn(sender, mac, gratuitous, at, timestamp));
+        }
+        alerts
+    }
+
+    fn count(&mut self, mac: MacAddr, at: DateTime<Utc>, timestamp: &str) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        if let Some(count) = self.rates.entry(mac, at, || Rate::new(at)).count(at, FLOOD_RATE) {
+            alerts.push(Alert::new(
+                timestamp,
+                AlertSeverity::Warning,
+                "arp.flood",
+                format!("{} sent {} ARP packets within a second", mac, count),
+            ));
+        }
+        // Floods from random MACs stay below the rate of each of them
+        if let Some(count) = self.total.get_or_insert_with(|| Rate::new(at)).count(at, TOTAL_FLOOD_RATE) {
+            alerts.push(Alert::new(
+                timestamp,
+                AlertSeverity::Warning,
+                "arp.flood",
+                format!("{} ARP packets within a second from all hosts", count),
+            ));
+        }
+        alerts
+    }
+
+    fn learn(&mut self, ip: Ipv4Addr, mac: MacAddr, gratuitous: bool, at: DateTime<Utc>, timestamp: &str) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        let binding = self.bindings.entry(ip, at, || ArpBinding {
+            ip,
+            mac: mac.to_string(),
+            previous_macs: Vec::new(),
+            packets: 0,
+            first_seen: at,
+            last_seen: at,
+            changed_at: None,
+        });
+        let mac_string = mac.to_string();
+        if binding.mac != mac_string {
+            let seen_before = binding.previous_macs.contains(&mac_string);
+            alerts.push(Alert::new(
+                timestamp,
+                AlertSeverity::Critical,
+                "arp.mac_changed",
+                format!(
+                    "{} moved from {} to {}{}{}, possible ARP spoofing",
+                    ip,
+                    binding.mac,
+                    mac_string,
+                    if gratuitous { " by gratuitous ARP" } else { "" },
+                    if seen_before { " (flip-flop)" } else { "" }
+                ),
+            ));
+            let previous = std::mem::replace(&mut binding.mac, mac_string.clone());
+            binding.previous_macs.retain(|old| *old != mac_string && *old != previous);
+            binding.previous_macs.push(previous);
+            binding.changed_at = Some(at);
+        }
+        binding.packets += 1;
+        binding.last_seen = at;
+
+        let claims = self.claims.entry(mac, at, Claims::default);
+        claims.addresses.insert(ip);
+        // Drop addresses that have since moved to another MAC or were forgotten
+        claims
+            .addresses
+            .retain(|address| self.bindings.get(address).is_some_and(|binding| binding.mac == mac_string));
+        if claims.addresses.len() > MAX_ADDRESSES_PER_MAC && !claims.reported {
+            claims.reported = true;
+            let mut claimed: Vec<&Ipv4Addr> = claims.addresses.iter().collect();
+            claimed.sort();
+            let claimed: Vec<String> = claimed.iter().map(|ip| ip.to_string()).collect();
+            alerts.push(Alert::new(
+                timestamp,
+                AlertSeverity::Warning,
+                "arp.many_addresses",
+                format!("{} claims {} addresses: {}", mac, claimed.len(), claimed.join(", ")),
+            ));
+        }
+        alerts
+    }
+
+    /// Bindings ordered by address
+    pub fn bindings(&self) -> Vec<ArpBinding> {
+        let mut bindings: Vec<ArpBinding> = self.bindings.values().cloned().collect();
+        bindings.sort_by_key(|binding| binding.ip);
+        bindings
+    }
+}
+
+fn operation_name(packet: &ArpPacket) -> &'static str {
+    match packet.get_operation() {
+        ArpOperations::Request => "request",
+        ArpOperations::Reply => "reply",
+        _ => "packet",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissector::testing::at;
+    use pnet::packet::arp::{ArpHardwareTypes, ArpOperation, MutableArpPacket};
+    use pnet::packet::ethernet::EtherTypes;
+
+    fn arp(operation: ArpOperation, mac: MacAddr, sender: [u8; 4], target: [u8; 4]) -> Vec<u8> {
+        let mut buffer = vec![0; 28];
+        let mut packet = MutableArpPacket::new(&mut buffer).unwrap();
+        packet.set_hardware_type(ArpHardwareTypes::Ethernet);
+        packet.set_protocol_type(EtherTypes::Ipv4);
+        packet.set_hw_addr_len(6);
+        packet.set_proto_addr_len(4);
+        packet.set_operation(operation);
+        packet.set_sender_hw_addr(mac);
+        packet.set_sender_proto_addr(sender.into());
+        packet.set_target_proto_addr(target.into());
+        buffer
+    }
+
+    fn update(watch: &mut ArpWatch, packet: &[u8], millis: i64) -> Vec<String> {
+        let packet = ArpPacket::new(packet).unwrap();
+        let alerts = watch.update(&packet, at(0) + Duration::milliseconds(millis), "");
+        alerts.into_iter().map(|alert| alert.category).collect()
+    }
+
+    fn mac(number: u16) -> MacAddr {
+        MacAddr::new(0x02, 0, 0, 0, (number >> 8) as u8, number as u8)
+    }
+
+    #[test]
+    fn reports_a_changed_binding() {
+        let mut watch = ArpWatch::default();
+        let request = arp(ArpOperations::Request, mac(1), [10, 0, 0, 1], [10, 0, 0, 2]);
+        assert!(update(&mut watch, &request, 0).is_empty());
+        let reply = arp(ArpOperations::Reply, mac(2), [10, 0, 0, 2], [10, 0, 0, 1]);
+        assert!(update(&mut watch, &reply, 10).is_empty());
+        let spoofed = arp(ArpOperations::Reply, mac(3), [10, 0, 0, 2], [10, 0, 0, 1]);
+        assert_eq!(update(&mut watch, &spoofed, 20), ["arp.unsolicited_reply", "arp.mac_changed"]);
+        assert_eq!(watch.bindings()[1].previous_macs, [mac(2).to_string()]);
+    }
+
+    #[test]
+    fn reports_a_flood_once() {
+        let mut watch = ArpWatch::default();
+        let request = arp(ArpOperations::Request, mac(1), [10, 0, 0, 1], [10, 0, 0, 2]);
+        let floods: usize = (0..200)
+            .map(|i| update(&mut watch, &request, i).iter().filter(|alert| *alert == "arp.flood").count())
+            .sum();
+        assert_eq!(floods, 1);
+    }
+
+    #[test]
+    fn reports_floods_from_random_macs() {
+        let mut watch = ArpWatch::default();
+        let mut floods = 0;
+        for i in 0..TOTAL_FLOOD_RATE {
+            let request = arp(ArpOperations::Request, mac(i as u16), [10, 1, (i >> 8) as u8, i as u8], [10, 0, 0, 1]);
+            floods += update(&mut watch, &request, i as i64).iter().filter(|alert| *alert == "arp.flood").count();
+        }
+        assert_eq!(floods, 1);
+    }
+
+    #[test]
+    fn forgets_idle_bindings() {
+        let mut watch = ArpWatch::default();
+        let request = arp(ArpOperations::Request, mac(1), [10, 0, 0, 1], [10, 0, 0, 2]);
+        update(&mut watch, &request, 0);
+        let later = arp(ArpOperations::Request, mac(2), [10, 0, 0, 3], [10, 0, 0, 2]);
+        update(&mut watch, &later, (BINDING_TIMEOUT_SECS + 1) * 1000);
+        let addresses: Vec<Ipv4Addr> = watch.bindings().iter().map(|binding| binding.ip).collect();
+        assert_eq!(addresses, [Ipv4Addr::new(10, 0, 0, 3)]);
+        assert!(watch.claims.get(&mac(1)).is_none());
+        assert!(watch.rates.get(&mac(1)).is_none());
+    }
+
+    #[test]
+    fn reports_a_gratuitous_binding_once() {
+        let mut watch = ArpWatch::default();
+        let announcement = arp(ArpOperations::Request, mac(1), [10, 0, 0, 1], [10, 0, 0, 1]);
+        let alerts: Vec<String> = (0..5).flat_map(|i| update(&mut watch, &announcement, i * 1000)).collect();
+        assert_eq!(alerts, ["arp.gratuitous"]);
+
+        let takeover = arp(ArpOperations::Reply, mac(2), [10, 0, 0, 1], [10, 0, 0, 1]);
+        assert_eq!(update(&mut watch, &takeover, 10_000), ["arp.gratuitous", "arp.mac_changed"]);
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+
+/// Bounds-checked big-endian reader over a byte slice
+pub(crate) struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Offset of the next byte to read
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(anyhow::anyhow!(
+                "Unexpected end of data: need {} bytes at offset {}, have {}",
+                len,
+                self.pos,
+                self.remaining()
+            ));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let b = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let b = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn read_u24(&mut self) -> Result<u32> {
+        let b = self.read_bytes(3)?;
+        Ok(u32::from_be_bytes([0, b[0], b[1], b[2]]))
+    }
+
+    /// Reads a QUIC variable-length integer (RFC 9000, section 16)
+    pub fn read_varint(&mut self) -> Result<u64> {
+        let first = self.read_u8()?;
+        let rest = self.read_bytes((1 << (first >> 6)) - 1)?;
+        Ok(rest.iter().fold((first & 0x3f) as u64, |value, b| value << 8 | *b as u64))
+    }
+
+    /// Reads a vector prefixed with a one-byte length
+    pub fn read_vec8(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u8()? as usize;
+        self.read_bytes(len)
+    }
+
+    /// Reads a vector prefixed with a two-byte length
+    pub fn read_vec16(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u16()? as usize;
+        self.read_bytes(len)
+    }
+
+    /// Reads a vector prefixed with a three-byte length
+    pub fn read_vec24(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_u24()? as usize;
+        self.read_bytes(len)
+    }
+}
+
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes a hex string; `None` for an odd length or a non-hex character
+pub(crate) fn from_hex(text: &str) -> Option<Vec<u8>> {
+    let digits = text.as_bytes();
+    if !digits.len().is_multiple_of(2) || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let value = |digit: u8| (digit as char).to_digit(16).unwrap_or_default() as u8;
+    Some(digits.chunks_exact(2).map(|pair| value(pair[0]) << 4 | value(pair[1])).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_hex() {
+        assert_eq!(from_hex("00ff7Fa0"), Some(vec![0x00, 0xff, 0x7f, 0xa0]));
+        assert_eq!(to_hex(&[0x00, 0xff, 0x7f, 0xa0]), "00ff7fa0");
+        assert_eq!(from_hex(""), Some(Vec::new()));
+    }
+
+    #[test]
+    fn rejects_invalid_hex() {
+        for text in ["0", "0g", "+1", "-1", " 1", "ü", "aü", "a\u{fffd}b"] {
+            assert_eq!(from_hex(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn reader_stops_at_the_end() {
+        let mut reader = ByteReader::new(&[0x00, 0x05, 0x01]);
+        assert!(reader.read_vec16().is_err());
+        let mut reader = ByteReader::new(&[0x01, 0x02, 0x03]);
+        assert_eq!(reader.read_u16().unwrap(), 0x0102);
+        assert!(reader.read_u16().is_err());
+        assert_eq!(reader.read_u8().unwrap(), 0x03);
+        assert!(reader.is_empty());
+    }
+}
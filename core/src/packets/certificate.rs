@@ -0,0 +1,248 @@
+use anyhow::Result;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::*;
+use x509_parser::public_key::PublicKey;
+use super::bytes::to_hex;
+
+/// Summary of an X.509 certificate seen in a TLS handshake
+#[derive(Debug, Clone)]
+pub struct CertificateInfo {
+    pub der: Vec<u8>,
+    pub subject: String,
+    pub issuer: String,
+    pub common_name: Option<String>,
+    pub subject_alt_names: Vec<String>,
+    pub serial: String,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    pub key_type: String,
+    pub key_bits: usize,
+    pub sha256: String,
+    pub is_ca: bool,
+    pub self_signed: bool,
+}
+
+impl CertificateInfo {
+    pub fn parse(der: &[u8]) -> Result<Self> {
+        let (_, cert) = X509Certificate::from_der(der)
+            .map_err(|e| anyhow::anyhow!("Failed to parse certificate: {}", e))?;
+
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(|cn| cn.to_string());
+
+        let subject_alt_names = match cert.subject_alternative_name() {
+            Ok(Some(san)) => san
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(dns) => Some(dns.to_string()),
+                    GeneralName::IPAddress(ip) => format_ip(ip),
+                    GeneralName::RFC822Name(email) => Some(email.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let (key_type, key_bits) = match cert.public_key().parsed() {
+            Ok(PublicKey::RSA(rsa)) => ("RSA".to_string(), integer_bits(rsa.modulus)),
+            Ok(PublicKey::EC(point)) => ("EC".to_string(), point.key_size()),
+            Ok(PublicKey::DSA(y)) => ("DSA".to_string(), integer_bits(y)),
+            _ => {
+                let oid = cert.public_key().algorithm.algorithm.to_id_string();
+                let name = match oid.as_str() {
+                    "1.3.101.112" => "Ed25519".to_string(),
+                    "1.3.101.113" => "Ed448".to_string(),
+                    _ => oid,
+                };
+                (name, cert.public_key().subject_public_key.data.len() * 8)
+            }
+        };
+
+        let validity = cert.validity();
+        let self_signed = cert.subject().as_raw() == cert.issuer().as_raw();
+
+        Ok(Self {
+            der: der.to_vec(),
+            subject: cert.subject().to_string(),
+            issuer: cert.issuer().to_string(),
+            common_name,
+            subject_alt_names,
+            serial: cert.raw_serial_as_string(),
+            not_before: to_datetime(validity.not_before.timestamp()),
+            not_after: to_datetime(validity.not_after.timestamp()),
+            key_type,
+            key_bits,
+            sha256: to_hex(&Sha256::digest(der)),
+            is_ca: cert.is_ca(),
+            self_signed,
+        })
+    }
+
+    pub fn is_expired(&self, at: DateTime<Utc>) -> bool {
+        at > self.not_after
+    }
+
+    pub fn is_not_yet_valid(&self, at: DateTime<Utc>) -> bool {
+        at < self.not_before
+    }
+
+    /// Checks the host name against the SANs, falling back to the CN when no DNS SAN is present
+    pub fn matches_hostname(&self, hostname: &str) -> bool {
+        let hostname = hostname.trim_end_matches('.').to_lowercase();
+        let dns_names: Vec<&String> = self
+            .subject_alt_names
+            .iter()
+            .filter(|name| !name.contains('@'))
+            .collect();
+
+        if dns_names.is_empty() {
+            return self
+                .common_name
+                .as_ref()
+                .map(|cn| hostname_matches_pattern(&hostname, cn))
+                .unwrap_or(false);
+        }
+
+        dns_names
+            .iter()
+            .any(|pattern| hostname_matches_pattern(&hostname, pattern))
+    }
+
+    pub fn to_pem(&self) -> String {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&self.der);
+        let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+        for line in encoded.as_bytes().chunks(64) {
+            pem.push_str(std::str::from_utf8(line).unwrap());
+            pem.push('\n');
+        }
+        pem.push_str("-----END CERTIFICATE-----\n");
+        pem
+    }
+
+    pub fn format_info(&self) -> String {
+        format!(
+            "{} (issuer {}), {} {} bits, valid {} - {}",
+            self.common_name.as_deref().unwrap_or(&self.subject),
+            self.issuer,
+            self.key_type,
+            self.key_bits,
+            self.not_before.format("%Y-%m-%d"),
+            self.not_after.format("%Y-%m-%d")
+        )
+    }
+}
+
+fn hostname_matches_pattern(hostname: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.').to_lowercase();
+    if let Some(suffix) = pattern.strip_prefix("*.") {
+        // A wildcard covers exactly one left-most label
+        match hostname.split_once('.') {
+            Some((label, rest)) => !label.is_empty() && rest == suffix,
+            None => false,
+        }
+    } else {
+        hostname == pattern
+    }
+}
+
+fn integer_bits(value: &[u8]) -> usize {
+    let value = match value.iter().position(|&b| b != 0) {
+        Some(start) => &value[start..],
+        None => return 0,
+    };
+    value.len() * 8 - value[0].leading_zeros() as usize
+}
+
+fn format_ip(ip: &[u8]) -> Option<String> {
+    match ip.len() {
+        4 => Some(std::net::Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]).to_string()),
+        16 => {
+            let octets: [u8; 16] = ip.try_into().ok()?;
+            Some(std::net::Ipv6Addr::from(octets).to_string())
+        }
+        _ => None,
+    }
+}
+
+fn to_datetime(timestamp: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(timestamp, 0).unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    /// Self-signed EC root "Test Root CA", valid from 2020 to 2040
+    pub(crate) const ROOT: &[u8] = include_bytes!("testdata/root.der");
+    /// "www.example.com" issued by [`ROOT`] and valid during 2024, for
+    /// `*.example.com`, `example.com`, 192.0.2.1 and admin@example.com
+    pub(crate) const LEAF: &[u8] = include_bytes!("testdata/leaf.der");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::testing::{LEAF, ROOT};
+
+    fn at(date: &str) -> DateTime<Utc> {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_a_certificate() {
+        let leaf = CertificateInfo::parse(LEAF).unwrap();
+        assert_eq!(leaf.subject, "CN=www.example.com");
+        assert_eq!(leaf.issuer, "CN=Test Root CA");
+        assert_eq!(leaf.common_name.as_deref(), Some("www.example.com"));
+        assert_eq!(leaf.subject_alt_names, ["*.example.com", "example.com", "192.0.2.1", "admin@example.com"]);
+        assert_eq!(leaf.serial, "10:01");
+        assert_eq!((leaf.key_type.as_str(), leaf.key_bits), ("EC", 256));
+        assert_eq!(leaf.sha256, "21eae2212670cb55834d38b4e5d7ce4699c16eac570dbf91e947b8de8f1d3b2d");
+        assert!(!leaf.is_ca && !leaf.self_signed);
+        assert_eq!(
+            leaf.format_info(),
+            "www.example.com (issuer CN=Test Root CA), EC 256 bits, valid 2024-01-01 - 2025-01-01"
+        );
+        assert!(leaf.to_pem().starts_with("-----BEGIN CERTIFICATE-----\nMII"));
+
+        let root = CertificateInfo::parse(ROOT).unwrap();
+        assert!(root.is_ca && root.self_signed);
+        assert!(root.subject_alt_names.is_empty());
+
+        assert!(CertificateInfo::parse(&LEAF[..LEAF.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn checks_the_validity_period() {
+        let leaf = CertificateInfo::parse(LEAF).unwrap();
+        assert!(leaf.is_not_yet_valid(at("2023-12-31T23:59:59Z")));
+        for date in ["2024-01-01T00:00:00Z", "2024-06-01T12:00:00Z", "2025-01-01T00:00:00Z"] {
+            assert!(!leaf.is_not_yet_valid(at(date)) && !leaf.is_expired(at(date)), "{}", date);
+        }
+        assert!(leaf.is_expired(at("2025-01-01T00:00:01Z")));
+    }
+
+    #[test]
+    fn matches_host_names() {
+        let mut leaf = CertificateInfo::parse(LEAF).unwrap();
+        for hostname in ["www.example.com", "api.example.com", "example.com", "WWW.Example.COM.", "192.0.2.1"] {
+            assert!(leaf.matches_hostname(hostname), "{}", hostname);
+        }
+        // The wildcard covers one label, and e-mail addresses are not host names
+        for hostname in ["a.b.example.com", ".example.com", "example.org", "admin@example.com"] {
+            assert!(!leaf.matches_hostname(hostname), "{}", hostname);
+        }
+
+        // Without DNS names, the common name is used
+        leaf.subject_alt_names.retain(|name| name.contains('@'));
+        assert!(leaf.matches_hostname("www.example.com"));
+        assert!(!leaf.matches_hostname("api.example.com"));
+    }
+}
@@ -0,0 +1,249 @@
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, Mutex};
+use super::dissector::{Dissector, DissectorRegistry, DissectorTable, Transport};
+use super::flow::{FlowKey, FlowTable};
+use super::tree::Field;
+
+/// Minimum confidence for a classification to stick to the flow
+const CLASSIFY_THRESHOLD: u8 = 50;
+/// Bonus for a payload match on a port the dissector is registered for
+const PORT_BONUS: u8 = 10;
+/// Confidence of a guess made from the port alone
+const PORT_ONLY_CONFIDENCE: u8 = 25;
+/// Confidence of a connection announced by another one
+const EXPECTED_CONFIDENCE: u8 = 100;
+
+/// Outcome of a heuristic probe over the first bytes of a flow
+#[derive(Debug, Clone, Copy)]
+pub struct ProbeResult {
+    /// 0-100
+    pub confidence: u8,
+    pub reason: &'static str,
+}
+
+impl ProbeResult {
+    pub fn new(confidence: u8, reason: &'static str) -> Self {
+        Self { confidence, reason }
+    }
+}
+
+/// Why a flow was attributed to a protocol
+#[derive(Clone)]
+pub struct Detection {
+    pub dissector: Arc<dyn Dissector>,
+    pub confidence: u8,
+    pub reason: String,
+    /// The classification was made on this packet
+    pub new: bool,
+}
+
+impl Detection {
+    pub fn protocol(&self) -> &'static str {
+        self.dissector.name()
+    }
+
+    pub fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::new("app.protocol", self.protocol()),
+            Field::new("app.confidence", self.confidence),
+            Field::new("app.reason", &self.reason),
+        ]
+    }
+
+    /// Note appended to the packet that classified its flow
+    pub fn format_note(&self) -> String {
+        format!(" [{} detected: {}, confidence {}%]", self.protocol(), self.reason, self.confidence)
+    }
+}
+
+/// Remembers the protocol of every flow of one transport once the
+/// heuristics are confident
+pub struct FlowClassifier {
+    transport: Transport,
+    /// Detections by canonical flow
+    flows: Mutex<FlowTable<FlowKey, Detection>>,
+}
+
+impl FlowClassifier {
+    pub fn new(transport: Transport) -> Self {
+        Self {
+            transport,
+            flows: Mutex::new(FlowTable::new()),
+        }
+    }
+
+    /// Returns the protocol of the flow, probing the heuristic dissectors on
+    /// the payload while the flow is still unclassified. A guess from the
+    /// port alone is returned but not remembered. `at` is the capture time of
+    /// the packet.
+    pub fn classify(&self, flow: &FlowKey, registry: &DissectorRegistry, payload: &[u8], at: DateTime<Utc>) -> Option<Detection> {
+        let key = flow.canonical();
+        let mut flows = self.flows.lock().unwrap();
+        if let Some(detection) = flows.seen(&key, at) {
+            return Some(Detection { new: false, ..detection.clone() });
+        }
+        if let Some(expectation) = registry.take_expected(self.transport, flow, at) {
+            let mut detection = Detection {
+                dissector: expectation.dissector,
+                confidence: EXPECTED_CONFIDENCE,
+                reason: expectation.reason,
+                new: false,
+            };
+            flows.insert(key, at, detection.clone());
+            detection.new = true;
+            return Some(detection);
+        }
+        if payload.is_empty() {
+            return None;
+        }
+
+        let port_dissector = |port: u16| registry.get(DissectorTable::Port(self.transport, port)).map(|d| (port, d));
+        // The lower port is usually the server's
+        let (low, high) = (flow.src_port.min(flow.dst_port), flow.src_port.max(flow.dst_port));
+        let by_port = port_dissector(low).or_else(|| port_dissector(high));
+
+        let best = registry
+            .heuristics(self.transport)
+            .iter()
+            .filter_map(|dissector| {
+                let probe = dissector.probe(payload)?;
+                let (confidence, reason) = match by_port {
+                    Some((port, registered)) if registered.name() == dissector.name() => (
+                        probe.confidence.saturating_add(PORT_BONUS).min(100),
+                        format!("{} on port {}", probe.reason, port),
+                    ),
+                    _ => (probe.confidence, probe.reason.to_string()),
+                };
+                Some(Detection { dissector: dissector.clone(), confidence, reason, new: false })
+            })
+            .max_by_key(|detection| detection.confidence);
+
+        match best {
+            Some(mut detection) if detection.confidence >= CLASSIFY_THRESHOLD => {
+                flows.insert(key, at, detection.clone());
+                detection.new = true;
+                Some(detection)
+            }
+            // A weak guess at another protocol does not override the port,
+            // e.g. DNS over TCP that a probe takes for something else
+            Some(detection) if by_port.is_none_or(|(_, dissector)| dissector.name() == detection.protocol()) => {
+                Some(detection)
+            }
+            _ => by_port.map(|(port, dissector)| Detection {
+                dissector: dissector.clone(),
+                confidence: PORT_ONLY_CONFIDENCE,
+                reason: format!("well-known port {}", port),
+                new: false,
+            }),
+        }
+    }
+
+    /// Forgets the protocol of a flow that was closed
+    pub fn forget(&self, flow: &FlowKey) {
+        self.flows.lock().unwrap().remove(&flow.canonical());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use super::super::dissector::testing::{at, flow};
+    use super::super::dissector::{DissectContext, Dissection, Expectation};
+
+    struct Probed(u8);
+
+    impl Dissector for Probed {
+        fn name(&self) -> &'static str {
+            "PROBED"
+        }
+
+        fn dissect(&self, _data: &[u8], _ctx: &mut DissectContext) -> Result<Dissection> {
+            Ok(Dissection::new("PROBED", ""))
+        }
+
+        fn probe(&self, _payload: &[u8]) -> Option<ProbeResult> {
+            Some(ProbeResult::new(self.0, "probed"))
+        }
+    }
+
+    /// Dissector recognised by its port only
+    struct Ported;
+
+    impl Dissector for Ported {
+        fn name(&self) -> &'static str {
+            "PORTED"
+        }
+
+        fn dissect(&self, _data: &[u8], _ctx: &mut DissectContext) -> Result<Dissection> {
+            Ok(Dissection::new("PORTED", ""))
+        }
+    }
+
+    fn registry(confidence: u8) -> DissectorRegistry {
+        let dissector: Arc<dyn Dissector> = Arc::new(Probed(confidence));
+        let mut registry = DissectorRegistry::new();
+        registry.register(DissectorTable::Port(Transport::Tcp, 7000), dissector.clone());
+        registry.register_heuristic(Transport::Tcp, dissector);
+        registry
+    }
+
+    #[test]
+    fn caps_the_port_bonus() {
+        let registry = registry(250);
+        let classifier = FlowClassifier::new(Transport::Tcp);
+        let detection = classifier.classify(&flow(7000), &registry, b"payload", at(0)).unwrap();
+        assert_eq!(detection.confidence, 100);
+        assert!(detection.new);
+    }
+
+    #[test]
+    fn forgets_flows() {
+        let registry = registry(60);
+        let classifier = FlowClassifier::new(Transport::Tcp);
+        assert!(classifier.classify(&flow(7000), &registry, b"payload", at(0)).unwrap().new);
+        assert!(!classifier.classify(&flow(7000).reversed(), &registry, b"", at(1)).unwrap().new);
+        classifier.forget(&flow(7000).reversed());
+        assert!(classifier.classify(&flow(7000), &registry, b"payload", at(2)).unwrap().new);
+    }
+
+    #[test]
+    fn expectations_expire() {
+        let registry = registry(0);
+        let classifier = FlowClassifier::new(Transport::Tcp);
+        let expectation = || Expectation { dissector: Arc::new(Probed(0)), reason: "announced".to_string() };
+        let server = flow(7000).dst;
+
+        registry.expect(Transport::Tcp, server, 7000, expectation(), at(0));
+        let detection = classifier.classify(&flow(7000), &registry, b"", at(60)).unwrap();
+        assert_eq!(detection.confidence, EXPECTED_CONFIDENCE);
+        assert_eq!(detection.reason, "announced");
+
+        let late = FlowKey::new("10.0.0.1".parse().unwrap(), 50001, server, 7000);
+        registry.expect(Transport::Tcp, server, 7000, expectation(), at(0));
+        assert!(classifier.classify(&late, &registry, b"", at(600)).is_none());
+    }
+
+    #[test]
+    fn prefers_the_port_to_a_weak_probe() {
+        let mut ported = DissectorRegistry::new();
+        ported.register(DissectorTable::Port(Transport::Tcp, 7000), Arc::new(Ported));
+        ported.register_heuristic(Transport::Tcp, Arc::new(Probed(30)));
+        let classifier = FlowClassifier::new(Transport::Tcp);
+        let detection = classifier.classify(&flow(7000), &ported, b"payload", at(0)).unwrap();
+        assert_eq!(detection.protocol(), "PORTED");
+        assert_eq!((detection.confidence, detection.reason.as_str()), (PORT_ONLY_CONFIDENCE, "well-known port 7000"));
+        assert!(!detection.new);
+
+        // A weak probe of the protocol of the port is kept, and neither is remembered
+        let detection = classifier.classify(&flow(7000), &registry(20), b"payload", at(1)).unwrap();
+        assert_eq!((detection.protocol(), detection.confidence), ("PROBED", 30));
+        assert_eq!(detection.reason, "probed on port 7000");
+        assert!(classifier.classify(&flow(7000), &registry(20), b"", at(2)).is_none());
+
+        // Without a port dissector the weak probe is the best guess
+        let mut unported = DissectorRegistry::new();
+        unported.register_heuristic(Transport::Tcp, Arc::new(Probed(30)));
+        assert_eq!(classifier.classify(&flow(7000), &unported, b"payload", at(3)).unwrap().protocol(), "PROBED");
+    }
+}
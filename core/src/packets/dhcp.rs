@@ -1,95 +1,182 @@
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Mutex;
 use anyhow::Result;
+use pnet::util::MacAddr;
+use super::bytes::ByteReader;
+use super::detection::ProbeResult;
+use super::dhcp_leases::{DhcpAllowList, DhcpLease, LeaseTable};
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::tree::FieldValue;
 
-#[derive(Debug)]
+const MAGIC_COOKIE: u32 = 0x6382_5363;
+/// Fixed BOOTP header and magic cookie preceding the options
+const OPTIONS_OFFSET: usize = 240;
+
+pub const OPTION_SUBNET_MASK: u8 = 1;
+pub const OPTION_ROUTER: u8 = 3;
+pub const OPTION_DNS_SERVER: u8 = 6;
+pub const OPTION_HOSTNAME: u8 = 12;
+pub const OPTION_DOMAIN_NAME: u8 = 15;
+pub const OPTION_BROADCAST: u8 = 28;
+pub const OPTION_NTP_SERVER: u8 = 42;
+pub const OPTION_REQUESTED_IP: u8 = 50;
+pub const OPTION_LEASE_TIME: u8 = 51;
+pub const OPTION_MESSAGE_TYPE: u8 = 53;
+pub const OPTION_SERVER_ID: u8 = 54;
+pub const OPTION_PARAMETER_LIST: u8 = 55;
+pub const OPTION_MESSAGE: u8 = 56;
+pub const OPTION_MAX_SIZE: u8 = 57;
+pub const OPTION_RENEWAL_TIME: u8 = 58;
+pub const OPTION_REBINDING_TIME: u8 = 59;
+pub const OPTION_VENDOR_CLASS: u8 = 60;
+pub const OPTION_CLIENT_ID: u8 = 61;
+pub const OPTION_CLIENT_FQDN: u8 = 81;
+pub const OPTION_RELAY_AGENT: u8 = 82;
+
+pub const DHCPDISCOVER: u8 = 1;
+pub const DHCPOFFER: u8 = 2;
+pub const DHCPREQUEST: u8 = 3;
+pub const DHCPDECLINE: u8 = 4;
+pub const DHCPACK: u8 = 5;
+pub const DHCPNAK: u8 = 6;
+pub const DHCPRELEASE: u8 = 7;
+pub const DHCPINFORM: u8 = 8;
+
+#[derive(Debug, Clone)]
 pub struct DhcpPacket {
-    op: u8,
-    htype: u8,
-    hlen: u8,
-    hops: u8,
-    xid: u32,
-    secs: u16,
-    flags: u16,
-    ciaddr: Ipv4Addr,
-    yiaddr: Ipv4Addr,
-    siaddr: Ipv4Addr,
-    giaddr: Ipv4Addr,
-    chaddr: [u8; 16],
-    sname: [u8; 64],
-    file: [u8; 128],
-    options: Vec<DhcpOption>,
+    pub op: u8,
+    pub htype: u8,
+    pub hlen: u8,
+    pub hops: u8,
+    pub xid: u32,
+    pub secs: u16,
+    pub flags: u16,
+    pub ciaddr: Ipv4Addr,
+    pub yiaddr: Ipv4Addr,
+    pub siaddr: Ipv4Addr,
+    pub giaddr: Ipv4Addr,
+    pub chaddr: [u8; 16],
+    pub sname: String,
+    pub file: String,
+    pub options: Vec<DhcpOption>,
+    /// Code of an option that runs past the end of the packet
+    pub truncated_option: Option<u8>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DhcpOption {
-    code: u8,
-    length: u8,
-    value: Vec<u8>,
+    pub code: u8,
+    pub value: Vec<u8>,
+    /// Offset of the option code in the packet
+    offset: usize,
 }
 
 impl DhcpPacket {
     pub fn from_bytes(payload: &[u8]) -> Result<Self> {
-        if payload.len() < 240 {
+        if payload.len() < OPTIONS_OFFSET {
             return Err(anyhow::anyhow!("DHCP packet too short"));
         }
+        let mut reader = ByteReader::new(payload);
+        let op = reader.read_u8()?;
+        let htype = reader.read_u8()?;
+        let hlen = reader.read_u8()?;
+        let hops = reader.read_u8()?;
+        let xid = reader.read_u32()?;
+        let secs = reader.read_u16()?;
+        let flags = reader.read_u16()?;
+        let mut address = || -> Result<Ipv4Addr> { Ok(Ipv4Addr::from(reader.read_u32()?)) };
+        let (ciaddr, yiaddr, siaddr, giaddr) = (address()?, address()?, address()?, address()?);
+        let chaddr: [u8; 16] = reader.read_bytes(16)?.try_into()?;
+        let sname = c_string(reader.read_bytes(64)?);
+        let file = c_string(reader.read_bytes(128)?);
+        if reader.read_u32()? != MAGIC_COOKIE {
+            return Err(anyhow::anyhow!("Missing DHCP magic cookie"));
+        }
 
+        // Начало секции опций
         let mut options = Vec::new();
-        let mut i = 240; // Начало секции опций
-
-        while i < payload.len() {
-            if payload[i] == 0xff { // Конец опций
-                break;
+        let mut truncated_option = None;
+        while !reader.is_empty() {
+            let offset = reader.position();
+            match reader.read_u8()? {
+                0 => continue,
+                0xff => break,
+                code => match reader.read_vec8() {
+                    Ok(value) => options.push(DhcpOption { code, value: value.to_vec(), offset }),
+                    // Keep the options decoded so far
+                    Err(_) => {
+                        truncated_option = Some(code);
+                        break;
+                    }
+                },
             }
-
-            let code = payload[i];
-            let length = payload[i + 1];
-            let value = payload[i + 2..i + 2 + length as usize].to_vec();
-            
-            options.push(DhcpOption {
-                code,
-                length,
-                value,
-            });
-
-            i += 2 + length as usize;
         }
 
         Ok(Self {
-            op: payload[0],
-            htype: payload[1],
-            hlen: payload[2],
-            hops: payload[3],
-            xid: u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]),
-            secs: u16::from_be_bytes([payload[8], payload[9]]),
-            flags: u16::from_be_bytes([payload[10], payload[11]]),
-            ciaddr: Ipv4Addr::new(payload[12], payload[13], payload[14], payload[15]),
-            yiaddr: Ipv4Addr::new(payload[16], payload[17], payload[18], payload[19]),
-            siaddr: Ipv4Addr::new(payload[20], payload[21], payload[22], payload[23]),
-            giaddr: Ipv4Addr::new(payload[24], payload[25], payload[26], payload[27]),
-            chaddr: payload[28..44].try_into().unwrap(),
-            sname: payload[44..108].try_into().unwrap(),
-            file: payload[108..236].try_into().unwrap(),
+            op,
+            htype,
+            hlen,
+            hops,
+            xid,
+            secs,
+            flags,
+            ciaddr,
+            yiaddr,
+            siaddr,
+            giaddr,
+            chaddr,
+            sname,
+            file,
             options,
+            truncated_option,
         })
     }
 
+    pub fn option(&self, code: u8) -> Option<&[u8]> {
+        self.options.iter().find(|option| option.code == code).map(|option| option.value.as_slice())
+    }
+
+    pub fn message_type(&self) -> Option<u8> {
+        self.option(OPTION_MESSAGE_TYPE)?.first().copied()
+    }
+
     pub fn get_message_type(&self) -> String {
-        for option in &self.options {
-            if option.code == 53 { // DHCP Message Type
-                return match option.value[0] {
-                    1 => "DHCPDISCOVER".to_string(),
-                    2 => "DHCPOFFER".to_string(),
-                    3 => "DHCPREQUEST".to_string(),
-                    4 => "DHCPDECLINE".to_string(),
-                    5 => "DHCPACK".to_string(),
-                    6 => "DHCPNAK".to_string(),
-                    7 => "DHCPRELEASE".to_string(),
-                    8 => "DHCPINFORM".to_string(),
-                    _ => "Unknown".to_string(),
-                };
-            }
+        match self.message_type() {
+            Some(message_type) => format!("DHCP{}", message_type_name(message_type).to_ascii_uppercase()),
+            None => "Unknown".to_string(),
         }
-        "Unknown".to_string()
+    }
+
+    /// Client hardware address, if it is an Ethernet MAC
+    pub fn client_mac(&self) -> Option<MacAddr> {
+        (self.htype == 1 && self.hlen == 6).then(|| {
+            let c = &self.chaddr;
+            MacAddr::new(c[0], c[1], c[2], c[3], c[4], c[5])
+        })
+    }
+
+    pub fn requested_ip(&self) -> Option<Ipv4Addr> {
+        self.option(OPTION_REQUESTED_IP).and_then(ipv4)
+    }
+
+    pub fn server_id(&self) -> Option<Ipv4Addr> {
+        self.option(OPTION_SERVER_ID).and_then(ipv4)
+    }
+
+    pub fn lease_time(&self) -> Option<u32> {
+        self.option(OPTION_LEASE_TIME).and_then(u32_value)
+    }
+
+    pub fn routers(&self) -> Vec<Ipv4Addr> {
+        self.option(OPTION_ROUTER).map(ipv4_list).unwrap_or_default()
+    }
+
+    pub fn dns_servers(&self) -> Vec<Ipv4Addr> {
+        self.option(OPTION_DNS_SERVER).map(ipv4_list).unwrap_or_default()
+    }
+
+    pub fn hostname(&self) -> Option<String> {
+        self.option(OPTION_HOSTNAME).map(|value| String::from_utf8_lossy(value).into_owned())
     }
 
     pub fn get_client_ip(&self) -> String {
@@ -99,16 +186,287 @@ impl DhcpPacket {
     pub fn get_server_ip(&self) -> String {
         self.siaddr.to_string()
     }
+
+    fn add_fields(&self, ctx: &mut DissectContext) {
+        if let Some(message_type) = self.message_type() {
+            ctx.add_field("dhcp.option.dhcp", message_type_name(message_type));
+        }
+        ctx.add_field_at("dhcp.type", 0, 1, if self.op == 1 { "Boot Request" } else { "Boot Reply" });
+        ctx.add_field_at("dhcp.hw.type", 1, 1, self.htype);
+        ctx.add_field_at("dhcp.hw.len", 2, 1, self.hlen);
+        ctx.add_field_at("dhcp.hops", 3, 1, self.hops);
+        ctx.add_field_at("dhcp.id", 4, 4, FieldValue::Hex(self.xid as u64));
+        ctx.add_field_at("dhcp.secs", 8, 2, self.secs);
+        ctx.add_field_at("dhcp.flags", 10, 2, FieldValue::Hex(self.flags as u64));
+        ctx.add_field_at("dhcp.flags.bc", 10, 1, self.flags & 0x8000 != 0);
+        ctx.add_field_at("dhcp.ip.client", 12, 4, self.ciaddr);
+        ctx.add_field_at("dhcp.ip.your", 16, 4, self.yiaddr);
+        ctx.add_field_at("dhcp.ip.server", 20, 4, self.siaddr);
+        ctx.add_field_at("dhcp.ip.relay", 24, 4, self.giaddr);
+        match self.client_mac() {
+            Some(mac) => ctx.add_field_at("dhcp.hw.mac_addr", 28, 6, mac),
+            None => ctx.add_field_at("dhcp.hw.addr", 28, 16, FieldValue::Bytes(self.chaddr.to_vec())),
+        }
+        if !self.sname.is_empty() {
+            ctx.add_field_at("dhcp.server", 44, 64, &self.sname);
+        }
+        if !self.file.is_empty() {
+            ctx.add_field_at("dhcp.file", 108, 128, &self.file);
+        }
+        ctx.add_field_at("dhcp.cookie", 236, 4, FieldValue::Hex(MAGIC_COOKIE as u64));
+
+        for option in &self.options {
+            option.add_fields(ctx);
+        }
+        if let Some(code) = self.truncated_option {
+            ctx.add_field("_ws.malformed", format!("DHCP option {} runs past the packet", code));
+        }
+    }
+
+    pub fn format_info(&self) -> String {
+        let message_type = match self.message_type() {
+            Some(message_type) => message_type_name(message_type),
+            None if self.op == 1 => "BOOTP Request".to_string(),
+            None => "BOOTP Reply".to_string(),
+        };
+        let mut info = format!("DHCP {} - Transaction ID 0x{:08x}", message_type, self.xid);
+        match self.message_type() {
+            Some(DHCPOFFER) | Some(DHCPACK) => info.push_str(&format!(" {}", self.yiaddr)),
+            Some(DHCPREQUEST) => {
+                if let Some(requested) = self.requested_ip() {
+                    info.push_str(&format!(" {}", requested));
+                }
+            }
+            _ => {}
+        }
+        if let Some(code) = self.truncated_option {
+            info.push_str(&format!(" [Malformed option {}]", code));
+        }
+        info
+    }
+}
+
+impl DhcpOption {
+    fn add_fields(&self, ctx: &mut DissectContext) {
+        let (offset, value) = (self.offset + 2, &self.value);
+        let len = value.len();
+        ctx.add_field_at("dhcp.option.type", self.offset, 1, self.code);
+        let text = || String::from_utf8_lossy(value).trim_end_matches('\0').to_string();
+        let add_list = |ctx: &mut DissectContext, name: &str| {
+            for (index, address) in ipv4_list(value).into_iter().enumerate() {
+                ctx.add_field_at(name, offset + index * 4, 4, address);
+            }
+        };
+
+        match self.code {
+            OPTION_MESSAGE_TYPE => {}
+            OPTION_SUBNET_MASK if len == 4 => add_list(ctx, "dhcp.option.subnet_mask"),
+            OPTION_ROUTER => add_list(ctx, "dhcp.option.router"),
+            OPTION_DNS_SERVER => add_list(ctx, "dhcp.option.domain_name_server"),
+            OPTION_BROADCAST if len == 4 => add_list(ctx, "dhcp.option.broadcast_address"),
+            OPTION_NTP_SERVER => add_list(ctx, "dhcp.option.ntp_server"),
+            OPTION_REQUESTED_IP if len == 4 => add_list(ctx, "dhcp.option.requested_ip_address"),
+            OPTION_SERVER_ID if len == 4 => add_list(ctx, "dhcp.option.dhcp_server_id"),
+            OPTION_HOSTNAME => ctx.add_field_at("dhcp.option.hostname", offset, len, text()),
+            OPTION_DOMAIN_NAME => ctx.add_field_at("dhcp.option.domain_name", offset, len, text()),
+            OPTION_MESSAGE => ctx.add_field_at("dhcp.option.message", offset, len, text()),
+            OPTION_VENDOR_CLASS => ctx.add_field_at("dhcp.option.vendor_class_id", offset, len, text()),
+            OPTION_LEASE_TIME | OPTION_RENEWAL_TIME | OPTION_REBINDING_TIME if len == 4 => {
+                let name = match self.code {
+                    OPTION_LEASE_TIME => "dhcp.option.ip_address_lease_time",
+                    OPTION_RENEWAL_TIME => "dhcp.option.renewal_time_value",
+                    _ => "dhcp.option.rebinding_time_value",
+                };
+                ctx.add_field_at(name, offset, 4, u32_value(value).unwrap_or_default());
+            }
+            OPTION_MAX_SIZE if len == 2 => {
+                ctx.add_field_at("dhcp.option.max_dhcp_message_size", offset, 2, u16::from_be_bytes([value[0], value[1]]));
+            }
+            OPTION_PARAMETER_LIST => {
+                for (index, code) in value.iter().enumerate() {
+                    ctx.add_field_at("dhcp.option.request_list_item", offset + index, 1, *code);
+                }
+            }
+            OPTION_CLIENT_ID if !value.is_empty() => {
+                ctx.add_field_at("dhcp.client_id.type", offset, 1, value[0]);
+                if value[0] == 1 && len == 7 {
+                    let mac = MacAddr::new(value[1], value[2], value[3], value[4], value[5], value[6]);
+                    ctx.add_field_at("dhcp.client_id.mac", offset + 1, 6, mac);
+                } else {
+                    ctx.add_field_at("dhcp.client_id", offset + 1, len - 1, FieldValue::Bytes(value[1..].to_vec()));
+                }
+            }
+            OPTION_CLIENT_FQDN if len >= 3 => {
+                ctx.add_field_at("dhcp.fqdn.flags", offset, 1, FieldValue::Hex(value[0] as u64));
+                let name = String::from_utf8_lossy(&value[3..]).into_owned();
+                ctx.add_field_at("dhcp.fqdn.name", offset + 3, len - 3, name);
+            }
+            OPTION_RELAY_AGENT => {
+                // Sub-options share the code/length layout of the options
+                let mut reader = ByteReader::new(value);
+                while let (Ok(code), Ok(sub)) = (reader.read_u8(), reader.read_vec8()) {
+                    let at = offset + reader.position() - sub.len();
+                    let name = match code {
+                        1 => "dhcp.option.agent_information_option.agent_circuit_id",
+                        2 => "dhcp.option.agent_information_option.agent_remote_id",
+                        5 => "dhcp.option.agent_information_option.link_selection",
+                        11 => "dhcp.option.agent_information_option.server_id_override",
+                        _ => "dhcp.option.agent_information_option.value",
+                    };
+                    match code {
+                        5 | 11 if sub.len() == 4 => ctx.add_field_at(name, at, 4, ipv4(sub).unwrap_or(Ipv4Addr::UNSPECIFIED)),
+                        _ => ctx.add_field_at(name, at, sub.len(), FieldValue::Bytes(sub.to_vec())),
+                    }
+                }
+            }
+            _ => ctx.add_field_at("dhcp.option.value", offset, len, FieldValue::Bytes(value.clone())),
+        }
+    }
+}
+
+fn message_type_name(message_type: u8) -> String {
+    match message_type {
+        DHCPDISCOVER => "Discover".to_string(),
+        DHCPOFFER => "Offer".to_string(),
+        DHCPREQUEST => "Request".to_string(),
+        DHCPDECLINE => "Decline".to_string(),
+        DHCPACK => "ACK".to_string(),
+        DHCPNAK => "NAK".to_string(),
+        DHCPRELEASE => "Release".to_string(),
+        DHCPINFORM => "Inform".to_string(),
+        _ => format!("Unknown ({})", message_type),
+    }
 }
 
-pub struct DhcpProcessor;
+/// NUL-terminated text of the `sname` and `file` fields
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn ipv4(value: &[u8]) -> Option<Ipv4Addr> {
+    let octets: [u8; 4] = value.try_into().ok()?;
+    Some(Ipv4Addr::from(octets))
+}
+
+fn ipv4_list(value: &[u8]) -> Vec<Ipv4Addr> {
+    value.chunks_exact(4).filter_map(ipv4).collect()
+}
+
+fn u32_value(value: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(value.try_into().ok()?))
+}
+
+pub struct DhcpProcessor {
+    leases: Mutex<LeaseTable>,
+}
 
 impl DhcpProcessor {
     pub fn new() -> Self {
-        Self
+        Self {
+            leases: Mutex::new(LeaseTable::default()),
+        }
+    }
+
+    /// Current lease of every client seen
+    pub fn leases(&self) -> Vec<DhcpLease> {
+        self.leases.lock().unwrap().leases()
+    }
+
+    /// Sets the servers, routers and DNS servers DHCP may hand out
+    pub fn set_allow_list(&self, allow_list: DhcpAllowList) {
+        self.leases.lock().unwrap().set_allow_list(allow_list);
     }
 
     pub fn process(&self, payload: &[u8]) -> Result<DhcpPacket> {
         DhcpPacket::from_bytes(payload)
     }
-} 
\ No newline at end of file
+}
+
+impl Dissector for DhcpProcessor {
+    fn name(&self) -> &'static str {
+        "DHCP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let packet = self.process(data)?;
+        packet.add_fields(ctx);
+        let source = match ctx.src_ip {
+            Some(IpAddr::V4(source)) => Some(source),
+            _ => None,
+        };
+        let alerts = self.leases.lock().unwrap().update(&packet, source, ctx.captured_at, &ctx.timestamp);
+        for alert in alerts {
+            ctx.raise(alert);
+        }
+        Ok(Dissection::new("DHCP", packet.format_info()))
+    }
+
+    /// Heuristic probe: a BOOTP operation and the DHCP magic cookie
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        let cookie = payload.get(236..OPTIONS_OFFSET)?;
+        (matches!(payload[0], 1 | 2) && cookie == MAGIC_COOKIE.to_be_bytes())
+            .then(|| ProbeResult::new(90, "BOOTP header with DHCP magic cookie"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// DHCPREQUEST for 192.168.1.10 from 00:11:22:33:44:55, with the options in `options`
+    fn request(options: &[u8]) -> Vec<u8> {
+        let mut packet = vec![1, 1, 6, 0, 0x3c, 0x11, 0x22, 0x33];
+        packet.resize(28, 0);
+        packet.extend([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        packet.resize(236, 0);
+        packet.extend(MAGIC_COOKIE.to_be_bytes());
+        packet.extend(options);
+        packet
+    }
+
+    #[test]
+    fn parses_a_request() {
+        let packet = DhcpPacket::from_bytes(&request(&[
+            53, 1, DHCPREQUEST, 0, 50, 4, 192, 168, 1, 10, 12, 4, b'h', b'o', b's', b't', 0xff, 1, 2,
+        ]))
+        .unwrap();
+        assert_eq!(packet.message_type(), Some(DHCPREQUEST));
+        assert_eq!(packet.client_mac(), Some(MacAddr::new(0x00, 0x11, 0x22, 0x33, 0x44, 0x55)));
+        assert_eq!(packet.requested_ip(), Some(Ipv4Addr::new(192, 168, 1, 10)));
+        assert_eq!(packet.hostname().as_deref(), Some("host"));
+        assert_eq!(packet.options.len(), 3);
+        assert_eq!(packet.format_info(), "DHCP Request - Transaction ID 0x3c112233 192.168.1.10");
+    }
+
+    #[test]
+    fn keeps_options_before_a_truncated_one() {
+        let packet = DhcpPacket::from_bytes(&request(&[53, 1, DHCPDISCOVER, 51, 4, 0, 0])).unwrap();
+        assert_eq!(packet.message_type(), Some(DHCPDISCOVER));
+        assert_eq!(packet.lease_time(), None);
+        assert_eq!(packet.truncated_option, Some(OPTION_LEASE_TIME));
+        assert!(packet.format_info().ends_with("[Malformed option 51]"));
+    }
+
+    #[test]
+    fn rejects_short_packets_and_a_missing_cookie() {
+        let packet = request(&[]);
+        for len in [0, 1, 100, 239] {
+            assert!(DhcpPacket::from_bytes(&packet[..len]).is_err(), "{} bytes", len);
+        }
+        let mut packet = packet;
+        packet[236] = 0;
+        assert!(DhcpPacket::from_bytes(&packet).is_err());
+    }
+
+    #[test]
+    fn ignores_malformed_option_values() {
+        // Addresses and times of the wrong length
+        let packet = DhcpPacket::from_bytes(&request(&[50, 3, 1, 2, 3, 51, 2, 0, 1, 3, 5, 1, 2, 3, 4, 5])).unwrap();
+        assert_eq!(packet.requested_ip(), None);
+        assert_eq!(packet.lease_time(), None);
+        assert_eq!(packet.routers(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(packet.message_type(), None);
+        assert_eq!(packet.format_info(), "DHCP BOOTP Request - Transaction ID 0x3c112233");
+    }
+}
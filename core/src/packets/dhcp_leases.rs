@@ -0,0 +1,411 @@
+use chrono::{DateTime, Duration, Utc};
+use pnet::util::MacAddr;
+use serde::Serialize;
+use std::fmt;
+use std::net::Ipv4Addr;
+use crate::alerts::{Alert, AlertSeverity};
+use super::dhcp::{DhcpPacket, DHCPACK, DHCPDECLINE, DHCPNAK, DHCPOFFER, DHCPRELEASE};
+use super::flow::FlowTable;
+
+/// Leases, servers and reported addresses not seen for this long are forgotten
+const LEASE_TIMEOUT_SECS: i64 = 86400;
+/// Clients, segments and reported addresses kept at once, against starvation
+/// floods from random MACs
+const MAX_LEASES: usize = 65536;
+/// Servers remembered per segment; the oldest make room beyond it
+const MAX_SERVERS_PER_SEGMENT: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaseState {
+    Offered,
+    Bound,
+    Released,
+    Declined,
+    /// The server answered the request with a NAK
+    Rejected,
+}
+
+impl fmt::Display for LeaseState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            LeaseState::Offered => "offered",
+            LeaseState::Bound => "bound",
+            LeaseState::Released => "released",
+            LeaseState::Declined => "declined",
+            LeaseState::Rejected => "rejected",
+        };
+        f.write_str(state)
+    }
+}
+
+/// The address a DHCP client holds, keyed by its MAC
+#[derive(Debug, Clone, Serialize)]
+pub struct DhcpLease {
+    pub client_mac: String,
+    pub ip: Option<Ipv4Addr>,
+    pub hostname: Option<String>,
+    /// Seconds
+    pub lease_time: Option<u32>,
+    pub server: Option<Ipv4Addr>,
+    pub state: LeaseState,
+    /// Time of the last ACK
+    pub bound_at: Option<DateTime<Utc>>,
+    pub last_seen: DateTime<Utc>,
+}
+
+impl DhcpLease {
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        Some(self.bound_at? + Duration::seconds(self.lease_time? as i64))
+    }
+
+    pub fn format_info(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        format!(
+            "{:<17} {:<15} {:<8} {:<20} {:>8} {:<15} {}",
+            self.client_mac,
+            optional(self.ip.map(|ip| ip.to_string())),
+            self.state.to_string(),
+            optional(self.hostname.clone()),
+            optional(self.lease_time.map(|time| time.to_string())),
+            optional(self.server.map(|server| server.to_string())),
+            self.last_seen.format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
+
+/// DHCP servers, routers and DNS servers expected on the network; an empty
+/// list allows any address
+#[derive(Debug, Clone, Default)]
+pub struct DhcpAllowList {
+    pub servers: Vec<Ipv4Addr>,
+    pub routers: Vec<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+}
+
+/// Lease table built from the DHCP exchanges, watching for rogue servers
+pub(crate) struct LeaseTable {
+    leases: FlowTable<MacAddr, DhcpLease>,
+    /// Servers that sent an OFFER or ACK, by segment (the relay address,
+    /// unspecified for the local segment), oldest first
+    servers: FlowTable<Ipv4Addr, Vec<Ipv4Addr>>,
+    /// (server, address) pairs already reported as not allowed
+    reported: FlowTable<(Ipv4Addr, Ipv4Addr), ()>,
+    allow_list: DhcpAllowList,
+}
+
+impl Default for LeaseTable {
+    fn default() -> Self {
+        let timeout = Duration::seconds(LEASE_TIMEOUT_SECS);
+        Self {
+            leases: FlowTable::with_limits(timeout, MAX_LEASES),
+            servers: FlowTable::with_limits(timeout, MAX_LEASES),
+            reported: FlowTable::with_limits(timeout, MAX_LEASES),
+            allow_list: DhcpAllowList::default(),
+        }
+    }
+}
+
+impl LeaseTable {
+    pub fn set_allow_list(&mut self, allow_list: DhcpAllowList) {
+        self.allow_list = allow_list;
+    }
+
+    /// Updates the lease of the client; `source` is the IP source address of
+    /// the packet. Returns alerts for unexpected servers.
+    pub fn update(&mut self, packet: &DhcpPacket, source: Option<Ipv4Addr>, at: DateTime<Utc>, timestamp: &str) -> Vec<Alert> {
+        let message_type = packet.message_type();
+        let server = packet.server_id().or(source.filter(|_| packet.op == 2));
+        let mut alerts = Vec::new();
+        if let (Some(DHCPOFFER | DHCPACK), Some(server)) = (message_type, server) {
+            alerts = self.inspect_server(packet, server, at, timestamp);
+        }
+
+        let Some(mac) = packet.client_mac() else {
+            return alerts;
+        };
+        let lease = self.leases.entry(mac, at, || DhcpLease {
+            client_mac: mac.to_string(),
+            ip: None,
+            hostname: None,
+            lease_time: None,
+            server: None,
+            state: LeaseState::Offered,
+            bound_at: None,
+            last_seen: at,
+        });
+        lease.last_seen = at;
+        if let Some(hostname) = packet.hostname() {
+            lease.hostname = Some(hostname);
+        }
+
+        let assigned = (!packet.yiaddr.is_unspecified()).then_some(packet.yiaddr);
+        match message_type {
+            // An offer does not replace a lease the client holds
+            Some(DHCPOFFER) if lease.state != LeaseState::Bound => {
+                lease.ip = assigned.or(lease.ip);
+                lease.lease_time = packet.lease_time().or(lease.lease_time);
+                lease.server = server;
+                lease.state = LeaseState::Offered;
+            }
+            Some(DHCPACK) => {
+                // An ACK to an INFORM assigns no address
+                if assigned.is_some() {
+                    lease.ip = assigned;
+                    lease.state = LeaseState::Bound;
+                    lease.bound_at = Some(at);
+                }
+                lease.lease_time = packet.lease_time().or(lease.lease_time);
+                lease.server = server.or(lease.server);
+            }
+            Some(DHCPNAK) => lease.state = LeaseState::Rejected,
+            Some(DHCPRELEASE) => lease.state = LeaseState::Released,
+            Some(DHCPDECLINE) => lease.state = LeaseState::Declined,
+            _ => {}
+        }
+        alerts
+    }
+
+    fn inspect_server(
+        &mut self,
+        packet: &DhcpPacket,
+        server: Ipv4Addr,
+        at: DateTime<Utc>,
+        timestamp: &str,
+    ) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        let message = packet.get_message_type();
+        let segment = packet.giaddr;
+        let segment_name = if segment.is_unspecified() {
+            "the local segment".to_string()
+        } else {
+            format!("the segment relayed by {}", segment)
+        };
+
+        let allowed = &self.allow_list.servers;
+        let known = self.servers.entry(segment, at, Vec::new);
+        if !known.contains(&server) {
+            if !allowed.is_empty() && !allowed.contains(&server) {
+                alerts.push(Alert::new(
+                    timestamp,
+                    AlertSeverity::Critical,
+                    "dhcp.rogue_server",
+                    format!("DHCP server {} not in the allow-list sent {} on {}", server, message, segment_name),
+                ));
+            } else if allowed.is_empty() && !known.is_empty() {
+                let others: Vec<String> = known.iter().map(|s| s.to_string()).collect();
+                alerts.push(Alert::new(
+                    timestamp,
+                    AlertSeverity::Critical,
+                    "dhcp.rogue_server",
+                    format!(
+                        "DHCP server {} sent {} on {}, which is already served by {}",
+                        server,
+                        message,
+                        segment_name,
+                        others.join(", ")
+                    ),
+                ));
+            }
+            if known.len() >= MAX_SERVERS_PER_SEGMENT {
+                known.remove(0);
+            }
+            known.push(server);
+        }
+
+        let checks = [
+            ("router", packet.routers(), &self.allow_list.routers, "dhcp.unexpected_router"),
+            ("DNS server", packet.dns_servers(), &self.allow_list.dns_servers, "dhcp.unexpected_dns_server"),
+        ];
+        for (kind, offered, allowed, category) in checks {
+            if allowed.is_empty() {
+                continue;
+            }
+            for address in offered {
+                if allowed.contains(&address) || self.reported.seen(&(server, address), at).is_some() {
+                    continue;
+                }
+                self.reported.insert((server, address), at, ());
+                alerts.push(Alert::new(
+                    timestamp,
+                    AlertSeverity::Critical,
+                    category,
+                    format!("DHCP server {} offered {} {} which is not in the allow-list", server, kind, address),
+                ));
+            }
+        }
+        alerts
+    }
+
+    /// Leases ordered by address, clients without one last
+    pub fn leases(&self) -> Vec<DhcpLease> {
+        let mut leases: Vec<DhcpLease> = self.leases.values().cloned().collect();
+        leases.sort_by(|a, b| (a.ip.is_none(), a.ip, &a.client_mac).cmp(&(b.ip.is_none(), b.ip, &b.client_mac)));
+        leases
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissector::testing::at;
+    use super::super::dhcp::{DHCPDISCOVER, DHCPINFORM, DHCPREQUEST};
+
+    const SERVER: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 1);
+    const ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+
+    /// Packet of 00:11:22:33:44:55 assigning `yiaddr`, relayed by `giaddr`
+    fn packet(message_type: u8, yiaddr: [u8; 4], giaddr: [u8; 4], options: &[u8]) -> DhcpPacket {
+        let op = if matches!(message_type, DHCPOFFER | DHCPACK | DHCPNAK) { 2 } else { 1 };
+        let mut data = vec![op, 1, 6, 0, 0x3c, 0x11, 0x22, 0x33];
+        data.resize(16, 0);
+        data.extend(yiaddr);
+        data.extend([0; 4]);
+        data.extend(giaddr);
+        data.extend([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        data.resize(236, 0);
+        data.extend([0x63, 0x82, 0x53, 0x63, 53, 1, message_type]);
+        data.extend(options);
+        DhcpPacket::from_bytes(&data).unwrap()
+    }
+
+    /// Offer or ACK of `server` for 192.168.1.10 with a one hour lease
+    fn reply(message_type: u8, server: Ipv4Addr, options: &[u8]) -> DhcpPacket {
+        let mut all = vec![54, 4];
+        all.extend(server.octets());
+        all.extend([51, 4, 0, 0, 0x0e, 0x10]);
+        all.extend(options);
+        packet(message_type, [192, 168, 1, 10], [0; 4], &all)
+    }
+
+    fn lease(table: &LeaseTable) -> DhcpLease {
+        let leases = table.leases();
+        assert_eq!(leases.len(), 1);
+        leases[0].clone()
+    }
+
+    fn categories(alerts: &[Alert]) -> Vec<&str> {
+        alerts.iter().map(|alert| alert.category.as_str()).collect()
+    }
+
+    #[test]
+    fn follows_the_lease_lifecycle() {
+        let mut table = LeaseTable::default();
+        table.update(&packet(DHCPDISCOVER, [0; 4], [0; 4], &[12, 4, b'h', b'o', b's', b't']), None, at(0), "");
+        let discovered = lease(&table);
+        assert_eq!(discovered.client_mac, "00:11:22:33:44:55");
+        assert_eq!((discovered.state, discovered.ip), (LeaseState::Offered, None));
+        assert_eq!(discovered.hostname.as_deref(), Some("host"));
+
+        assert!(table.update(&reply(DHCPOFFER, SERVER, &[]), Some(SERVER), at(1), "").is_empty());
+        let offered = lease(&table);
+        assert_eq!((offered.state, offered.ip, offered.server), (LeaseState::Offered, Some(ADDRESS), Some(SERVER)));
+        assert_eq!(offered.lease_time, Some(3600));
+
+        table.update(&packet(DHCPREQUEST, [0; 4], [0; 4], &[]), None, at(2), "");
+        assert_eq!(lease(&table).state, LeaseState::Offered);
+        table.update(&reply(DHCPACK, SERVER, &[]), Some(SERVER), at(3), "");
+        let bound = lease(&table);
+        assert_eq!((bound.state, bound.bound_at, bound.expires_at()), (LeaseState::Bound, Some(at(3)), Some(at(3603))));
+
+        // Neither another offer nor the ACK to an INFORM moves the client
+        let other = packet(DHCPOFFER, [192, 168, 1, 20], [0; 4], &[54, 4, 192, 168, 1, 1]);
+        table.update(&other, Some(SERVER), at(4), "");
+        table.update(&packet(DHCPINFORM, [0; 4], [0; 4], &[]), None, at(5), "");
+        table.update(&packet(DHCPACK, [0; 4], [0; 4], &[54, 4, 192, 168, 1, 1]), Some(SERVER), at(5), "");
+        let bound = lease(&table);
+        assert_eq!((bound.state, bound.ip, bound.bound_at), (LeaseState::Bound, Some(ADDRESS), Some(at(3))));
+
+        table.update(&packet(DHCPRELEASE, [0; 4], [0; 4], &[]), None, at(6), "");
+        let released = lease(&table);
+        assert_eq!((released.state, released.last_seen), (LeaseState::Released, at(6)));
+    }
+
+    #[test]
+    fn records_naks_and_declines() {
+        let mut table = LeaseTable::default();
+        table.update(&packet(DHCPREQUEST, [0; 4], [0; 4], &[50, 4, 192, 168, 1, 99]), None, at(0), "");
+        table.update(&packet(DHCPNAK, [0; 4], [0; 4], &[54, 4, 192, 168, 1, 1]), Some(SERVER), at(1), "");
+        assert_eq!(lease(&table).state, LeaseState::Rejected);
+
+        table.update(&reply(DHCPACK, SERVER, &[]), Some(SERVER), at(2), "");
+        table.update(&packet(DHCPDECLINE, [0; 4], [0; 4], &[]), None, at(3), "");
+        let declined = lease(&table);
+        assert_eq!((declined.state, declined.ip), (LeaseState::Declined, Some(ADDRESS)));
+    }
+
+    #[test]
+    fn alerts_on_a_second_server() {
+        let mut table = LeaseTable::default();
+        let other = Ipv4Addr::new(192, 168, 1, 2);
+        assert!(table.update(&reply(DHCPOFFER, SERVER, &[]), Some(SERVER), at(0), "").is_empty());
+        assert!(table.update(&reply(DHCPACK, SERVER, &[]), Some(SERVER), at(1), "").is_empty());
+
+        let alerts = table.update(&reply(DHCPOFFER, other, &[]), Some(other), at(2), "t");
+        assert_eq!(categories(&alerts), ["dhcp.rogue_server"]);
+        assert_eq!(
+            alerts[0].message,
+            "DHCP server 192.168.1.2 sent DHCPOFFER on the local segment, which is already served by 192.168.1.1"
+        );
+        assert!(table.update(&reply(DHCPOFFER, other, &[]), Some(other), at(3), "").is_empty());
+
+        // Each relayed segment has its own servers
+        let relayed = packet(DHCPOFFER, [10, 1, 0, 10], [10, 1, 0, 1], &[54, 4, 10, 0, 0, 1]);
+        assert!(table.update(&relayed, None, at(4), "").is_empty());
+        let relayed = packet(DHCPOFFER, [10, 1, 0, 10], [10, 1, 0, 1], &[54, 4, 192, 168, 1, 1]);
+        let alerts = table.update(&relayed, None, at(5), "");
+        assert!(alerts[0].message.ends_with("on the segment relayed by 10.1.0.1, which is already served by 10.0.0.1"));
+    }
+
+    #[test]
+    fn checks_the_allow_list() {
+        let mut table = LeaseTable::default();
+        table.set_allow_list(DhcpAllowList {
+            servers: vec![SERVER],
+            routers: vec![SERVER],
+            dns_servers: vec![SERVER, Ipv4Addr::new(192, 168, 1, 2)],
+        });
+
+        let options = [3, 4, 192, 168, 1, 254, 6, 8, 192, 168, 1, 1, 8, 8, 8, 8];
+        let alerts = table.update(&reply(DHCPOFFER, SERVER, &options), Some(SERVER), at(0), "");
+        assert_eq!(categories(&alerts), ["dhcp.unexpected_router", "dhcp.unexpected_dns_server"]);
+        assert_eq!(
+            alerts[0].message,
+            "DHCP server 192.168.1.1 offered router 192.168.1.254 which is not in the allow-list"
+        );
+        assert_eq!(
+            alerts[1].message,
+            "DHCP server 192.168.1.1 offered DNS server 8.8.8.8 which is not in the allow-list"
+        );
+        // Reported once per server and address
+        assert!(table.update(&reply(DHCPACK, SERVER, &options), Some(SERVER), at(1), "").is_empty());
+
+        let rogue = Ipv4Addr::new(192, 168, 1, 66);
+        let alerts = table.update(&reply(DHCPOFFER, rogue, &[3, 4, 192, 168, 1, 1]), Some(rogue), at(2), "");
+        assert_eq!(categories(&alerts), ["dhcp.rogue_server"]);
+        assert_eq!(
+            alerts[0].message,
+            "DHCP server 192.168.1.66 not in the allow-list sent DHCPOFFER on the local segment"
+        );
+    }
+
+    #[test]
+    fn stays_bounded_under_a_starvation_flood() {
+        let mut table = LeaseTable::default();
+        let mut discover = packet(DHCPDISCOVER, [0; 4], [0; 4], &[]);
+        for index in 0..=MAX_LEASES as u32 {
+            discover.chaddr[2..6].copy_from_slice(&index.to_be_bytes());
+            table.update(&discover, None, at(1), "");
+        }
+        assert_eq!(table.leases.len(), MAX_LEASES - MAX_LEASES / 4 + 1);
+
+        // Spoofed servers only keep the latest of the segment
+        for index in 0..=MAX_SERVERS_PER_SEGMENT as u8 {
+            let server = Ipv4Addr::new(10, 0, 0, index);
+            table.update(&reply(DHCPOFFER, server, &[]), Some(server), at(2), "");
+        }
+        let known = table.servers.get(&Ipv4Addr::UNSPECIFIED).unwrap();
+        assert_eq!(known.len(), MAX_SERVERS_PER_SEGMENT);
+        assert_eq!(known[0], Ipv4Addr::new(10, 0, 0, 1));
+    }
+}
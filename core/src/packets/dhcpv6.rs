@@ -0,0 +1,328 @@
+use anyhow::Result;
+use std::net::Ipv6Addr;
+use super::bytes::{to_hex, ByteReader};
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::dns::read_name;
+use super::tree::FieldValue;
+
+const RELAY_FORW: u8 = 12;
+const RELAY_REPL: u8 = 13;
+
+const OPTION_CLIENTID: u16 = 1;
+const OPTION_SERVERID: u16 = 2;
+const OPTION_IA_NA: u16 = 3;
+const OPTION_IA_TA: u16 = 4;
+const OPTION_IAADDR: u16 = 5;
+const OPTION_ORO: u16 = 6;
+const OPTION_PREFERENCE: u16 = 7;
+const OPTION_ELAPSED_TIME: u16 = 8;
+const OPTION_RELAY_MSG: u16 = 9;
+const OPTION_STATUS_CODE: u16 = 13;
+const OPTION_RAPID_COMMIT: u16 = 14;
+const OPTION_USER_CLASS: u16 = 15;
+const OPTION_VENDOR_CLASS: u16 = 16;
+const OPTION_INTERFACE_ID: u16 = 18;
+const OPTION_DNS_SERVERS: u16 = 23;
+const OPTION_DOMAIN_LIST: u16 = 24;
+const OPTION_IA_PD: u16 = 25;
+const OPTION_IAPREFIX: u16 = 26;
+const OPTION_CLIENT_FQDN: u16 = 39;
+
+/// Nesting allowed for relayed messages and IA sub-options
+const MAX_DEPTH: usize = 8;
+
+/// A DHCPv6 message (RFC 8415); relay messages carry the relayed message
+/// in their options
+#[derive(Default)]
+pub struct Dhcpv6Processor;
+
+impl Dhcpv6Processor {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Dissector for Dhcpv6Processor {
+    fn name(&self) -> &'static str {
+        "DHCPv6"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let mut info = Vec::new();
+        dissect_message(data, 0, 0, ctx, &mut info)?;
+        Ok(Dissection::new("DHCPv6", info.join(" ")))
+    }
+}
+
+/// Decodes the message at `base` of the dissected data, appending its
+/// summary to `info`
+fn dissect_message(data: &[u8], base: usize, depth: usize, ctx: &mut DissectContext, info: &mut Vec<String>) -> Result<()> {
+    let mut reader = ByteReader::new(data);
+    let msg_type = reader.read_u8()?;
+    ctx.add_field_at("dhcpv6.msgtype", base, 1, message_type_name(msg_type));
+
+    if matches!(msg_type, RELAY_FORW | RELAY_REPL) {
+        let hop_count = reader.read_u8()?;
+        let link = ipv6(reader.read_bytes(16)?)?;
+        let peer = ipv6(reader.read_bytes(16)?)?;
+        ctx.add_field_at("dhcpv6.hopcount", base + 1, 1, hop_count);
+        ctx.add_field_at("dhcpv6.linkaddr", base + 2, 16, link);
+        ctx.add_field_at("dhcpv6.peeraddr", base + 18, 16, peer);
+        info.push(format!("{} L: {} P: {}", message_type_name(msg_type), link, peer));
+    } else {
+        let xid = reader.read_u24()?;
+        ctx.add_field_at("dhcpv6.xid", base + 1, 3, FieldValue::Hex(xid as u64));
+        info.push(format!("{} XID: 0x{:06x}", message_type_name(msg_type), xid));
+    }
+
+    let start = reader.position();
+    dissect_options(&data[start..], base + start, depth, ctx, info)
+}
+
+fn dissect_options(data: &[u8], base: usize, depth: usize, ctx: &mut DissectContext, info: &mut Vec<String>) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(anyhow::anyhow!("DHCPv6 options nested too deeply"));
+    }
+    let mut reader = ByteReader::new(data);
+    while !reader.is_empty() {
+        let offset = base + reader.position();
+        let code = reader.read_u16()?;
+        let value = reader
+            .read_vec16()
+            .map_err(|_| anyhow::anyhow!("DHCPv6 option {} runs past the message", code))?;
+        let at = offset + 4;
+        ctx.add_field_at("dhcpv6.option.type", offset, 2, code);
+
+        match code {
+            OPTION_CLIENTID | OPTION_SERVERID => {
+                let prefix = if code == OPTION_CLIENTID { "dhcpv6.client" } else { "dhcpv6.server" };
+                ctx.add_field_at(&format!("{}_duid", prefix), at, value.len(), FieldValue::Bytes(value.to_vec()));
+                add_duid(value, at, ctx);
+                if code == OPTION_CLIENTID {
+                    info.push(format!("CID: {}", to_hex(value)));
+                }
+            }
+            OPTION_IA_NA | OPTION_IA_PD if value.len() >= 12 => {
+                let mut ia = ByteReader::new(value);
+                ctx.add_field_at("dhcpv6.iaid", at, 4, FieldValue::Hex(ia.read_u32()? as u64));
+                ctx.add_field_at("dhcpv6.iaid.t1", at + 4, 4, ia.read_u32()?);
+                ctx.add_field_at("dhcpv6.iaid.t2", at + 8, 4, ia.read_u32()?);
+                dissect_options(&value[12..], at + 12, depth + 1, ctx, info)?;
+            }
+            OPTION_IA_TA if value.len() >= 4 => {
+                ctx.add_field_at("dhcpv6.iata", at, 4, FieldValue::Hex(u32::from_be_bytes(value[..4].try_into()?) as u64));
+                dissect_options(&value[4..], at + 4, depth + 1, ctx, info)?;
+            }
+            OPTION_IAADDR if value.len() >= 24 => {
+                let mut address = ByteReader::new(value);
+                let ip = ipv6(address.read_bytes(16)?)?;
+                ctx.add_field_at("dhcpv6.iaaddr.ip", at, 16, ip);
+                ctx.add_field_at("dhcpv6.iaaddr.pref_lifetime", at + 16, 4, address.read_u32()?);
+                ctx.add_field_at("dhcpv6.iaaddr.valid_lifetime", at + 20, 4, address.read_u32()?);
+                info.push(format!("IAA: {}", ip));
+                dissect_options(&value[24..], at + 24, depth + 1, ctx, info)?;
+            }
+            OPTION_IAPREFIX if value.len() >= 25 => {
+                let mut prefix = ByteReader::new(value);
+                ctx.add_field_at("dhcpv6.iaprefix.pref_lifetime", at, 4, prefix.read_u32()?);
+                ctx.add_field_at("dhcpv6.iaprefix.valid_lifetime", at + 4, 4, prefix.read_u32()?);
+                let len = prefix.read_u8()?;
+                let address = ipv6(prefix.read_bytes(16)?)?;
+                ctx.add_field_at("dhcpv6.iaprefix.pref_len", at + 8, 1, len);
+                ctx.add_field_at("dhcpv6.iaprefix.pref_addr", at + 9, 16, address);
+                info.push(format!("IAPD: {}/{}", address, len));
+                dissect_options(&value[25..], at + 25, depth + 1, ctx, info)?;
+            }
+            OPTION_ORO => {
+                for (index, requested) in value.chunks_exact(2).enumerate() {
+                    let requested = u16::from_be_bytes([requested[0], requested[1]]);
+                    ctx.add_field_at("dhcpv6.requested_option_code", at + index * 2, 2, requested);
+                }
+            }
+            OPTION_PREFERENCE if value.len() == 1 => ctx.add_field_at("dhcpv6.preference", at, 1, value[0]),
+            OPTION_ELAPSED_TIME if value.len() == 2 => {
+                // Hundredths of a second
+                let elapsed = u16::from_be_bytes([value[0], value[1]]);
+                ctx.add_field_at("dhcpv6.elapsed_time", at, 2, elapsed as f64 / 100.0);
+            }
+            OPTION_RELAY_MSG => dissect_message(value, at, depth + 1, ctx, info)?,
+            OPTION_STATUS_CODE if value.len() >= 2 => {
+                let status = u16::from_be_bytes([value[0], value[1]]);
+                ctx.add_field_at("dhcpv6.status_code", at, 2, status_name(status));
+                if value.len() > 2 {
+                    let message = String::from_utf8_lossy(&value[2..]).into_owned();
+                    ctx.add_field_at("dhcpv6.status_message", at + 2, value.len() - 2, message);
+                }
+                if status != 0 {
+                    info.push(format!("Status: {}", status_name(status)));
+                }
+            }
+            OPTION_RAPID_COMMIT => ctx.add_field_at("dhcpv6.rapid_commit", offset, 4, true),
+            OPTION_USER_CLASS | OPTION_VENDOR_CLASS => {
+                // Vendor class data is preceded by the enterprise number
+                let (name, mut class) = if code == OPTION_VENDOR_CLASS && value.len() >= 4 {
+                    let enterprise = u32::from_be_bytes(value[..4].try_into()?);
+                    ctx.add_field_at("dhcpv6.vendorclass.enterprise", at, 4, enterprise);
+                    ("dhcpv6.vendorclass.data", ByteReader::new(&value[4..]))
+                } else {
+                    ("dhcpv6.userclass.data", ByteReader::new(value))
+                };
+                let skip = value.len() - class.remaining();
+                while let Ok(item) = class.read_vec16() {
+                    let item_at = at + skip + class.position() - item.len();
+                    ctx.add_field_at(name, item_at, item.len(), String::from_utf8_lossy(item).into_owned());
+                }
+            }
+            OPTION_INTERFACE_ID => ctx.add_field_at("dhcpv6.interface_id", at, value.len(), FieldValue::Bytes(value.to_vec())),
+            OPTION_DNS_SERVERS => {
+                for (index, server) in value.chunks_exact(16).enumerate() {
+                    ctx.add_field_at("dhcpv6.dns_server", at + index * 16, 16, ipv6(server)?);
+                }
+            }
+            OPTION_DOMAIN_LIST => {
+                let mut position = 0;
+                while position < value.len() {
+                    let (name, len) = read_name(value, position)?;
+                    ctx.add_field_at("dhcpv6.domain", at + position, len, name);
+                    position += len;
+                }
+            }
+            OPTION_CLIENT_FQDN if !value.is_empty() => {
+                ctx.add_field_at("dhcpv6.client_fqdn.flags", at, 1, FieldValue::Hex(value[0] as u64));
+                if value.len() > 1 {
+                    let (name, _) = read_name(value, 1)?;
+                    ctx.add_field_at("dhcpv6.client_fqdn", at + 1, value.len() - 1, name);
+                }
+            }
+            _ => ctx.add_field_at("dhcpv6.option.value", at, value.len(), FieldValue::Bytes(value.to_vec())),
+        }
+    }
+    Ok(())
+}
+
+/// Decodes the DUID types carrying a link-layer address or an enterprise number
+fn add_duid(duid: &[u8], at: usize, ctx: &mut DissectContext) {
+    let mut reader = ByteReader::new(duid);
+    let Ok(duid_type) = reader.read_u16() else {
+        return;
+    };
+    ctx.add_field_at("dhcpv6.duid.type", at, 2, duid_type);
+    match duid_type {
+        // DUID-LLT: hardware type, time, link-layer address
+        1 if duid.len() >= 8 => {
+            ctx.add_field_at("dhcpv6.duid.hwtype", at + 2, 2, u16::from_be_bytes([duid[2], duid[3]]));
+            ctx.add_field_at("dhcpv6.duidllt.time", at + 4, 4, u32::from_be_bytes([duid[4], duid[5], duid[6], duid[7]]));
+            add_link_layer(&duid[8..], at + 8, ctx);
+        }
+        // DUID-EN: enterprise number, identifier
+        2 if duid.len() >= 6 => {
+            ctx.add_field_at("dhcpv6.duiden.enterprise", at + 2, 4, u32::from_be_bytes([duid[2], duid[3], duid[4], duid[5]]));
+            ctx.add_field_at("dhcpv6.duiden.identifier", at + 6, duid.len() - 6, FieldValue::Bytes(duid[6..].to_vec()));
+        }
+        // DUID-LL: hardware type, link-layer address
+        3 if duid.len() >= 4 => {
+            ctx.add_field_at("dhcpv6.duid.hwtype", at + 2, 2, u16::from_be_bytes([duid[2], duid[3]]));
+            add_link_layer(&duid[4..], at + 4, ctx);
+        }
+        _ => {}
+    }
+}
+
+fn add_link_layer(address: &[u8], at: usize, ctx: &mut DissectContext) {
+    match <[u8; 6]>::try_from(address) {
+        Ok(mac) => ctx.add_field_at("dhcpv6.duid.link_layer_addr", at, 6, FieldValue::Mac(mac)),
+        Err(_) => ctx.add_field_at("dhcpv6.duid.link_layer_addr", at, address.len(), FieldValue::Bytes(address.to_vec())),
+    }
+}
+
+fn ipv6(bytes: &[u8]) -> Result<Ipv6Addr> {
+    let octets: [u8; 16] = bytes.try_into()?;
+    Ok(Ipv6Addr::from(octets))
+}
+
+fn message_type_name(msg_type: u8) -> String {
+    let name = match msg_type {
+        1 => "Solicit",
+        2 => "Advertise",
+        3 => "Request",
+        4 => "Confirm",
+        5 => "Renew",
+        6 => "Rebind",
+        7 => "Reply",
+        8 => "Release",
+        9 => "Decline",
+        10 => "Reconfigure",
+        11 => "Information-request",
+        RELAY_FORW => "Relay-forw",
+        RELAY_REPL => "Relay-reply",
+        _ => return format!("Unknown ({})", msg_type),
+    };
+    name.to_string()
+}
+
+fn status_name(status: u16) -> String {
+    let name = match status {
+        0 => "Success",
+        1 => "UnspecFail",
+        2 => "NoAddrsAvail",
+        3 => "NoBinding",
+        4 => "NotOnLink",
+        5 => "UseMulticast",
+        6 => "NoPrefixAvail",
+        _ => return format!("Unknown ({})", status),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissector::testing;
+    use super::super::tree::Field;
+
+    /// Solicit with a DUID-LL client identifier, an elapsed time and an IA_NA
+    const SOLICIT: &[u8] = &[
+        0x01, 0x12, 0x34, 0x56, //
+        0x00, 0x01, 0x00, 0x0a, 0x00, 0x03, 0x00, 0x01, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, //
+        0x00, 0x08, 0x00, 0x02, 0x00, 0x64, //
+        0x00, 0x03, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x00, 0x15, 0x18,
+    ];
+
+    fn dissect(data: &[u8]) -> (Result<Dissection>, Vec<Field>) {
+        testing::dissect(&Dhcpv6Processor::new(), data, |_| {})
+    }
+
+    fn field(fields: &[Field], name: &str) -> Option<String> {
+        testing::field(fields, name).map(ToString::to_string)
+    }
+
+    #[test]
+    fn dissects_a_solicit() {
+        let (dissection, fields) = dissect(SOLICIT);
+        assert_eq!(dissection.unwrap().info, "Solicit XID: 0x123456 CID: 00030001001122334455");
+        assert_eq!(field(&fields, "dhcpv6.duid.link_layer_addr").as_deref(), Some("00:11:22:33:44:55"));
+        assert_eq!(field(&fields, "dhcpv6.elapsed_time").as_deref(), Some("1"));
+        assert_eq!(field(&fields, "dhcpv6.iaid.t2").as_deref(), Some("5400"));
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        for len in [0, 1, 3, 5, 17, 20, 27] {
+            assert!(dissect(&SOLICIT[..len]).0.is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn limits_relay_nesting() {
+        let mut message = SOLICIT.to_vec();
+        for _ in 0..=MAX_DEPTH + 1 {
+            let mut relay = vec![RELAY_FORW, 0];
+            relay.extend([0; 32]);
+            relay.extend(OPTION_RELAY_MSG.to_be_bytes());
+            relay.extend((message.len() as u16).to_be_bytes());
+            relay.extend(message);
+            message = relay;
+        }
+        assert!(dissect(&message).0.is_err());
+    }
+}
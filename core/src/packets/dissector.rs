@@ -0,0 +1,455 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use pnet::packet::tcp::TcpFlags;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use crate::alerts::{Alert, AlertChannel};
+use super::detection::ProbeResult;
+use super::flow::{FlowKey, FlowTable, MAX_FLOWS};
+use super::tree::{Field, FieldValue, Layer};
+
+/// Link type of Ethernet frames in pcap files
+pub const LINKTYPE_ETHERNET: u32 = 1;
+/// Time within which an announced connection has to be opened
+pub(crate) const EXPECTATION_TIMEOUT_SECS: i64 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// Key under which a dissector is registered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DissectorTable {
+    /// pcap link type of the frame, e.g. [`LINKTYPE_ETHERNET`]
+    LinkType(u32),
+    Ethertype(u16),
+    /// IPv4 protocol or IPv6 next header
+    IpProtocol(u8),
+    /// Well-known port; used as a hint for heuristic detection and as the
+    /// fallback when no heuristic dissector recognises a flow
+    Port(Transport, u16),
+}
+
+/// Summary of a packet produced by the dissector of its topmost layer
+#[derive(Debug, Clone)]
+pub struct Dissection {
+    pub protocol: String,
+    pub info: String,
+}
+
+impl Dissection {
+    pub fn new(protocol: impl Into<String>, info: impl Into<String>) -> Self {
+        Self {
+            protocol: protocol.into(),
+            info: info.into(),
+        }
+    }
+}
+
+/// A protocol dissector.
+///
+/// A dissector decodes its layer, records fields in the [`DissectContext`] and
+/// hands its payload to the next layer with [`DissectContext::dissect_next`].
+/// It returns the summary of the topmost layer it reached.
+pub trait Dissector: Send + Sync {
+    /// Protocol name shown in the output, e.g. `"TLS"`
+    fn name(&self) -> &'static str;
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection>;
+
+    /// Heuristic probe over the first bytes of a flow; only called for
+    /// dissectors registered with [`DissectorRegistry::register_heuristic`]
+    fn probe(&self, _payload: &[u8]) -> Option<ProbeResult> {
+        None
+    }
+}
+
+/// A connection announced by another one, e.g. an FTP data connection
+#[derive(Clone)]
+pub struct Expectation {
+    pub dissector: Arc<dyn Dissector>,
+    pub reason: String,
+}
+
+/// Last packet seen on a TCP or UDP flow, for ICMP errors to refer to
+#[derive(Debug, Clone, Copy)]
+pub struct FlowRecord {
+    pub last_frame: u64,
+    /// Application protocol detected on the flow
+    pub protocol: Option<&'static str>,
+    /// Direction of the flow from its client to its server
+    pub client: FlowKey,
+}
+
+/// Direction from client to server of a flow whose first packet went the way
+/// of `flow`. A SYN or the first datagram comes from the client, a SYN-ACK
+/// from the server; TCP flows picked up midway are taken to have the server
+/// on the lower port.
+fn client_direction(flow: &FlowKey, tcp_flags: Option<u8>) -> FlowKey {
+    let Some(flags) = tcp_flags else {
+        return *flow;
+    };
+    let handshake = flags & (TcpFlags::SYN | TcpFlags::ACK);
+    if handshake == TcpFlags::SYN || handshake != TcpFlags::SYN | TcpFlags::ACK && flow.src_port > flow.dst_port {
+        *flow
+    } else {
+        flow.reversed()
+    }
+}
+
+/// A flow followed by the registry, with the FINs seen in the direction of
+/// its canonical key and in the opposite one
+struct TrackedFlow {
+    record: FlowRecord,
+    fin: [bool; 2],
+}
+
+/// Dissectors by the table entries they are registered for
+pub struct DissectorRegistry {
+    tables: HashMap<DissectorTable, Arc<dyn Dissector>>,
+    heuristics: HashMap<Transport, Vec<Arc<dyn Dissector>>>,
+    /// Expected connections by the endpoint they are announced for
+    expected: Mutex<FlowTable<(Transport, IpAddr, u16), Expectation>>,
+    /// Open flows, by their canonical key
+    flows: Mutex<FlowTable<(Transport, FlowKey), TrackedFlow>>,
+}
+
+impl Default for DissectorRegistry {
+    fn default() -> Self {
+        Self {
+            tables: HashMap::new(),
+            heuristics: HashMap::new(),
+            expected: Mutex::new(FlowTable::with_limits(Duration::seconds(EXPECTATION_TIMEOUT_SECS), MAX_FLOWS)),
+            flows: Mutex::new(FlowTable::new()),
+        }
+    }
+}
+
+impl DissectorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a dissector, replacing the one registered for the same key
+    pub fn register(&mut self, table: DissectorTable, dissector: Arc<dyn Dissector>) {
+        self.tables.insert(table, dissector);
+    }
+
+    /// Registers a dissector whose [`Dissector::probe`] is tried on unclassified flows
+    pub fn register_heuristic(&mut self, transport: Transport, dissector: Arc<dyn Dissector>) {
+        self.heuristics.entry(transport).or_default().push(dissector);
+    }
+
+    pub fn get(&self, table: DissectorTable) -> Option<&Arc<dyn Dissector>> {
+        self.tables.get(&table)
+    }
+
+    pub fn heuristics(&self, transport: Transport) -> &[Arc<dyn Dissector>] {
+        self.heuristics.get(&transport).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Finds a registered dissector by its protocol name
+    pub fn by_name(&self, name: &str) -> Option<&Arc<dyn Dissector>> {
+        self.tables
+            .values()
+            .chain(self.heuristics.values().flatten())
+            .find(|dissector| dissector.name() == name)
+    }
+
+    /// Hands the next flow to or from `address:port` to `dissector`, whatever
+    /// its payload, if it is opened within two minutes of `at`
+    pub fn expect(&self, transport: Transport, address: IpAddr, port: u16, expectation: Expectation, at: DateTime<Utc>) {
+        self.expected.lock().unwrap().insert((transport, address, port), at, expectation);
+    }
+
+    /// Takes the expectation one of the endpoints of `flow` was announced for
+    pub fn take_expected(&self, transport: Transport, flow: &FlowKey, at: DateTime<Utc>) -> Option<Expectation> {
+        let mut expected = self.expected.lock().unwrap();
+        expected
+            .take(&(transport, flow.dst, flow.dst_port), at)
+            .or_else(|| expected.take(&(transport, flow.src, flow.src_port), at))
+    }
+
+    /// Records frame `number`, captured at `at`, as the last one of `flow`.
+    /// `tcp_flags` of the first segment tell which side opened a TCP flow.
+    pub fn note_flow(
+        &self,
+        transport: Transport,
+        flow: &FlowKey,
+        tcp_flags: Option<u8>,
+        number: u64,
+        protocol: Option<&'static str>,
+        at: DateTime<Utc>,
+    ) -> FlowRecord {
+        let mut flows = self.flows.lock().unwrap();
+        let tracked = flows.entry((transport, flow.canonical()), at, || TrackedFlow {
+            record: FlowRecord { last_frame: number, protocol, client: client_direction(flow, tcp_flags) },
+            fin: [false, false],
+        });
+        tracked.record.last_frame = number;
+        tracked.record.protocol = protocol.or(tracked.record.protocol);
+        tracked.record
+    }
+
+    /// Notes the FIN or RST of a TCP segment of `flow` and forgets the flow
+    /// once both sides sent FIN or either reset it. Returns `true` if it did.
+    pub fn close_flow(&self, flow: &FlowKey, flags: u8) -> bool {
+        let key = (Transport::Tcp, flow.canonical());
+        let mut flows = self.flows.lock().unwrap();
+        let Some(tracked) = flows.get_mut(&key) else {
+            return false;
+        };
+        if flags & TcpFlags::FIN != 0 {
+            tracked.fin[(*flow != flow.canonical()) as usize] = true;
+        }
+        if flags & TcpFlags::RST == 0 && tracked.fin != [true, true] {
+            return false;
+        }
+        flows.remove(&key);
+        true
+    }
+
+    /// The flow in either direction, if it was seen
+    pub fn flow(&self, transport: Transport, flow: &FlowKey) -> Option<FlowRecord> {
+        self.flows.lock().unwrap().get(&(transport, flow.canonical())).map(|tracked| tracked.record)
+    }
+}
+
+/// State shared by the dissectors of one packet
+pub struct DissectContext<'a> {
+    registry: &'a DissectorRegistry,
+    alerts: &'a AlertChannel,
+    frame: &'a [u8],
+    layers: Vec<Layer>,
+    /// Position of the frame in the capture, starting at 1
+    pub number: u64,
+    pub captured_at: DateTime<Utc>,
+    /// Capture time formatted for display
+    pub timestamp: String,
+    /// Addresses shown in the output; set by the link and network layers
+    pub source: String,
+    pub destination: String,
+    pub src_ip: Option<IpAddr>,
+    pub dst_ip: Option<IpAddr>,
+    /// Transport flow of the packet, set by the TCP and UDP dissectors
+    pub flow: Option<FlowKey>,
+    /// Direction of `flow` from its client to its server
+    pub client_flow: Option<FlowKey>,
+    pub tcp_sequence: Option<u32>,
+    pub tcp_flags: Option<u8>,
+    /// The segment ends its TCP flow: both sides sent FIN or either reset it.
+    /// Set by the TCP dissector before it hands on the payload, so the
+    /// dissectors above drop their state of the connection.
+    pub flow_closed: bool,
+}
+
+impl<'a> DissectContext<'a> {
+    /// Starts the dissection of `frame` with a `Frame` layer covering all of it
+    pub fn new(
+        registry: &'a DissectorRegistry,
+        alerts: &'a AlertChannel,
+        frame: &'a [u8],
+        number: u64,
+        captured_at: DateTime<Utc>,
+        timestamp: String,
+    ) -> Self {
+        Self {
+            registry,
+            alerts,
+            frame,
+            layers: vec![Layer::new("Frame", Some(0), frame.len())],
+            number,
+            captured_at,
+            timestamp,
+            source: "Unknown".to_string(),
+            destination: "Unknown".to_string(),
+            src_ip: None,
+            dst_ip: None,
+            flow: None,
+            client_flow: None,
+            tcp_sequence: None,
+            tcp_flags: None,
+            flow_closed: false,
+        }
+    }
+
+    pub fn registry(&self) -> &'a DissectorRegistry {
+        self.registry
+    }
+
+    /// Adds a field to the current layer without a position in the frame
+    pub fn add_field(&mut self, name: &str, value: impl Into<FieldValue>) {
+        self.push_field(Field::new(name, value));
+    }
+
+    /// Adds a field read from `length` bytes at `offset` of the data handed to
+    /// the current dissector
+    pub fn add_field_at(&mut self, name: &str, offset: usize, length: usize, value: impl Into<FieldValue>) {
+        let base = self.layers.last().and_then(|layer| layer.offset);
+        self.push_field(Field {
+            name: name.to_string(),
+            value: value.into(),
+            offset: base.map(|base| base + offset),
+            length,
+        });
+    }
+
+    pub fn add_fields(&mut self, fields: impl IntoIterator<Item = Field>) {
+        for field in fields {
+            self.push_field(field);
+        }
+    }
+
+    fn push_field(&mut self, field: Field) {
+        if let Some(layer) = self.layers.last_mut() {
+            layer.fields.push(field);
+        }
+    }
+
+    pub fn raise(&self, alert: Alert) {
+        self.alerts.raise(alert);
+    }
+
+    /// Hands `payload` to the dissector registered for `table`. Returns `None`
+    /// if there is none; a failing dissector keeps the fields it decoded and
+    /// yields a malformed packet summary.
+    pub fn dissect_next(&mut self, table: DissectorTable, payload: &[u8]) -> Option<Dissection> {
+        let dissector = self.registry.get(table)?;
+        self.begin_layer(dissector.name(), payload);
+        Some(dissector.dissect(payload, self).unwrap_or_else(|e| {
+            self.add_field("_ws.malformed", e.to_string());
+            Dissection::new(dissector.name(), format!("[Malformed Packet: {}]", e))
+        }))
+    }
+
+    /// Tries `dissector` on `payload`; on failure the layers it added are
+    /// dropped so the caller can fall back to another interpretation
+    pub fn dissect_with(&mut self, dissector: &dyn Dissector, payload: &[u8]) -> Result<Dissection> {
+        let depth = self.layers.len();
+        self.begin_layer(dissector.name(), payload);
+        let result = dissector.dissect(payload, self);
+        if result.is_err() {
+            self.layers.truncate(depth);
+        }
+        result
+    }
+
+    /// Takes the layers decoded so far
+    pub fn take_layers(&mut self) -> Vec<Layer> {
+        std::mem::take(&mut self.layers)
+    }
+
+    fn begin_layer(&mut self, protocol: &str, data: &[u8]) {
+        // Payloads are usually slices of the frame; decrypted and reassembled
+        // data lives elsewhere and has no position in it
+        let frame_start = self.frame.as_ptr() as usize;
+        let data_start = data.as_ptr() as usize;
+        let offset = (data_start >= frame_start && data_start + data.len() <= frame_start + self.frame.len())
+            .then(|| data_start - frame_start);
+        self.layers.push(Layer::new(protocol, offset, data.len()));
+    }
+}
+
+/// Runs single dissectors in tests
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Capture time `seconds` after a fixed instant
+    pub(crate) fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    /// Flow of the client 10.0.0.1:50000 to `port` of the server 10.0.0.2
+    pub(crate) fn flow(port: u16) -> FlowKey {
+        FlowKey::new("10.0.0.1".parse().unwrap(), 50000, "10.0.0.2".parse().unwrap(), port)
+    }
+
+    /// Runs `dissector` over `data` as the first layer of a packet; `setup`
+    /// sets what the lower layers would have, e.g. the flow. Returns the
+    /// outcome and the fields of every layer decoded.
+    pub(crate) fn dissect(
+        dissector: &dyn Dissector,
+        data: &[u8],
+        setup: impl FnOnce(&mut DissectContext),
+    ) -> (Result<Dissection>, Vec<Field>) {
+        dissect_in(&DissectorRegistry::new(), &AlertChannel::new(), dissector, data, setup)
+    }
+
+    /// [`dissect`] with the given registry, raising alerts on `alerts`
+    pub(crate) fn dissect_in(
+        registry: &DissectorRegistry,
+        alerts: &AlertChannel,
+        dissector: &dyn Dissector,
+        data: &[u8],
+        setup: impl FnOnce(&mut DissectContext),
+    ) -> (Result<Dissection>, Vec<Field>) {
+        let mut ctx = DissectContext::new(registry, alerts, data, 1, Utc::now(), String::new());
+        setup(&mut ctx);
+        let dissection = dissector.dissect(data, &mut ctx);
+        (dissection, ctx.take_layers().into_iter().flat_map(|layer| layer.fields).collect())
+    }
+
+    /// What the TCP dissector sets in `ctx.flow_closed` for a segment of
+    /// `flow` with `flags`
+    pub(crate) fn close_flow(registry: &DissectorRegistry, flow: &FlowKey, flags: u8, at: DateTime<Utc>) -> bool {
+        registry.note_flow(Transport::Tcp, flow, Some(flags), 0, None, at);
+        registry.close_flow(flow, flags)
+    }
+
+    /// First value of the named field
+    pub(crate) fn field<'a>(fields: &'a [Field], name: &str) -> Option<&'a FieldValue> {
+        fields.iter().find(|field| field.name == name).map(|field| &field.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::testing::flow;
+
+    #[test]
+    fn forgets_flows_once_closed() {
+        let registry = DissectorRegistry::new();
+        let at = testing::at(0);
+
+        registry.note_flow(Transport::Tcp, &flow(80), Some(TcpFlags::SYN), 1, Some("HTTP"), at);
+        assert!(!registry.close_flow(&flow(80), TcpFlags::FIN | TcpFlags::ACK));
+        assert!(!registry.close_flow(&flow(80), TcpFlags::FIN));
+        assert_eq!(registry.flow(Transport::Tcp, &flow(80).reversed()).unwrap().protocol, Some("HTTP"));
+        assert!(registry.close_flow(&flow(80).reversed(), TcpFlags::FIN));
+        assert!(registry.flow(Transport::Tcp, &flow(80)).is_none());
+
+        registry.note_flow(Transport::Tcp, &flow(80), Some(TcpFlags::ACK), 2, None, at);
+        assert!(registry.close_flow(&flow(80).reversed(), TcpFlags::RST));
+        assert!(registry.flow(Transport::Tcp, &flow(80)).is_none());
+    }
+
+    #[test]
+    fn tells_the_client_of_a_flow() {
+        let registry = DissectorRegistry::new();
+        let at = testing::at(0);
+        let client = |flow: &FlowKey, flags| registry.note_flow(Transport::Tcp, flow, flags, 1, None, at).client;
+        let server = FlowKey::new("10.0.0.2".parse().unwrap(), 80, "10.0.0.1".parse().unwrap(), 50000);
+
+        assert_eq!(client(&flow(80), Some(TcpFlags::SYN)), flow(80));
+        // Later segments keep the direction of the first one
+        assert_eq!(client(&server, Some(TcpFlags::SYN | TcpFlags::ACK)), flow(80));
+        registry.close_flow(&flow(80), TcpFlags::RST);
+
+        assert_eq!(client(&server, Some(TcpFlags::SYN | TcpFlags::ACK)), flow(80));
+        registry.close_flow(&flow(80), TcpFlags::RST);
+        // Without the handshake the server is on the lower port
+        assert_eq!(client(&server, Some(TcpFlags::ACK)), flow(80));
+        registry.close_flow(&flow(80), TcpFlags::RST);
+
+        // A low port opening a connection to a high one is still the client
+        let low = FlowKey::new("10.0.0.3".parse().unwrap(), 1023, "10.0.0.4".parse().unwrap(), 2049);
+        assert_eq!(client(&low.reversed(), Some(TcpFlags::SYN | TcpFlags::ACK)), low);
+        assert_eq!(registry.note_flow(Transport::Udp, &server, None, 1, None, at).client, server);
+    }
+}
@@ -1,75 +1,1319 @@
 use anyhow::Result;
-use dns_parser::{Packet, ResponseCode};
+use chrono::{DateTime, Utc};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use super::bytes::{to_hex, ByteReader};
+use crate::alerts::{Alert, AlertSeverity};
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::dns_stats::{DnsStatistics, DnsTracker, Transaction};
+use super::flow::{FlowKey, FlowTable};
+use super::reassembly::StreamBuffer;
+use super::tree::FieldValue;
 
-pub struct DnsProcessor;
+const HEADER_LEN: usize = 12;
+/// Compression pointers followed while reading one name, against pointer loops
+const MAX_POINTERS: usize = 64;
+const MAX_NAME_LEN: usize = 255;
 
-impl DnsProcessor {
-    pub fn new() -> Self {
-        Self
+const MDNS_PORT: u16 = 5353;
+const LLMNR_PORT: u16 = 5355;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_MX: u16 = 15;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_DNAME: u16 = 39;
+pub const TYPE_OPT: u16 = 41;
+pub const TYPE_DS: u16 = 43;
+pub const TYPE_RRSIG: u16 = 46;
+pub const TYPE_NSEC: u16 = 47;
+pub const TYPE_DNSKEY: u16 = 48;
+pub const TYPE_NSEC3: u16 = 50;
+pub const TYPE_CDS: u16 = 59;
+pub const TYPE_CDNSKEY: u16 = 60;
+pub const TYPE_SVCB: u16 = 64;
+pub const TYPE_HTTPS: u16 = 65;
+pub const TYPE_CAA: u16 = 257;
+
+const OPTION_CLIENT_SUBNET: u16 = 8;
+const OPTION_COOKIE: u16 = 10;
+const OPTION_PADDING: u16 = 12;
+const OPTION_EXTENDED_ERROR: u16 = 15;
+
+#[derive(Debug, Clone)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+    /// mDNS QU bit: the querier accepts a unicast response
+    pub unicast_response: bool,
+    offset: usize,
+    name_len: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsRecord {
+    pub name: String,
+    pub rtype: u16,
+    /// UDP payload size for OPT records
+    pub class: u16,
+    /// mDNS cache-flush bit
+    pub cache_flush: bool,
+    pub ttl: u32,
+    pub data: RecordData,
+    offset: usize,
+    name_len: usize,
+    rdata_offset: usize,
+    rdata_len: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    /// CNAME, NS, PTR and DNAME
+    Name(String),
+    Mx { preference: u16, exchange: String },
+    Txt(Vec<String>),
+    Soa {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    Srv { priority: u16, weight: u16, port: u16, target: String },
+    Caa { flags: u8, tag: String, value: String },
+    Rrsig {
+        type_covered: u16,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: String,
+        signature: Vec<u8>,
+    },
+    /// DNSKEY and CDNSKEY
+    Dnskey { flags: u16, protocol: u8, algorithm: u8, public_key: Vec<u8>, key_tag: u16 },
+    /// DS and CDS
+    Ds { key_tag: u16, algorithm: u8, digest_type: u8, digest: Vec<u8> },
+    Nsec { next: String, types: Vec<u16> },
+    Nsec3 {
+        algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next_hashed: Vec<u8>,
+        types: Vec<u16>,
+    },
+    /// SVCB and HTTPS
+    Svcb { priority: u16, target: String, params: Vec<SvcParam> },
+    Opt(Vec<EdnsOption>),
+    Unknown(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub enum SvcParam {
+    Mandatory(Vec<u16>),
+    Alpn(Vec<String>),
+    NoDefaultAlpn,
+    Port(u16),
+    Ipv4Hint(Vec<Ipv4Addr>),
+    Ech(Vec<u8>),
+    Ipv6Hint(Vec<Ipv6Addr>),
+    DohPath(String),
+    Other(u16, Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: EdnsData,
+    offset: usize,
+    length: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum EdnsData {
+    /// RFC 7871; `address` is the significant part of the prefix padded with zeros
+    ClientSubnet { family: u16, source_prefix: u8, scope_prefix: u8, address: Option<IpAddr> },
+    Cookie { client: Vec<u8>, server: Vec<u8> },
+    Padding(usize),
+    /// RFC 8914
+    ExtendedError { code: u16, text: String },
+    Other(Vec<u8>),
+}
+
+/// EDNS0 parameters carried in the OPT pseudo-record
+#[derive(Debug, Clone)]
+pub struct Edns {
+    pub udp_size: u16,
+    pub ext_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsMessage {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+    pub authorities: Vec<DnsRecord>,
+    pub additionals: Vec<DnsRecord>,
+    pub edns: Option<Edns>,
+}
+
+impl DnsMessage {
+    /// Parses a message; `mdns` enables the QU and cache-flush class bits
+    pub fn parse(data: &[u8], mdns: bool) -> Result<Self> {
+        let mut reader = ByteReader::new(data);
+        let id = reader.read_u16()?;
+        let flags = reader.read_u16()?;
+        let counts = [reader.read_u16()?, reader.read_u16()?, reader.read_u16()?, reader.read_u16()?];
+
+        let mut questions = Vec::new();
+        for _ in 0..counts[0] {
+            let offset = reader.position();
+            let (name, name_len) = read_name(data, offset)?;
+            reader.read_bytes(name_len)?;
+            let qtype = reader.read_u16()?;
+            let qclass = reader.read_u16()?;
+            let unicast_response = mdns && qclass & 0x8000 != 0;
+            questions.push(DnsQuestion {
+                name,
+                qtype,
+                qclass: if mdns { qclass & 0x7fff } else { qclass },
+                unicast_response,
+                offset,
+                name_len,
+            });
+        }
+
+        let mut sections = [Vec::new(), Vec::new(), Vec::new()];
+        for (section, count) in sections.iter_mut().zip(&counts[1..]) {
+            for _ in 0..*count {
+                section.push(read_record(data, &mut reader, mdns)?);
+            }
+        }
+        let [answers, authorities, additionals] = sections;
+
+        let edns = additionals.iter().find_map(|record| match &record.data {
+            RecordData::Opt(options) => Some(Edns {
+                udp_size: record.class,
+                ext_rcode: (record.ttl >> 24) as u8,
+                version: (record.ttl >> 16) as u8,
+                dnssec_ok: record.ttl & 0x8000 != 0,
+                options: options.clone(),
+            }),
+            _ => None,
+        });
+
+        Ok(Self { id, flags, questions, answers, authorities, additionals, edns })
     }
 
-    pub fn process<'a>(&self, payload: &'a [u8]) -> Result<Packet<'a>> {
-        Packet::parse(payload)
-            .map_err(|e| anyhow::anyhow!("Failed to parse DNS packet: {}", e))
+    pub fn is_response(&self) -> bool {
+        self.flags & 0x8000 != 0
     }
 
-    pub fn get_query_info(&self, packet: &Packet) -> String {
-        let mut info = String::new();
-        
-        // Добавляем тип запроса (query/response)
-        if packet.header.query {
-            info.push_str("Standard query ");
-        } else {
-            info.push_str("Standard query response ");
+    pub fn opcode(&self) -> u8 {
+        ((self.flags >> 11) & 0x0f) as u8
+    }
+
+    /// Response code, extended by the upper bits from EDNS0
+    pub fn rcode(&self) -> u16 {
+        let ext = self.edns.as_ref().map(|edns| edns.ext_rcode as u16).unwrap_or(0);
+        (ext << 4) | (self.flags & 0x0f)
+    }
+
+    /// Wireshark style summary, e.g. `Standard query response 0x1a2b A example.com A 93.184.216.34`
+    pub fn format_info(&self) -> String {
+        let operation = match self.opcode() {
+            0 => "Standard query",
+            1 => "Inverse query",
+            2 => "Server status request",
+            4 => "Zone change notification",
+            5 => "Dynamic update",
+            _ => "Unknown operation",
+        };
+        let mut info = format!(
+            "{}{} 0x{:04x}",
+            operation,
+            if self.is_response() { " response" } else { "" },
+            self.id
+        );
+        if self.is_response() && self.rcode() != 0 {
+            info.push(' ');
+            info.push_str(&rcode_description(self.rcode()));
         }
-        
-        // Добавляем ID запроса
-        info.push_str(&format!("0x{:04x} ", packet.header.id));
-        
-        // Добавляем информацию о запросе
-        if let Some(question) = packet.questions.first() {
-            info.push_str(&format!("{} ", question.qname));
-            info.push_str(&format!("{:?}", question.qtype));
+        for question in &self.questions {
+            info.push_str(&format!(" {} {}", type_name(question.qtype), question.name));
         }
-        
-        // Добавляем информацию об ответах
-        if !packet.header.query && !packet.answers.is_empty() {
-            info.push_str(" ");
-            for answer in &packet.answers {
-                info.push_str(&format!("{:?} ", answer.data));
+        for record in self.answers.iter().chain(&self.authorities).chain(&self.additionals) {
+            info.push(' ');
+            info.push_str(&type_name(record.rtype));
+            let summary = record.data.summary();
+            if !summary.is_empty() {
+                info.push(' ');
+                info.push_str(&summary);
             }
         }
-        
-        // Добавляем информацию о CNAME
-        if !packet.header.query && !packet.nameservers.is_empty() {
-            for ns in &packet.nameservers {
-                if let dns_parser::RData::CNAME(cname) = &ns.data {
-                    info.push_str(&format!("CNAME {:?} ", cname));
+        info
+    }
+
+    /// Records the header and every section; `base` is the offset of the
+    /// message in the data handed to the dissector, `None` if reassembled
+    fn add_fields(&self, ctx: &mut DissectContext, base: Option<usize>) {
+        let mut out = FieldSink { ctx, base };
+        out.at("dns.id", 0, 2, FieldValue::Hex(self.id as u64));
+        out.at("dns.flags", 2, 2, FieldValue::Hex(self.flags as u64));
+        out.at("dns.flags.response", 2, 1, self.is_response());
+        out.at("dns.flags.opcode", 2, 1, self.opcode());
+        out.at("dns.flags.authoritative", 2, 1, self.flags & 0x0400 != 0);
+        out.at("dns.flags.truncated", 2, 1, self.flags & 0x0200 != 0);
+        out.at("dns.flags.recdesired", 2, 1, self.flags & 0x0100 != 0);
+        out.at("dns.flags.recavail", 3, 1, self.flags & 0x0080 != 0);
+        out.at("dns.flags.authenticated", 3, 1, self.flags & 0x0020 != 0);
+        out.at("dns.flags.checkdisable", 3, 1, self.flags & 0x0010 != 0);
+        out.at("dns.flags.rcode", 3, 1, self.flags & 0x0f);
+        out.at("dns.count.queries", 4, 2, self.questions.len());
+        out.at("dns.count.answers", 6, 2, self.answers.len());
+        out.at("dns.count.auth_rr", 8, 2, self.authorities.len());
+        out.at("dns.count.add_rr", 10, 2, self.additionals.len());
+
+        for question in &self.questions {
+            let end = question.offset + question.name_len;
+            out.at("dns.qry.name", question.offset, question.name_len, &question.name);
+            out.at("dns.qry.type", end, 2, question.qtype);
+            out.at("dns.qry.class", end + 2, 2, FieldValue::Hex(question.qclass as u64));
+            if question.unicast_response {
+                out.at("dns.qry.qu", end + 2, 1, true);
+            }
+        }
+        for record in self.answers.iter().chain(&self.authorities).chain(&self.additionals) {
+            record.add_fields(&mut out);
+        }
+        if let Some(edns) = &self.edns {
+            if edns.ext_rcode != 0 {
+                out.add("dns.resp.ext_rcode", edns.ext_rcode);
+            }
+        }
+    }
+}
+
+impl DnsRecord {
+    fn add_fields(&self, out: &mut FieldSink) {
+        let header = self.offset + self.name_len;
+        let rd = self.rdata_offset;
+        let rd_len = self.rdata_len;
+        out.at("dns.resp.name", self.offset, self.name_len, &self.name);
+        out.at("dns.resp.type", header, 2, self.rtype);
+
+        if let RecordData::Opt(options) = &self.data {
+            out.at("dns.rr.udp_payload_size", header + 2, 2, self.class);
+            out.at("dns.resp.ext_rcode", header + 4, 1, (self.ttl >> 24) as u8);
+            out.at("dns.resp.edns0_version", header + 5, 1, (self.ttl >> 16) as u8);
+            out.at("dns.resp.z.do", header + 6, 1, self.ttl & 0x8000 != 0);
+            out.at("dns.resp.len", header + 8, 2, rd_len);
+            for option in options {
+                option.add_fields(out);
+            }
+            return;
+        }
+
+        out.at("dns.resp.class", header + 2, 2, FieldValue::Hex(self.class as u64));
+        if self.cache_flush {
+            out.at("dns.resp.cache_flush", header + 2, 1, true);
+        }
+        out.at("dns.resp.ttl", header + 4, 4, self.ttl);
+        out.at("dns.resp.len", header + 8, 2, rd_len);
+        out.add(
+            "dns.rr",
+            format!(
+                "{}: type {}, class {}, ttl {} ({}), {}",
+                self.name,
+                type_name(self.rtype),
+                class_name(self.class),
+                self.ttl,
+                format_ttl(self.ttl),
+                if self.data.summary().is_empty() { format!("{} bytes", rd_len) } else { self.data.summary() }
+            ),
+        );
+
+        match &self.data {
+            RecordData::A(address) => out.at("dns.a", rd, 4, *address),
+            RecordData::Aaaa(address) => out.at("dns.aaaa", rd, 16, *address),
+            RecordData::Name(name) => {
+                let field = match self.rtype {
+                    TYPE_NS => "dns.ns",
+                    TYPE_PTR => "dns.ptr.domain_name",
+                    TYPE_DNAME => "dns.dname",
+                    _ => "dns.cname",
+                };
+                out.at(field, rd, rd_len, name);
+            }
+            RecordData::Mx { preference, exchange } => {
+                out.at("dns.mx.preference", rd, 2, *preference);
+                out.at("dns.mx.mail_exchange", rd + 2, rd_len.saturating_sub(2), exchange);
+            }
+            RecordData::Txt(strings) => {
+                for text in strings {
+                    out.at("dns.txt", rd, rd_len, text);
                 }
             }
+            RecordData::Soa { mname, rname, serial, refresh, retry, expire, minimum } => {
+                // The names may be compressed; the counters are the last 20 bytes
+                let counters = rd + rd_len.saturating_sub(20);
+                out.at("dns.soa.mname", rd, rd_len.saturating_sub(20), mname);
+                out.at("dns.soa.rname", rd, rd_len.saturating_sub(20), rname);
+                out.at("dns.soa.serial_number", counters, 4, *serial);
+                out.at("dns.soa.refresh_interval", counters + 4, 4, *refresh);
+                out.at("dns.soa.retry_interval", counters + 8, 4, *retry);
+                out.at("dns.soa.expire_limit", counters + 12, 4, *expire);
+                out.at("dns.soa.minimum_ttl", counters + 16, 4, *minimum);
+            }
+            RecordData::Srv { priority, weight, port, target } => {
+                out.at("dns.srv.priority", rd, 2, *priority);
+                out.at("dns.srv.weight", rd + 2, 2, *weight);
+                out.at("dns.srv.port", rd + 4, 2, *port);
+                out.at("dns.srv.target", rd + 6, rd_len.saturating_sub(6), target);
+            }
+            RecordData::Caa { flags, tag, value } => {
+                out.at("dns.caa.flags", rd, 1, *flags);
+                out.at("dns.caa.tag", rd + 2, tag.len(), tag);
+                out.at("dns.caa.value", rd + 2 + tag.len(), rd_len.saturating_sub(2 + tag.len()), value);
+            }
+            RecordData::Rrsig {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+            } => {
+                out.at("dns.rrsig.type_covered", rd, 2, type_name(*type_covered));
+                out.at("dns.rrsig.algorithm", rd + 2, 1, algorithm_name(*algorithm));
+                out.at("dns.rrsig.labels", rd + 3, 1, *labels);
+                out.at("dns.rrsig.original_ttl", rd + 4, 4, *original_ttl);
+                out.at("dns.rrsig.signature_expiration", rd + 8, 4, format_signature_time(*expiration));
+                out.at("dns.rrsig.signature_inception", rd + 12, 4, format_signature_time(*inception));
+                out.at("dns.rrsig.key_tag", rd + 16, 2, *key_tag);
+                let signature_start = rd + rd_len - signature.len();
+                out.at("dns.rrsig.signers_name", rd + 18, signature_start - rd - 18, signer);
+                out.at("dns.rrsig.signature", signature_start, signature.len(), FieldValue::Bytes(signature.clone()));
+            }
+            RecordData::Dnskey { flags, protocol, algorithm, public_key, key_tag } => {
+                out.at("dns.dnskey.flags", rd, 2, FieldValue::Hex(*flags as u64));
+                out.at("dns.dnskey.flags.zone_key", rd, 2, flags & 0x0100 != 0);
+                out.at("dns.dnskey.flags.secure_entry_point", rd + 1, 1, flags & 0x0001 != 0);
+                out.at("dns.dnskey.protocol", rd + 2, 1, *protocol);
+                out.at("dns.dnskey.algorithm", rd + 3, 1, algorithm_name(*algorithm));
+                out.add("dns.dnskey.key_id", *key_tag);
+                out.at("dns.dnskey.public_key", rd + 4, public_key.len(), FieldValue::Bytes(public_key.clone()));
+            }
+            RecordData::Ds { key_tag, algorithm, digest_type, digest } => {
+                out.at("dns.ds.key_id", rd, 2, *key_tag);
+                out.at("dns.ds.algorithm", rd + 2, 1, algorithm_name(*algorithm));
+                out.at("dns.ds.digest_type", rd + 3, 1, digest_type_name(*digest_type));
+                out.at("dns.ds.digest", rd + 4, digest.len(), FieldValue::Bytes(digest.clone()));
+            }
+            RecordData::Nsec { next, types } => {
+                out.at("dns.nsec.next_domain_name", rd, rd_len, next);
+                for rtype in types {
+                    out.add("dns.nsec.type", type_name(*rtype));
+                }
+            }
+            RecordData::Nsec3 { algorithm, flags, iterations, salt, next_hashed, types } => {
+                out.at("dns.nsec3.algo", rd, 1, *algorithm);
+                out.at("dns.nsec3.flags.opt_out", rd + 1, 1, flags & 0x01 != 0);
+                out.at("dns.nsec3.iterations", rd + 2, 2, *iterations);
+                out.at("dns.nsec3.salt_value", rd + 5, salt.len(), FieldValue::Bytes(salt.clone()));
+                let hash_start = rd + 6 + salt.len();
+                out.at("dns.nsec3.hash_value", hash_start, next_hashed.len(), FieldValue::Bytes(next_hashed.clone()));
+                for rtype in types {
+                    out.add("dns.nsec3.type", type_name(*rtype));
+                }
+            }
+            RecordData::Svcb { priority, target, params } => {
+                out.at("dns.svcb.svcpriority", rd, 2, *priority);
+                out.add("dns.svcb.targetname", target);
+                for param in params {
+                    param.add_fields(out);
+                }
+            }
+            RecordData::Opt(_) => {}
+            RecordData::Unknown(data) => out.at("dns.data", rd, rd_len, FieldValue::Bytes(data.clone())),
         }
-        
-        // Добавляем код ответа, если есть ошибка
-        if packet.header.response_code != ResponseCode::NoError {
-            info.push_str(&format!("RCode: {:?}", packet.header.response_code));
+    }
+}
+
+impl RecordData {
+    /// Record data in presentation format, empty for the DNSSEC records
+    /// whose data does not fit a summary line
+    pub fn summary(&self) -> String {
+        match self {
+            RecordData::A(address) => address.to_string(),
+            RecordData::Aaaa(address) => address.to_string(),
+            RecordData::Name(name) => name.clone(),
+            RecordData::Mx { preference, exchange } => format!("{} {}", preference, exchange),
+            RecordData::Txt(strings) => strings.iter().map(|s| format!("\"{}\"", s)).collect::<Vec<_>>().join(" "),
+            RecordData::Soa { mname, .. } => mname.clone(),
+            RecordData::Srv { priority, weight, port, target } => format!("{} {} {} {}", priority, weight, port, target),
+            RecordData::Caa { flags, tag, value } => format!("{} {} \"{}\"", flags, tag, value),
+            RecordData::Svcb { priority, target, params } => {
+                let mut summary = format!("{} {}", priority, target);
+                for param in params {
+                    summary.push(' ');
+                    summary.push_str(&param.to_string());
+                }
+                summary
+            }
+            RecordData::Rrsig { .. }
+            | RecordData::Dnskey { .. }
+            | RecordData::Ds { .. }
+            | RecordData::Nsec { .. }
+            | RecordData::Nsec3 { .. }
+            | RecordData::Opt(_)
+            | RecordData::Unknown(_) => String::new(),
         }
-        
-        info
     }
+}
 
-    pub fn get_query_type(&self, packet: &dns_parser::Packet) -> String {
-        if let Some(question) = packet.questions.first() {
-            format!("{:?}", question.qtype)
-        } else {
-            "No query".to_string()
+impl std::fmt::Display for SvcParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |items: Vec<String>| items.join(",");
+        match self {
+            SvcParam::Mandatory(keys) => write!(f, "mandatory={}", join(keys.iter().map(|k| svc_key_name(*k)).collect())),
+            SvcParam::Alpn(protocols) => write!(f, "alpn={}", protocols.join(",")),
+            SvcParam::NoDefaultAlpn => f.write_str("no-default-alpn"),
+            SvcParam::Port(port) => write!(f, "port={}", port),
+            SvcParam::Ipv4Hint(addresses) => write!(f, "ipv4hint={}", join(addresses.iter().map(|a| a.to_string()).collect())),
+            SvcParam::Ech(config) => write!(f, "ech={} bytes", config.len()),
+            SvcParam::Ipv6Hint(addresses) => write!(f, "ipv6hint={}", join(addresses.iter().map(|a| a.to_string()).collect())),
+            SvcParam::DohPath(template) => write!(f, "dohpath={}", template),
+            SvcParam::Other(key, value) => write!(f, "key{}={}", key, to_hex(value)),
+        }
+    }
+}
+
+impl SvcParam {
+    fn add_fields(&self, out: &mut FieldSink) {
+        match self {
+            SvcParam::Mandatory(keys) => {
+                for key in keys {
+                    out.add("dns.svcb.svcparam.mandatory.key", svc_key_name(*key));
+                }
+            }
+            SvcParam::Alpn(protocols) => {
+                for protocol in protocols {
+                    out.add("dns.svcb.svcparam.alpn", protocol);
+                }
+            }
+            SvcParam::NoDefaultAlpn => out.add("dns.svcb.svcparam.no_default_alpn", true),
+            SvcParam::Port(port) => out.add("dns.svcb.svcparam.port", *port),
+            SvcParam::Ipv4Hint(addresses) => {
+                for address in addresses {
+                    out.add("dns.svcb.svcparam.ipv4hint.ip", *address);
+                }
+            }
+            SvcParam::Ech(config) => out.add("dns.svcb.svcparam.ech.config_list", FieldValue::Bytes(config.clone())),
+            SvcParam::Ipv6Hint(addresses) => {
+                for address in addresses {
+                    out.add("dns.svcb.svcparam.ipv6hint.ip", *address);
+                }
+            }
+            SvcParam::DohPath(template) => out.add("dns.svcb.svcparam.dohpath", template),
+            SvcParam::Other(key, value) => {
+                out.add("dns.svcb.svcparam.key", *key);
+                out.add("dns.svcb.svcparam.value", FieldValue::Bytes(value.clone()));
+            }
+        }
+    }
+}
+
+impl EdnsOption {
+    fn add_fields(&self, out: &mut FieldSink) {
+        let (offset, length) = (self.offset, self.length);
+        out.at("dns.opt.code", offset, 2, self.code);
+        out.at("dns.opt.len", offset + 2, 2, length - 4);
+        let data = offset + 4;
+        match &self.data {
+            EdnsData::ClientSubnet { family, source_prefix, scope_prefix, address } => {
+                out.at("dns.opt.client.family", data, 2, *family);
+                out.at("dns.opt.client.netmask", data + 2, 1, *source_prefix);
+                out.at("dns.opt.client.scope", data + 3, 1, *scope_prefix);
+                match address {
+                    Some(IpAddr::V4(address)) => out.at("dns.opt.client.addr4", data + 4, length - 8, *address),
+                    Some(IpAddr::V6(address)) => out.at("dns.opt.client.addr6", data + 4, length - 8, *address),
+                    None => {}
+                }
+            }
+            EdnsData::Cookie { client, server } => {
+                out.at("dns.opt.cookie.client", data, client.len(), FieldValue::Bytes(client.clone()));
+                if !server.is_empty() {
+                    out.at("dns.opt.cookie.server", data + client.len(), server.len(), FieldValue::Bytes(server.clone()));
+                }
+            }
+            EdnsData::Padding(len) => out.at("dns.opt.padding", data, *len, *len),
+            EdnsData::ExtendedError { code, text } => {
+                out.at("dns.opt.ede.info_code", data, 2, *code);
+                if !text.is_empty() {
+                    out.at("dns.opt.ede.extra_text", data + 2, text.len(), text);
+                }
+            }
+            EdnsData::Other(value) => out.at("dns.opt.data", data, value.len(), FieldValue::Bytes(value.clone())),
+        }
+    }
+}
+
+/// Adds fields at message offsets, which are shifted by the position of the
+/// message in the dissected data
+struct FieldSink<'c, 'a> {
+    ctx: &'c mut DissectContext<'a>,
+    base: Option<usize>,
+}
+
+impl FieldSink<'_, '_> {
+    fn at(&mut self, name: &str, offset: usize, length: usize, value: impl Into<FieldValue>) {
+        match self.base {
+            Some(base) => self.ctx.add_field_at(name, base + offset, length, value),
+            None => self.ctx.add_field(name, value),
+        }
+    }
+
+    fn add(&mut self, name: &str, value: impl Into<FieldValue>) {
+        self.ctx.add_field(name, value);
+    }
+}
+
+/// Reads the name at `pos`, following compression pointers. Returns the name
+/// and the number of bytes it takes at `pos`.
+pub(crate) fn read_name(message: &[u8], pos: usize) -> Result<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut position = pos;
+    let mut wire_len = None;
+    let mut pointers = 0;
+    let mut name_len = 0;
+
+    loop {
+        let len = *message.get(position).ok_or_else(|| anyhow::anyhow!("DNS name runs past the message"))? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => {
+                wire_len.get_or_insert_with(|| position + 1 - pos);
+                break;
+            }
+            0x00 => {
+                let label = message
+                    .get(position + 1..position + 1 + len)
+                    .ok_or_else(|| anyhow::anyhow!("DNS label runs past the message"))?;
+                name_len += len + 1;
+                if name_len > MAX_NAME_LEN {
+                    return Err(anyhow::anyhow!("DNS name longer than {} bytes", MAX_NAME_LEN));
+                }
+                labels.push(escape_label(label));
+                position += len + 1;
+            }
+            0xc0 => {
+                let low = *message.get(position + 1).ok_or_else(|| anyhow::anyhow!("Truncated DNS pointer"))? as usize;
+                wire_len.get_or_insert_with(|| position + 2 - pos);
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(anyhow::anyhow!("DNS compression pointer loop"));
+                }
+                position = ((len & 0x3f) << 8) | low;
+            }
+            _ => return Err(anyhow::anyhow!("Unsupported DNS label type {:#04x}", len)),
         }
     }
 
-    pub fn get_answer_type(&self, packet: &dns_parser::Packet) -> String {
-        if let Some(answer) = packet.answers.first() {
-            format!("{:?}", answer.data)
+    let name = if labels.is_empty() { "<Root>".to_string() } else { labels.join(".") };
+    Ok((name, wire_len.unwrap_or_default()))
+}
+
+/// Presentation format of a label: dots, backslashes and non-printable bytes escaped
+fn escape_label(label: &[u8]) -> String {
+    let mut text = String::new();
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' => {
+                text.push('\\');
+                text.push(byte as char);
+            }
+            0x21..=0x7e => text.push(byte as char),
+            _ => text.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    text
+}
+
+fn read_record(message: &[u8], reader: &mut ByteReader, mdns: bool) -> Result<DnsRecord> {
+    let offset = reader.position();
+    let (name, name_len) = read_name(message, offset)?;
+    reader.read_bytes(name_len)?;
+    let rtype = reader.read_u16()?;
+    let class = reader.read_u16()?;
+    let ttl = reader.read_u32()?;
+    let rdata_len = reader.read_u16()? as usize;
+    let rdata_offset = reader.position();
+    let rdata = reader.read_bytes(rdata_len)?;
+
+    let cache_flush = mdns && rtype != TYPE_OPT && class & 0x8000 != 0;
+    let class = if mdns && rtype != TYPE_OPT { class & 0x7fff } else { class };
+    let data = read_rdata(message, rtype, rdata, rdata_offset)
+        .map_err(|e| anyhow::anyhow!("{} record of {}: {}", type_name(rtype), name, e))?;
+
+    Ok(DnsRecord { name, rtype, class, cache_flush, ttl, data, offset, name_len, rdata_offset, rdata_len })
+}
+
+fn read_rdata(message: &[u8], rtype: u16, rdata: &[u8], offset: usize) -> Result<RecordData> {
+    let mut reader = ByteReader::new(rdata);
+    // Names in the data may point anywhere in the message
+    let name = |reader: &mut ByteReader| -> Result<String> {
+        let (name, len) = read_name(message, offset + reader.position())?;
+        if len > reader.remaining() {
+            return Err(anyhow::anyhow!("name runs past the record data"));
+        }
+        reader.read_bytes(len)?;
+        Ok(name)
+    };
+
+    let data = match rtype {
+        TYPE_A => {
+            let b = reader.read_bytes(4)?;
+            RecordData::A(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+        }
+        TYPE_AAAA => {
+            let b: [u8; 16] = reader.read_bytes(16)?.try_into()?;
+            RecordData::Aaaa(Ipv6Addr::from(b))
+        }
+        TYPE_CNAME | TYPE_NS | TYPE_PTR | TYPE_DNAME => RecordData::Name(name(&mut reader)?),
+        TYPE_MX => RecordData::Mx { preference: reader.read_u16()?, exchange: name(&mut reader)? },
+        TYPE_TXT => {
+            let mut strings = Vec::new();
+            while !reader.is_empty() {
+                strings.push(String::from_utf8_lossy(reader.read_vec8()?).into_owned());
+            }
+            RecordData::Txt(strings)
+        }
+        TYPE_SOA => RecordData::Soa {
+            mname: name(&mut reader)?,
+            rname: name(&mut reader)?,
+            serial: reader.read_u32()?,
+            refresh: reader.read_u32()?,
+            retry: reader.read_u32()?,
+            expire: reader.read_u32()?,
+            minimum: reader.read_u32()?,
+        },
+        TYPE_SRV => RecordData::Srv {
+            priority: reader.read_u16()?,
+            weight: reader.read_u16()?,
+            port: reader.read_u16()?,
+            target: name(&mut reader)?,
+        },
+        TYPE_CAA => {
+            let flags = reader.read_u8()?;
+            let tag = String::from_utf8_lossy(reader.read_vec8()?).into_owned();
+            let value = String::from_utf8_lossy(reader.read_bytes(reader.remaining())?).into_owned();
+            RecordData::Caa { flags, tag, value }
+        }
+        TYPE_RRSIG => RecordData::Rrsig {
+            type_covered: reader.read_u16()?,
+            algorithm: reader.read_u8()?,
+            labels: reader.read_u8()?,
+            original_ttl: reader.read_u32()?,
+            expiration: reader.read_u32()?,
+            inception: reader.read_u32()?,
+            key_tag: reader.read_u16()?,
+            signer: name(&mut reader)?,
+            signature: reader.read_bytes(reader.remaining())?.to_vec(),
+        },
+        TYPE_DNSKEY | TYPE_CDNSKEY => RecordData::Dnskey {
+            flags: reader.read_u16()?,
+            protocol: reader.read_u8()?,
+            algorithm: reader.read_u8()?,
+            public_key: reader.read_bytes(reader.remaining())?.to_vec(),
+            key_tag: key_tag(rdata),
+        },
+        TYPE_DS | TYPE_CDS => RecordData::Ds {
+            key_tag: reader.read_u16()?,
+            algorithm: reader.read_u8()?,
+            digest_type: reader.read_u8()?,
+            digest: reader.read_bytes(reader.remaining())?.to_vec(),
+        },
+        TYPE_NSEC => RecordData::Nsec {
+            next: name(&mut reader)?,
+            types: read_type_bitmap(&mut reader)?,
+        },
+        TYPE_NSEC3 => RecordData::Nsec3 {
+            algorithm: reader.read_u8()?,
+            flags: reader.read_u8()?,
+            iterations: reader.read_u16()?,
+            salt: reader.read_vec8()?.to_vec(),
+            next_hashed: reader.read_vec8()?.to_vec(),
+            types: read_type_bitmap(&mut reader)?,
+        },
+        TYPE_SVCB | TYPE_HTTPS => {
+            let priority = reader.read_u16()?;
+            let target = name(&mut reader)?;
+            let mut params = Vec::new();
+            while !reader.is_empty() {
+                let key = reader.read_u16()?;
+                params.push(read_svc_param(key, reader.read_vec16()?)?);
+            }
+            RecordData::Svcb { priority, target, params }
+        }
+        TYPE_OPT => {
+            let mut options = Vec::new();
+            while !reader.is_empty() {
+                let option_offset = offset + reader.position();
+                let code = reader.read_u16()?;
+                let value = reader.read_vec16()?;
+                options.push(EdnsOption {
+                    code,
+                    data: read_edns_option(code, value)?,
+                    offset: option_offset,
+                    length: value.len() + 4,
+                });
+            }
+            RecordData::Opt(options)
+        }
+        _ => RecordData::Unknown(rdata.to_vec()),
+    };
+    Ok(data)
+}
+
+/// Types listed in an NSEC/NSEC3 window bitmap
+fn read_type_bitmap(reader: &mut ByteReader) -> Result<Vec<u16>> {
+    let mut types = Vec::new();
+    while !reader.is_empty() {
+        let window = reader.read_u8()? as u16;
+        let bitmap = reader.read_vec8()?;
+        // 32 bytes cover the 256 types of a window
+        if bitmap.len() > 32 {
+            return Err(anyhow::anyhow!("Type bitmap of window {} is {} bytes long", window, bitmap.len()));
+        }
+        for (index, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(window * 256 + index as u16 * 8 + bit);
+                }
+            }
+        }
+    }
+    Ok(types)
+}
+
+fn read_svc_param(key: u16, value: &[u8]) -> Result<SvcParam> {
+    let mut reader = ByteReader::new(value);
+    let param = match key {
+        0 => {
+            let mut keys = Vec::new();
+            while !reader.is_empty() {
+                keys.push(reader.read_u16()?);
+            }
+            SvcParam::Mandatory(keys)
+        }
+        1 => {
+            let mut protocols = Vec::new();
+            while !reader.is_empty() {
+                protocols.push(String::from_utf8_lossy(reader.read_vec8()?).into_owned());
+            }
+            SvcParam::Alpn(protocols)
+        }
+        2 => SvcParam::NoDefaultAlpn,
+        3 => SvcParam::Port(reader.read_u16()?),
+        4 => SvcParam::Ipv4Hint(
+            value.chunks_exact(4).map(|b| Ipv4Addr::new(b[0], b[1], b[2], b[3])).collect(),
+        ),
+        5 => SvcParam::Ech(value.to_vec()),
+        6 => SvcParam::Ipv6Hint(
+            value
+                .chunks_exact(16)
+                .map(|b| Ipv6Addr::from(<[u8; 16]>::try_from(b).unwrap_or_default()))
+                .collect(),
+        ),
+        7 => SvcParam::DohPath(String::from_utf8_lossy(value).into_owned()),
+        _ => SvcParam::Other(key, value.to_vec()),
+    };
+    Ok(param)
+}
+
+fn read_edns_option(code: u16, value: &[u8]) -> Result<EdnsData> {
+    let mut reader = ByteReader::new(value);
+    let data = match code {
+        OPTION_CLIENT_SUBNET => {
+            let family = reader.read_u16()?;
+            let source_prefix = reader.read_u8()?;
+            let scope_prefix = reader.read_u8()?;
+            let prefix = reader.read_bytes(reader.remaining())?;
+            let address = match family {
+                1 if prefix.len() <= 4 => {
+                    let mut octets = [0u8; 4];
+                    octets[..prefix.len()].copy_from_slice(prefix);
+                    Some(IpAddr::V4(Ipv4Addr::from(octets)))
+                }
+                2 if prefix.len() <= 16 => {
+                    let mut octets = [0u8; 16];
+                    octets[..prefix.len()].copy_from_slice(prefix);
+                    Some(IpAddr::V6(Ipv6Addr::from(octets)))
+                }
+                _ => None,
+            };
+            EdnsData::ClientSubnet { family, source_prefix, scope_prefix, address }
+        }
+        OPTION_COOKIE if value.len() >= 8 => EdnsData::Cookie {
+            client: value[..8].to_vec(),
+            server: value[8..].to_vec(),
+        },
+        OPTION_PADDING => EdnsData::Padding(value.len()),
+        OPTION_EXTENDED_ERROR => EdnsData::ExtendedError {
+            code: reader.read_u16()?,
+            text: String::from_utf8_lossy(reader.read_bytes(reader.remaining())?).into_owned(),
+        },
+        _ => EdnsData::Other(value.to_vec()),
+    };
+    Ok(data)
+}
+
+/// Key tag of a DNSKEY record (RFC 4034, appendix B)
+fn key_tag(rdata: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for (index, &byte) in rdata.iter().enumerate() {
+        sum += if index % 2 == 0 { (byte as u32) << 8 } else { byte as u32 };
+    }
+    sum += (sum >> 16) & 0xffff;
+    (sum & 0xffff) as u16
+}
+
+/// Mnemonic of a record type; unknown types as `TYPE<n>` (RFC 3597)
+pub fn type_name(rtype: u16) -> String {
+    let name = match rtype {
+        TYPE_A => "A",
+        TYPE_NS => "NS",
+        TYPE_CNAME => "CNAME",
+        TYPE_SOA => "SOA",
+        TYPE_PTR => "PTR",
+        13 => "HINFO",
+        TYPE_MX => "MX",
+        TYPE_TXT => "TXT",
+        TYPE_AAAA => "AAAA",
+        TYPE_SRV => "SRV",
+        35 => "NAPTR",
+        TYPE_DNAME => "DNAME",
+        TYPE_OPT => "OPT",
+        TYPE_DS => "DS",
+        44 => "SSHFP",
+        TYPE_RRSIG => "RRSIG",
+        TYPE_NSEC => "NSEC",
+        TYPE_DNSKEY => "DNSKEY",
+        TYPE_NSEC3 => "NSEC3",
+        51 => "NSEC3PARAM",
+        52 => "TLSA",
+        TYPE_CDS => "CDS",
+        TYPE_CDNSKEY => "CDNSKEY",
+        TYPE_SVCB => "SVCB",
+        TYPE_HTTPS => "HTTPS",
+        99 => "SPF",
+        251 => "IXFR",
+        252 => "AXFR",
+        255 => "ANY",
+        TYPE_CAA => "CAA",
+        _ => return format!("TYPE{}", rtype),
+    };
+    name.to_string()
+}
+
+fn class_name(class: u16) -> String {
+    match class {
+        1 => "IN".to_string(),
+        3 => "CH".to_string(),
+        4 => "HS".to_string(),
+        254 => "NONE".to_string(),
+        255 => "ANY".to_string(),
+        _ => format!("CLASS{}", class),
+    }
+}
+
+pub fn rcode_description(rcode: u16) -> String {
+    match rcode {
+        0 => "No error".to_string(),
+        1 => "Format error".to_string(),
+        2 => "Server failure".to_string(),
+        3 => "No such name".to_string(),
+        4 => "Not implemented".to_string(),
+        5 => "Refused".to_string(),
+        6 => "Name exists".to_string(),
+        7 => "RRset exists".to_string(),
+        8 => "RRset does not exist".to_string(),
+        9 => "Not authoritative".to_string(),
+        10 => "Name out of zone".to_string(),
+        16 => "Bad OPT version".to_string(),
+        23 => "Bad cookie".to_string(),
+        _ => format!("Unknown error {}", rcode),
+    }
+}
+
+fn algorithm_name(algorithm: u8) -> String {
+    let name = match algorithm {
+        5 => "RSA/SHA1",
+        7 => "RSASHA1-NSEC3-SHA1",
+        8 => "RSA/SHA-256",
+        10 => "RSA/SHA-512",
+        13 => "ECDSA Curve P-256 with SHA-256",
+        14 => "ECDSA Curve P-384 with SHA-384",
+        15 => "Ed25519",
+        16 => "Ed448",
+        _ => return format!("Unknown ({})", algorithm),
+    };
+    format!("{} ({})", name, algorithm)
+}
+
+fn digest_type_name(digest_type: u8) -> String {
+    let name = match digest_type {
+        1 => "SHA-1",
+        2 => "SHA-256",
+        4 => "SHA-384",
+        _ => return format!("Unknown ({})", digest_type),
+    };
+    format!("{} ({})", name, digest_type)
+}
+
+fn svc_key_name(key: u16) -> String {
+    match key {
+        0 => "mandatory".to_string(),
+        1 => "alpn".to_string(),
+        2 => "no-default-alpn".to_string(),
+        3 => "port".to_string(),
+        4 => "ipv4hint".to_string(),
+        5 => "ech".to_string(),
+        6 => "ipv6hint".to_string(),
+        7 => "dohpath".to_string(),
+        _ => format!("key{}", key),
+    }
+}
+
+/// RRSIG validity times, seconds since the epoch
+fn format_signature_time(time: u32) -> String {
+    DateTime::from_timestamp(time as i64, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| time.to_string())
+}
+
+/// TTL in words, e.g. `1 hour, 30 minutes`
+pub fn format_ttl(ttl: u32) -> String {
+    let units = [(86400, "day"), (3600, "hour"), (60, "minute"), (1, "second")];
+    let mut rest = ttl;
+    let mut parts = Vec::new();
+    for (seconds, unit) in units {
+        let count = rest / seconds;
+        rest %= seconds;
+        if count > 0 {
+            parts.push(format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" }));
+        }
+    }
+    if parts.is_empty() {
+        "0 seconds".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// One direction of a DNS over TCP connection
+#[derive(Default)]
+struct DnsStream {
+    messages: StreamBuffer,
+}
+
+/// DNS over UDP and TCP, including mDNS and LLMNR
+pub struct DnsProcessor {
+    /// DNS over TCP messages split over several segments, per direction
+    streams: Mutex<FlowTable<FlowKey, DnsStream>>,
+    transactions: Mutex<DnsTracker>,
+}
+
+impl DnsProcessor {
+    pub fn new() -> Self {
+        Self {
+            streams: Mutex::new(FlowTable::new()),
+            transactions: Mutex::new(DnsTracker::default()),
+        }
+    }
+
+    /// Resolver statistics of the transactions seen so far
+    pub fn statistics(&self, top: usize) -> DnsStatistics {
+        self.transactions.lock().unwrap().statistics(top)
+    }
+
+    /// Pairs the message with its query or response and records the outcome
+    /// as fields. mDNS is not tracked: its responses are multicast and
+    /// carry no question.
+    fn track(&self, message: &DnsMessage, ctx: &mut DissectContext) {
+        let Some(flow) = ctx.flow else {
+            return;
+        };
+        let transaction = self.transactions.lock().unwrap().track(message, flow, ctx.number, ctx.captured_at);
+        match transaction {
+            Some(Transaction::Query) | None => {}
+            Some(Transaction::Retransmission { request_in }) => {
+                ctx.add_field("dns.retransmission", true);
+                ctx.add_field("dns.retransmit_request_in", request_in);
+            }
+            Some(Transaction::Response { request_in, time }) => {
+                ctx.add_field("dns.response_to", request_in);
+                ctx.add_field("dns.time", time);
+            }
+            Some(Transaction::Unsolicited) => ctx.add_field("dns.unsolicited", true),
+            Some(Transaction::IdMismatch { request_in, expected_id }) => {
+                ctx.add_field("dns.id_mismatch", true);
+                ctx.add_field("dns.response_to", request_in);
+                ctx.raise(Alert::new(
+                    &ctx.timestamp,
+                    AlertSeverity::Warning,
+                    "dns.id_mismatch",
+                    format!(
+                        "Response 0x{:04x} on {} does not match the query 0x{:04x} in frame {}",
+                        message.id, flow, expected_id, request_in
+                    ),
+                ));
+            }
+        }
+    }
+
+    pub fn process(&self, payload: &[u8], mdns: bool) -> Result<DnsMessage> {
+        DnsMessage::parse(payload, mdns).map_err(|e| anyhow::anyhow!("Failed to parse DNS packet: {}", e))
+    }
+
+    /// Feeds a TCP segment of a DNS connection and returns the complete
+    /// messages it finishes, each with its offset in the segment if it lies
+    /// entirely within it
+    fn process_segment(
+        &self,
+        flow: &FlowKey,
+        seq: u32,
+        payload: &[u8],
+        at: DateTime<Utc>,
+    ) -> Vec<(Vec<u8>, Option<usize>)> {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = &mut streams.entry(*flow, at, DnsStream::default).messages;
+        let fresh = stream.data().is_empty();
+        stream.push(seq, payload);
+        // Offsets in the segment are only known while the buffer mirrors it
+        let in_segment = fresh && stream.data().len() == payload.len();
+
+        let mut messages = Vec::new();
+        let mut consumed = 0;
+        loop {
+            let data = stream.data();
+            if data.len() < 2 {
+                break;
+            }
+            let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+            if data.len() < 2 + len {
+                break;
+            }
+            messages.push((data[2..2 + len].to_vec(), in_segment.then_some(consumed + 2)));
+            stream.consume(2 + len);
+            consumed += 2 + len;
+        }
+        messages
+    }
+
+    /// Drops both directions of a closed connection
+    fn close(&self, flow: &FlowKey) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.remove(flow);
+        streams.remove(&flow.reversed());
+    }
+}
+
+impl Dissector for DnsProcessor {
+    fn name(&self) -> &'static str {
+        "DNS"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let ports = ctx.flow.map(|flow| [flow.src_port, flow.dst_port]).unwrap_or_default();
+        let protocol = if ports.contains(&MDNS_PORT) {
+            "MDNS"
+        } else if ports.contains(&LLMNR_PORT) {
+            "LLMNR"
         } else {
-            "No answer".to_string()
+            "DNS"
+        };
+        let mdns = protocol == "MDNS";
+
+        // Over TCP every message is preceded by its two-byte length
+        let (Some(flow), Some(seq)) = (ctx.flow, ctx.tcp_sequence) else {
+            let message = self.process(data, mdns)?;
+            message.add_fields(ctx, Some(0));
+            if !mdns {
+                self.track(&message, ctx);
+            }
+            return Ok(Dissection::new(protocol, message.format_info()));
+        };
+
+        let messages = if data.is_empty() {
+            Vec::new()
+        } else {
+            self.process_segment(&flow, seq, data, ctx.captured_at)
+        };
+        if ctx.flow_closed {
+            self.close(&flow);
+        }
+        if data.is_empty() {
+            return Err(anyhow::anyhow!("No DNS data"));
+        }
+        if messages.is_empty() {
+            return Err(anyhow::anyhow!("No complete DNS message"));
+        }
+        let mut infos = Vec::new();
+        for (bytes, offset) in &messages {
+            if let Some(offset) = offset {
+                ctx.add_field_at("dns.length", offset - 2, 2, bytes.len());
+            }
+            let message = self.process(bytes, mdns)?;
+            message.add_fields(ctx, *offset);
+            self.track(&message, ctx);
+            infos.push(message.format_info());
+        }
+        Ok(Dissection::new(protocol, infos.join("; ")))
+    }
+
+    /// Heuristic probe: a plausible header whose question section parses,
+    /// optionally preceded by the two-byte length used over TCP
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        let plausible = |message: &[u8]| {
+            if message.len() < HEADER_LEN {
+                return false;
+            }
+            let opcode = (message[2] >> 3) & 0x0f;
+            let questions = u16::from_be_bytes([message[4], message[5]]);
+            opcode <= 5 && questions > 0 && questions <= 16
+        };
+
+        let tcp_message = payload
+            .get(..2)
+            .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+            .and_then(|len| payload.get(2..2 + len));
+        if let Some(message) = tcp_message.filter(|message| plausible(message)) {
+            let message = DnsMessage::parse(message, false).ok()?;
+            return (!message.questions.is_empty()).then(|| ProbeResult::new(70, "length-prefixed DNS message"));
+        }
+        if !plausible(payload) {
+            return None;
+        }
+        let message = DnsMessage::parse(payload, false).ok()?;
+        (!message.questions.is_empty()).then(|| ProbeResult::new(70, "DNS header with valid questions"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::AlertChannel;
+    use pnet::packet::tcp::TcpFlags;
+    use super::super::dissector::testing::{self, flow};
+    use super::super::dissector::DissectorRegistry;
+
+    /// Response 0x1234 for `example.com A`, its answer name compressed
+    const RESPONSE: &[u8] = &[
+        0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, //
+        7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0x00, 0x01, 0x00, 0x01, //
+        0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 93, 184, 216, 34,
+    ];
+
+    #[test]
+    fn parses_a_response() {
+        let message = DnsMessage::parse(RESPONSE, false).unwrap();
+        assert!(message.is_response());
+        assert_eq!(message.questions[0].name, "example.com");
+        assert_eq!(message.answers[0].name, "example.com");
+        assert_eq!(message.answers[0].ttl, 3600);
+        assert_eq!(message.format_info(), "Standard query response 0x1234 A example.com A 93.184.216.34");
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        for len in 0..RESPONSE.len() {
+            assert!(DnsMessage::parse(&RESPONSE[..len], false).is_err(), "{} bytes", len);
         }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn rejects_pointer_loops() {
+        let message = [0xc0, 0x02, 0xc0, 0x00];
+        assert!(read_name(&message, 0).is_err());
+        assert!(read_name(&[0x40, 0x00], 0).is_err());
+        assert!(read_name(&[5, b'a', b'b'], 0).is_err());
+    }
+
+    #[test]
+    fn reads_type_bitmaps() {
+        // A, MX and RRSIG in window 0, CAA (257) in window 1
+        let bitmap = [0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x02, 0x01, 0x01, 0x40];
+        let types = read_type_bitmap(&mut ByteReader::new(&bitmap)).unwrap();
+        assert_eq!(types, [1, 15, 46, 257]);
+
+        // The last window with a bitmap longer than 32 bytes would overflow
+        let mut bitmap = vec![0xff, 33];
+        bitmap.extend([0xff; 33]);
+        assert!(read_type_bitmap(&mut ByteReader::new(&bitmap)).is_err());
+        assert!(read_type_bitmap(&mut ByteReader::new(&[0x00, 0x02, 0x40])).is_err());
+    }
+
+    #[test]
+    fn reassembles_messages_over_tcp() {
+        let dns = DnsProcessor::new();
+        let mut stream = (RESPONSE.len() as u16).to_be_bytes().to_vec();
+        stream.extend_from_slice(RESPONSE);
+        let now = Utc::now();
+
+        assert!(dns.process_segment(&flow(53), 1, &stream[..10], now).is_empty());
+        let messages = dns.process_segment(&flow(53), 11, &stream[10..], now);
+        assert_eq!(messages, [(RESPONSE.to_vec(), None)]);
+        let messages = dns.process_segment(&flow(53), 1 + stream.len() as u32, &stream, now);
+        assert_eq!(messages, [(RESPONSE.to_vec(), Some(2))]);
+    }
+
+    #[test]
+    fn forgets_connections_once_closed() {
+        let dns = DnsProcessor::new();
+        let registry = DissectorRegistry::new();
+        // A segment as the TCP dissector hands it on
+        let segment = |flow: FlowKey, seq: u32, flags: u8, data: &[u8]| {
+            let closed = testing::close_flow(&registry, &flow, flags, Utc::now());
+            let _ = testing::dissect_in(&registry, &AlertChannel::new(), &dns, data, |ctx| {
+                ctx.flow = Some(flow);
+                ctx.tcp_sequence = Some(seq);
+                ctx.tcp_flags = Some(flags);
+                ctx.flow_closed = closed;
+            });
+        };
+        segment(flow(53), 1, TcpFlags::ACK, &[0x00, 0x20, 0x12]);
+        segment(flow(53).reversed(), 1, TcpFlags::ACK, &[0x00, 0x20, 0x12]);
+
+        segment(flow(53), 4, TcpFlags::FIN | TcpFlags::ACK, &[]);
+        assert!(dns.streams.lock().unwrap().get(&flow(53)).is_some());
+        segment(flow(53).reversed(), 4, TcpFlags::FIN | TcpFlags::ACK, &[]);
+        assert!(dns.streams.lock().unwrap().get(&flow(53)).is_none());
+        assert!(dns.streams.lock().unwrap().get(&flow(53).reversed()).is_none());
+
+        segment(flow(53), 5, TcpFlags::ACK, &[0x00, 0x20, 0x12]);
+        segment(flow(53).reversed(), 5, TcpFlags::RST, &[]);
+        assert!(dns.streams.lock().unwrap().get(&flow(53)).is_none());
+    }
+}
@@ -0,0 +1,562 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use super::dns::{rcode_description, type_name, DnsMessage};
+use super::flow::FlowKey;
+
+/// Queries without a response after this long count as unanswered
+const TRANSACTION_TIMEOUT_SECS: i64 = 30;
+/// Distinct names counted at once; the least queried make room beyond it
+const MAX_NAMES: usize = 16384;
+/// Response times kept per server for the percentiles
+const LATENCY_SAMPLES: usize = 4096;
+
+/// A transaction is identified by the query ID, the client to server flow
+/// and the question
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct TransactionKey {
+    id: u16,
+    flow: FlowKey,
+    qname: String,
+    qtype: u16,
+}
+
+impl TransactionKey {
+    /// Key of the first question; `flow` runs from the client to the server
+    fn new(message: &DnsMessage, flow: FlowKey) -> Option<Self> {
+        let question = message.questions.first()?;
+        Some(Self {
+            id: message.id,
+            flow,
+            qname: question.name.to_ascii_lowercase(),
+            qtype: question.qtype,
+        })
+    }
+}
+
+struct PendingQuery {
+    frame: u64,
+    sent_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct ServerCounters {
+    queries: u64,
+    responses: u64,
+    unanswered: u64,
+    retransmissions: u64,
+    latencies: LatencySample,
+}
+
+/// Uniform sample of the response times of a server in milliseconds, every
+/// one of them until [`LATENCY_SAMPLES`] (reservoir sampling)
+#[derive(Default)]
+struct LatencySample {
+    values: Vec<f64>,
+    seen: u64,
+    /// xorshift state choosing the values replaced
+    random: u64,
+}
+
+impl LatencySample {
+    fn push(&mut self, value: f64) {
+        self.seen += 1;
+        if self.values.len() < LATENCY_SAMPLES {
+            self.values.push(value);
+            return;
+        }
+        if self.random == 0 {
+            self.random = 0x9e37_79b9_7f4a_7c15;
+        }
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        let slot = (self.random % self.seen) as usize;
+        if slot < LATENCY_SAMPLES {
+            self.values[slot] = value;
+        }
+    }
+}
+
+/// How a message relates to the transactions seen so far
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Transaction {
+    Query,
+    /// The same query was already sent in frame `request_in`
+    Retransmission { request_in: u64 },
+    /// Response to the query in frame `request_in`, `time` seconds later
+    Response { request_in: u64, time: f64 },
+    /// Response to no outstanding query
+    Unsolicited,
+    /// Response on a flow with an outstanding query for the same question
+    /// under another ID, or for the same ID with another question
+    IdMismatch { request_in: u64, expected_id: u16 },
+}
+
+/// Pairs DNS queries with their responses and accumulates resolver statistics
+#[derive(Default)]
+pub(crate) struct DnsTracker {
+    pending: HashMap<TransactionKey, PendingQuery>,
+    servers: HashMap<IpAddr, ServerCounters>,
+    rcodes: HashMap<u16, u64>,
+    names: HashMap<String, u64>,
+    qtypes: HashMap<u16, u64>,
+    unsolicited: u64,
+    id_mismatches: u64,
+    last_expiry: Option<DateTime<Utc>>,
+}
+
+impl DnsTracker {
+    /// Records a message; `flow` is the flow of the packet carrying it
+    pub fn track(&mut self, message: &DnsMessage, flow: FlowKey, frame: u64, at: DateTime<Utc>) -> Option<Transaction> {
+        self.expire(at);
+        if message.is_response() {
+            Some(self.response(message, flow.reversed(), at))
+        } else {
+            self.query(message, flow, frame, at)
+        }
+    }
+
+    fn query(&mut self, message: &DnsMessage, flow: FlowKey, frame: u64, at: DateTime<Utc>) -> Option<Transaction> {
+        let key = TransactionKey::new(message, flow)?;
+        let server = self.servers.entry(flow.dst).or_default();
+        server.queries += 1;
+
+        // The retransmission restarts the clock, so latency is per attempt
+        if let Some(pending) = self.pending.get_mut(&key) {
+            server.retransmissions += 1;
+            let request_in = pending.frame;
+            *pending = PendingQuery { frame, sent_at: at };
+            return Some(Transaction::Retransmission { request_in });
+        }
+
+        if self.names.len() >= MAX_NAMES && !self.names.contains_key(&key.qname) {
+            self.evict_least_queried();
+        }
+        *self.names.entry(key.qname.clone()).or_default() += 1;
+        *self.qtypes.entry(key.qtype).or_default() += 1;
+        self.pending.insert(key, PendingQuery { frame, sent_at: at });
+        Some(Transaction::Query)
+    }
+
+    /// Drops the least queried quarter of the names, so random names (e.g. a
+    /// water torture attack) do not grow the counts without bound
+    fn evict_least_queried(&mut self) {
+        let mut by_count: Vec<(u64, String)> = self.names.iter().map(|(name, count)| (*count, name.clone())).collect();
+        let count = (by_count.len() / 4).max(1);
+        by_count.select_nth_unstable_by_key(count - 1, |(count, _)| *count);
+        for (_, name) in by_count.into_iter().take(count) {
+            self.names.remove(&name);
+        }
+    }
+
+    fn response(&mut self, message: &DnsMessage, flow: FlowKey, at: DateTime<Utc>) -> Transaction {
+        *self.rcodes.entry(message.rcode()).or_default() += 1;
+        let server = self.servers.entry(flow.dst).or_default();
+        server.responses += 1;
+
+        if let Some(key) = TransactionKey::new(message, flow) {
+            if let Some(pending) = self.pending.remove(&key) {
+                let time = (at - pending.sent_at).num_microseconds().unwrap_or_default().max(0) as f64 / 1e6;
+                server.latencies.push(time * 1000.0);
+                return Transaction::Response { request_in: pending.frame, time };
+            }
+
+            let mismatch = self.pending.iter().find(|(pending, _)| {
+                pending.flow == key.flow
+                    && ((pending.id != key.id && pending.qname == key.qname && pending.qtype == key.qtype)
+                        || (pending.id == key.id && (pending.qname != key.qname || pending.qtype != key.qtype)))
+            });
+            if let Some((pending, query)) = mismatch {
+                self.id_mismatches += 1;
+                return Transaction::IdMismatch { request_in: query.frame, expected_id: pending.id };
+            }
+        }
+
+        self.unsolicited += 1;
+        Transaction::Unsolicited
+    }
+
+    /// Moves queries older than the timeout to the unanswered counters, at
+    /// most once per second of capture time
+    fn expire(&mut self, now: DateTime<Utc>) {
+        if self.last_expiry.is_some_and(|last| (now - last).num_seconds() < 1) {
+            return;
+        }
+        self.last_expiry = Some(now);
+        let servers = &mut self.servers;
+        self.pending.retain(|key, query| {
+            let expired = (now - query.sent_at).num_seconds() >= TRANSACTION_TIMEOUT_SECS;
+            if expired {
+                servers.entry(key.flow.dst).or_default().unanswered += 1;
+            }
+            !expired
+        });
+    }
+
+    /// Report with the `top` most frequent names and query types; queries
+    /// still outstanding count as unanswered
+    pub fn statistics(&self, top: usize) -> DnsStatistics {
+        let mut servers: Vec<ServerStatistics> = self
+            .servers
+            .iter()
+            .map(|(address, counters)| {
+                let outstanding = self.pending.keys().filter(|key| key.flow.dst == *address).count() as u64;
+                ServerStatistics {
+                    server: *address,
+                    queries: counters.queries,
+                    responses: counters.responses,
+                    unanswered: counters.unanswered + outstanding,
+                    retransmissions: counters.retransmissions,
+                    latency: LatencyPercentiles::new(&counters.latencies.values),
+                }
+            })
+            .collect();
+        servers.sort_by(|a, b| b.queries.cmp(&a.queries).then(a.server.cmp(&b.server)));
+
+        let responses: u64 = self.rcodes.values().sum();
+        let mut rcodes: Vec<RcodeCount> = self
+            .rcodes
+            .iter()
+            .map(|(rcode, count)| RcodeCount {
+                rcode: rcode_name(*rcode),
+                count: *count,
+                rate: *count as f64 / responses as f64,
+            })
+            .collect();
+        rcodes.sort_by(|a, b| b.count.cmp(&a.count).then(a.rcode.cmp(&b.rcode)));
+        let rate = |rcode: u16| self.rcodes.get(&rcode).map_or(0.0, |count| *count as f64 / responses as f64);
+
+        let qtypes: HashMap<String, u64> = self.qtypes.iter().map(|(qtype, count)| (type_name(*qtype), *count)).collect();
+
+        DnsStatistics {
+            queries: servers.iter().map(|s| s.queries).sum(),
+            responses,
+            unanswered: servers.iter().map(|s| s.unanswered).sum(),
+            retransmissions: servers.iter().map(|s| s.retransmissions).sum(),
+            unsolicited: self.unsolicited,
+            id_mismatches: self.id_mismatches,
+            nxdomain_rate: rate(3),
+            servfail_rate: rate(2),
+            servers,
+            rcodes,
+            top_names: top_counts(&self.names, top),
+            top_types: top_counts(&qtypes, top),
+        }
+    }
+}
+
+fn top_counts(counts: &HashMap<String, u64>, top: usize) -> Vec<NameCount> {
+    let mut counts: Vec<NameCount> = counts
+        .iter()
+        .map(|(name, count)| NameCount { name: name.clone(), count: *count })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then(a.name.cmp(&b.name)));
+    counts.truncate(top);
+    counts
+}
+
+/// Mnemonic of a response code, e.g. `NXDOMAIN`
+fn rcode_name(rcode: u16) -> String {
+    match rcode {
+        0 => "NOERROR".to_string(),
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        _ => rcode_description(rcode),
+    }
+}
+
+/// Response times in milliseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyPercentiles {
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl LatencyPercentiles {
+    fn new(latencies: &[f64]) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = latencies.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        // Nearest-rank percentile
+        let percentile = |p: f64| sorted[((p / 100.0 * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1];
+        Some(Self {
+            min: sorted[0],
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerStatistics {
+    pub server: IpAddr,
+    pub queries: u64,
+    pub responses: u64,
+    pub unanswered: u64,
+    pub retransmissions: u64,
+    pub latency: Option<LatencyPercentiles>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RcodeCount {
+    pub rcode: String,
+    pub count: u64,
+    /// Share of all responses, 0-1
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NameCount {
+    pub name: String,
+    pub count: u64,
+}
+
+/// Resolver performance report built from the DNS transactions of a capture
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsStatistics {
+    /// Query packets, retransmissions included
+    pub queries: u64,
+    pub responses: u64,
+    pub unanswered: u64,
+    pub retransmissions: u64,
+    pub unsolicited: u64,
+    pub id_mismatches: u64,
+    pub nxdomain_rate: f64,
+    pub servfail_rate: f64,
+    pub servers: Vec<ServerStatistics>,
+    pub rcodes: Vec<RcodeCount>,
+    /// Most queried names, retransmissions not counted; with too many distinct
+    /// names the rarely queried ones are dropped
+    pub top_names: Vec<NameCount>,
+    pub top_types: Vec<NameCount>,
+}
+
+impl fmt::Display for DnsStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "DNS transactions")?;
+        writeln!(
+            f,
+            "  queries: {}  responses: {}  unanswered: {}  retransmissions: {}  unsolicited: {}  id mismatches: {}",
+            self.queries, self.responses, self.unanswered, self.retransmissions, self.unsolicited, self.id_mismatches
+        )?;
+        writeln!(
+            f,
+            "  NXDOMAIN rate: {:.1}%  SERVFAIL rate: {:.1}%",
+            self.nxdomain_rate * 100.0,
+            self.servfail_rate * 100.0
+        )?;
+
+        writeln!(f, "\nServers (response time in ms)")?;
+        writeln!(
+            f,
+            "  {:<39} {:>8} {:>9} {:>10} {:>7} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "server", "queries", "responses", "unanswered", "retrans", "min", "p50", "p90", "p99", "max"
+        )?;
+        for server in &self.servers {
+            let latency = match &server.latency {
+                Some(l) => format!("{:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>9.2}", l.min, l.p50, l.p90, l.p99, l.max),
+                None => format!("{:>9} {:>9} {:>9} {:>9} {:>9}", "-", "-", "-", "-", "-"),
+            };
+            writeln!(
+                f,
+                "  {:<39} {:>8} {:>9} {:>10} {:>7} {}",
+                server.server.to_string(),
+                server.queries,
+                server.responses,
+                server.unanswered,
+                server.retransmissions,
+                latency
+            )?;
+        }
+
+        writeln!(f, "\nResponse codes")?;
+        for rcode in &self.rcodes {
+            writeln!(f, "  {:<12} {:>8} {:>6.1}%", rcode.rcode, rcode.count, rcode.rate * 100.0)?;
+        }
+
+        writeln!(f, "\nTop queried names")?;
+        for name in &self.top_names {
+            writeln!(f, "  {:>8}  {}", name.count, name.name)?;
+        }
+
+        writeln!(f, "\nTop query types")?;
+        for qtype in &self.top_types {
+            writeln!(f, "  {:>8}  {}", qtype.count, qtype.name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use super::super::dissector::testing;
+
+    fn at(milliseconds: i64) -> DateTime<Utc> {
+        testing::at(0) + Duration::milliseconds(milliseconds)
+    }
+
+    /// Query, or response with `rcode`, for `name` with no records
+    fn message(id: u16, rcode: Option<u16>, name: &str, qtype: u16) -> DnsMessage {
+        let flags = rcode.map_or(0x0100, |rcode| 0x8180 | rcode);
+        let mut data = vec![(id >> 8) as u8, id as u8, (flags >> 8) as u8, flags as u8, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            data.push(label.len() as u8);
+            data.extend(label.as_bytes());
+        }
+        data.extend([0, (qtype >> 8) as u8, qtype as u8, 0, 1]);
+        DnsMessage::parse(&data, false).unwrap()
+    }
+
+    fn query(id: u16, name: &str) -> DnsMessage {
+        message(id, None, name, 1)
+    }
+
+    fn response(id: u16, name: &str) -> DnsMessage {
+        message(id, Some(0), name, 1)
+    }
+
+    fn flow(server: u8) -> FlowKey {
+        FlowKey::new("10.0.0.1".parse().unwrap(), 50000, IpAddr::from([10, 0, 0, server]), 53)
+    }
+
+    #[test]
+    fn measures_latency_percentiles() {
+        let mut tracker = DnsTracker::default();
+        for index in 1..=10 {
+            let name = format!("host{}.example", index);
+            tracker.track(&query(index, &name), flow(53), index as u64, at(0));
+            let reply = response(index, &name);
+            let transaction = tracker.track(&reply, flow(53).reversed(), 100 + index as u64, at(index as i64 * 10));
+            let Some(Transaction::Response { request_in, time }) = transaction else {
+                panic!("{:?}", transaction);
+            };
+            assert_eq!(request_in, index as u64);
+            assert!((time - index as f64 / 100.0).abs() < 1e-9, "{}", time);
+        }
+
+        let statistics = tracker.statistics(10);
+        let latency = statistics.servers[0].latency.as_ref().unwrap();
+        let percentiles = [latency.min, latency.p50, latency.p90, latency.p99, latency.max];
+        for (percentile, expected) in percentiles.into_iter().zip([10.0, 50.0, 90.0, 100.0, 100.0]) {
+            assert!((percentile - expected).abs() < 1e-9, "{:?}", latency);
+        }
+        assert_eq!((statistics.queries, statistics.responses, statistics.unanswered), (10, 10, 0));
+    }
+
+    #[test]
+    fn counts_unanswered_queries_after_the_timeout() {
+        let mut tracker = DnsTracker::default();
+        tracker.track(&query(1, "lost.example"), flow(53), 1, at(0));
+        tracker.track(&query(2, "slow.example"), flow(53), 2, at(29_000));
+        tracker.track(&query(3, "pending.example"), flow(53), 3, at(30_000));
+        let transaction = tracker.track(&response(2, "slow.example"), flow(53).reversed(), 4, at(30_000));
+        assert!(matches!(transaction, Some(Transaction::Response { request_in: 2, .. })));
+
+        // Too late to pair with its query
+        let transaction = tracker.track(&response(1, "lost.example"), flow(53).reversed(), 5, at(31_000));
+        assert!(matches!(transaction, Some(Transaction::Unsolicited)));
+
+        let statistics = tracker.statistics(10);
+        // One timed out, one still outstanding
+        assert_eq!(statistics.servers[0].unanswered, 2);
+        assert_eq!((statistics.unanswered, statistics.unsolicited), (2, 1));
+    }
+
+    #[test]
+    fn classifies_retransmissions_and_mismatches() {
+        let mut tracker = DnsTracker::default();
+        assert!(matches!(tracker.track(&query(1, "a.example"), flow(53), 1, at(0)), Some(Transaction::Query)));
+        let transaction = tracker.track(&query(1, "A.example"), flow(53), 2, at(1000));
+        assert!(matches!(transaction, Some(Transaction::Retransmission { request_in: 1 })));
+        // Latency runs from the retransmission
+        let transaction = tracker.track(&response(1, "a.example"), flow(53).reversed(), 3, at(1050));
+        let Some(Transaction::Response { request_in: 2, time }) = transaction else {
+            panic!("{:?}", transaction);
+        };
+        assert!((time - 0.05).abs() < 1e-9, "{}", time);
+
+        tracker.track(&query(2, "b.example"), flow(53), 4, at(2000));
+        let transaction = tracker.track(&response(3, "b.example"), flow(53).reversed(), 5, at(2010));
+        assert!(matches!(transaction, Some(Transaction::IdMismatch { request_in: 4, expected_id: 2 })));
+        let transaction = tracker.track(&response(2, "c.example"), flow(53).reversed(), 6, at(2020));
+        assert!(matches!(transaction, Some(Transaction::IdMismatch { request_in: 4, expected_id: 2 })));
+        // Same question, but on another flow
+        let transaction = tracker.track(&response(3, "b.example"), flow(54).reversed(), 7, at(2030));
+        assert!(matches!(transaction, Some(Transaction::Unsolicited)));
+
+        let statistics = tracker.statistics(10);
+        assert_eq!(
+            (statistics.queries, statistics.retransmissions, statistics.id_mismatches, statistics.unsolicited),
+            (3, 1, 2, 1)
+        );
+    }
+
+    #[test]
+    fn orders_top_names_and_codes() {
+        let mut tracker = DnsTracker::default();
+        let queries = [
+            (53, "a.example", 1, 3),
+            (53, "B.example", 28, 0),
+            (53, "b.example", 1, 0),
+            (53, "c.example", 1, 3),
+            (53, "a.example", 28, 0),
+            (54, "a.example", 1, 0),
+            (54, "d.example", 1, 2),
+            (53, "c.example", 28, 0),
+        ];
+        for (index, (server, name, qtype, rcode)) in queries.into_iter().enumerate() {
+            let id = index as u16;
+            tracker.track(&message(id, None, name, qtype), flow(server), index as u64, at(0));
+            tracker.track(&message(id, Some(rcode), name, qtype), flow(server).reversed(), index as u64, at(10));
+        }
+
+        let statistics = tracker.statistics(3);
+        let counts = |counts: &[NameCount]| counts.iter().map(|c| (c.name.clone(), c.count)).collect::<Vec<_>>();
+        assert_eq!(
+            counts(&statistics.top_names),
+            [("a.example".to_string(), 3), ("b.example".to_string(), 2), ("c.example".to_string(), 2)]
+        );
+        assert_eq!(counts(&statistics.top_types), [("A".to_string(), 5), ("AAAA".to_string(), 3)]);
+        let rcodes: Vec<(&str, u64)> = statistics.rcodes.iter().map(|r| (r.rcode.as_str(), r.count)).collect();
+        assert_eq!(rcodes, [("NOERROR", 5), ("NXDOMAIN", 2), ("SERVFAIL", 1)]);
+        assert_eq!((statistics.nxdomain_rate, statistics.servfail_rate), (0.25, 0.125));
+        let servers: Vec<(IpAddr, u64)> = statistics.servers.iter().map(|s| (s.server, s.queries)).collect();
+        assert_eq!(servers, [(IpAddr::from([10, 0, 0, 53]), 6), (IpAddr::from([10, 0, 0, 54]), 2)]);
+    }
+
+    #[test]
+    fn bounds_names_and_latencies() {
+        let mut tracker = DnsTracker::default();
+        for _ in 0..3 {
+            tracker.track(&query(0, "popular.example"), flow(53), 0, at(0));
+            tracker.track(&response(0, "popular.example"), flow(53).reversed(), 0, at(5));
+        }
+        for index in 0..2 * MAX_NAMES {
+            let name = format!("{}.example", index);
+            tracker.track(&query(index as u16, &name), flow(53), index as u64, at(0));
+            tracker.track(&response(index as u16, &name), flow(53).reversed(), index as u64, at(10));
+        }
+        assert!(tracker.names.len() <= MAX_NAMES);
+        assert_eq!(tracker.names.get("popular.example"), Some(&3));
+
+        let latencies = &tracker.servers[&IpAddr::from([10, 0, 0, 53])].latencies;
+        assert_eq!((latencies.values.len(), latencies.seen), (LATENCY_SAMPLES, 2 * MAX_NAMES as u64 + 3));
+        let latency = tracker.statistics(1).servers[0].latency.clone().unwrap();
+        assert_eq!((latency.p50, latency.max), (10.0, 10.0));
+    }
+}
@@ -1,4 +1,9 @@
+use anyhow::Result;
 use pnet::packet::ethernet::EthernetPacket;
+use pnet::packet::Packet;
+use super::dissector::{Dissection, DissectContext, Dissector, DissectorTable};
+use super::format_mac;
+use super::tree::FieldValue;
 
 pub struct EthernetProcessor;
 
@@ -10,4 +15,25 @@ impl EthernetProcessor {
     pub fn process<'a>(&self, data: &'a [u8]) -> Option<EthernetPacket<'a>> {
         EthernetPacket::new(data)
     }
-} 
\ No newline at end of file
+}
+
+impl Dissector for EthernetProcessor {
+    fn name(&self) -> &'static str {
+        "Ethernet"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let ethernet = self.process(data).ok_or_else(|| anyhow::anyhow!("Frame too short for Ethernet"))?;
+        ctx.add_field_at("eth.dst", 0, 6, ethernet.get_destination());
+        ctx.add_field_at("eth.src", 6, 6, ethernet.get_source());
+        ctx.add_field_at("eth.type", 12, 2, FieldValue::Hex(ethernet.get_ethertype().0 as u64));
+        ctx.source = format_mac(&ethernet.get_source().octets());
+        ctx.destination = format_mac(&ethernet.get_destination().octets());
+
+        Ok(ctx
+            .dissect_next(DissectorTable::Ethertype(ethernet.get_ethertype().0), ethernet.payload())
+            .unwrap_or_else(|| {
+                Dissection::new("Unknown", format!("Unknown ethertype: {}", ethernet.get_ethertype()))
+            }))
+    }
+}
@@ -0,0 +1,245 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::net::IpAddr;
+
+/// Idle time after which the state kept for a flow is dropped
+pub(crate) const FLOW_IDLE_TIMEOUT_SECS: i64 = 300;
+/// Flows a table keeps at once; the least recently seen make room beyond it
+pub(crate) const MAX_FLOWS: usize = 16384;
+
+/// Transport-level conversation key for one direction of a flow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub src: IpAddr,
+    pub src_port: u16,
+    pub dst: IpAddr,
+    pub dst_port: u16,
+}
+
+impl FlowKey {
+    pub fn new(src: IpAddr, src_port: u16, dst: IpAddr, dst_port: u16) -> Self {
+        Self { src, src_port, dst, dst_port }
+    }
+
+    /// Key of the opposite direction
+    pub fn reversed(&self) -> Self {
+        Self {
+            src: self.dst,
+            src_port: self.dst_port,
+            dst: self.src,
+            dst_port: self.src_port,
+        }
+    }
+
+    /// Direction-independent key, identical for both directions of a conversation
+    pub fn canonical(&self) -> Self {
+        if (self.src, self.src_port) <= (self.dst, self.dst_port) {
+            *self
+        } else {
+            self.reversed()
+        }
+    }
+}
+
+impl fmt::Display for FlowKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{} > {}:{}", self.src, self.src_port, self.dst, self.dst_port)
+    }
+}
+
+/// Per-flow state that is dropped once the flow has been idle for a while or
+/// the table is full. Time is the capture time of the packets.
+pub(crate) struct FlowTable<K, V> {
+    entries: HashMap<K, (DateTime<Utc>, V)>,
+    idle_timeout: Duration,
+    capacity: usize,
+    swept_at: Option<DateTime<Utc>>,
+}
+
+impl<K: Eq + Hash + Clone, V> FlowTable<K, V> {
+    pub fn new() -> Self {
+        Self::with_limits(Duration::seconds(FLOW_IDLE_TIMEOUT_SECS), MAX_FLOWS)
+    }
+
+    pub fn with_limits(idle_timeout: Duration, capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            idle_timeout,
+            capacity: capacity.max(1),
+            swept_at: None,
+        }
+    }
+
+    /// The state of `key`, created by `default` if missing, marked as seen at `now`
+    pub fn entry(&mut self, key: K, now: DateTime<Utc>, default: impl FnOnce() -> V) -> &mut V {
+        self.entry_with(key, now, default, |_, _| {})
+    }
+
+    /// [`entry`](Self::entry) handing the entries dropped for being idle or
+    /// to make room to `dropped`, for state that outlives its flow
+    pub fn entry_with(
+        &mut self,
+        key: K,
+        now: DateTime<Utc>,
+        default: impl FnOnce() -> V,
+        mut dropped: impl FnMut(K, V),
+    ) -> &mut V {
+        self.make_room(&key, now, &mut dropped);
+        let entry = self.entries.entry(key).or_insert_with(|| (now, default()));
+        entry.0 = entry.0.max(now);
+        &mut entry.1
+    }
+
+    pub fn insert(&mut self, key: K, now: DateTime<Utc>, value: V) {
+        self.make_room(&key, now, &mut |_, _| {});
+        self.entries.insert(key, (now, value));
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.entries.get_mut(key).map(|(_, value)| value)
+    }
+
+    /// The state of `key` if present, marked as seen at `now`
+    pub fn seen(&mut self, key: &K, now: DateTime<Utc>) -> Option<&mut V> {
+        self.entries.get_mut(key).map(|(seen, value)| {
+            *seen = (*seen).max(now);
+            value
+        })
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.entries.remove(key).map(|(_, value)| value)
+    }
+
+    /// Removes the state of `key`, which only counts if it was seen within the timeout
+    pub fn take(&mut self, key: &K, now: DateTime<Utc>) -> Option<V> {
+        let (seen, value) = self.entries.remove(key)?;
+        (now - seen <= self.idle_timeout).then_some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, (_, value))| (key, value))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.values().map(|(_, value)| value)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &V) -> bool) {
+        self.entries.retain(|key, (_, value)| keep(key, value));
+    }
+
+    fn make_room(&mut self, key: &K, now: DateTime<Utc>, dropped: &mut impl FnMut(K, V)) {
+        self.expire(now, dropped);
+        if !self.entries.contains_key(key) && self.entries.len() >= self.capacity {
+            self.evict_oldest(dropped);
+        }
+    }
+
+    /// Drops the entries idle for longer than the timeout; runs at most once
+    /// per timeout, so an entry lives at most twice as long
+    fn expire(&mut self, now: DateTime<Utc>, dropped: &mut impl FnMut(K, V)) {
+        if self.swept_at.is_some_and(|swept_at| now - swept_at < self.idle_timeout) {
+            return;
+        }
+        self.swept_at = Some(now);
+        let timeout = self.idle_timeout;
+        let idle: Vec<K> = self
+            .entries
+            .iter()
+            .filter(|(_, (seen, _))| now - *seen > timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in idle {
+            if let Some((_, value)) = self.entries.remove(&key) {
+                dropped(key, value);
+            }
+        }
+    }
+
+    /// Drops the least recently seen quarter of a full table
+    fn evict_oldest(&mut self, dropped: &mut impl FnMut(K, V)) {
+        let mut by_age: Vec<(DateTime<Utc>, K)> =
+            self.entries.iter().map(|(key, (seen, _))| (*seen, key.clone())).collect();
+        let count = (by_age.len() / 4).max(1);
+        by_age.select_nth_unstable_by_key(count - 1, |(seen, _)| *seen);
+        for (_, key) in by_age.into_iter().take(count) {
+            if let Some((_, value)) = self.entries.remove(&key) {
+                dropped(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissector::testing;
+
+    #[test]
+    fn canonical_key_is_the_same_both_ways() {
+        let flow = FlowKey::new("10.0.0.2".parse().unwrap(), 443, "10.0.0.1".parse().unwrap(), 50000);
+        assert_eq!(flow.canonical(), flow.reversed().canonical());
+        assert_eq!(flow.reversed().reversed(), flow);
+    }
+
+    #[test]
+    fn expires_idle_entries() {
+        let mut table = FlowTable::with_limits(Duration::seconds(10), 100);
+        table.insert(1, testing::at(0), "idle");
+        table.insert(2, testing::at(0), "active");
+        *table.entry(2, testing::at(8), || "new") = "touched";
+        // The sweep runs once per timeout
+        table.insert(3, testing::at(9), "late");
+        assert!(table.get(&1).is_some());
+        table.insert(4, testing::at(15), "next");
+        assert_eq!(table.get(&1), None);
+        assert_eq!(table.get(&2), Some(&"touched"));
+        assert_eq!(table.get(&3), Some(&"late"));
+        assert_eq!(table.take(&3, testing::at(30)), None);
+        assert_eq!(table.take(&4, testing::at(20)), Some("next"));
+    }
+
+    #[test]
+    fn makes_room_by_dropping_the_oldest() {
+        let mut table = FlowTable::with_limits(Duration::seconds(1000), 8);
+        for key in 0..8 {
+            table.insert(key, testing::at(key), key);
+        }
+        table.entry(0, testing::at(20), || 0);
+        table.insert(8, testing::at(21), 8);
+        // A quarter of the table goes, least recently seen first
+        assert_eq!(table.get(&1), None);
+        assert_eq!(table.get(&2), None);
+        assert_eq!(table.get(&0), Some(&0));
+        assert_eq!(table.get(&3), Some(&3));
+        assert_eq!(table.get(&8), Some(&8));
+        assert_eq!(table.remove(&8), Some(8));
+        assert_eq!(table.get(&8), None);
+    }
+
+    #[test]
+    fn hands_over_dropped_entries() {
+        let mut table = FlowTable::with_limits(Duration::seconds(10), 4);
+        let mut dropped = Vec::new();
+        for key in 0..4 {
+            table.entry_with(key, testing::at(0), || key, |key, _| dropped.push(key));
+        }
+        table.entry_with(4, testing::at(1), || 4, |key, _| dropped.push(key));
+        assert_eq!(dropped.len(), 1);
+        table.entry_with(5, testing::at(20), || 5, |key, _| dropped.push(key));
+        dropped.sort();
+        assert_eq!(dropped, [0, 1, 2, 3, 4]);
+        assert_eq!(table.len(), 1);
+    }
+}
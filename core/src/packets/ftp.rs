@@ -1,55 +1,850 @@
 use anyhow::Result;
-use std::str;
+use chrono::{DateTime, Duration, Utc};
+use pnet::packet::tcp::TcpFlags;
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+use crate::output::safe_file_name;
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector, Expectation, Transport, EXPECTATION_TIMEOUT_SECS};
+use super::flow::{FlowKey, FlowTable, MAX_FLOWS};
+use super::reassembly::StreamBuffer;
 
-pub struct FtpProcessor;
+/// Longest control line kept while waiting for its end
+const MAX_LINE: usize = 8192;
+/// Commands whose data connection carries a file
+const FILE_COMMANDS: &[&str] = &["RETR", "STOR", "STOU", "APPE"];
+/// Commands that use a data connection
+const DATA_COMMANDS: &[&str] = &["RETR", "STOR", "STOU", "APPE", "LIST", "NLST", "MLSD"];
+/// Bytes kept of one transferred file
+const MAX_FILE_SIZE: usize = 256 * 1024 * 1024;
+/// Bytes kept of all the transferred files
+const MAX_STORED_BYTES: usize = 512 * 1024 * 1024;
+
+/// A command or a (possibly multi-line) reply of the control connection
+#[derive(Debug, Clone)]
+pub enum FtpMessage {
+    Request {
+        command: String,
+        arg: Option<String>,
+    },
+    Response {
+        code: u16,
+        /// Text of every line, without the code
+        lines: Vec<String>,
+    },
+}
+
+impl FtpMessage {
+    pub fn format_info(&self) -> String {
+        match self {
+            FtpMessage::Request { command, arg: Some(arg) } => format!("Request: {} {}", command, arg),
+            FtpMessage::Request { command, arg: None } => format!("Request: {}", command),
+            FtpMessage::Response { code, lines } => {
+                format!("Response: {} {}", code, lines.first().map(String::as_str).unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// A file carried by an FTP data connection
+#[derive(Debug, Clone, Serialize)]
+pub struct FtpTransfer {
+    /// RETR, STOR, STOU or APPE
+    pub command: String,
+    /// Path given to the command
+    pub name: String,
+    pub client: IpAddr,
+    pub server: IpAddr,
+    /// Bytes received on the data connection, those past the storage
+    /// limits included
+    pub size: usize,
+    /// Size announced by the server, from a SIZE or 150 reply
+    pub expected_size: Option<u64>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// The data connection was closed without missing segments and all
+    /// its bytes were kept
+    pub complete: bool,
+    /// Final reply of the server, e.g. `226 Transfer complete.`
+    pub status: Option<String>,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl FtpTransfer {
+    pub fn is_upload(&self) -> bool {
+        self.command != "RETR"
+    }
+
+    /// Transfer time in seconds
+    pub fn duration(&self) -> Option<f64> {
+        self.finished_at
+            .map(|finished| (finished - self.started_at).num_microseconds().unwrap_or_default() as f64 / 1e6)
+    }
+
+    /// Last component of the path with characters unsafe in file names replaced
+    pub fn file_name(&self) -> String {
+        let base = self.name.rsplit(['/', '\\']).next().unwrap_or_default();
+        safe_file_name(base, "ftp-object")
+    }
+
+    pub fn format_info(&self) -> String {
+        let duration = self.duration().map(|d| format!("{:.3} s", d)).unwrap_or_else(|| "-".to_string());
+        let mut info = format!(
+            "{} {} {} {} {} bytes in {}",
+            self.command,
+            self.name,
+            if self.is_upload() { "to" } else { "from" },
+            self.server,
+            self.size,
+            duration
+        );
+        if let Some(expected) = self.expected_size.filter(|&expected| expected != self.size as u64) {
+            info.push_str(&format!(" (expected {})", expected));
+        }
+        if !self.complete {
+            info.push_str(" [incomplete]");
+        }
+        info
+    }
+}
+
+/// One direction of a control connection, split into lines
+#[derive(Default)]
+struct ControlStream {
+    buffer: StreamBuffer,
+    /// Multi-line reply being read, with its offset in the current segment
+    reply: Option<(u16, Vec<String>, Option<usize>)>,
+}
+
+impl ControlStream {
+    /// Appends a segment and returns the messages it completes with their
+    /// offset in the segment when they start in it
+    fn push(&mut self, seq: u32, payload: &[u8]) -> Vec<(FtpMessage, Option<usize>)> {
+        let before = self.buffer.data().len();
+        // Start of the segment in the buffer and the retransmitted bytes dropped from it
+        let (start, skipped) = if self.buffer.push(seq, payload) {
+            let appended = self.buffer.data().len() - before;
+            (before, payload.len() - appended)
+        } else {
+            self.reply = None;
+            (0, 0)
+        };
+        // A reply started in an earlier segment has no offset in this one
+        if let Some((_, _, offset)) = &mut self.reply {
+            *offset = None;
+        }
+
+        let mut messages = Vec::new();
+        let mut consumed = 0;
+        while let Some(end) = self.buffer.data()[consumed..].iter().position(|&b| b == b'\n') {
+            let line_start = consumed;
+            let raw = &self.buffer.data()[line_start..line_start + end];
+            let line = String::from_utf8_lossy(raw.strip_suffix(b"\r").unwrap_or(raw)).to_string();
+            consumed += end + 1;
+            let offset = (line_start >= start).then(|| line_start - start + skipped);
+            if let Some(message) = self.read_line(line, offset) {
+                messages.push(message);
+            }
+        }
+        self.buffer.consume(consumed);
+        if self.buffer.data().len() > MAX_LINE {
+            self.buffer.clear();
+        }
+        messages
+    }
+
+    fn read_line(&mut self, line: String, offset: Option<usize>) -> Option<(FtpMessage, Option<usize>)> {
+        let code = line
+            .get(..3)
+            .filter(|code| code.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|code| code.parse::<u16>().ok());
+        let separator = line.as_bytes().get(3).copied();
+
+        if let Some((reply_code, lines, _)) = &mut self.reply {
+            // Only `code SP` ends a multi-line reply
+            if code == Some(*reply_code) && separator != Some(b'-') {
+                lines.push(line.get(4..).unwrap_or_default().to_string());
+                let (code, lines, offset) = self.reply.take()?;
+                return Some((FtpMessage::Response { code, lines }, offset));
+            }
+            lines.push(line);
+            return None;
+        }
+
+        match (code, separator) {
+            (Some(code), Some(b'-')) => {
+                self.reply = Some((code, vec![line[4..].to_string()], offset));
+                None
+            }
+            (Some(code), Some(b' ') | None) => {
+                let lines = vec![line.get(4..).unwrap_or_default().to_string()];
+                Some((FtpMessage::Response { code, lines }, offset))
+            }
+            _ => {
+                let line = line.trim();
+                if line.is_empty() {
+                    return None;
+                }
+                let (command, arg) = match line.split_once(' ') {
+                    Some((command, arg)) => (command, Some(arg.trim().to_string())),
+                    None => (line, None),
+                };
+                let command = command.to_ascii_uppercase();
+                Some((FtpMessage::Request { command, arg }, offset))
+            }
+        }
+    }
+}
+
+/// Data connection announced by PORT, PASV, EPRT or EPSV
+#[derive(Clone)]
+struct DataSetup {
+    /// Canonical key of the control connection
+    control: FlowKey,
+    method: &'static str,
+    frame: u64,
+}
+
+#[derive(Debug, Clone)]
+struct PendingCommand {
+    command: String,
+    arg: String,
+}
+
+/// State of one control connection
+struct Session {
+    client: IpAddr,
+    server: IpAddr,
+    last_command: String,
+    /// Data connection opened for the last PORT/PASV
+    channel: Option<FlowKey>,
+    /// Transfer command waiting for its data connection
+    pending: Option<PendingCommand>,
+    /// Transfer waiting for its final reply
+    awaiting_status: Option<usize>,
+    expected_size: Option<u64>,
+}
+
+struct DataChannel {
+    setup: DataSetup,
+    /// e.g. `RETR file.txt`
+    command: Option<String>,
+    transfer: Option<usize>,
+    /// Segments were lost
+    gap: bool,
+}
+
+struct FtpState {
+    streams: FlowTable<FlowKey, ControlStream>,
+    /// Control connections by canonical key
+    sessions: FlowTable<FlowKey, Session>,
+    /// Announced data connections by endpoint, until they are opened
+    expected: FlowTable<(IpAddr, u16), DataSetup>,
+    /// Data connections by canonical key
+    channels: FlowTable<FlowKey, DataChannel>,
+    data_streams: FlowTable<FlowKey, StreamBuffer>,
+    transfers: Vec<FtpTransfer>,
+    /// Bytes of file data kept
+    stored: usize,
+}
+
+impl Default for FtpState {
+    fn default() -> Self {
+        Self {
+            streams: FlowTable::new(),
+            sessions: FlowTable::new(),
+            expected: FlowTable::with_limits(Duration::seconds(EXPECTATION_TIMEOUT_SECS), MAX_FLOWS),
+            channels: FlowTable::new(),
+            data_streams: FlowTable::new(),
+            transfers: Vec::new(),
+            stored: 0,
+        }
+    }
+}
+
+impl FtpState {
+    /// Pairs the pending transfer command of a session with its data connection
+    fn bind(&mut self, control: &FlowKey, at: DateTime<Utc>) {
+        let Some(session) = self.sessions.get_mut(control) else {
+            return;
+        };
+        let Some(channel) = session.channel.and_then(|key| self.channels.get_mut(&key)) else {
+            return;
+        };
+        if channel.command.is_some() {
+            return;
+        }
+        let Some(pending) = session.pending.take() else {
+            return;
+        };
+
+        channel.command = Some(format!("{} {}", pending.command, pending.arg).trim_end().to_string());
+        if FILE_COMMANDS.contains(&pending.command.as_str()) {
+            self.transfers.push(FtpTransfer {
+                command: pending.command,
+                name: pending.arg,
+                client: session.client,
+                server: session.server,
+                size: 0,
+                expected_size: session.expected_size.take(),
+                started_at: at,
+                finished_at: None,
+                complete: false,
+                status: None,
+                data: Vec::new(),
+            });
+            channel.transfer = Some(self.transfers.len() - 1);
+            session.awaiting_status = channel.transfer;
+        }
+    }
+
+    /// Data connection `flow` belongs to, registering it on its first packet
+    fn channel(&mut self, flow: &FlowKey, at: DateTime<Utc>) -> Option<FlowKey> {
+        let key = flow.canonical();
+        if let Some(channel) = self.channels.seen(&key, at) {
+            // A transfer keeps its idle control connection alive
+            let control = channel.setup.control;
+            self.sessions.seen(&control, at);
+            return Some(key);
+        }
+        let setup = self
+            .expected
+            .take(&(flow.dst, flow.dst_port), at)
+            .or_else(|| self.expected.take(&(flow.src, flow.src_port), at))?;
+        let control = setup.control;
+        self.channels.insert(key, at, DataChannel { setup, command: None, transfer: None, gap: false });
+        if let Some(session) = self.sessions.seen(&control, at) {
+            session.channel = Some(key);
+        }
+        self.bind(&control, at);
+        Some(key)
+    }
+
+    /// Moves the bytes received on the data connection to its transfer; those
+    /// past the storage limits are only counted
+    fn flush(&mut self, channel: &FlowKey, flow: &FlowKey) {
+        let Some(index) = self.channels.get(channel).and_then(|channel| channel.transfer) else {
+            return;
+        };
+        if let Some(stream) = self.data_streams.get_mut(flow) {
+            let transfer = &mut self.transfers[index];
+            let data = stream.data();
+            let room = (MAX_FILE_SIZE - transfer.data.len()).min(MAX_STORED_BYTES - self.stored);
+            let kept = data.len().min(room);
+            transfer.data.extend_from_slice(&data[..kept]);
+            transfer.size += data.len();
+            self.stored += kept;
+            stream.clear();
+        }
+    }
+
+    fn close(&mut self, channel: &FlowKey, flow: &FlowKey, at: DateTime<Utc>) {
+        self.flush(channel, flow);
+        self.flush(channel, &flow.reversed());
+        self.data_streams.remove(flow);
+        self.data_streams.remove(&flow.reversed());
+        let Some(closed) = self.channels.remove(channel) else {
+            return;
+        };
+        if let Some(index) = closed.transfer {
+            let transfer = &mut self.transfers[index];
+            transfer.finished_at = Some(at);
+            transfer.complete = !closed.gap && transfer.data.len() == transfer.size;
+        }
+        if let Some(session) = self.sessions.get_mut(&closed.setup.control) {
+            if session.channel == Some(*channel) {
+                session.channel = None;
+            }
+        }
+    }
+
+    /// Drops a closed control connection with the data connections it
+    /// announced; those still open end with it
+    fn end_session(&mut self, control: &FlowKey, at: DateTime<Utc>) {
+        self.sessions.remove(control);
+        self.streams.remove(control);
+        self.streams.remove(&control.reversed());
+        self.expected.retain(|_, setup| setup.control != *control);
+        let channels: Vec<FlowKey> = self
+            .channels
+            .iter()
+            .filter(|(_, channel)| channel.setup.control == *control)
+            .map(|(key, _)| *key)
+            .collect();
+        for channel in channels {
+            self.close(&channel, &channel, at);
+        }
+    }
+}
+
+/// FTP control connection: commands and replies, line by line, and the data
+/// connections they announce
+pub struct FtpProcessor {
+    state: Arc<Mutex<FtpState>>,
+}
 
 impl FtpProcessor {
     pub fn new() -> Self {
-        Self
-    }
-
-    pub fn process(&self, payload: &[u8]) -> Result<FtpPacket> {
-        // Try to parse the payload as UTF-8 string
-        if let Ok(text) = str::from_utf8(payload) {
-            // Check if it's a command (starts with a command code)
-            if let Some((code, message)) = text.split_once(' ') {
-                if code.chars().all(|c| c.is_ascii_digit()) {
-                    return Ok(FtpPacket::Response {
-                        code: code.to_string(),
-                        message: message.trim().to_string(),
-                    });
+        Self {
+            state: Arc::new(Mutex::new(FtpState::default())),
+        }
+    }
+
+    /// Dissector of the data connections, sharing the state of the control connections
+    pub fn data_dissector(&self) -> FtpDataProcessor {
+        FtpDataProcessor {
+            state: self.state.clone(),
+        }
+    }
+
+    /// Files transferred so far, including those still in progress
+    pub fn transfers(&self) -> Vec<FtpTransfer> {
+        self.state.lock().unwrap().transfers.clone()
+    }
+
+    fn handle(&self, state: &mut FtpState, message: &FtpMessage, flow: &FlowKey, ctx: &mut DissectContext) {
+        let control = flow.canonical();
+        let session = state.sessions.entry(control, ctx.captured_at, || Session {
+            client: flow.src,
+            server: flow.dst,
+            last_command: String::new(),
+            channel: None,
+            pending: None,
+            awaiting_status: None,
+            expected_size: None,
+        });
+
+        match message {
+            FtpMessage::Request { command, arg } => {
+                session.client = flow.src;
+                session.server = flow.dst;
+                session.last_command = command.clone();
+                let arg = arg.clone().unwrap_or_default();
+                match command.as_str() {
+                    "PORT" | "EPRT" => {
+                        let endpoint = if command == "PORT" { parse_port(&arg) } else { parse_eprt(&arg) };
+                        if let Some((address, port)) = endpoint {
+                            ctx.add_field("ftp.active.cip", address.to_string());
+                            ctx.add_field("ftp.active.port", port);
+                            let method = if command == "PORT" { "PORT" } else { "EPRT" };
+                            self.expect(state, ctx, control, address, port, method);
+                        }
+                    }
+                    command if DATA_COMMANDS.contains(&command) => {
+                        session.pending = Some(PendingCommand { command: command.to_string(), arg });
+                        state.bind(&control, ctx.captured_at);
+                    }
+                    _ => {}
+                }
+            }
+            FtpMessage::Response { code, lines } => {
+                session.server = flow.src;
+                session.client = flow.dst;
+                let text = lines.first().map(String::as_str).unwrap_or_default();
+                match code {
+                    227 => {
+                        if let Some((mut address, port)) = parse_pasv(text) {
+                            // Servers behind NAT may announce an unusable address
+                            if address.is_unspecified() {
+                                address = flow.src;
+                            }
+                            ctx.add_field("ftp.passive.ip", address.to_string());
+                            ctx.add_field("ftp.passive.port", port);
+                            self.expect(state, ctx, control, address, port, "PASV");
+                        }
+                    }
+                    229 => {
+                        if let Some(port) = parse_epsv(text) {
+                            ctx.add_field("ftp.passive.port", port);
+                            self.expect(state, ctx, control, flow.src, port, "EPSV");
+                        }
+                    }
+                    213 if session.last_command == "SIZE" => {
+                        session.expected_size = text.trim().parse().ok();
+                    }
+                    125 | 150 => {
+                        if let Some(size) = parse_announced_size(text) {
+                            match session.awaiting_status {
+                                Some(index) => {
+                                    state.transfers[index].expected_size.get_or_insert(size);
+                                }
+                                None => session.expected_size = Some(size),
+                            }
+                        }
+                    }
+                    226 | 250 | 400..=599 => {
+                        if let Some(index) = session.awaiting_status.take() {
+                            state.transfers[index].status = Some(format!("{} {}", code, text));
+                        } else if *code >= 400 && DATA_COMMANDS.contains(&session.last_command.as_str()) {
+                            // The command failed before a data connection was used
+                            session.pending = None;
+                            session.expected_size = None;
+                        }
+                    }
+                    _ => {}
                 }
             }
-            
-            // If it's not a response, treat it as a command
-            return Ok(FtpPacket::Command(text.trim().to_string()));
         }
+    }
 
-        Err(anyhow::anyhow!("Failed to parse FTP packet"))
+    fn expect(
+        &self,
+        state: &mut FtpState,
+        ctx: &DissectContext,
+        control: FlowKey,
+        address: IpAddr,
+        port: u16,
+        method: &'static str,
+    ) {
+        state.expected.insert((address, port), ctx.captured_at, DataSetup { control, method, frame: ctx.number });
+        if let Some(dissector) = ctx.registry().by_name("FTP-DATA") {
+            let reason = format!("{} in frame {}", method, ctx.number);
+            let expectation = Expectation { dissector: dissector.clone(), reason };
+            ctx.registry().expect(Transport::Tcp, address, port, expectation, ctx.captured_at);
+        }
+    }
+
+    /// Records the messages completed by a segment and follows the session
+    fn dissect_messages(
+        &self,
+        state: &mut FtpState,
+        data: &[u8],
+        messages: &[(FtpMessage, Option<usize>)],
+        flow: &FlowKey,
+        ctx: &mut DissectContext,
+    ) -> Result<Dissection> {
+        if data.is_empty() {
+            anyhow::bail!("No FTP payload");
+        }
+
+        let mut info = Vec::new();
+        for (message, offset) in messages {
+            let field = |name: &str, ctx: &mut DissectContext, len: usize, value: String| match offset {
+                Some(offset) => ctx.add_field_at(name, *offset, len, value),
+                None => ctx.add_field(name, value),
+            };
+            match message {
+                FtpMessage::Request { command, arg } => {
+                    ctx.add_field("ftp.request", true);
+                    field("ftp.request.command", ctx, command.len(), command.clone());
+                    if let Some(arg) = arg {
+                        ctx.add_field("ftp.request.arg", arg.clone());
+                    }
+                }
+                FtpMessage::Response { code, lines } => {
+                    ctx.add_field("ftp.response", true);
+                    field("ftp.response.code", ctx, 3, code.to_string());
+                    for line in lines {
+                        ctx.add_field("ftp.response.arg", line.clone());
+                    }
+                }
+            }
+            self.handle(state, message, flow, ctx);
+            info.push(message.format_info());
+        }
+
+        let info = match info.len() {
+            0 => "Continuation".to_string(),
+            _ => info.join(" | "),
+        };
+        Ok(Dissection::new("FTP", info))
     }
 }
 
-pub enum FtpPacket {
-    Command(String),
-    Response {
-        code: String,
-        message: String,
-    },
+impl Dissector for FtpProcessor {
+    fn name(&self) -> &'static str {
+        "FTP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let flow = ctx.flow.ok_or_else(|| anyhow::anyhow!("FTP outside of a TCP flow"))?;
+        let mut state = self.state.lock().unwrap();
+        let messages = state
+            .streams
+            .entry(flow, ctx.captured_at, ControlStream::default)
+            .push(ctx.tcp_sequence.unwrap_or_default(), data);
+        let flags = ctx.tcp_flags.unwrap_or_default();
+        if flags & (TcpFlags::FIN | TcpFlags::RST) != 0 {
+            state.streams.remove(&flow);
+        }
+        let dissection = self.dissect_messages(&mut state, data, &messages, &flow, ctx);
+        if ctx.flow_closed {
+            state.end_session(&flow.canonical(), ctx.captured_at);
+        }
+        dissection
+    }
+
+    /// Heuristic probe: the greeting of an FTP server
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        let line_end = payload.iter().position(|&b| b == b'\n').unwrap_or(payload.len());
+        let line = String::from_utf8_lossy(&payload[..line_end]).to_ascii_uppercase();
+        if (line.starts_with("220 ") || line.starts_with("220-")) && line.contains("FTP") {
+            return Some(ProbeResult::new(80, "FTP server greeting"));
+        }
+        None
+    }
 }
 
-impl FtpPacket {
-    pub fn get_command(&self) -> String {
-        match self {
-            FtpPacket::Command(cmd) => cmd.to_string(),
-            FtpPacket::Response { code, message } => format!("{} {}", code, message),
+/// FTP data connection; files are reassembled into [`FtpTransfer`]s
+pub struct FtpDataProcessor {
+    state: Arc<Mutex<FtpState>>,
+}
+
+impl Dissector for FtpDataProcessor {
+    fn name(&self) -> &'static str {
+        "FTP-DATA"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let flow = ctx.flow.ok_or_else(|| anyhow::anyhow!("FTP data outside of a TCP flow"))?;
+        let at = ctx.captured_at;
+        let mut state = self.state.lock().unwrap();
+        let channel = state.channel(&flow, at);
+
+        let mut info = format!("FTP Data: {} bytes", data.len());
+        if let Some(channel) = channel {
+            if !data.is_empty() {
+                let in_sync = state
+                    .data_streams
+                    .entry(flow, at, StreamBuffer::default)
+                    .push(ctx.tcp_sequence.unwrap_or_default(), data);
+                if let Some(channel) = state.channels.get_mut(&channel).filter(|_| !in_sync) {
+                    channel.gap = true;
+                }
+                state.flush(&channel, &flow);
+            }
+
+            let Some(channel_state) = state.channels.get(&channel) else {
+                anyhow::bail!("FTP data connection state lost");
+            };
+            ctx.add_field("ftp-data.setup-method", channel_state.setup.method);
+            ctx.add_field("ftp-data.setup-frame", channel_state.setup.frame);
+            info.push_str(&format!(" ({})", channel_state.setup.method));
+            if let Some(command) = &channel_state.command {
+                ctx.add_field("ftp-data.command", command.clone());
+                info.push_str(&format!(" ({})", command));
+            }
+
+            if ctx.tcp_flags.is_some_and(|flags| flags & (TcpFlags::FIN | TcpFlags::RST) != 0) {
+                state.close(&channel, &flow, at);
+            }
         }
+        if data.is_empty() {
+            anyhow::bail!("No FTP data");
+        }
+        Ok(Dissection::new("FTP-DATA", info))
     }
+}
+
+/// `h1,h2,h3,h4,p1,p2` of a PORT command
+fn parse_port(arg: &str) -> Option<(IpAddr, u16)> {
+    let numbers: Vec<u8> = arg.trim().split(',').map(|n| n.trim().parse().ok()).collect::<Option<_>>()?;
+    let [a, b, c, d, p1, p2] = numbers[..] else {
+        return None;
+    };
+    Some((IpAddr::V4(Ipv4Addr::new(a, b, c, d)), u16::from_be_bytes([p1, p2])))
+}
+
+/// `227 Entering Passive Mode (h1,h2,h3,h4,p1,p2).`
+fn parse_pasv(text: &str) -> Option<(IpAddr, u16)> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let end = text[start..]
+        .find(|c: char| !c.is_ascii_digit() && c != ',' && c != ' ')
+        .map_or(text.len(), |end| start + end);
+    parse_port(&text[start..end])
+}
+
+/// `|1|132.235.1.2|6275|` of an EPRT command
+fn parse_eprt(arg: &str) -> Option<(IpAddr, u16)> {
+    let arg = arg.trim();
+    let delimiter = arg.chars().next()?;
+    let parts: Vec<&str> = arg.split(delimiter).collect();
+    let [_, _, address, port, ..] = parts[..] else {
+        return None;
+    };
+    Some((address.parse().ok()?, port.parse().ok()?))
+}
 
-    pub fn is_command(&self) -> bool {
-        matches!(self, FtpPacket::Command(_))
+/// `229 Entering Extended Passive Mode (|||6446|)`
+fn parse_epsv(text: &str) -> Option<u16> {
+    let start = text.find('(')? + 1;
+    let inner = &text[start..start + text[start..].find(')')?];
+    let delimiter = inner.chars().next()?;
+    inner.split(delimiter).nth(3)?.parse().ok()
+}
+
+/// `150 Opening BINARY mode data connection for file.txt (1234 bytes).`
+fn parse_announced_size(text: &str) -> Option<u64> {
+    let start = text.rfind('(')? + 1;
+    let inner = &text[start..start + text[start..].find(')')?];
+    inner.strip_suffix(" bytes")?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::AlertChannel;
+    use super::super::dissector::testing::{self, at, flow};
+    use super::super::dissector::DissectorRegistry;
+    use super::super::flow::FLOW_IDLE_TIMEOUT_SECS;
+
+    /// Reply announcing the passive data connection of [`data_flow`]
+    const PASV_REPLY: &[u8] = b"227 Entering Passive Mode (10,0,0,2,7,138).\r\n";
+
+    /// Dissects one control segment of `flow`, tracking the flow in `registry`
+    /// as the TCP dissector does
+    fn segment(
+        ftp: &FtpProcessor,
+        registry: &DissectorRegistry,
+        flow: FlowKey,
+        seq: u32,
+        data: &[u8],
+        flags: u8,
+        seconds: i64,
+    ) {
+        let closed = testing::close_flow(registry, &flow, flags, at(seconds));
+        let _ = testing::dissect_in(registry, &AlertChannel::new(), ftp, data, |ctx| {
+            ctx.captured_at = at(seconds);
+            ctx.flow = Some(flow);
+            ctx.tcp_sequence = Some(seq);
+            ctx.tcp_flags = Some(flags);
+            ctx.flow_closed = closed;
+        });
+    }
+
+    #[test]
+    fn parses_data_connection_announcements() {
+        let address: IpAddr = "192.168.1.2".parse().unwrap();
+        assert_eq!(parse_port("192,168,1,2,7,138"), Some((address, 1930)));
+        assert_eq!(parse_port("192,168,1,2,7"), None);
+        assert_eq!(parse_port("192,168,1,256,7,138"), None);
+        assert_eq!(parse_pasv("Entering Passive Mode (192,168,1,2,7,138)."), Some((address, 1930)));
+        assert_eq!(parse_eprt("|1|192.168.1.2|1930|"), Some((address, 1930)));
+        assert_eq!(parse_eprt("|1|"), None);
+        assert_eq!(parse_epsv("Entering Extended Passive Mode (|||6446|)"), Some(6446));
+        assert_eq!(parse_epsv("Entering Extended Passive Mode )|||6446|("), None);
+        assert_eq!(parse_epsv("() (|||6446|)"), None);
+        assert_eq!(parse_epsv("(é"), None);
+        assert_eq!(parse_announced_size("Opening BINARY mode data connection for a.txt (1234 bytes)."), Some(1234));
+        assert_eq!(parse_announced_size(")( bytes"), None);
     }
 
-    pub fn is_response(&self) -> bool {
-        matches!(self, FtpPacket::Response { .. })
+    #[test]
+    fn reads_multi_line_replies() {
+        let mut stream = ControlStream::default();
+        let messages = stream.push(1, b"230-Welcome\r\n230-\r\n230 Logged in\r\nUSER");
+        assert_eq!(messages.len(), 1);
+        let (FtpMessage::Response { code, lines }, Some(0)) = &messages[0] else {
+            panic!("expected a reply at the start of the segment");
+        };
+        assert_eq!((*code, lines.len()), (230, 3));
+        let messages = stream.push(39, b" anonymous\r\n");
+        assert_eq!(messages[0].0.format_info(), "Request: USER anonymous");
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn forgets_sessions_once_closed() {
+        let ftp = FtpProcessor::new();
+        let registry = DissectorRegistry::new();
+        segment(&ftp, &registry, flow(21), 1, b"PASV\r\n", TcpFlags::ACK, 0);
+        segment(&ftp, &registry, flow(21).reversed(), 1, PASV_REPLY, TcpFlags::ACK, 0);
+        {
+            let state = ftp.state.lock().unwrap();
+            assert!(state.sessions.get(&flow(21).canonical()).is_some());
+            assert!(state.expected.get(&("10.0.0.2".parse().unwrap(), 1930)).is_some());
+        }
+
+        segment(&ftp, &registry, flow(21), 7, b"", TcpFlags::FIN | TcpFlags::ACK, 1);
+        assert_eq!(ftp.state.lock().unwrap().sessions.len(), 1);
+        segment(&ftp, &registry, flow(21).reversed(), 47, b"", TcpFlags::FIN | TcpFlags::ACK, 1);
+        let state = ftp.state.lock().unwrap();
+        assert_eq!(state.sessions.len(), 0);
+        assert_eq!(state.streams.len(), 0);
+        assert!(state.expected.get(&("10.0.0.2".parse().unwrap(), 1930)).is_none());
+    }
+
+    #[test]
+    fn announced_connections_expire() {
+        let ftp = FtpProcessor::new();
+        let registry = DissectorRegistry::new();
+        segment(&ftp, &registry, flow(21), 1, b"PORT 10,0,0,1,7,138\r\n", TcpFlags::ACK, 0);
+        let data = FlowKey::new("10.0.0.2".parse().unwrap(), 20, "10.0.0.1".parse().unwrap(), 1930);
+        let mut state = ftp.state.lock().unwrap();
+        assert!(state.channel(&data, at(EXPECTATION_TIMEOUT_SECS + 1)).is_none());
+        assert_eq!(state.channels.len(), 0);
+    }
+
+    /// Server side of the passive data connection announced in [`retrieve`]
+    fn data_flow() -> FlowKey {
+        FlowKey::new("10.0.0.2".parse().unwrap(), 1930, "10.0.0.1".parse().unwrap(), 50001)
+    }
+
+    /// PASV and RETR of `a.txt`
+    fn retrieve(ftp: &FtpProcessor, registry: &DissectorRegistry) {
+        segment(ftp, registry, flow(21), 1, b"PASV\r\n", TcpFlags::ACK, 0);
+        segment(ftp, registry, flow(21).reversed(), 1, PASV_REPLY, TcpFlags::ACK, 0);
+        segment(ftp, registry, flow(21), 7, b"RETR a.txt\r\n", TcpFlags::ACK, 0);
+    }
+
+    fn send_data(ftp: &FtpProcessor, seq: u32, data: &[u8], flags: u8, seconds: i64) {
+        let _ = testing::dissect(&ftp.data_dissector(), data, |ctx| {
+            ctx.captured_at = at(seconds);
+            ctx.flow = Some(data_flow());
+            ctx.tcp_sequence = Some(seq);
+            ctx.tcp_flags = Some(flags);
+        });
+    }
+
+    #[test]
+    fn reassembles_transferred_files() {
+        let ftp = FtpProcessor::new();
+        let registry = DissectorRegistry::new();
+        retrieve(&ftp, &registry);
+        send_data(&ftp, 1, b"hello ", TcpFlags::ACK, 1);
+        send_data(&ftp, 7, b"world", TcpFlags::FIN | TcpFlags::ACK, 2);
+
+        let transfers = ftp.transfers();
+        assert_eq!(transfers.len(), 1);
+        assert_eq!((transfers[0].command.as_str(), transfers[0].name.as_str()), ("RETR", "a.txt"));
+        assert_eq!(transfers[0].data, b"hello world");
+        assert_eq!(transfers[0].size, 11);
+        assert!(transfers[0].complete);
+        assert_eq!(ftp.state.lock().unwrap().data_streams.len(), 0);
+    }
+
+    #[test]
+    fn keeps_to_the_storage_limit() {
+        let ftp = FtpProcessor::new();
+        let registry = DissectorRegistry::new();
+        ftp.state.lock().unwrap().stored = MAX_STORED_BYTES - 3;
+        retrieve(&ftp, &registry);
+        send_data(&ftp, 1, b"hello", TcpFlags::FIN | TcpFlags::ACK, 1);
+
+        let transfer = &ftp.transfers()[0];
+        assert_eq!(transfer.data, b"hel");
+        assert_eq!(transfer.size, 5);
+        assert!(!transfer.complete);
+        assert!(transfer.format_info().ends_with("[incomplete]"));
+    }
+
+    #[test]
+    fn transfers_keep_their_control_connection() {
+        let ftp = FtpProcessor::new();
+        let registry = DissectorRegistry::new();
+        retrieve(&ftp, &registry);
+        let idle = FLOW_IDLE_TIMEOUT_SECS + 1;
+        send_data(&ftp, 1, b"hello", TcpFlags::ACK, 1);
+        send_data(&ftp, 6, b"world", TcpFlags::ACK, idle);
+        segment(&ftp, &registry, flow(21).reversed(), 46, b"226 Transfer complete.\r\n", TcpFlags::ACK, idle + 200);
+        assert_eq!(ftp.transfers()[0].status.as_deref(), Some("226 Transfer complete."));
+
+        // Connections idle for too long are forgotten
+        let other = FlowKey::new("10.0.0.3".parse().unwrap(), 50000, "10.0.0.2".parse().unwrap(), 21);
+        segment(&ftp, &registry, other, 1, b"NOOP\r\n", TcpFlags::ACK, 5 * idle);
+        let state = ftp.state.lock().unwrap();
+        assert_eq!(state.sessions.len(), 1);
+        assert!(state.sessions.get(&other.canonical()).is_some());
+    }
+}
@@ -0,0 +1,179 @@
+use colored::*;
+use super::tree::Layer;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Colours cycled through the layers, outermost first
+const LAYER_COLORS: [Color; 6] = [
+    Color::Blue,
+    Color::Green,
+    Color::Yellow,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Red,
+];
+
+/// Formats a hex and ASCII dump of `data`, colouring every byte by the
+/// innermost layer it belongs to; bytes of a decoded field are shown in bold.
+/// With `layer` set, only the bytes of the first layer with that protocol
+/// name are dumped. Returns `None` if that layer is missing or not part of
+/// the frame, e.g. decrypted data.
+pub fn format_hex_dump(data: &[u8], layers: &[Layer], layer: Option<&str>) -> Option<String> {
+    // The Frame layer covers every byte and is left uncoloured
+    let layers: Vec<&Layer> = layers.iter().filter(|l| l.offset.is_some()).skip(1).collect();
+
+    let mut styles: Vec<Option<(Color, bool)>> = vec![None; data.len()];
+    for (index, l) in layers.iter().enumerate() {
+        let color = LAYER_COLORS[index % LAYER_COLORS.len()];
+        let start = l.offset.unwrap_or_default().min(data.len());
+        let end = (start + l.length).min(data.len());
+        for style in &mut styles[start..end] {
+            *style = Some((color, false));
+        }
+        for field in &l.fields {
+            let Some(offset) = field.offset else {
+                continue;
+            };
+            let end = (offset + field.length).min(data.len());
+            for style in styles[offset.min(end)..end].iter_mut().flatten() {
+                style.1 = true;
+            }
+        }
+    }
+
+    let (start, end) = match layer {
+        Some(name) => {
+            let l = layers.iter().find(|l| l.protocol.eq_ignore_ascii_case(name))?;
+            let start = l.offset?.min(data.len());
+            (start, (start + l.length).min(data.len()))
+        }
+        None => (0, data.len()),
+    };
+
+    let paint = |text: String, style: Option<(Color, bool)>| match style {
+        Some((color, true)) => text.color(color).bold().to_string(),
+        Some((color, false)) => text.color(color).to_string(),
+        None => text,
+    };
+
+    let mut dump = String::new();
+    let mut line_start = start;
+    while line_start < end {
+        let line_end = (line_start + BYTES_PER_LINE).min(end);
+        let mut hex = String::new();
+        let mut ascii = String::new();
+        for position in line_start..line_start + BYTES_PER_LINE {
+            if position - line_start == BYTES_PER_LINE / 2 {
+                hex.push(' ');
+            }
+            if position >= line_end {
+                hex.push_str("   ");
+                continue;
+            }
+            let byte = data[position];
+            let style = styles[position];
+            hex.push_str(&paint(format!("{:02x}", byte), style));
+            hex.push(' ');
+            let character = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' };
+            ascii.push_str(&paint(character.to_string(), style));
+        }
+        dump.push_str(&format!("{:04x}  {} {}\n", line_start, hex, ascii));
+        line_start = line_end;
+    }
+
+    let legend: Vec<String> = layers
+        .iter()
+        .enumerate()
+        .map(|(index, l)| l.protocol.color(LAYER_COLORS[index % LAYER_COLORS.len()]).to_string())
+        .collect();
+    dump.push_str(&legend.join(" "));
+    dump.push('\n');
+    Some(dump)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tree::Field;
+
+    /// 40-byte frame of printable bytes: Ethernet, then IPv4 from offset 14
+    /// and TLS decrypted from it
+    fn frame() -> (Vec<u8>, Vec<Layer>) {
+        let data: Vec<u8> = (0x40..0x68).collect();
+        let mut ethernet = Layer::new("Ethernet", Some(0), 40);
+        ethernet.fields.push(Field { offset: Some(12), length: 2, ..Field::new("eth.type", 0x0800u16) });
+        let mut ipv4 = Layer::new("IPv4", Some(14), 26);
+        let destination = std::net::Ipv4Addr::new(10, 0, 0, 1);
+        ipv4.fields.push(Field { offset: Some(30), length: 4, ..Field::new("ip.dst", destination) });
+        let layers = vec![
+            Layer::new("Frame", Some(0), 40),
+            ethernet,
+            ipv4,
+            Layer::new("TLS", None, 100),
+        ];
+        (data, layers)
+    }
+
+    /// `dump` without its colours
+    fn plain(dump: &str) -> String {
+        let mut text = String::new();
+        let mut escape = false;
+        for character in dump.chars() {
+            match character {
+                '\x1b' => escape = true,
+                'm' if escape => escape = false,
+                _ if !escape => text.push(character),
+                _ => {}
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn dumps_the_whole_frame() {
+        let (data, layers) = frame();
+        let dump = plain(&format_hex_dump(&data, &layers, None).unwrap());
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "0000  40 41 42 43 44 45 46 47  48 49 4a 4b 4c 4d 4e 4f  @ABCDEFGHIJKLMNO",
+                "0010  50 51 52 53 54 55 56 57  58 59 5a 5b 5c 5d 5e 5f  PQRSTUVWXYZ[\\]^_",
+                "0020  60 61 62 63 64 65 66 67                           `abcdefg",
+                "Ethernet IPv4",
+            ]
+        );
+    }
+
+    #[test]
+    fn dumps_one_layer() {
+        let (data, layers) = frame();
+        let dump = plain(&format_hex_dump(&data, &layers, Some("ipv4")).unwrap());
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "000e  4e 4f 50 51 52 53 54 55  56 57 58 59 5a 5b 5c 5d  NOPQRSTUVWXYZ[\\]",
+                "001e  5e 5f 60 61 62 63 64 65  66 67                    ^_`abcdefg",
+                "Ethernet IPv4",
+            ]
+        );
+
+        // Missing layers and layers not read from the frame have no bytes to dump
+        assert!(format_hex_dump(&data, &layers, Some("UDP")).is_none());
+        assert!(format_hex_dump(&data, &layers, Some("TLS")).is_none());
+    }
+
+    #[test]
+    fn stops_at_the_end_of_a_short_frame() {
+        let (data, mut layers) = frame();
+        layers.push(Layer::new("Trailer", Some(64), 4));
+        let data = &data[..20];
+
+        let dump = plain(&format_hex_dump(data, &layers, Some("IPv4")).unwrap());
+        assert_eq!(dump.lines().next(), Some("000e  4e 4f 50 51 52 53                                 NOPQRS"));
+        let dump = plain(&format_hex_dump(data, &layers, Some("Trailer")).unwrap());
+        assert_eq!(dump, "Ethernet IPv4 Trailer\n");
+        assert_eq!(plain(&format_hex_dump(&[], &layers, None).unwrap()), "Ethernet IPv4 Trailer\n");
+    }
+}
@@ -1,5 +1,10 @@
-use http::{Request, Response};
 use anyhow::Result;
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::tree::Field;
+
+const MAX_HEADERS: usize = 64;
+const METHODS: &[&str] = &["GET", "POST", "HEAD", "PUT", "DELETE", "OPTIONS", "PATCH", "CONNECT", "TRACE"];
 
 pub struct HttpProcessor;
 
@@ -9,57 +14,213 @@ impl HttpProcessor {
     }
 
     pub fn process(&self, payload: &[u8]) -> Result<HttpPacket> {
-        // Пробуем разобрать как HTTP запрос
-        if let Ok(request) = Request::builder()
-            .method("GET")
-            .uri("/")
-            .body(payload.to_vec())
-        {
-            return Ok(HttpPacket::Request(request));
+        // Пробуем разобрать как HTTP ответ
+        if payload.starts_with(b"HTTP/") {
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut response = httparse::Response::new(&mut headers);
+            if response.parse(payload).is_ok() {
+                if let Some(code) = response.code {
+                    return Ok(HttpPacket::Response {
+                        version: format!("HTTP/1.{}", response.version.unwrap_or(1)),
+                        code,
+                        reason: response.reason.unwrap_or_default().to_string(),
+                        headers: collect_headers(response.headers),
+                    });
+                }
+            }
         }
 
-        // Пробуем разобрать как HTTP ответ
-        if let Ok(response) = Response::builder()
-            .status(200)
-            .body(payload.to_vec())
-        {
-            return Ok(HttpPacket::Response(response));
+        // Пробуем разобрать как HTTP запрос
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+        if request.parse(payload).is_ok() {
+            if let (Some(method), Some(path), Some(version)) = (request.method, request.path, request.version) {
+                return Ok(HttpPacket::Request {
+                    version: format!("HTTP/1.{}", version),
+                    method: method.to_string(),
+                    path: path.to_string(),
+                    headers: collect_headers(request.headers),
+                });
+            }
         }
 
         Err(anyhow::anyhow!("Failed to parse HTTP packet"))
     }
 }
 
+/// Collects the headers parsed so far; a partial parse leaves trailing empty slots
+fn collect_headers(headers: &[httparse::Header]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .take_while(|h| !h.name.is_empty())
+        .map(|h| (h.name.to_string(), String::from_utf8_lossy(h.value).to_string()))
+        .collect()
+}
+
+impl Dissector for HttpProcessor {
+    fn name(&self) -> &'static str {
+        "HTTP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let http = self.process(data)?;
+        ctx.add_fields(http.fields());
+        Ok(Dissection::new(
+            "HTTP",
+            format!("{} {} {}", http.get_method(), http.get_path(), http.get_host()),
+        ))
+    }
+
+    /// Heuristic probe: an HTTP/1.x request or status line
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        let line_end = payload.iter().position(|&b| b == b'\n').unwrap_or(payload.len());
+        let line = &payload[..line_end];
+        if line.starts_with(b"HTTP/1.") && line.len() >= 12 && line[8] == b' ' && line[9..12].iter().all(u8::is_ascii_digit) {
+            return Some(ProbeResult::new(95, "HTTP/1.x status line"));
+        }
+
+        METHODS
+            .iter()
+            .find(|method| line.starts_with(method.as_bytes()) && line.get(method.len()) == Some(&b' '))?;
+        if line.windows(7).any(|w| w == b"HTTP/1.") {
+            Some(ProbeResult::new(95, "HTTP/1.x request line"))
+        } else {
+            Some(ProbeResult::new(40, "HTTP method token"))
+        }
+    }
+}
+
 pub enum HttpPacket {
-    Request(Request<Vec<u8>>),
-    Response(Response<Vec<u8>>),
+    Request {
+        version: String,
+        method: String,
+        path: String,
+        headers: Vec<(String, String)>,
+    },
+    Response {
+        version: String,
+        code: u16,
+        reason: String,
+        headers: Vec<(String, String)>,
+    },
 }
 
 impl HttpPacket {
     pub fn get_method(&self) -> String {
         match self {
-            HttpPacket::Request(req) => req.method().to_string(),
-            HttpPacket::Response(_) => "Response".to_string(),
+            HttpPacket::Request { method, .. } => method.clone(),
+            HttpPacket::Response { .. } => "Response".to_string(),
         }
     }
 
     pub fn get_path(&self) -> String {
         match self {
-            HttpPacket::Request(req) => req.uri().to_string(),
-            HttpPacket::Response(res) => res.status().to_string(),
+            HttpPacket::Request { path, .. } => path.clone(),
+            HttpPacket::Response { code, reason, .. } => format!("{} {}", code, reason),
         }
     }
 
     pub fn get_host(&self) -> String {
         match self {
-            HttpPacket::Request(req) => {
-                req.headers()
-                    .get("host")
-                    .and_then(|h| h.to_str().ok())
-                    .unwrap_or("Unknown")
-                    .to_string()
+            HttpPacket::Request { .. } => self.get_header("host").unwrap_or("Unknown").to_string(),
+            HttpPacket::Response { .. } => "Server".to_string(),
+        }
+    }
+
+    pub fn get_headers(&self) -> &[(String, String)] {
+        match self {
+            HttpPacket::Request { headers, .. } | HttpPacket::Response { headers, .. } => headers,
+        }
+    }
+
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.get_headers()
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn format_info(&self) -> String {
+        match self {
+            HttpPacket::Request { version, method, path, .. } => {
+                format!("{} {} {} {}", method, path, version, self.get_host())
+            }
+            HttpPacket::Response { version, code, reason, .. } => {
+                format!("{} {} {}", version, code, reason)
+            }
+        }
+    }
+
+    /// Returns the decoded HTTP fields as `(name, value)` pairs for display filtering
+    pub fn fields(&self) -> Vec<Field> {
+        let mut fields = Vec::new();
+        match self {
+            HttpPacket::Request { method, path, version, .. } => {
+                fields.push(Field::new("http.request.method", method.clone()));
+                fields.push(Field::new("http.request.uri", path.clone()));
+                fields.push(Field::new("http.request.version", version.clone()));
+                if let Some(host) = self.get_header("host") {
+                    fields.push(Field::new("http.host", host));
+                }
+                if let Some(agent) = self.get_header("user-agent") {
+                    fields.push(Field::new("http.user_agent", agent));
+                }
+            }
+            HttpPacket::Response { code, reason, version, .. } => {
+                fields.push(Field::new("http.response.code", *code));
+                fields.push(Field::new("http.response.phrase", reason.clone()));
+                fields.push(Field::new("http.response.version", version.clone()));
+                if let Some(content_type) = self.get_header("content-type") {
+                    fields.push(Field::new("http.content_type", content_type));
+                }
             }
-            HttpPacket::Response(_) => "Server".to_string(),
         }
+        fields
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissector::testing::{self, field};
+
+    #[test]
+    fn dissects_a_request() {
+        let request = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nUser-Agent: curl/8.5.0\r\n\r\n";
+        let (dissection, fields) = testing::dissect(&HttpProcessor::new(), request, |_| {});
+        assert_eq!(dissection.unwrap().info, "GET /index.html example.com");
+        assert_eq!(field(&fields, "http.request.method"), Some(&"GET".into()));
+        assert_eq!(field(&fields, "http.request.version"), Some(&"HTTP/1.1".into()));
+        assert_eq!(field(&fields, "http.user_agent"), Some(&"curl/8.5.0".into()));
+    }
+
+    #[test]
+    fn dissects_a_response() {
+        let response = b"HTTP/1.0 404 Not Found\r\nContent-Type: text/html\r\nContent-Length: 9\r\n\r\nnot found";
+        let http = HttpProcessor::new().process(response).unwrap();
+        assert_eq!(http.format_info(), "HTTP/1.0 404 Not Found");
+        assert_eq!(http.get_header("content-length"), Some("9"));
+        let fields = http.fields();
+        assert_eq!(field(&fields, "http.response.code"), Some(&404u16.into()));
+        assert_eq!(field(&fields, "http.content_type"), Some(&"text/html".into()));
+    }
+
+    #[test]
+    fn keeps_the_headers_of_a_partial_message() {
+        // The headers continue in the next segment
+        let http = HttpProcessor::new().process(b"POST /upload HTTP/1.1\r\nHost: a.example\r\nContent-Le").unwrap();
+        assert_eq!(http.get_headers(), [("Host".to_string(), "a.example".to_string())]);
+        assert!(HttpProcessor::new().process(b"\x16\x03\x01\x00\x05hello").is_err());
+        assert!(HttpProcessor::new().process(b"GET").is_err());
+    }
+
+    #[test]
+    fn probes_request_and_status_lines() {
+        let http = HttpProcessor::new();
+        assert_eq!(http.probe(b"HTTP/1.1 200 OK\r\n").unwrap().confidence, 95);
+        assert_eq!(http.probe(b"DELETE /item/1 HTTP/1.1\r\n").unwrap().confidence, 95);
+        assert_eq!(http.probe(b"GET /index.html").unwrap().confidence, 40);
+        assert!(http.probe(b"GETTER /").is_none());
+        assert!(http.probe(b"HTTP/1.1 2").is_none());
+    }
+}
@@ -0,0 +1,487 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use loona_hpack::Decoder;
+use std::sync::Mutex;
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::flow::{FlowKey, FlowTable};
+use super::tree::Field;
+
+const CONNECTION_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const FRAME_HEADER_LEN: usize = 9;
+
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+/// Largest header block buffered, in one frame or across CONTINUATION frames
+const MAX_HEADER_BLOCK_LEN: usize = 256 * 1024;
+
+/// Per-direction HTTP/2 state: unparsed bytes, the HPACK dynamic table and
+/// a header block that continues in CONTINUATION frames
+struct Http2Stream {
+    buffer: Vec<u8>,
+    /// Payload bytes of the current frame still to be skipped
+    skip: usize,
+    decoder: Decoder<'static>,
+    pending_headers: Vec<u8>,
+    preface_checked: bool,
+    /// A header block failed to decode; the dynamic table no longer matches
+    /// the encoder's, so later blocks cannot be decoded either
+    compression_failed: bool,
+}
+
+impl Default for Http2Stream {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::new(),
+            skip: 0,
+            decoder: Decoder::new(),
+            pending_headers: Vec::new(),
+            preface_checked: false,
+            compression_failed: false,
+        }
+    }
+}
+
+pub struct Http2Processor {
+    /// Frame parsing state, keyed by the flow of each direction
+    streams: Mutex<FlowTable<FlowKey, Http2Stream>>,
+}
+
+impl Http2Processor {
+    pub fn new() -> Self {
+        Self {
+            streams: Mutex::new(FlowTable::new()),
+        }
+    }
+
+    /// Checks for the client connection preface
+    pub fn is_preface(data: &[u8]) -> bool {
+        data.starts_with(CONNECTION_PREFACE)
+    }
+
+    /// Parses the HTTP/2 frames of one direction of a connection. `data` must be
+    /// the in-order byte stream (e.g. decrypted TLS application data); `at` is
+    /// its capture time. Only header blocks are buffered, frames of other types
+    /// are reported as soon as their header arrives.
+    pub fn process(&self, flow: &FlowKey, data: &[u8], at: DateTime<Utc>) -> Vec<Http2Frame> {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.entry(*flow, at, Http2Stream::default);
+
+        stream.buffer.extend_from_slice(data);
+        if !stream.preface_checked {
+            if stream.buffer.len() < CONNECTION_PREFACE.len() && CONNECTION_PREFACE.starts_with(&stream.buffer) {
+                return Vec::new();
+            }
+            if stream.buffer.starts_with(CONNECTION_PREFACE) {
+                stream.buffer.drain(..CONNECTION_PREFACE.len());
+            }
+            stream.preface_checked = true;
+        }
+
+        let mut frames = Vec::new();
+        loop {
+            let skipped = stream.skip.min(stream.buffer.len());
+            stream.buffer.drain(..skipped);
+            stream.skip -= skipped;
+            if stream.buffer.len() < FRAME_HEADER_LEN {
+                break;
+            }
+
+            let header = &stream.buffer[..FRAME_HEADER_LEN];
+            let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            let frame_type = header[3];
+            let flags = header[4];
+            let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+            let mut frame = Http2Frame {
+                frame_type,
+                flags,
+                stream_id,
+                length,
+                headers: Vec::new(),
+                header_error: None,
+            };
+
+            let carries_headers = frame_type == FRAME_HEADERS || frame_type == FRAME_CONTINUATION;
+            if !carries_headers || length > MAX_HEADER_BLOCK_LEN {
+                if carries_headers {
+                    // The entries it adds to the dynamic table are lost with it
+                    stream.pending_headers.clear();
+                    stream.compression_failed = true;
+                    frame.header_error = Some("header block too long".to_string());
+                }
+                stream.buffer.drain(..FRAME_HEADER_LEN);
+                stream.skip = length;
+                frames.push(frame);
+                continue;
+            }
+            if stream.buffer.len() < FRAME_HEADER_LEN + length {
+                break;
+            }
+            let payload: Vec<u8> = stream.buffer.drain(..FRAME_HEADER_LEN + length).skip(FRAME_HEADER_LEN).collect();
+
+            let fragment = if frame_type == FRAME_HEADERS {
+                header_block_fragment(flags, &payload)
+            } else {
+                Some(payload.as_slice())
+            };
+            if let Some(fragment) = fragment {
+                stream.pending_headers.extend_from_slice(fragment);
+            }
+            if stream.pending_headers.len() > MAX_HEADER_BLOCK_LEN {
+                stream.pending_headers.clear();
+                stream.compression_failed = true;
+                frame.header_error = Some("header block too long".to_string());
+            } else if flags & FLAG_END_HEADERS != 0 {
+                let block = std::mem::take(&mut stream.pending_headers);
+                if stream.compression_failed {
+                    frame.header_error = Some("header compression state lost".to_string());
+                } else {
+                    match stream.decoder.decode(&block) {
+                        Ok(headers) => {
+                            frame.headers = headers
+                                .into_iter()
+                                .map(|(name, value)| {
+                                    (
+                                        String::from_utf8_lossy(&name).to_string(),
+                                        String::from_utf8_lossy(&value).to_string(),
+                                    )
+                                })
+                                .collect();
+                        }
+                        Err(e) => {
+                            stream.compression_failed = true;
+                            frame.header_error = Some(e.to_string());
+                        }
+                    }
+                }
+            }
+
+            frames.push(frame);
+        }
+
+        frames
+    }
+
+    /// Drops both directions of a closed connection
+    fn close(&self, flow: &FlowKey) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.remove(flow);
+        streams.remove(&flow.reversed());
+    }
+}
+
+impl Default for Http2Processor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dissector for Http2Processor {
+    fn name(&self) -> &'static str {
+        "HTTP2"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let flow = ctx.flow.ok_or_else(|| anyhow::anyhow!("HTTP/2 needs a transport flow"))?;
+        let frames = if data.is_empty() {
+            Vec::new()
+        } else {
+            self.process(&flow, data, ctx.captured_at)
+        };
+        if ctx.flow_closed {
+            self.close(&flow);
+        }
+        if frames.is_empty() {
+            return Err(anyhow::anyhow!("No complete HTTP/2 frame"));
+        }
+        let mut summaries = Vec::new();
+        for frame in &frames {
+            ctx.add_fields(frame.fields());
+            summaries.push(frame.format_info());
+        }
+        Ok(Dissection::new("HTTP2", summaries.join(", ")))
+    }
+
+    /// Heuristic probe: the client connection preface of cleartext HTTP/2
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        Self::is_preface(payload).then(|| ProbeResult::new(100, "HTTP/2 connection preface"))
+    }
+}
+
+/// Strips padding and priority fields from a HEADERS frame payload
+fn header_block_fragment(flags: u8, payload: &[u8]) -> Option<&[u8]> {
+    let mut start = 0;
+    let mut end = payload.len();
+    if flags & FLAG_PADDED != 0 {
+        let pad = *payload.first()? as usize;
+        start += 1;
+        end = end.checked_sub(pad)?;
+    }
+    if flags & FLAG_PRIORITY != 0 {
+        start += 5;
+    }
+    payload.get(start..end)
+}
+
+#[derive(Debug, Clone)]
+pub struct Http2Frame {
+    pub frame_type: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub length: usize,
+    /// Decoded header list of a complete HEADERS block
+    pub headers: Vec<(String, String)>,
+    /// Why the header block could not be decoded
+    pub header_error: Option<String>,
+}
+
+impl Http2Frame {
+    pub fn type_name(&self) -> &'static str {
+        match self.frame_type {
+            FRAME_DATA => "DATA",
+            FRAME_HEADERS => "HEADERS",
+            0x2 => "PRIORITY",
+            0x3 => "RST_STREAM",
+            0x4 => "SETTINGS",
+            0x5 => "PUSH_PROMISE",
+            0x6 => "PING",
+            0x7 => "GOAWAY",
+            0x8 => "WINDOW_UPDATE",
+            FRAME_CONTINUATION => "CONTINUATION",
+            _ => "UNKNOWN",
+        }
+    }
+
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn format_info(&self) -> String {
+        let mut info = format!("{}[{}]", self.type_name(), self.stream_id);
+        if let Some(status) = self.get_header(":status") {
+            info.push_str(&format!(" {}", status));
+        } else if let (Some(method), Some(path)) = (self.get_header(":method"), self.get_header(":path")) {
+            info.push_str(&format!(" {} {}", method, path));
+            if let Some(authority) = self.get_header(":authority") {
+                info.push_str(&format!(" {}", authority));
+            }
+        } else if self.frame_type == FRAME_DATA {
+            info.push_str(&format!(" {} bytes", self.length));
+        }
+        if self.flags & FLAG_END_STREAM != 0 && (self.frame_type == FRAME_DATA || self.frame_type == FRAME_HEADERS) {
+            info.push_str(" END_STREAM");
+        }
+        if let Some(error) = &self.header_error {
+            info.push_str(&format!(" [HPACK error: {}]", error));
+        }
+        info
+    }
+
+    /// Returns the decoded HTTP/2 fields as `(name, value)` pairs for display filtering
+    pub fn fields(&self) -> Vec<Field> {
+        let mut fields = vec![
+            Field::new("http2.type", self.type_name()),
+            Field::new("http2.streamid", self.stream_id),
+            Field::new("http2.length", self.length),
+        ];
+        for (name, value) in &self.headers {
+            match name.as_str() {
+                ":method" => fields.push(Field::new("http2.method", value.clone())),
+                ":path" => fields.push(Field::new("http2.path", value.clone())),
+                ":authority" => fields.push(Field::new("http2.authority", value.clone())),
+                ":status" => fields.push(Field::new("http2.status", value.clone())),
+                _ => {}
+            }
+            fields.push(Field::new("http2.header", format!("{}: {}", name, value)));
+        }
+        if let Some(error) = &self.header_error {
+            fields.push(Field::new("_ws.malformed", format!("HPACK: {}", error)));
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::AlertChannel;
+    use pnet::packet::tcp::TcpFlags;
+    use super::super::dissector::testing::{self, at, flow};
+    use super::super::dissector::{DissectorRegistry, DissectorTable, LINKTYPE_ETHERNET};
+    use super::super::PacketProcessor;
+
+    /// `GET / http` for www.example.com (RFC 7541, C.3.1)
+    const REQUEST_BLOCK: &[u8] = &[
+        0x82, 0x86, 0x84, 0x41, 0x0f, b'w', b'w', b'w', b'.', b'e', b'x', b'a', b'm', b'p', b'l', b'e', b'.', b'c',
+        b'o', b'm',
+    ];
+
+    fn frame(frame_type: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[frame_type, flags]);
+        frame.extend_from_slice(&stream_id.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// HEADERS frame on stream 1 with END_HEADERS and the given header block
+    fn headers(block: &[u8]) -> Vec<u8> {
+        frame(FRAME_HEADERS, FLAG_END_HEADERS, 1, block)
+    }
+
+    /// Ethernet frame of a TCP segment from 10.0.0.1:50000 to 10.0.0.2:80
+    fn tcp_frame(seq: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1, 0x08, 0x00];
+        frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        frame[16..18].copy_from_slice(&((40 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&50000u16.to_be_bytes());
+        frame.extend_from_slice(&80u16.to_be_bytes());
+        frame.extend_from_slice(&seq.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn dissects_a_request_and_its_response() {
+        let http2 = Http2Processor::new();
+        let dissect = |flow: FlowKey, data: &[u8]| {
+            let (dissection, fields) = testing::dissect(&http2, data, |ctx| ctx.flow = Some(flow));
+            (dissection.unwrap(), fields)
+        };
+
+        let settings = frame(0x4, 0, 0, &[]);
+        let request = [CONNECTION_PREFACE, &settings, &frame(FRAME_HEADERS, FLAG_END_HEADERS | FLAG_END_STREAM, 1, REQUEST_BLOCK)].concat();
+        let (dissection, fields) = dissect(flow(80), &request);
+        assert_eq!(dissection.info, "SETTINGS[0], HEADERS[1] GET / www.example.com END_STREAM");
+        assert_eq!(testing::field(&fields, "http2.authority"), Some(&"www.example.com".into()));
+        assert_eq!(testing::field(&fields, "http2.header"), Some(&":method: GET".into()));
+
+        let response = [headers(&[0x88]), frame(FRAME_DATA, FLAG_END_STREAM, 1, b"hello")].concat();
+        let (dissection, fields) = dissect(flow(80).reversed(), &response);
+        assert_eq!(dissection.info, "HEADERS[1] 200, DATA[1] 5 bytes END_STREAM");
+        assert_eq!(testing::field(&fields, "http2.status"), Some(&"200".into()));
+    }
+
+    #[test]
+    fn waits_for_the_preface_and_whole_frames() {
+        let http2 = Http2Processor::new();
+        assert!(Http2Processor::is_preface(CONNECTION_PREFACE));
+        assert!(http2.probe(&CONNECTION_PREFACE[..20]).is_none());
+        assert!(http2.probe(CONNECTION_PREFACE).is_some());
+
+        let request = [CONNECTION_PREFACE, &headers(REQUEST_BLOCK)].concat();
+        assert!(http2.process(&flow(80), &request[..10], at(0)).is_empty());
+        assert!(http2.process(&flow(80), &request[10..30], at(0)).is_empty());
+        let frames = http2.process(&flow(80), &request[30..], at(0));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].get_header(":path"), Some("/"));
+
+        // Without the preface the stream is taken to start at a frame
+        let frames = http2.process(&flow(80).reversed(), &headers(&[0x88]), at(0));
+        assert_eq!(frames[0].get_header(":status"), Some("200"));
+    }
+
+    #[test]
+    fn strips_padding_and_priority() {
+        let http2 = Http2Processor::new();
+        // Three bytes of padding, then the stream dependency and weight
+        let mut payload = vec![3, 0x80, 0, 0, 0, 15];
+        payload.extend_from_slice(REQUEST_BLOCK);
+        payload.extend_from_slice(&[0; 3]);
+        let padded = frame(FRAME_HEADERS, FLAG_END_HEADERS | FLAG_PADDED | FLAG_PRIORITY, 1, &payload);
+        let frames = http2.process(&flow(80), &padded, at(0));
+        assert_eq!(frames[0].get_header(":authority"), Some("www.example.com"));
+
+        assert_eq!(header_block_fragment(FLAG_PADDED, &[5, 1, 2]), None);
+        assert_eq!(header_block_fragment(FLAG_PRIORITY, &[0, 0, 0]), None);
+        assert_eq!(header_block_fragment(0, &[1, 2]), Some(&[1, 2][..]));
+    }
+
+    #[test]
+    fn joins_continuation_frames() {
+        let http2 = Http2Processor::new();
+        let (first, rest) = REQUEST_BLOCK.split_at(5);
+        let frames = http2.process(&flow(80), &frame(FRAME_HEADERS, 0, 1, first), at(0));
+        assert!(frames[0].headers.is_empty());
+        let frames = http2.process(&flow(80), &frame(FRAME_CONTINUATION, FLAG_END_HEADERS, 1, rest), at(0));
+        assert_eq!(frames[0].type_name(), "CONTINUATION");
+        assert_eq!(frames[0].get_header(":authority"), Some("www.example.com"));
+    }
+
+    #[test]
+    fn bounds_buffered_frames() {
+        let http2 = Http2Processor::new();
+        // A header block that never ends
+        let continuation = frame(FRAME_CONTINUATION, 0, 1, &[0x82; 16384]);
+        let mut errors = Vec::new();
+        for _ in 0..MAX_HEADER_BLOCK_LEN / 16384 + 1 {
+            let frames = http2.process(&flow(80), &continuation, at(0));
+            errors.extend(frames.into_iter().filter_map(|frame| frame.header_error));
+            assert!(http2.streams.lock().unwrap().get(&flow(80)).unwrap().pending_headers.len() <= MAX_HEADER_BLOCK_LEN);
+        }
+        assert_eq!(errors, ["header block too long"]);
+
+        // DATA is reported on its header and not kept
+        let data = frame(FRAME_DATA, 0, 3, &[0; 1000]);
+        let frames = http2.process(&flow(80).reversed(), &data[..100], at(0));
+        assert_eq!(frames[0].format_info(), "DATA[3] 1000 bytes");
+        assert!(http2.streams.lock().unwrap().get(&flow(80).reversed()).unwrap().buffer.is_empty());
+        let frames = http2.process(&flow(80).reversed(), &[&data[100..], &headers(&[0x88])[..]].concat(), at(0));
+        assert_eq!(frames[0].get_header(":status"), Some("200"));
+    }
+
+    #[test]
+    fn forgets_connections_once_closed() {
+        let http2 = Http2Processor::new();
+        let registry = DissectorRegistry::new();
+        // A segment as the TCP dissector hands it on
+        let segment = |flow: FlowKey, flags: u8, data: &[u8]| {
+            let closed = testing::close_flow(&registry, &flow, flags, at(0));
+            let _ = testing::dissect_in(&registry, &AlertChannel::new(), &http2, data, |ctx| {
+                ctx.flow = Some(flow);
+                ctx.tcp_flags = Some(flags);
+                ctx.flow_closed = closed;
+            });
+        };
+        segment(flow(80), TcpFlags::ACK, &headers(REQUEST_BLOCK));
+        segment(flow(80).reversed(), TcpFlags::ACK, &headers(&[0x88]));
+        segment(flow(80), TcpFlags::FIN | TcpFlags::ACK, &[]);
+        assert_eq!(http2.streams.lock().unwrap().len(), 2);
+        segment(flow(80).reversed(), TcpFlags::FIN | TcpFlags::ACK, &[]);
+        assert_eq!(http2.streams.lock().unwrap().len(), 0);
+
+        segment(flow(80), TcpFlags::ACK, &headers(REQUEST_BLOCK));
+        segment(flow(80).reversed(), TcpFlags::RST, &[]);
+        assert_eq!(http2.streams.lock().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn survives_malformed_header_blocks() {
+        let processor = PacketProcessor::new(false);
+        let ethernet = processor.registry.get(DissectorTable::LinkType(LINKTYPE_ETHERNET)).unwrap();
+        let dissect = |frame: &[u8]| testing::dissect_in(&processor.registry, &processor.alerts, ethernet.as_ref(), frame, |_| {});
+
+        // An integer running past the end of the block
+        let payload = [CONNECTION_PREFACE, &headers(&[0x3f])].concat();
+        let (dissection, fields) = dissect(&tcp_frame(1, &payload));
+        let dissection = dissection.unwrap();
+        assert_eq!(dissection.protocol, "HTTP2");
+        assert!(dissection.info.starts_with("HEADERS[1] [HPACK error: "), "{}", dissection.info);
+        assert!(testing::field(&fields, "_ws.malformed").is_some());
+
+        // Later blocks depend on the lost dynamic table; the flow is still dissected
+        let (dissection, _) = dissect(&tcp_frame(1 + payload.len() as u32, &headers(&[0x82])));
+        assert_eq!(dissection.unwrap().info, "HEADERS[1] [HPACK error: header compression state lost]");
+    }
+}
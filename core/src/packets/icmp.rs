@@ -1,10 +1,181 @@
+use anyhow::Result;
 use pnet::packet::icmp::{IcmpPacket, IcmpTypes};
-use pnet::packet::icmp::echo_request::EchoRequestPacket;
-use pnet::packet::icmp::echo_reply::EchoReplyPacket;
-use pnet::packet::Packet;
+use std::net::{IpAddr, Ipv4Addr};
+use super::dissector::{Dissection, DissectContext, Dissector, Transport};
+use super::flow::FlowKey;
+use super::tree::FieldValue;
+
+/// Offset of the datagram quoted by error messages
+const EMBEDDED_OFFSET: usize = 8;
 
 pub struct IcmpProcessor;
 
+/// Start of the datagram that caused an ICMP error: its IPv4 header and the
+/// first bytes of its transport header
+#[derive(Debug, Clone)]
+pub struct EmbeddedDatagram {
+    pub source: Ipv4Addr,
+    pub destination: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub identification: u16,
+    pub header_len: usize,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+    pub tcp_sequence: Option<u32>,
+    /// Identifier and sequence number of a quoted echo request
+    pub echo: Option<(u16, u16)>,
+}
+
+impl EmbeddedDatagram {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 20 || data[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (data[0] & 0x0f) as usize * 4;
+        if header_len < 20 || data.len() < header_len {
+            return None;
+        }
+        let protocol = data[9];
+        let transport = &data[header_len..];
+        let u16_at = |offset: usize| transport.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+        let (src_port, dst_port) = match protocol {
+            6 | 17 | 132 => (u16_at(0), u16_at(2)),
+            _ => (None, None),
+        };
+        let tcp_sequence = transport
+            .get(4..8)
+            .filter(|_| protocol == 6)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+        let echo = match (protocol, transport.first()) {
+            (1, Some(8)) => u16_at(4).zip(u16_at(6)),
+            _ => None,
+        };
+        Some(Self {
+            source: Ipv4Addr::new(data[12], data[13], data[14], data[15]),
+            destination: Ipv4Addr::new(data[16], data[17], data[18], data[19]),
+            protocol,
+            ttl: data[8],
+            identification: u16::from_be_bytes([data[4], data[5]]),
+            header_len,
+            src_port,
+            dst_port,
+            tcp_sequence,
+            echo,
+        })
+    }
+
+    /// Flow of the quoted TCP or UDP datagram
+    pub fn flow(&self) -> Option<(Transport, FlowKey)> {
+        let transport = match self.protocol {
+            6 => Transport::Tcp,
+            17 => Transport::Udp,
+            _ => return None,
+        };
+        let flow = FlowKey::new(
+            IpAddr::V4(self.source),
+            self.src_port?,
+            IpAddr::V4(self.destination),
+            self.dst_port?,
+        );
+        Some((transport, flow))
+    }
+
+    pub fn format_info(&self) -> String {
+        let protocol = match self.protocol {
+            1 => "ICMP".to_string(),
+            6 => "TCP".to_string(),
+            17 => "UDP".to_string(),
+            132 => "SCTP".to_string(),
+            other => format!("protocol {}", other),
+        };
+        match (self.src_port, self.dst_port) {
+            (Some(src_port), Some(dst_port)) => format!(
+                "{}:{} > {}:{} {}",
+                self.source, src_port, self.destination, dst_port, protocol
+            ),
+            _ => format!("{} > {} {}", self.source, self.destination, protocol),
+        }
+    }
+}
+
+/// Name of an ICMP type
+pub fn type_name(icmp_type: u8) -> &'static str {
+    match icmp_type {
+        0 => "Echo Reply",
+        3 => "Destination Unreachable",
+        4 => "Source Quench",
+        5 => "Redirect",
+        6 => "Alternate Host Address",
+        8 => "Echo Request",
+        9 => "Router Advertisement",
+        10 => "Router Solicitation",
+        11 => "Time Exceeded",
+        12 => "Parameter Problem",
+        13 => "Timestamp Request",
+        14 => "Timestamp Reply",
+        15 => "Information Request",
+        16 => "Information Reply",
+        17 => "Address Mask Request",
+        18 => "Address Mask Reply",
+        30 => "Traceroute",
+        40 => "Photuris",
+        42 => "Extended Echo Request",
+        43 => "Extended Echo Reply",
+        _ => "Unknown",
+    }
+}
+
+/// Meaning of the code of a type that has several
+pub fn code_name(icmp_type: u8, code: u8) -> Option<&'static str> {
+    Some(match (icmp_type, code) {
+        (3, 0) => "Network unreachable",
+        (3, 1) => "Host unreachable",
+        (3, 2) => "Protocol unreachable",
+        (3, 3) => "Port unreachable",
+        (3, 4) => "Fragmentation needed",
+        (3, 5) => "Source route failed",
+        (3, 6) => "Destination network unknown",
+        (3, 7) => "Destination host unknown",
+        (3, 8) => "Source host isolated",
+        (3, 9) => "Network administratively prohibited",
+        (3, 10) => "Host administratively prohibited",
+        (3, 11) => "Network unreachable for TOS",
+        (3, 12) => "Host unreachable for TOS",
+        (3, 13) => "Communication administratively prohibited",
+        (3, 14) => "Host precedence violation",
+        (3, 15) => "Precedence cutoff in effect",
+        (5, 0) => "Redirect for network",
+        (5, 1) => "Redirect for host",
+        (5, 2) => "Redirect for TOS and network",
+        (5, 3) => "Redirect for TOS and host",
+        (9, 0) => "Normal router advertisement",
+        (9, 16) => "Does not route common traffic",
+        (11, 0) => "TTL exceeded in transit",
+        (11, 1) => "Fragment reassembly time exceeded",
+        (12, 0) => "Pointer indicates the error",
+        (12, 1) => "Missing a required option",
+        (12, 2) => "Bad length",
+        (40, 0) => "Bad SPI",
+        (40, 1) => "Authentication failed",
+        (40, 2) => "Decompression failed",
+        (40, 3) => "Decryption failed",
+        (40, 4) => "Need authentication",
+        (40, 5) => "Need authorization",
+        (43, 0) => "No error",
+        (43, 1) => "Malformed query",
+        (43, 2) => "No such interface",
+        (43, 3) => "No such table entry",
+        (43, 4) => "Multiple interfaces satisfy query",
+        _ => return None,
+    })
+}
+
+/// Error messages quoting the datagram that caused them
+fn is_error(icmp_type: u8) -> bool {
+    matches!(icmp_type, 3 | 4 | 5 | 11 | 12)
+}
+
 impl IcmpProcessor {
     pub fn new() -> Self {
         Self
@@ -15,29 +186,298 @@ impl IcmpProcessor {
     }
 
     pub fn get_icmp_type(&self, packet: &IcmpPacket) -> String {
+        // The rest of the header (identifier, pointer, MTU...) follows the checksum
+        let data = pnet::packet::Packet::packet(packet);
+        let u16_at = |offset: usize| data.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+        let icmp_type = packet.get_icmp_type().0;
+        let code = packet.get_icmp_code().0;
+        let name = type_name(icmp_type);
+        let mut info = match code_name(icmp_type, code) {
+            Some(code_name) => format!("{} ({})", name, code_name),
+            None if name == "Unknown" => format!("Unknown ICMP type: {}", icmp_type),
+            None => name.to_string(),
+        };
+
         match packet.get_icmp_type() {
-            IcmpTypes::EchoRequest => {
-                if let Some(echo) = EchoRequestPacket::new(packet.payload()) {
-                    format!("Echo Request (id={}, seq={})", echo.get_identifier(), echo.get_sequence_number())
-                } else {
-                    "Echo Request".to_string()
+            IcmpTypes::EchoRequest
+            | IcmpTypes::EchoReply
+            | IcmpTypes::Timestamp
+            | IcmpTypes::TimestampReply
+            | IcmpTypes::InformationRequest
+            | IcmpTypes::InformationReply
+            | IcmpTypes::AddressMaskRequest
+            | IcmpTypes::AddressMaskReply => {
+                if let (Some(id), Some(seq)) = (u16_at(4), u16_at(6)) {
+                    info.push_str(&format!(" (id={}, seq={})", id, seq));
+                }
+            }
+            IcmpTypes::DestinationUnreachable if code == 4 => {
+                if let Some(mtu) = u16_at(6).filter(|&mtu| mtu != 0) {
+                    info = format!("{} ({}, next-hop MTU {})", name, code_name(3, 4).unwrap_or_default(), mtu);
+                }
+            }
+            IcmpTypes::RedirectMessage => {
+                if let Some(gateway) = data.get(4..8) {
+                    info.push_str(&format!(" to {}", Ipv4Addr::new(gateway[0], gateway[1], gateway[2], gateway[3])));
+                }
+            }
+            IcmpTypes::ParameterProblem if code == 0 => {
+                if let Some(pointer) = data.get(4) {
+                    info.push_str(&format!(" at byte {}", pointer));
+                }
+            }
+            _ => {}
+        }
+        info
+    }
+
+    /// Fields of the rest of the header, whose layout depends on the type
+    fn dissect_header(&self, data: &[u8], ctx: &mut DissectContext) {
+        let u16_at = |offset: usize| data.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+        let u32_at = |offset: usize| data.get(offset..offset + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+        let icmp_type = data[0];
+        let code = data[1];
+        match icmp_type {
+            0 | 8 | 13 | 14 | 15 | 16 | 17 | 18 => {
+                if let (Some(id), Some(seq)) = (u16_at(4), u16_at(6)) {
+                    ctx.add_field_at("icmp.ident", 4, 2, FieldValue::Hex(id as u64));
+                    ctx.add_field_at("icmp.seq", 6, 2, seq);
+                }
+                match icmp_type {
+                    13 | 14 => {
+                        let names = ["icmp.originate_timestamp", "icmp.receive_timestamp", "icmp.transmit_timestamp"];
+                        for (index, name) in names.into_iter().enumerate() {
+                            if let Some(timestamp) = u32_at(8 + index * 4) {
+                                ctx.add_field_at(name, 8 + index * 4, 4, timestamp);
+                            }
+                        }
+                    }
+                    17 | 18 => {
+                        if let Some(mask) = u32_at(8) {
+                            ctx.add_field_at("icmp.address_mask", 8, 4, IpAddr::V4(Ipv4Addr::from(mask)));
+                        }
+                    }
+                    _ => {
+                        if data.len() > 8 {
+                            ctx.add_field_at("icmp.data", 8, data.len() - 8, FieldValue::Bytes(data[8..].to_vec()));
+                        }
+                    }
                 }
             }
-            IcmpTypes::EchoReply => {
-                if let Some(echo) = EchoReplyPacket::new(packet.payload()) {
-                    format!("Echo Reply (id={}, seq={})", echo.get_identifier(), echo.get_sequence_number())
-                } else {
-                    "Echo Reply".to_string()
+            3 if code == 4 => {
+                if let Some(mtu) = u16_at(6) {
+                    ctx.add_field_at("icmp.mtu", 6, 2, mtu);
                 }
             }
-            IcmpTypes::DestinationUnreachable => "Destination Unreachable".to_string(),
-            IcmpTypes::TimeExceeded => "Time Exceeded".to_string(),
-            IcmpTypes::ParameterProblem => "Parameter Problem".to_string(),
-            IcmpTypes::SourceQuench => "Source Quench".to_string(),
-            IcmpTypes::TimestampReply => "Timestamp Reply".to_string(),
-            IcmpTypes::AddressMaskRequest => "Address Mask Request".to_string(),
-            IcmpTypes::AddressMaskReply => "Address Mask Reply".to_string(),
-            _ => format!("Unknown ICMP type: {}", packet.get_icmp_type().0),
+            5 => {
+                if let Some(gateway) = u32_at(4) {
+                    ctx.add_field_at("icmp.redir_gw", 4, 4, IpAddr::V4(Ipv4Addr::from(gateway)));
+                }
+            }
+            9 => {
+                let count = data.get(4).copied().unwrap_or_default() as usize;
+                let entry_size = data.get(5).copied().unwrap_or_default() as usize;
+                ctx.add_field_at("icmp.num_addrs", 4, 1, count);
+                if let Some(lifetime) = u16_at(6) {
+                    ctx.add_field_at("icmp.lifetime", 6, 2, lifetime);
+                }
+                // Entries are a router address and its preference, in 32-bit words
+                for index in 0..count {
+                    let offset = 8 + index * entry_size.max(2) * 4;
+                    let (Some(address), Some(preference)) = (u32_at(offset), u32_at(offset + 4)) else {
+                        break;
+                    };
+                    ctx.add_field_at("icmp.router_address", offset, 4, IpAddr::V4(Ipv4Addr::from(address)));
+                    ctx.add_field_at("icmp.preference_level", offset + 4, 4, FieldValue::Hex(preference as u64));
+                }
+            }
+            12 => {
+                if let Some(pointer) = data.get(4) {
+                    ctx.add_field_at("icmp.pointer", 4, 1, *pointer);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Fields of the quoted datagram, linked to the flow it belongs to.
+    /// Returns its summary.
+    fn dissect_embedded(&self, data: &[u8], ctx: &mut DissectContext) -> Option<String> {
+        let quoted = data.get(EMBEDDED_OFFSET..)?;
+        let datagram = EmbeddedDatagram::parse(quoted)?;
+        let at = |offset: usize| EMBEDDED_OFFSET + offset;
+        ctx.add_field_at("icmp.original.ip.src", at(12), 4, IpAddr::V4(datagram.source));
+        ctx.add_field_at("icmp.original.ip.dst", at(16), 4, IpAddr::V4(datagram.destination));
+        ctx.add_field_at("icmp.original.ip.proto", at(9), 1, datagram.protocol);
+        ctx.add_field_at("icmp.original.ip.ttl", at(8), 1, datagram.ttl);
+        ctx.add_field_at("icmp.original.ip.id", at(4), 2, FieldValue::Hex(datagram.identification as u64));
+        let transport = at(datagram.header_len);
+        if let (Some(src_port), Some(dst_port)) = (datagram.src_port, datagram.dst_port) {
+            ctx.add_field_at("icmp.original.srcport", transport, 2, src_port);
+            ctx.add_field_at("icmp.original.dstport", transport + 2, 2, dst_port);
         }
+        if let Some(sequence) = datagram.tcp_sequence {
+            ctx.add_field_at("icmp.original.tcp.seq", transport + 4, 4, sequence);
+        }
+        if let Some((id, seq)) = datagram.echo {
+            ctx.add_field_at("icmp.original.ident", transport + 4, 2, FieldValue::Hex(id as u64));
+            ctx.add_field_at("icmp.original.seq", transport + 6, 2, seq);
+        }
+
+        let mut info = datagram.format_info();
+        if let Some((transport, flow)) = datagram.flow() {
+            ctx.add_field("icmp.original.flow", flow.to_string());
+            if let Some(record) = ctx.registry().flow(transport, &flow) {
+                ctx.add_field("icmp.original.frame", record.last_frame);
+                if let Some(protocol) = record.protocol {
+                    ctx.add_field("icmp.original.protocol", protocol);
+                    info = format!("{} ({})", info, protocol);
+                }
+            }
+        }
+        Some(info)
+    }
+}
+
+impl Dissector for IcmpProcessor {
+    fn name(&self) -> &'static str {
+        "ICMP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let icmp = self.process(data).ok_or_else(|| anyhow::anyhow!("Packet too short for ICMP"))?;
+        let icmp_type = icmp.get_icmp_type().0;
+        ctx.add_field_at("icmp.type", 0, 1, icmp_type);
+        ctx.add_field_at("icmp.code", 1, 1, icmp.get_icmp_code().0);
+        ctx.add_field_at("icmp.checksum", 2, 2, FieldValue::Hex(icmp.get_checksum() as u64));
+        self.dissect_header(data, ctx);
+
+        let mut info = format!("{} > {}: {}", ctx.source, ctx.destination, self.get_icmp_type(&icmp));
+        if is_error(icmp_type) {
+            if let Some(original) = self.dissect_embedded(data, ctx) {
+                info.push_str(&format!(" for {}", original));
+            }
+        }
+        Ok(Dissection::new("ICMP", info))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::AlertChannel;
+    use chrono::Utc;
+    use super::super::dissector::{testing, DissectorRegistry};
+    use super::super::tree::Field;
+
+    /// IPv4 header from 10.0.0.1 to 10.0.0.2 carrying `transport`
+    fn quoted(protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let mut datagram = vec![0x45, 0, 0, 0, 0x12, 0x34, 0, 0, 64, protocol, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        datagram.extend(transport);
+        datagram
+    }
+
+    fn message(icmp_type: u8, code: u8, rest: [u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![icmp_type, code, 0, 0];
+        data.extend(rest);
+        data.extend(payload);
+        data
+    }
+
+    fn dissect_in(registry: &DissectorRegistry, data: &[u8]) -> (String, Vec<Field>) {
+        let (dissection, fields) = testing::dissect_in(registry, &AlertChannel::new(), &IcmpProcessor::new(), data, |ctx| {
+            ctx.source = "10.0.0.2".to_string();
+            ctx.destination = "10.0.0.1".to_string();
+        });
+        (dissection.unwrap().info, fields)
+    }
+
+    fn dissect(data: &[u8]) -> (String, Vec<Field>) {
+        dissect_in(&DissectorRegistry::new(), data)
+    }
+
+    #[test]
+    fn links_port_unreachable_to_the_quoted_flow() {
+        let registry = DissectorRegistry::new();
+        let flow = FlowKey::new("10.0.0.1".parse().unwrap(), 5353, "10.0.0.2".parse().unwrap(), 53);
+        registry.note_flow(Transport::Udp, &flow, None, 7, Some("DNS"), Utc::now());
+
+        let udp = [0x14, 0xe9, 0, 53, 0, 8, 0, 0];
+        let (info, fields) = dissect_in(&registry, &message(3, 3, [0; 4], &quoted(17, &udp)));
+        assert_eq!(
+            info,
+            "10.0.0.2 > 10.0.0.1: Destination Unreachable (Port unreachable) for 10.0.0.1:5353 > 10.0.0.2:53 UDP (DNS)"
+        );
+        assert_eq!(testing::field(&fields, "icmp.original.ip.proto"), Some(&FieldValue::Uint(17)));
+        assert_eq!(testing::field(&fields, "icmp.original.ip.id"), Some(&FieldValue::Hex(0x1234)));
+        assert_eq!(testing::field(&fields, "icmp.original.srcport"), Some(&FieldValue::Uint(5353)));
+        assert_eq!(testing::field(&fields, "icmp.original.dstport"), Some(&FieldValue::Uint(53)));
+        assert_eq!(testing::field(&fields, "icmp.original.frame"), Some(&FieldValue::Uint(7)));
+        assert_eq!(testing::field(&fields, "icmp.original.protocol"), Some(&FieldValue::Text("DNS".into())));
+    }
+
+    #[test]
+    fn reports_the_next_hop_mtu() {
+        let tcp = [0xc3, 0x50, 0, 80, 0, 0, 0x10, 0];
+        let (info, fields) = dissect(&message(3, 4, [0, 0, 0x05, 0x78], &quoted(6, &tcp)));
+        assert_eq!(
+            info,
+            "10.0.0.2 > 10.0.0.1: Destination Unreachable (Fragmentation needed, next-hop MTU 1400) \
+             for 10.0.0.1:50000 > 10.0.0.2:80 TCP"
+        );
+        assert_eq!(testing::field(&fields, "icmp.mtu"), Some(&FieldValue::Uint(1400)));
+        assert_eq!(testing::field(&fields, "icmp.original.tcp.seq"), Some(&FieldValue::Uint(0x1000)));
+        // Unknown flows are not linked
+        assert!(testing::field(&fields, "icmp.original.frame").is_none());
+    }
+
+    #[test]
+    fn dissects_redirects() {
+        let echo = [8, 0, 0, 0, 0, 1, 0, 2];
+        let (info, fields) = dissect(&message(5, 1, [10, 0, 0, 254], &quoted(1, &echo)));
+        assert_eq!(
+            info,
+            "10.0.0.2 > 10.0.0.1: Redirect (Redirect for host) to 10.0.0.254 for 10.0.0.1 > 10.0.0.2 ICMP"
+        );
+        assert_eq!(testing::field(&fields, "icmp.redir_gw"), Some(&FieldValue::Ip("10.0.0.254".parse().unwrap())));
+        assert_eq!(testing::field(&fields, "icmp.original.ident"), Some(&FieldValue::Hex(1)));
+        assert_eq!(testing::field(&fields, "icmp.original.seq"), Some(&FieldValue::Uint(2)));
+    }
+
+    #[test]
+    fn dissects_timestamps() {
+        let timestamps = [0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3];
+        let (info, fields) = dissect(&message(13, 0, [0x12, 0x34, 0, 1], &timestamps));
+        assert_eq!(info, "10.0.0.2 > 10.0.0.1: Timestamp Request (id=4660, seq=1)");
+        assert_eq!(testing::field(&fields, "icmp.ident"), Some(&FieldValue::Hex(0x1234)));
+        assert_eq!(testing::field(&fields, "icmp.originate_timestamp"), Some(&FieldValue::Uint(1)));
+        assert_eq!(testing::field(&fields, "icmp.receive_timestamp"), Some(&FieldValue::Uint(2)));
+        assert_eq!(testing::field(&fields, "icmp.transmit_timestamp"), Some(&FieldValue::Uint(3)));
+
+        let (info, _) = dissect(&message(14, 0, [0x12, 0x34, 0, 1], &timestamps));
+        assert_eq!(info, "10.0.0.2 > 10.0.0.1: Timestamp Reply (id=4660, seq=1)");
+        // Errors only: a request carries no quoted datagram
+        assert!(testing::field(&fields, "icmp.original.ip.src").is_none());
+    }
+
+    #[test]
+    fn tolerates_truncated_quoted_headers() {
+        let datagram = quoted(17, &[0x14, 0xe9, 0, 53]);
+
+        // IPv4 header cut short
+        let (info, fields) = dissect(&message(3, 3, [0; 4], &datagram[..16]));
+        assert_eq!(info, "10.0.0.2 > 10.0.0.1: Destination Unreachable (Port unreachable)");
+        assert!(testing::field(&fields, "icmp.original.ip.src").is_none());
+
+        // Options announced but missing
+        let mut options = datagram[..20].to_vec();
+        options[0] = 0x46;
+        assert!(dissect(&message(3, 3, [0; 4], &options)).1.iter().all(|field| !field.name.starts_with("icmp.original")));
+
+        // Only the source port quoted
+        let (info, fields) = dissect(&message(3, 3, [0; 4], &datagram[..22]));
+        assert!(info.ends_with("for 10.0.0.1 > 10.0.0.2 UDP"), "{}", info);
+        assert!(testing::field(&fields, "icmp.original.srcport").is_none());
+        assert!(testing::field(&fields, "icmp.original.flow").is_none());
     }
-} 
\ No newline at end of file
+}
@@ -1,13 +1,665 @@
+use anyhow::Result;
 use pnet::packet::icmpv6::Icmpv6Packet;
+use pnet::util::MacAddr;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use super::dissector::{Dissection, DissectContext, Dissector, Transport};
+use super::flow::FlowKey;
+use super::neighbors::{Ipv6Neighbor, NeighborTable};
+use super::tree::FieldValue;
 
-pub struct Icmpv6Processor;
+/// Offset of the packet quoted by error messages
+const EMBEDDED_OFFSET: usize = 8;
+
+/// ICMPv6 with Neighbor Discovery and Multicast Listener Discovery. The
+/// Neighbor Discovery messages feed an IPv6 neighbor table.
+pub struct Icmpv6Processor {
+    neighbors: Mutex<NeighborTable>,
+}
+
+/// Name of an ICMPv6 type
+pub fn type_name(icmp_type: u8) -> &'static str {
+    match icmp_type {
+        1 => "Destination Unreachable",
+        2 => "Packet Too Big",
+        3 => "Time Exceeded",
+        4 => "Parameter Problem",
+        128 => "Echo Request",
+        129 => "Echo Reply",
+        130 => "Multicast Listener Query",
+        131 => "Multicast Listener Report",
+        132 => "Multicast Listener Done",
+        133 => "Router Solicitation",
+        134 => "Router Advertisement",
+        135 => "Neighbor Solicitation",
+        136 => "Neighbor Advertisement",
+        137 => "Redirect",
+        138 => "Router Renumbering",
+        139 => "Node Information Query",
+        140 => "Node Information Response",
+        141 => "Inverse Neighbor Discovery Solicitation",
+        142 => "Inverse Neighbor Discovery Advertisement",
+        143 => "Multicast Listener Report v2",
+        144 => "Home Agent Address Discovery Request",
+        145 => "Home Agent Address Discovery Reply",
+        146 => "Mobile Prefix Solicitation",
+        147 => "Mobile Prefix Advertisement",
+        151 => "Multicast Router Advertisement",
+        152 => "Multicast Router Solicitation",
+        153 => "Multicast Router Termination",
+        155 => "RPL Control",
+        _ => "Unknown",
+    }
+}
+
+/// Meaning of the code of the error messages
+pub fn code_name(icmp_type: u8, code: u8) -> Option<&'static str> {
+    Some(match (icmp_type, code) {
+        (1, 0) => "No route to destination",
+        (1, 1) => "Administratively prohibited",
+        (1, 2) => "Beyond scope of source address",
+        (1, 3) => "Address unreachable",
+        (1, 4) => "Port unreachable",
+        (1, 5) => "Source address failed ingress/egress policy",
+        (1, 6) => "Reject route to destination",
+        (1, 7) => "Error in source routing header",
+        (3, 0) => "Hop limit exceeded in transit",
+        (3, 1) => "Fragment reassembly time exceeded",
+        (4, 0) => "Erroneous header field",
+        (4, 1) => "Unrecognized next header type",
+        (4, 2) => "Unrecognized IPv6 option",
+        (4, 3) => "First fragment has incomplete header chain",
+        _ => return None,
+    })
+}
+
+/// Record types of MLDv2 reports, shared with IGMPv3
+pub fn record_type_name(record_type: u8) -> &'static str {
+    match record_type {
+        1 => "MODE_IS_INCLUDE",
+        2 => "MODE_IS_EXCLUDE",
+        3 => "CHANGE_TO_INCLUDE_MODE",
+        4 => "CHANGE_TO_EXCLUDE_MODE",
+        5 => "ALLOW_NEW_SOURCES",
+        6 => "BLOCK_OLD_SOURCES",
+        _ => "Unknown",
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn ipv6_at(data: &[u8], offset: usize) -> Option<Ipv6Addr> {
+    let bytes: [u8; 16] = data.get(offset..offset + 16)?.try_into().ok()?;
+    Some(Ipv6Addr::from(bytes))
+}
+
+/// Neighbor Discovery options of interest
+#[derive(Debug, Default)]
+struct NdOptions {
+    source_mac: Option<MacAddr>,
+    target_mac: Option<MacAddr>,
+    prefixes: Vec<(Ipv6Addr, u8)>,
+    mtu: Option<u32>,
+    dns_servers: Vec<Ipv6Addr>,
+}
 
 impl Icmpv6Processor {
     pub fn new() -> Self {
-        Self
+        Self {
+            neighbors: Mutex::new(NeighborTable::default()),
+        }
     }
 
     pub fn process<'a>(&self, data: &'a [u8]) -> Option<Icmpv6Packet<'a>> {
         Icmpv6Packet::new(data)
     }
-} 
\ No newline at end of file
+
+    /// IPv6 neighbors learned so far
+    pub fn neighbors(&self) -> Vec<Ipv6Neighbor> {
+        self.neighbors.lock().unwrap().neighbors()
+    }
+
+    /// Decodes the Neighbor Discovery options starting at `start`
+    fn dissect_options(&self, data: &[u8], start: usize, ctx: &mut DissectContext) -> NdOptions {
+        let mut options = NdOptions::default();
+        let mut offset = start;
+        while offset + 2 <= data.len() {
+            let kind = data[offset];
+            let len = data[offset + 1] as usize * 8;
+            if len == 0 || offset + len > data.len() {
+                let problem = if len == 0 { "has length 0" } else { "runs past the packet" };
+                ctx.add_field("_ws.malformed", format!("ICMPv6 option {} {}", kind, problem));
+                break;
+            }
+            let option = &data[offset..offset + len];
+            ctx.add_field_at("icmpv6.opt.type", offset, 1, kind);
+            match kind {
+                1 | 2 if len >= 8 => {
+                    let mac = MacAddr::new(option[2], option[3], option[4], option[5], option[6], option[7]);
+                    let name = if kind == 1 { "icmpv6.opt.src_linkaddr" } else { "icmpv6.opt.target_linkaddr" };
+                    ctx.add_field_at(name, offset + 2, 6, FieldValue::Mac(mac.octets()));
+                    if kind == 1 {
+                        options.source_mac = Some(mac);
+                    } else {
+                        options.target_mac = Some(mac);
+                    }
+                }
+                3 if len >= 32 => {
+                    let prefix_len = option[2];
+                    let flags = option[3];
+                    ctx.add_field_at("icmpv6.opt.prefix.length", offset + 2, 1, prefix_len);
+                    ctx.add_field_at("icmpv6.opt.prefix.flag.l", offset + 3, 1, flags & 0x80 != 0);
+                    ctx.add_field_at("icmpv6.opt.prefix.flag.a", offset + 3, 1, flags & 0x40 != 0);
+                    if let (Some(valid), Some(preferred)) = (u32_at(option, 4), u32_at(option, 8)) {
+                        ctx.add_field_at("icmpv6.opt.prefix.valid_lifetime", offset + 4, 4, valid);
+                        ctx.add_field_at("icmpv6.opt.prefix.preferred_lifetime", offset + 8, 4, preferred);
+                    }
+                    if let Some(prefix) = ipv6_at(option, 16) {
+                        ctx.add_field_at("icmpv6.opt.prefix", offset + 16, 16, IpAddr::V6(prefix));
+                        options.prefixes.push((prefix, prefix_len));
+                    }
+                }
+                5 if len >= 8 => {
+                    if let Some(mtu) = u32_at(option, 4) {
+                        ctx.add_field_at("icmpv6.opt.mtu", offset + 4, 4, mtu);
+                        options.mtu = Some(mtu);
+                    }
+                }
+                24 if len >= 8 => {
+                    let prefix_len = option[2] as usize;
+                    let mut prefix = [0u8; 16];
+                    let available = (len - 8).min(16).min(prefix_len.div_ceil(8));
+                    prefix[..available].copy_from_slice(&option[8..8 + available]);
+                    ctx.add_field_at("icmpv6.opt.route_info.prefix", offset + 8, len - 8, IpAddr::V6(Ipv6Addr::from(prefix)));
+                    ctx.add_field_at("icmpv6.opt.route_info.prefix_len", offset + 2, 1, prefix_len);
+                    if let Some(lifetime) = u32_at(option, 4) {
+                        ctx.add_field_at("icmpv6.opt.route_lifetime", offset + 4, 4, lifetime);
+                    }
+                }
+                25 if len >= 24 => {
+                    if let Some(lifetime) = u32_at(option, 4) {
+                        ctx.add_field_at("icmpv6.opt.rdnss.lifetime", offset + 4, 4, lifetime);
+                    }
+                    for at in (8..len).step_by(16) {
+                        if let Some(server) = ipv6_at(option, at) {
+                            ctx.add_field_at("icmpv6.opt.rdnss", offset + at, 16, IpAddr::V6(server));
+                            options.dns_servers.push(server);
+                        }
+                    }
+                }
+                31 if len >= 8 => {
+                    if let Some(lifetime) = u32_at(option, 4) {
+                        ctx.add_field_at("icmpv6.opt.dnssl.lifetime", offset + 4, 4, lifetime);
+                    }
+                    for name in dns_search_list(&option[8..]) {
+                        ctx.add_field("icmpv6.opt.dnssl", name);
+                    }
+                }
+                _ => {}
+            }
+            offset += len;
+        }
+        options
+    }
+
+    /// Decodes the body of the message; returns the summary after the type name
+    fn dissect_message(&self, data: &[u8], ctx: &mut DissectContext) -> String {
+        let icmp_type = data[0];
+        let code = data[1];
+        let ip = |offset: usize| ipv6_at(data, offset);
+        match icmp_type {
+            1 | 3 | 4 => {
+                let mut info = code_name(icmp_type, code).map(|name| format!(" ({})", name)).unwrap_or_default();
+                if icmp_type == 4 {
+                    if let Some(pointer) = u32_at(data, 4) {
+                        ctx.add_field_at("icmpv6.pointer", 4, 4, pointer);
+                        info.push_str(&format!(" at byte {}", pointer));
+                    }
+                }
+                info + &self.dissect_embedded(data, ctx)
+            }
+            2 => {
+                let mtu = u32_at(data, 4).unwrap_or_default();
+                ctx.add_field_at("icmpv6.mtu", 4, 4, mtu);
+                format!(" (MTU {}){}", mtu, self.dissect_embedded(data, ctx))
+            }
+            128 | 129 => {
+                let (Some(id), Some(seq)) = (u16_at(data, 4), u16_at(data, 6)) else {
+                    return String::new();
+                };
+                ctx.add_field_at("icmpv6.echo.identifier", 4, 2, FieldValue::Hex(id as u64));
+                ctx.add_field_at("icmpv6.echo.sequence_number", 6, 2, seq);
+                if data.len() > 8 {
+                    ctx.add_field_at("icmpv6.data", 8, data.len() - 8, FieldValue::Bytes(data[8..].to_vec()));
+                }
+                format!(" (id={}, seq={})", id, seq)
+            }
+            130..=132 => {
+                let (Some(delay), Some(group)) = (u16_at(data, 4), ip(8)) else {
+                    return String::new();
+                };
+                ctx.add_field_at("icmpv6.mld.maximum_response_delay", 4, 2, delay);
+                ctx.add_field_at("icmpv6.mld.multicast_address", 8, 16, IpAddr::V6(group));
+                let target = if group.is_unspecified() { "general".to_string() } else { group.to_string() };
+                if icmp_type != 130 {
+                    return format!(" {}", group);
+                }
+                // MLDv2 queries are longer than the 24 bytes of MLDv1
+                if data.len() >= 28 {
+                    ctx.add_field_at("icmpv6.mld.flag.s", 24, 1, data[24] & 0x08 != 0);
+                    ctx.add_field_at("icmpv6.mld.qrv", 24, 1, data[24] & 0x07);
+                    ctx.add_field_at("icmpv6.mld.qqi", 25, 1, data[25]);
+                    let count = u16_at(data, 26).unwrap_or_default() as usize;
+                    ctx.add_field_at("icmpv6.mld.nb_sources", 26, 2, count);
+                    for index in 0..count {
+                        let Some(source) = ip(28 + index * 16) else {
+                            break;
+                        };
+                        ctx.add_field_at("icmpv6.mld.source_address", 28 + index * 16, 16, IpAddr::V6(source));
+                    }
+                    return format!(" v2 ({}, {} sources)", target, count);
+                }
+                format!(" ({})", target)
+            }
+            143 => {
+                let count = u16_at(data, 6).unwrap_or_default() as usize;
+                ctx.add_field_at("icmpv6.mldr.nb_mcast_records", 6, 2, count);
+                let mut records = Vec::new();
+                let mut offset = 8;
+                for _ in 0..count {
+                    let (Some(&kind), Some(&aux_len), Some(sources), Some(group)) =
+                        (data.get(offset), data.get(offset + 1), u16_at(data, offset + 2), ip(offset + 4))
+                    else {
+                        break;
+                    };
+                    ctx.add_field_at("icmpv6.mldr.mar.record_type", offset, 1, record_type_name(kind));
+                    ctx.add_field_at("icmpv6.mldr.mar.nb_sources", offset + 2, 2, sources);
+                    ctx.add_field_at("icmpv6.mldr.mar.multicast_address", offset + 4, 16, IpAddr::V6(group));
+                    for index in 0..sources as usize {
+                        if let Some(source) = ip(offset + 20 + index * 16) {
+                            ctx.add_field_at("icmpv6.mldr.mar.source_address", offset + 20 + index * 16, 16, IpAddr::V6(source));
+                        }
+                    }
+                    records.push(format!("{} {}", record_type_name(kind), group));
+                    offset += 20 + sources as usize * 16 + aux_len as usize * 4;
+                }
+                format!(": {}", records.join(", "))
+            }
+            133 => {
+                let options = self.dissect_options(data, 8, ctx);
+                let mut info = String::new();
+                if let Some(mac) = options.source_mac {
+                    info.push_str(&format!(" from {}", mac));
+                    self.learn(ctx, ctx.src_ip, mac, None, "RS");
+                }
+                info
+            }
+            134 => {
+                if data.len() < 16 {
+                    return String::new();
+                }
+                let flags = data[5];
+                let lifetime = u16_at(data, 6).unwrap_or_default();
+                ctx.add_field_at("icmpv6.nd.ra.cur_hop_limit", 4, 1, data[4]);
+                ctx.add_field_at("icmpv6.nd.ra.flag.m", 5, 1, flags & 0x80 != 0);
+                ctx.add_field_at("icmpv6.nd.ra.flag.o", 5, 1, flags & 0x40 != 0);
+                ctx.add_field_at("icmpv6.nd.ra.router_lifetime", 6, 2, lifetime);
+                ctx.add_field_at("icmpv6.nd.ra.reachable_time", 8, 4, u32_at(data, 8).unwrap_or_default());
+                ctx.add_field_at("icmpv6.nd.ra.retrans_timer", 12, 4, u32_at(data, 12).unwrap_or_default());
+                let options = self.dissect_options(data, 16, ctx);
+                if let Some(mac) = options.source_mac {
+                    self.learn(ctx, ctx.src_ip, mac, Some(lifetime != 0), "RA");
+                }
+
+                let mut info = format!(" (lifetime {} s", lifetime);
+                if flags & 0x80 != 0 {
+                    info.push_str(", M");
+                }
+                if flags & 0x40 != 0 {
+                    info.push_str(", O");
+                }
+                info.push(')');
+                for (prefix, len) in &options.prefixes {
+                    info.push_str(&format!(" prefix {}/{}", prefix, len));
+                }
+                if let Some(mtu) = options.mtu {
+                    info.push_str(&format!(" MTU {}", mtu));
+                }
+                if !options.dns_servers.is_empty() {
+                    let servers: Vec<String> = options.dns_servers.iter().map(|s| s.to_string()).collect();
+                    info.push_str(&format!(" DNS {}", servers.join(",")));
+                }
+                info
+            }
+            135 => {
+                let Some(target) = ip(8) else {
+                    return String::new();
+                };
+                ctx.add_field_at("icmpv6.nd.ns.target_address", 8, 16, IpAddr::V6(target));
+                let options = self.dissect_options(data, 24, ctx);
+                let mut info = format!(" for {}", target);
+                if let Some(mac) = options.source_mac {
+                    info.push_str(&format!(" from {}", mac));
+                    self.learn(ctx, ctx.src_ip, mac, None, "NS");
+                } else if ctx.src_ip.is_some_and(|src| src.is_unspecified()) {
+                    info.push_str(" (duplicate address detection)");
+                }
+                info
+            }
+            136 => {
+                let Some(target) = ip(8) else {
+                    return String::new();
+                };
+                let flags = data[4];
+                let router = flags & 0x80 != 0;
+                ctx.add_field_at("icmpv6.nd.na.flag.r", 4, 1, router);
+                ctx.add_field_at("icmpv6.nd.na.flag.s", 4, 1, flags & 0x40 != 0);
+                ctx.add_field_at("icmpv6.nd.na.flag.o", 4, 1, flags & 0x20 != 0);
+                ctx.add_field_at("icmpv6.nd.na.target_address", 8, 16, IpAddr::V6(target));
+                let options = self.dissect_options(data, 24, ctx);
+
+                let names: Vec<&str> = [(0x80, "rtr"), (0x40, "sol"), (0x20, "ovr")]
+                    .into_iter()
+                    .filter(|(flag, _)| flags & flag != 0)
+                    .map(|(_, name)| name)
+                    .collect();
+                let mut info = format!(" {}", target);
+                if !names.is_empty() {
+                    info.push_str(&format!(" ({})", names.join(", ")));
+                }
+                if let Some(mac) = options.target_mac {
+                    info.push_str(&format!(" is at {}", mac));
+                    self.learn(ctx, Some(IpAddr::V6(target)), mac, Some(router), "NA");
+                }
+                info
+            }
+            137 => {
+                let (Some(target), Some(destination)) = (ip(8), ip(24)) else {
+                    return String::new();
+                };
+                ctx.add_field_at("icmpv6.nd.rd.target_address", 8, 16, IpAddr::V6(target));
+                ctx.add_field_at("icmpv6.nd.rd.destination_address", 24, 16, IpAddr::V6(destination));
+                let options = self.dissect_options(data, 40, ctx);
+                if let Some(mac) = options.target_mac {
+                    self.learn(ctx, Some(IpAddr::V6(target)), mac, None, "RD");
+                }
+                format!(" to {} for {}", target, destination)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn learn(&self, ctx: &DissectContext, ip: Option<IpAddr>, mac: MacAddr, is_router: Option<bool>, learned_from: &'static str) {
+        if let Some(IpAddr::V6(ip)) = ip {
+            self.neighbors
+                .lock()
+                .unwrap()
+                .learn(ip, mac.to_string(), is_router, learned_from, ctx.captured_at);
+        }
+    }
+
+    /// Fields of the packet quoted by an error message, linked to the flow it
+    /// belongs to. Returns the summary to append.
+    fn dissect_embedded(&self, data: &[u8], ctx: &mut DissectContext) -> String {
+        let quoted = data.get(EMBEDDED_OFFSET..).unwrap_or_default();
+        let (Some(source), Some(destination), Some(&next_header)) =
+            (ipv6_at(quoted, 8), ipv6_at(quoted, 24), quoted.get(6))
+        else {
+            return String::new();
+        };
+        let at = |offset: usize| EMBEDDED_OFFSET + offset;
+        ctx.add_field_at("icmpv6.original.ipv6.src", at(8), 16, IpAddr::V6(source));
+        ctx.add_field_at("icmpv6.original.ipv6.dst", at(24), 16, IpAddr::V6(destination));
+        ctx.add_field_at("icmpv6.original.ipv6.nxt", at(6), 1, next_header);
+
+        let transport = match next_header {
+            6 => Transport::Tcp,
+            17 => Transport::Udp,
+            _ => return format!(" for {} > {}", source, destination),
+        };
+        let (Some(src_port), Some(dst_port)) = (u16_at(quoted, 40), u16_at(quoted, 42)) else {
+            return format!(" for {} > {}", source, destination);
+        };
+        ctx.add_field_at("icmpv6.original.srcport", at(40), 2, src_port);
+        ctx.add_field_at("icmpv6.original.dstport", at(42), 2, dst_port);
+        let flow = FlowKey::new(IpAddr::V6(source), src_port, IpAddr::V6(destination), dst_port);
+        ctx.add_field("icmpv6.original.flow", flow.to_string());
+        let mut info = format!(
+            " for [{}]:{} > [{}]:{} {}",
+            source,
+            src_port,
+            destination,
+            dst_port,
+            if next_header == 6 { "TCP" } else { "UDP" }
+        );
+        if let Some(record) = ctx.registry().flow(transport, &flow) {
+            ctx.add_field("icmpv6.original.frame", record.last_frame);
+            if let Some(protocol) = record.protocol {
+                ctx.add_field("icmpv6.original.protocol", protocol);
+                info.push_str(&format!(" ({})", protocol));
+            }
+        }
+        info
+    }
+}
+
+/// Domain names of a DNS Search List option, in DNS wire format
+fn dns_search_list(data: &[u8]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut labels = Vec::new();
+    let mut offset = 0;
+    while let Some(&len) = data.get(offset) {
+        let len = len as usize;
+        if len == 0 {
+            if !labels.is_empty() {
+                names.push(labels.join("."));
+                labels.clear();
+            }
+            offset += 1;
+            continue;
+        }
+        let Some(label) = data.get(offset + 1..offset + 1 + len) else {
+            break;
+        };
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        offset += 1 + len;
+    }
+    names
+}
+
+impl Dissector for Icmpv6Processor {
+    fn name(&self) -> &'static str {
+        "ICMPv6"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let icmpv6 = self.process(data).ok_or_else(|| anyhow::anyhow!("Packet too short for ICMPv6"))?;
+        let icmp_type = icmpv6.get_icmpv6_type().0;
+        ctx.add_field_at("icmpv6.type", 0, 1, icmp_type);
+        ctx.add_field_at("icmpv6.code", 1, 1, icmpv6.get_icmpv6_code().0);
+        ctx.add_field_at("icmpv6.checksum", 2, 2, FieldValue::Hex(icmpv6.get_checksum() as u64));
+
+        let name = match type_name(icmp_type) {
+            "Unknown" => format!("Unknown ICMPv6 type: {}", icmp_type),
+            name => name.to_string(),
+        };
+        let details = self.dissect_message(data, ctx);
+        Ok(Dissection::new(
+            "ICMPv6",
+            format!("{} > {}: {}{}", ctx.source, ctx.destination, name, details),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissector::testing;
+    use super::super::tree::Field;
+
+    const MAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    fn address(text: &str) -> [u8; 16] {
+        text.parse::<Ipv6Addr>().unwrap().octets()
+    }
+
+    /// Link-layer address option: 1 for the source, 2 for the target
+    fn link_layer(kind: u8) -> Vec<u8> {
+        let mut option = vec![kind, 1];
+        option.extend(MAC);
+        option
+    }
+
+    /// Dissects `data` sent by `source`; returns the summary after the type name
+    fn dissect(icmpv6: &Icmpv6Processor, source: &str, data: &[u8]) -> (String, Vec<Field>) {
+        let (dissection, fields) = testing::dissect(icmpv6, data, |ctx| {
+            ctx.src_ip = Some(source.parse().unwrap());
+        });
+        let info = dissection.unwrap().info;
+        let name = type_name(data[0]);
+        (info[info.find(name).unwrap() + name.len()..].to_string(), fields)
+    }
+
+    fn values<'a>(fields: &'a [Field], name: &str) -> Vec<&'a FieldValue> {
+        fields.iter().filter(|field| field.name == name).map(|field| &field.value).collect()
+    }
+
+    fn ip(text: &str) -> FieldValue {
+        FieldValue::Ip(text.parse().unwrap())
+    }
+
+    #[test]
+    fn dissects_router_discovery() {
+        let icmpv6 = Icmpv6Processor::new();
+        let mut solicitation = vec![133, 0, 0, 0, 0, 0, 0, 0];
+        solicitation.extend(link_layer(1));
+        let (info, _) = dissect(&icmpv6, "fe80::2", &solicitation);
+        assert_eq!(info, " from 00:11:22:33:44:55");
+
+        let mut advertisement = vec![134, 0, 0, 0, 64, 0xc0, 0x07, 0x08, 0, 0, 0, 0, 0, 0, 0, 0];
+        advertisement.extend(link_layer(1));
+        advertisement.extend([3, 4, 64, 0xc0, 0, 0, 0x0e, 0x10, 0, 0, 0x07, 0x08, 0, 0, 0, 0]);
+        advertisement.extend(address("2001:db8::"));
+        advertisement.extend([5, 1, 0, 0, 0, 0, 0x05, 0xdc]);
+        advertisement.extend([25, 3, 0, 0, 0, 0, 0x0e, 0x10]);
+        advertisement.extend(address("2001:db8::53"));
+        let (info, fields) = dissect(&icmpv6, "fe80::1", &advertisement);
+        assert_eq!(info, " (lifetime 1800 s, M, O) prefix 2001:db8::/64 MTU 1500 DNS 2001:db8::53");
+        assert_eq!(testing::field(&fields, "icmpv6.opt.prefix.valid_lifetime"), Some(&FieldValue::Uint(3600)));
+        assert_eq!(testing::field(&fields, "icmpv6.opt.prefix.flag.a"), Some(&FieldValue::Bool(true)));
+        assert_eq!(testing::field(&fields, "icmpv6.opt.rdnss"), Some(&ip("2001:db8::53")));
+        assert!(testing::field(&fields, "_ws.malformed").is_none());
+
+        let neighbors = icmpv6.neighbors();
+        assert_eq!(neighbors.len(), 2);
+        assert_eq!((neighbors[0].ip.to_string(), neighbors[0].is_router, neighbors[0].learned_from), ("fe80::1".to_string(), true, "RA"));
+        assert_eq!((neighbors[1].ip.to_string(), neighbors[1].is_router, neighbors[1].learned_from), ("fe80::2".to_string(), false, "RS"));
+        assert_eq!(neighbors[0].mac, "00:11:22:33:44:55");
+    }
+
+    #[test]
+    fn learns_from_neighbor_discovery() {
+        let icmpv6 = Icmpv6Processor::new();
+        let mut solicitation = vec![135, 0, 0, 0, 0, 0, 0, 0];
+        solicitation.extend(address("fe80::1"));
+        let (info, _) = dissect(&icmpv6, "::", &solicitation);
+        assert_eq!(info, " for fe80::1 (duplicate address detection)");
+        solicitation.extend(link_layer(1));
+        let (info, _) = dissect(&icmpv6, "fe80::2", &solicitation);
+        assert_eq!(info, " for fe80::1 from 00:11:22:33:44:55");
+
+        // The target address is learned, whoever sends the advertisement
+        let mut advertisement = vec![136, 0, 0, 0, 0xe0, 0, 0, 0];
+        advertisement.extend(address("fe80::1"));
+        advertisement.extend(link_layer(2));
+        let (info, fields) = dissect(&icmpv6, "fe80::9", &advertisement);
+        assert_eq!(info, " fe80::1 (rtr, sol, ovr) is at 00:11:22:33:44:55");
+        assert_eq!(values(&fields, "icmpv6.opt.target_linkaddr"), [&FieldValue::Mac(MAC)]);
+
+        let addresses: Vec<String> = icmpv6.neighbors().iter().map(|neighbor| neighbor.ip.to_string()).collect();
+        assert_eq!(addresses, ["fe80::1", "fe80::2"]);
+        let router = &icmpv6.neighbors()[0];
+        assert_eq!((router.is_router, router.learned_from), (true, "NA"));
+    }
+
+    #[test]
+    fn dissects_redirects() {
+        let icmpv6 = Icmpv6Processor::new();
+        let mut redirect = vec![137, 0, 0, 0, 0, 0, 0, 0];
+        redirect.extend(address("fe80::1"));
+        redirect.extend(address("2001:db8::80"));
+        redirect.extend(link_layer(2));
+        let (info, fields) = dissect(&icmpv6, "fe80::9", &redirect);
+        assert_eq!(info, " to fe80::1 for 2001:db8::80");
+        assert_eq!(testing::field(&fields, "icmpv6.nd.rd.destination_address"), Some(&ip("2001:db8::80")));
+        let neighbors = icmpv6.neighbors();
+        assert_eq!((neighbors[0].ip.to_string(), neighbors[0].learned_from), ("fe80::1".to_string(), "RD"));
+    }
+
+    #[test]
+    fn dissects_multicast_listener_discovery() {
+        let icmpv6 = Icmpv6Processor::new();
+        let mut query = vec![130, 0, 0, 0, 0x27, 0x10, 0, 0];
+        query.extend([0; 16]);
+        assert_eq!(dissect(&icmpv6, "fe80::1", &query).0, " (general)");
+
+        let mut report = vec![131, 0, 0, 0, 0, 0, 0, 0];
+        report.extend(address("ff02::1:3"));
+        assert_eq!(dissect(&icmpv6, "fe80::2", &report).0, " ff02::1:3");
+
+        // MLDv2 query for a group and source, S set, QRV 2, QQIC 125
+        let mut query = vec![130, 0, 0, 0, 0x03, 0xe8, 0, 0];
+        query.extend(address("ff02::1:3"));
+        query.extend([0x0a, 125, 0, 1]);
+        query.extend(address("2001:db8::1"));
+        let (info, fields) = dissect(&icmpv6, "fe80::1", &query);
+        assert_eq!(info, " v2 (ff02::1:3, 1 sources)");
+        assert_eq!(testing::field(&fields, "icmpv6.mld.flag.s"), Some(&FieldValue::Bool(true)));
+        assert_eq!(testing::field(&fields, "icmpv6.mld.qrv"), Some(&FieldValue::Uint(2)));
+        assert_eq!(testing::field(&fields, "icmpv6.mld.qqi"), Some(&FieldValue::Uint(125)));
+        assert_eq!(testing::field(&fields, "icmpv6.mld.source_address"), Some(&ip("2001:db8::1")));
+
+        // MLDv2 report: a record with a source and auxiliary data, then one without
+        let mut report = vec![143, 0, 0, 0, 0, 0, 0, 2];
+        report.extend([1, 1, 0, 1]);
+        report.extend(address("ff05::2"));
+        report.extend(address("2001:db8::1"));
+        report.extend([0xaa; 4]);
+        report.extend([4, 0, 0, 0]);
+        report.extend(address("ff02::fb"));
+        let (info, fields) = dissect(&icmpv6, "fe80::2", &report);
+        assert_eq!(info, ": MODE_IS_INCLUDE ff05::2, CHANGE_TO_EXCLUDE_MODE ff02::fb");
+        assert_eq!(values(&fields, "icmpv6.mldr.mar.source_address"), [&ip("2001:db8::1")]);
+    }
+
+    #[test]
+    fn stops_at_truncated_options() {
+        let icmpv6 = Icmpv6Processor::new();
+        let mut advertisement = vec![134, 0, 0, 0, 64, 0, 0x07, 0x08, 0, 0, 0, 0, 0, 0, 0, 0];
+        advertisement.extend([5, 1, 0, 0, 0, 0, 0x05, 0xdc]);
+        advertisement.extend([3, 4, 64, 0xc0, 0, 0, 0x0e, 0x10]);
+        let (info, fields) = dissect(&icmpv6, "fe80::1", &advertisement);
+        assert_eq!(info, " (lifetime 1800 s) MTU 1500");
+        assert_eq!(
+            testing::field(&fields, "_ws.malformed"),
+            Some(&FieldValue::Text("ICMPv6 option 3 runs past the packet".to_string()))
+        );
+
+        let mut solicitation = vec![133, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0];
+        let (info, fields) = dissect(&icmpv6, "fe80::2", &solicitation);
+        assert_eq!(info, "");
+        assert_eq!(
+            testing::field(&fields, "_ws.malformed"),
+            Some(&FieldValue::Text("ICMPv6 option 1 has length 0".to_string()))
+        );
+        solicitation.truncate(9);
+        assert_eq!(dissect(&icmpv6, "fe80::2", &solicitation).0, "");
+        assert!(icmpv6.neighbors().is_empty());
+    }
+}
@@ -1,12 +1,74 @@
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Mutex;
 use anyhow::Result;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::icmpv6::record_type_name;
+use super::multicast::{MembershipTable, MulticastMembership};
+use super::tree::FieldValue;
+
+/// IGMPv3 query fields following the group address
+#[derive(Debug, Clone)]
+pub struct Igmpv3Query {
+    /// S flag: routers skip their timer updates
+    pub suppress_router_processing: bool,
+    /// Querier's robustness variable
+    pub robustness: u8,
+    /// Querier's query interval in seconds
+    pub query_interval: u32,
+    pub sources: Vec<Ipv4Addr>,
+}
+
+/// Group record of an IGMPv3 report
+#[derive(Debug, Clone)]
+pub struct GroupRecord {
+    pub record_type: u8,
+    pub group: Ipv4Addr,
+    pub sources: Vec<Ipv4Addr>,
+    /// Offset of the record in the message
+    pub offset: usize,
+}
+
+impl GroupRecord {
+    fn format_info(&self) -> String {
+        let sources: Vec<String> = self.sources.iter().map(|s| s.to_string()).collect();
+        let sources = format!("{{{}}}", sources.join(", "));
+        match (self.record_type, self.sources.is_empty()) {
+            (2 | 4, true) => format!("Join group {} for any sources", self.group),
+            (2 | 4, false) => format!("Join group {} excluding sources {}", self.group, sources),
+            (1 | 3, true) => format!("Leave group {}", self.group),
+            (1 | 3 | 5, false) => format!("Join group {} for sources {}", self.group, sources),
+            (6, _) => format!("Leave group {} for sources {}", self.group, sources),
+            _ => format!("{} {}", record_type_name(self.record_type), self.group),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct IgmpPacket {
     type_: u8,
     max_resp_time: u8,
     checksum: u16,
-    group_address: Ipv4Addr,
+    /// Unspecified in general queries and in v3 reports
+    pub group_address: Ipv4Addr,
+    /// Set for IGMPv3 queries, longer than 8 bytes
+    pub query: Option<Igmpv3Query>,
+    pub records: Vec<GroupRecord>,
+}
+
+/// Value of an IGMPv3 code that switches to a floating point form from 128
+fn exponential(code: u8) -> u32 {
+    if code < 128 {
+        code as u32
+    } else {
+        (((code & 0x0f) | 0x10) as u32) << (((code >> 4) & 0x07) + 3)
+    }
+}
+
+fn ipv4_list(data: &[u8], offset: usize, count: usize) -> Result<Vec<Ipv4Addr>> {
+    let bytes = data
+        .get(offset..offset + count * 4)
+        .ok_or_else(|| anyhow::anyhow!("IGMP source list truncated"))?;
+    Ok(bytes.chunks(4).map(|b| Ipv4Addr::new(b[0], b[1], b[2], b[3])).collect())
 }
 
 impl IgmpPacket {
@@ -15,12 +77,44 @@ impl IgmpPacket {
             return Err(anyhow::anyhow!("IGMP packet too short"));
         }
 
-        Ok(Self {
-            type_: payload[0],
+        let type_ = payload[0];
+        let mut packet = Self {
+            type_,
             max_resp_time: payload[1],
             checksum: u16::from_be_bytes([payload[2], payload[3]]),
             group_address: Ipv4Addr::new(payload[4], payload[5], payload[6], payload[7]),
-        })
+            query: None,
+            records: Vec::new(),
+        };
+        if type_ == 0x11 && payload.len() >= 12 {
+            let count = u16::from_be_bytes([payload[10], payload[11]]) as usize;
+            packet.query = Some(Igmpv3Query {
+                suppress_router_processing: payload[8] & 0x08 != 0,
+                robustness: payload[8] & 0x07,
+                query_interval: exponential(payload[9]),
+                sources: ipv4_list(payload, 12, count)?,
+            });
+        } else if type_ == 0x22 {
+            // Bytes 4 to 8 hold a reserved field and the record count
+            packet.group_address = Ipv4Addr::UNSPECIFIED;
+            let count = u16::from_be_bytes([payload[6], payload[7]]) as usize;
+            let mut offset = 8;
+            for _ in 0..count {
+                let header = payload
+                    .get(offset..offset + 8)
+                    .ok_or_else(|| anyhow::anyhow!("IGMPv3 group record truncated"))?;
+                let sources = u16::from_be_bytes([header[2], header[3]]) as usize;
+                packet.records.push(GroupRecord {
+                    record_type: header[0],
+                    group: Ipv4Addr::new(header[4], header[5], header[6], header[7]),
+                    sources: ipv4_list(payload, offset + 8, sources)?,
+                    offset,
+                });
+                // The auxiliary data length is in 32-bit words
+                offset += 8 + sources * 4 + header[1] as usize * 4;
+            }
+        }
+        Ok(packet)
     }
 
     pub fn get_type(&self) -> IgmpType {
@@ -38,13 +132,45 @@ impl IgmpPacket {
         self.group_address.to_string()
     }
 
+    /// Version of a query: v1 has no maximum response time, v3 is longer
+    pub fn query_version(&self) -> u8 {
+        match (&self.query, self.max_resp_time) {
+            (Some(_), _) => 3,
+            (None, 0) => 1,
+            (None, _) => 2,
+        }
+    }
+
+    /// Maximum response time in tenths of a second
+    pub fn max_response_time(&self) -> u32 {
+        match self.query {
+            Some(_) => exponential(self.max_resp_time),
+            None => self.max_resp_time as u32,
+        }
+    }
+
     pub fn format_info(&self) -> String {
         match self.get_type() {
-            IgmpType::MembershipQuery => format!("Membership Query, general"),
+            IgmpType::MembershipQuery => {
+                let mut info = format!("Membership Query v{}", self.query_version());
+                if self.group_address.is_unspecified() {
+                    info.push_str(", general");
+                } else {
+                    info.push_str(&format!(", specific for group {}", self.group_address));
+                }
+                if let Some(query) = self.query.as_ref().filter(|query| !query.sources.is_empty()) {
+                    let sources: Vec<String> = query.sources.iter().map(|s| s.to_string()).collect();
+                    info.push_str(&format!(", sources {{{}}}", sources.join(", ")));
+                }
+                info
+            }
             IgmpType::MembershipReportV1 => format!("Membership Report / Join group {}", self.get_group_address()),
             IgmpType::MembershipReportV2 => format!("Membership Report / Join group {}", self.get_group_address()),
             IgmpType::LeaveGroup => format!("Leave group {}", self.get_group_address()),
-            IgmpType::MembershipReportV3 => format!("Membership Report / Join group {} for any sources", self.get_group_address()),
+            IgmpType::MembershipReportV3 => {
+                let records: Vec<String> = self.records.iter().map(GroupRecord::format_info).collect();
+                format!("Membership Report / {}", records.join(" / "))
+            }
             IgmpType::Unknown => format!("Unknown IGMP type: {}", self.type_),
         }
     }
@@ -60,14 +186,124 @@ pub enum IgmpType {
     Unknown,
 }
 
-pub struct IgmpProcessor;
+/// IGMP, with the membership table built from the reports
+pub struct IgmpProcessor {
+    memberships: Mutex<MembershipTable>,
+}
 
 impl IgmpProcessor {
     pub fn new() -> Self {
-        Self
+        Self {
+            memberships: Mutex::new(MembershipTable::default()),
+        }
     }
 
     pub fn process(&self, payload: &[u8]) -> Result<IgmpPacket> {
         IgmpPacket::from_bytes(payload)
     }
-} 
\ No newline at end of file
+
+    /// Group memberships of every host seen
+    pub fn memberships(&self) -> Vec<MulticastMembership> {
+        self.memberships.lock().unwrap().memberships()
+    }
+}
+
+impl Dissector for IgmpProcessor {
+    fn name(&self) -> &'static str {
+        "IGMP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let igmp = self.process(data)?;
+        ctx.add_field_at("igmp.type", 0, 1, FieldValue::Hex(igmp.type_ as u64));
+        if matches!(igmp.get_type(), IgmpType::MembershipQuery) {
+            ctx.add_field_at("igmp.max_resp", 1, 1, igmp.max_response_time() as f64 / 10.0);
+        }
+        ctx.add_field_at("igmp.checksum", 2, 2, FieldValue::Hex(igmp.checksum as u64));
+        if !matches!(igmp.get_type(), IgmpType::MembershipReportV3) {
+            ctx.add_field_at("igmp.maddr", 4, 4, igmp.get_group_address());
+        }
+        if let Some(query) = &igmp.query {
+            ctx.add_field_at("igmp.s", 8, 1, query.suppress_router_processing);
+            ctx.add_field_at("igmp.qrv", 8, 1, query.robustness);
+            ctx.add_field_at("igmp.qqic", 9, 1, query.query_interval);
+            ctx.add_field_at("igmp.num_src", 10, 2, query.sources.len());
+            for (index, source) in query.sources.iter().enumerate() {
+                ctx.add_field_at("igmp.saddr", 12 + index * 4, 4, source.to_string());
+            }
+        }
+        if matches!(igmp.get_type(), IgmpType::MembershipReportV3) {
+            ctx.add_field_at("igmp.num_grp_recs", 6, 2, igmp.records.len());
+        }
+        for record in &igmp.records {
+            ctx.add_field_at("igmp.record_type", record.offset, 1, record_type_name(record.record_type));
+            ctx.add_field_at("igmp.num_src", record.offset + 2, 2, record.sources.len());
+            ctx.add_field_at("igmp.maddr", record.offset + 4, 4, record.group.to_string());
+            for (index, source) in record.sources.iter().enumerate() {
+                ctx.add_field_at("igmp.saddr", record.offset + 8 + index * 4, 4, source.to_string());
+            }
+        }
+
+        if let Some(IpAddr::V4(host)) = ctx.src_ip {
+            self.memberships.lock().unwrap().update(&igmp, host, ctx.captured_at);
+        }
+        Ok(Dissection::new("IGMP", igmp.format_info()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissector::testing;
+
+    fn dissect(igmp: &IgmpProcessor, data: &[u8]) -> (String, Vec<super::super::tree::Field>) {
+        let (dissection, fields) = testing::dissect(igmp, data, |ctx| {
+            ctx.src_ip = Some("10.0.0.1".parse().unwrap());
+        });
+        (dissection.unwrap().info, fields)
+    }
+
+    #[test]
+    fn tells_query_versions_apart() {
+        let igmp = IgmpProcessor::new();
+        assert_eq!(dissect(&igmp, &[0x11, 0, 0, 0, 0, 0, 0, 0]).0, "Membership Query v1, general");
+        let (info, fields) = dissect(&igmp, &[0x11, 100, 0, 0, 239, 1, 1, 1]);
+        assert_eq!(info, "Membership Query v2, specific for group 239.1.1.1");
+        assert_eq!(testing::field(&fields, "igmp.max_resp"), Some(&FieldValue::Float(10.0)));
+        assert!(testing::field(&fields, "igmp.qrv").is_none());
+    }
+
+    #[test]
+    fn decodes_v3_queries() {
+        let igmp = IgmpProcessor::new();
+        let query = [0x11, 0x8f, 0, 0, 0, 0, 0, 0, 0x0a, 125, 0, 1, 10, 0, 0, 9];
+        let (info, fields) = dissect(&igmp, &query);
+        assert_eq!(info, "Membership Query v3, general, sources {10.0.0.9}");
+        assert_eq!(testing::field(&fields, "igmp.max_resp"), Some(&FieldValue::Float(24.8)));
+        assert_eq!(testing::field(&fields, "igmp.s"), Some(&FieldValue::Bool(true)));
+        assert_eq!(testing::field(&fields, "igmp.qrv"), Some(&FieldValue::Uint(2)));
+        assert_eq!(testing::field(&fields, "igmp.qqic"), Some(&FieldValue::Uint(125)));
+
+        // From 128 on, codes are floating point values
+        let packet = IgmpPacket::from_bytes(&[0x11, 0, 0, 0, 0, 0, 0, 0, 0x07, 0x8c, 0, 0]).unwrap();
+        let query = packet.query.unwrap();
+        assert_eq!((query.suppress_router_processing, query.robustness, query.query_interval), (false, 7, 224));
+        assert!(IgmpPacket::from_bytes(&[0x11, 0, 0, 0, 0, 0, 0, 0, 0x02, 125, 0, 2, 10, 0, 0, 9]).is_err());
+    }
+
+    #[test]
+    fn dissects_v3_reports() {
+        let igmp = IgmpProcessor::new();
+        let mut report = vec![0x22, 0, 0, 0, 0, 0, 0, 2];
+        report.extend([1, 1, 0, 1, 232, 1, 1, 1, 192, 0, 2, 1, 0xaa, 0xaa, 0xaa, 0xaa]);
+        report.extend([4, 0, 0, 0, 239, 1, 1, 1]);
+        let (info, fields) = dissect(&igmp, &report);
+        assert_eq!(
+            info,
+            "Membership Report / Join group 232.1.1.1 for sources {192.0.2.1} / Join group 239.1.1.1 for any sources"
+        );
+        assert_eq!(testing::field(&fields, "igmp.num_grp_recs"), Some(&FieldValue::Uint(2)));
+        assert_eq!(igmp.memberships().len(), 2);
+        assert!(IgmpPacket::from_bytes(&report[..report.len() - 1]).is_err());
+    }
+}
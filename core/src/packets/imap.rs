@@ -0,0 +1,314 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::flow::{FlowKey, FlowTable};
+use super::mail::{self, Connection, MailMessage, MailStore, SaslExchange, MAX_MESSAGE_SIZE};
+
+/// Literal being read: bytes left, content kept when it is a whole message
+struct Literal {
+    remaining: usize,
+    content: Option<Vec<u8>>,
+}
+
+impl Literal {
+    /// A literal of `size` bytes; the content of a whole message is kept
+    /// unless it is too large to be extracted complete
+    fn new(size: usize, whole: bool) -> Self {
+        Self {
+            remaining: size,
+            content: (whole && size <= MAX_MESSAGE_SIZE).then(Vec::new),
+        }
+    }
+
+    /// `{n}` or `{n+}` at the end of a line, with the text before it
+    fn parse(line: &str) -> Option<(usize, &str)> {
+        let line = line.strip_suffix('}')?;
+        let start = line.rfind('{')?;
+        let size = line[start + 1..].trim_end_matches('+');
+        let size = size.parse().ok().filter(|_| size.bytes().all(|b| b.is_ascii_digit()))?;
+        Some((size, &line[..start]))
+    }
+
+    /// Reads from `stream`; returns `true` once all of the literal is read
+    fn read(&mut self, stream: &mut mail::LineStream) -> bool {
+        let data = stream.take(self.remaining);
+        self.remaining -= data.len();
+        if let Some(content) = &mut self.content {
+            content.extend_from_slice(&data);
+        }
+        self.remaining == 0
+    }
+}
+
+struct Session {
+    conn: Connection,
+    /// Commands waiting for their tagged response, by tag
+    pending: HashMap<String, String>,
+    client_literal: Option<Literal>,
+    server_literal: Option<Literal>,
+    /// Tag of the running AUTHENTICATE command
+    auth: Option<(String, SaslExchange)>,
+}
+
+impl Session {
+    fn read_client(&mut self, ctx: &mut DissectContext, info: &mut Vec<String>) -> Vec<MailMessage> {
+        let mut messages = Vec::new();
+        // A command line following a literal continues the command before it
+        let mut continued = false;
+        loop {
+            if let Some(literal) = &mut self.client_literal {
+                if !literal.read(&mut self.conn.client_stream) {
+                    break;
+                }
+                if let Some(data) = self.client_literal.take().and_then(|literal| literal.content) {
+                    messages.push(MailMessage::new("IMAP", self.conn.client, self.conn.server, data, ctx.captured_at));
+                }
+                continued = true;
+            }
+            let Some(line) = self.conn.client_stream.line() else {
+                break;
+            };
+            if let Some((_, auth)) = &mut self.auth {
+                match auth.response(&line) {
+                    Some(username) => {
+                        ctx.add_field("imap.request.username", username.clone());
+                        info.push(format!("Request: User: {}", username));
+                    }
+                    None if line == "*" => info.push("Request: * (cancel)".to_string()),
+                    None => info.push(format!("Request: {} response", auth.mechanism)),
+                }
+                continue;
+            }
+
+            let literal = Literal::parse(&line);
+            if std::mem::take(&mut continued) {
+                if let Some((size, _)) = literal {
+                    self.client_literal = Some(Literal::new(size, false));
+                }
+                continue;
+            }
+            let mut parts = line.splitn(3, ' ');
+            let tag = parts.next().unwrap_or_default().to_string();
+            let command = parts.next().unwrap_or_default().to_ascii_uppercase();
+            let arguments = parts.next().unwrap_or_default();
+            if tag.is_empty() || command.is_empty() {
+                continue;
+            }
+            ctx.add_field("imap.request.tag", tag.clone());
+            ctx.add_field("imap.request.command", command.clone());
+            let shown = match command.as_str() {
+                // The password is never shown
+                "LOGIN" => {
+                    let username = arguments.split(' ').next().unwrap_or_default().trim_matches('"').to_string();
+                    ctx.add_field("imap.request.username", username.clone());
+                    username
+                }
+                "AUTHENTICATE" => {
+                    let mut words = arguments.split_whitespace();
+                    let mut auth = SaslExchange::new(words.next().unwrap_or_default());
+                    ctx.add_field("imap.auth.mechanism", auth.mechanism.clone());
+                    if let Some(username) = words.next().and_then(|initial| auth.response(initial)) {
+                        ctx.add_field("imap.request.username", username);
+                    }
+                    let mechanism = auth.mechanism.clone();
+                    self.auth = Some((tag.clone(), auth));
+                    mechanism
+                }
+                _ => arguments.to_string(),
+            };
+            if let Some((size, _)) = literal {
+                // APPEND uploads a whole message
+                self.client_literal = Some(Literal::new(size, command == "APPEND"));
+            }
+            info.push(format!("Request: {} {} {}", tag, command, shown).trim_end().to_string());
+            self.pending.insert(tag, command);
+        }
+        messages
+    }
+
+    fn read_server(&mut self, ctx: &mut DissectContext, info: &mut Vec<String>) -> Vec<MailMessage> {
+        let mut messages = Vec::new();
+        let mut continued = false;
+        loop {
+            if let Some(literal) = &mut self.server_literal {
+                if !literal.read(&mut self.conn.server_stream) {
+                    break;
+                }
+                if let Some(data) = self.server_literal.take().and_then(|literal| literal.content) {
+                    messages.push(MailMessage::new("IMAP", self.conn.client, self.conn.server, data, ctx.captured_at));
+                }
+                continued = true;
+            }
+            let Some(line) = self.conn.server_stream.line() else {
+                break;
+            };
+
+            let literal = Literal::parse(&line).map(|(size, before)| {
+                // Only whole messages are extracted, not parts or partial fetches
+                let upper = before.trim_end().to_ascii_uppercase();
+                let whole = ["BODY[]", "BODY.PEEK[]", "RFC822", "BINARY[]"].iter().any(|item| upper.ends_with(item));
+                Literal::new(size, whole)
+            });
+            let first = !std::mem::take(&mut continued);
+            self.server_literal = literal;
+            if !first {
+                continue;
+            }
+
+            let mut parts = line.splitn(3, ' ');
+            let tag = parts.next().unwrap_or_default().to_string();
+            let status = parts.next().unwrap_or_default().to_ascii_uppercase();
+            info.push(format!("Response: {}", line));
+            match tag.as_str() {
+                "+" => continue,
+                "*" => {
+                    ctx.add_field("imap.response.status", status);
+                    continue;
+                }
+                _ => {}
+            }
+            ctx.add_field("imap.response.tag", tag.clone());
+            ctx.add_field("imap.response.status", status.clone());
+            let Some(command) = self.pending.remove(&tag) else {
+                continue;
+            };
+            ctx.add_field("imap.response.command", command.clone());
+            if self.auth.as_ref().is_some_and(|(auth_tag, _)| *auth_tag == tag) {
+                self.auth = None;
+            }
+            if command == "STARTTLS" && status == "OK" {
+                ctx.add_field("imap.starttls", true);
+                self.conn.start_tls();
+                break;
+            }
+        }
+        messages
+    }
+}
+
+/// IMAP commands and responses over reassembled streams, with the messages
+/// fetched or appended
+pub struct ImapProcessor {
+    sessions: Mutex<FlowTable<FlowKey, Session>>,
+    messages: Arc<Mutex<MailStore>>,
+}
+
+impl ImapProcessor {
+    pub(crate) fn new(messages: Arc<Mutex<MailStore>>) -> Self {
+        Self {
+            sessions: Mutex::new(FlowTable::new()),
+            messages,
+        }
+    }
+}
+
+impl Dissector for ImapProcessor {
+    fn name(&self) -> &'static str {
+        "IMAP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let flow = ctx.flow.ok_or_else(|| anyhow::anyhow!("IMAP outside of a TCP flow"))?;
+        let key = flow.canonical();
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.entry(key, ctx.captured_at, || Session {
+            conn: Connection::new(&ctx.client_flow.unwrap_or(flow)),
+            pending: HashMap::new(),
+            client_literal: None,
+            server_literal: None,
+            auth: None,
+        });
+        let from_client = session.conn.is_client(&flow);
+        let tls = session.conn.tls;
+        let closed = ctx.flow_closed;
+        if tls {
+            if closed {
+                sessions.remove(&key);
+            }
+            drop(sessions);
+            if data.is_empty() {
+                anyhow::bail!("No IMAP payload");
+            }
+            return mail::dissect_tls(data, ctx);
+        }
+
+        let mut info = Vec::new();
+        let sequence = ctx.tcp_sequence.unwrap_or_default();
+        let messages = if from_client {
+            if !session.conn.client_stream.push(sequence, data) {
+                session.client_literal = None;
+            }
+            session.read_client(ctx, &mut info)
+        } else {
+            if !session.conn.server_stream.push(sequence, data) {
+                session.server_literal = None;
+            }
+            session.read_server(ctx, &mut info)
+        };
+        if closed {
+            sessions.remove(&key);
+        }
+        drop(sessions);
+        if data.is_empty() {
+            anyhow::bail!("No IMAP payload");
+        }
+
+        let side = if from_client { "Request" } else { "Response" };
+        for message in &messages {
+            message.add_fields(ctx);
+            info.push(format!("{}: {}", side, message.summary()));
+        }
+        self.messages.lock().unwrap().extend(messages);
+        let info = match info.len() {
+            0 => "Continuation".to_string(),
+            _ => info.join(" | "),
+        };
+        Ok(Dissection::new("IMAP", info))
+    }
+
+    /// Heuristic probe: the greeting of an IMAP server
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        let line_end = payload.iter().position(|&b| b == b'\n').unwrap_or(payload.len());
+        let line = String::from_utf8_lossy(&payload[..line_end]).to_ascii_uppercase();
+        if !line.starts_with("* OK") && !line.starts_with("* PREAUTH") {
+            return None;
+        }
+        match line.contains("IMAP") {
+            true => Some(ProbeResult::new(85, "IMAP server greeting")),
+            false => Some(ProbeResult::new(75, "IMAP untagged greeting")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_literal_announcements() {
+        assert_eq!(Literal::parse("* 1 FETCH (BODY[] {342}"), Some((342, "* 1 FETCH (BODY[] ")));
+        assert_eq!(Literal::parse("A1 APPEND INBOX {12+}"), Some((12, "A1 APPEND INBOX ")));
+        assert_eq!(Literal::parse("A1 LOGIN {+5}"), None);
+        assert_eq!(Literal::parse("A1 NOOP"), None);
+    }
+
+    #[test]
+    fn keeps_only_whole_messages() {
+        let mut stream = mail::LineStream::default();
+        stream.push(1, b"Subject: hi\r\n\r\nbody");
+        let mut literal = Literal::new(21, true);
+        assert!(!literal.read(&mut stream));
+        stream.push(20, b"\r\n)\r\n");
+        assert!(literal.read(&mut stream));
+        assert_eq!(literal.content.as_deref(), Some(&b"Subject: hi\r\n\r\nbody\r\n"[..]));
+        assert_eq!(stream.line().as_deref(), Some(")"));
+
+        // A message beyond the limit would be exported cut short
+        let mut literal = Literal::new(MAX_MESSAGE_SIZE + 1, true);
+        stream.push(25, b"Subject: big\r\n");
+        assert!(!literal.read(&mut stream));
+        assert!(literal.content.is_none());
+    }
+}
@@ -1,4 +1,9 @@
+use anyhow::Result;
 use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::Packet;
+use std::net::IpAddr;
+use super::dissector::{Dissection, DissectContext, Dissector, DissectorTable};
+use super::tree::FieldValue;
 
 pub struct Ipv4Processor;
 
@@ -10,4 +15,37 @@ impl Ipv4Processor {
     pub fn process<'a>(&self, data: &'a [u8]) -> Option<Ipv4Packet<'a>> {
         Ipv4Packet::new(data)
     }
-} 
\ No newline at end of file
+}
+
+impl Dissector for Ipv4Processor {
+    fn name(&self) -> &'static str {
+        "IPv4"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let ipv4 = self.process(data).ok_or_else(|| anyhow::anyhow!("Packet too short for IPv4"))?;
+        let source = ipv4.get_source();
+        let destination = ipv4.get_destination();
+        let protocol = ipv4.get_next_level_protocol();
+        ctx.add_field_at("ip.version", 0, 1, ipv4.get_version());
+        ctx.add_field_at("ip.hdr_len", 0, 1, ipv4.get_header_length() as usize * 4);
+        ctx.add_field_at("ip.len", 2, 2, ipv4.get_total_length());
+        ctx.add_field_at("ip.id", 4, 2, FieldValue::Hex(ipv4.get_identification() as u64));
+        ctx.add_field_at("ip.ttl", 8, 1, ipv4.get_ttl());
+        ctx.add_field_at("ip.proto", 9, 1, protocol.0);
+        ctx.add_field_at("ip.checksum", 10, 2, FieldValue::Hex(ipv4.get_checksum() as u64));
+        ctx.add_field_at("ip.src", 12, 4, source);
+        ctx.add_field_at("ip.dst", 16, 4, destination);
+        ctx.source = source.to_string();
+        ctx.destination = destination.to_string();
+        ctx.src_ip = Some(IpAddr::V4(source));
+        ctx.dst_ip = Some(IpAddr::V4(destination));
+
+        let payload = ipv4.payload();
+        Ok(ctx
+            .dissect_next(DissectorTable::IpProtocol(protocol.0), payload)
+            .unwrap_or_else(|| {
+                Dissection::new(format!("Unknown({})", protocol), format!("Unknown protocol, length {}", payload.len()))
+            }))
+    }
+}
@@ -1,4 +1,9 @@
+use anyhow::Result;
 use pnet::packet::ipv6::Ipv6Packet;
+use pnet::packet::Packet;
+use std::net::IpAddr;
+use super::dissector::{Dissection, DissectContext, Dissector, DissectorTable};
+use super::tree::FieldValue;
 
 pub struct Ipv6Processor;
 
@@ -10,4 +15,77 @@ impl Ipv6Processor {
     pub fn process<'a>(&self, data: &'a [u8]) -> Option<Ipv6Packet<'a>> {
         Ipv6Packet::new(data)
     }
-} 
\ No newline at end of file
+}
+
+impl Dissector for Ipv6Processor {
+    fn name(&self) -> &'static str {
+        "IPv6"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let ipv6 = self.process(data).ok_or_else(|| anyhow::anyhow!("Packet too short for IPv6"))?;
+        let source = ipv6.get_source();
+        let destination = ipv6.get_destination();
+        let next_header = ipv6.get_next_header();
+        ctx.add_field_at("ipv6.flow", 1, 3, FieldValue::Hex(ipv6.get_flow_label() as u64));
+        ctx.add_field_at("ipv6.plen", 4, 2, ipv6.get_payload_length());
+        ctx.add_field_at("ipv6.nxt", 6, 1, next_header.0);
+        ctx.add_field_at("ipv6.hlim", 7, 1, ipv6.get_hop_limit());
+        ctx.add_field_at("ipv6.src", 8, 16, source);
+        ctx.add_field_at("ipv6.dst", 24, 16, destination);
+        ctx.source = source.to_string();
+        ctx.destination = destination.to_string();
+        ctx.src_ip = Some(IpAddr::V6(source));
+        ctx.dst_ip = Some(IpAddr::V6(destination));
+
+        // Extension headers sit between the fixed header and the upper layer;
+        // MLD, for one, always comes after a hop-by-hop header
+        let payload = ipv6.payload();
+        let mut next_header = next_header.0;
+        let mut offset = 0;
+        while let Some(name) = extension_name(next_header) {
+            let Some(header) = payload.get(offset..offset + 8) else {
+                break;
+            };
+            let len = if next_header == 44 { 8 } else { (header[1] as usize + 1) * 8 };
+            ctx.add_field_at(&format!("ipv6.{}.nxt", name), 40 + offset, 1, header[0]);
+            if next_header == 44 {
+                let fragment_offset = u16::from_be_bytes([header[2], header[3]]) >> 3;
+                ctx.add_field_at("ipv6.fragment.offset", 40 + offset + 2, 2, fragment_offset * 8);
+                ctx.add_field_at("ipv6.fragment.more", 40 + offset + 3, 1, header[3] & 0x01 != 0);
+                ctx.add_field_at("ipv6.fragment.id", 40 + offset + 4, 4, FieldValue::Hex(
+                    u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as u64,
+                ));
+                // Only the first fragment starts with the upper layer header
+                if fragment_offset != 0 {
+                    return Ok(Dissection::new(
+                        "IPv6",
+                        format!("{} > {}: Fragment offset {}", source, destination, fragment_offset * 8),
+                    ));
+                }
+            }
+            next_header = header[0];
+            offset += len;
+        }
+
+        Ok(ctx
+            .dissect_next(DissectorTable::IpProtocol(next_header), payload.get(offset..).unwrap_or_default())
+            .unwrap_or_else(|| {
+                Dissection::new(
+                    format!("Unknown({})", next_header),
+                    format!("{} > {}: Unknown protocol", source, destination),
+                )
+            }))
+    }
+}
+
+/// Field prefix of the extension headers that are skipped
+fn extension_name(next_header: u8) -> Option<&'static str> {
+    match next_header {
+        0 => Some("hopopts"),
+        43 => Some("routing"),
+        44 => Some("fragment"),
+        60 => Some("dstopts"),
+        _ => None,
+    }
+}
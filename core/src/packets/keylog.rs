@@ -0,0 +1,221 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use super::bytes::from_hex;
+
+/// pcapng Decryption Secrets Block type
+const DSB_BLOCK_TYPE: u32 = 0x0000_000a;
+/// Secrets type of an NSS key log inside a Decryption Secrets Block ("TLSK")
+const DSB_TLS_KEY_LOG: u32 = 0x544c_534b;
+
+/// Secrets of one TLS session, keyed by client random in [`KeyLog`]
+#[derive(Debug, Clone, Default)]
+pub struct SessionSecrets {
+    /// TLS 1.2 and below
+    pub master_secret: Option<Vec<u8>>,
+    pub client_handshake_traffic_secret: Option<Vec<u8>>,
+    pub server_handshake_traffic_secret: Option<Vec<u8>>,
+    pub client_traffic_secret_0: Option<Vec<u8>>,
+    pub server_traffic_secret_0: Option<Vec<u8>>,
+}
+
+/// Secrets from an NSS key log (`SSLKEYLOGFILE`)
+#[derive(Debug, Default)]
+pub struct KeyLog {
+    sessions: HashMap<Vec<u8>, SessionSecrets>,
+    file: Option<PathBuf>,
+    modified: Option<SystemTime>,
+}
+
+impl KeyLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a key log file; the file is re-read on lookup misses so keys
+    /// written while a live capture is running are picked up
+    pub fn load_file(&mut self, path: &Path) -> Result<usize> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read key log {}: {}", path.display(), e))?;
+        self.file = Some(path.to_path_buf());
+        self.modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        Ok(self.add_text(&text))
+    }
+
+    /// Parses key log lines and returns the number of secrets added
+    pub fn add_text(&mut self, text: &str) -> usize {
+        let mut added = 0;
+        for line in text.lines() {
+            let mut parts = line.split_whitespace();
+            let (Some(label), Some(random), Some(secret)) = (parts.next(), parts.next(), parts.next()) else {
+                continue;
+            };
+            let (Some(random), Some(secret)) = (from_hex(random), from_hex(secret)) else {
+                continue;
+            };
+            // A client random is 32 bytes, a master secret 48 and a TLS 1.3
+            // secret as long as the SHA-256 or SHA-384 hash of its suite
+            let secret_lengths: &[usize] = if label == "CLIENT_RANDOM" { &[48] } else { &[32, 48] };
+            if random.len() != 32 || !secret_lengths.contains(&secret.len()) {
+                continue;
+            }
+
+            let session = self.sessions.entry(random).or_default();
+            let slot = match label {
+                "CLIENT_RANDOM" => &mut session.master_secret,
+                "CLIENT_HANDSHAKE_TRAFFIC_SECRET" => &mut session.client_handshake_traffic_secret,
+                "SERVER_HANDSHAKE_TRAFFIC_SECRET" => &mut session.server_handshake_traffic_secret,
+                "CLIENT_TRAFFIC_SECRET_0" => &mut session.client_traffic_secret_0,
+                "SERVER_TRAFFIC_SECRET_0" => &mut session.server_traffic_secret_0,
+                _ => continue,
+            };
+            *slot = Some(secret);
+            added += 1;
+        }
+        added
+    }
+
+    /// Secrets are known or may still be written to the key log file
+    pub fn has_source(&self) -> bool {
+        !self.sessions.is_empty() || self.file.is_some()
+    }
+
+    pub fn lookup(&mut self, client_random: &[u8]) -> Option<SessionSecrets> {
+        if !self.sessions.contains_key(client_random) {
+            self.reload_if_changed();
+        }
+        self.sessions.get(client_random).cloned()
+    }
+
+    fn reload_if_changed(&mut self) {
+        let Some(path) = self.file.clone() else {
+            return;
+        };
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified != self.modified {
+            if let Ok(text) = std::fs::read_to_string(&path) {
+                self.add_text(&text);
+                self.modified = modified;
+            }
+        }
+    }
+}
+
+/// Extracts the TLS key log text stored in pcapng Decryption Secrets Blocks.
+///
+/// Returns an empty string for classic pcap files.
+pub fn read_pcapng_secrets(path: &Path) -> Result<String> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    let mut secrets = String::new();
+    if data.len() < 12 || data[0..4] != [0x0a, 0x0d, 0x0d, 0x0a] {
+        return Ok(secrets);
+    }
+
+    // The byte-order magic of the Section Header Block decides the endianness
+    let little_endian = data[8..12] == [0x4d, 0x3c, 0x2b, 0x1a];
+    let read_u32 = |bytes: &[u8]| -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+
+    let mut offset = 0;
+    while offset + 12 <= data.len() {
+        let block_type = read_u32(&data[offset..]);
+        let block_len = read_u32(&data[offset + 4..]) as usize;
+        if block_len < 12 || offset + block_len > data.len() {
+            break;
+        }
+
+        if block_type == DSB_BLOCK_TYPE && block_len >= 20 {
+            let secrets_type = read_u32(&data[offset + 8..]);
+            let secrets_len = read_u32(&data[offset + 12..]) as usize;
+            let start = offset + 16;
+            if secrets_type == DSB_TLS_KEY_LOG && start + secrets_len <= offset + block_len {
+                secrets.push_str(&String::from_utf8_lossy(&data[start..start + secrets_len]));
+                secrets.push('\n');
+            }
+        }
+
+        offset += block_len;
+    }
+
+    Ok(secrets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_key_log_lines() {
+        let random = "aa".repeat(32);
+        let text = format!(
+            "# comment\nCLIENT_RANDOM {random} {}\nSERVER_TRAFFIC_SECRET_0 {random} {}\nUNKNOWN_LABEL {random} 00\n",
+            "01".repeat(48),
+            "02".repeat(32),
+        );
+        let mut keylog = KeyLog::new();
+        assert_eq!(keylog.add_text(&text), 2);
+        let secrets = keylog.lookup(&[0xaa; 32]).unwrap();
+        assert_eq!(secrets.master_secret, Some(vec![0x01; 48]));
+        assert_eq!(secrets.server_traffic_secret_0, Some(vec![0x02; 32]));
+        assert_eq!(secrets.client_traffic_secret_0, None);
+    }
+
+    #[test]
+    fn skips_lines_that_are_not_hex() {
+        let mut keylog = KeyLog::new();
+        // Multi-byte characters, as left by a lossy UTF-8 conversion of a secrets block
+        assert_eq!(keylog.add_text("CLIENT_RANDOM a\u{fffd}b 0011\nCLIENT_RANDOM 00ü0 0011\nCLIENT_RANDOM +1 0011"), 0);
+        assert_eq!(keylog.add_text("CLIENT_RANDOM 0g 0011\nCLIENT_RANDOM 001 0011"), 0);
+        assert!(!keylog.has_source());
+    }
+
+    #[test]
+    fn skips_secrets_of_the_wrong_length() {
+        let random = "aa".repeat(32);
+        let mut keylog = KeyLog::new();
+        let text = format!(
+            "CLIENT_RANDOM {random} {}\nCLIENT_TRAFFIC_SECRET_0 {random} {}\nCLIENT_TRAFFIC_SECRET_0 aa {}\n",
+            "01".repeat(32),
+            "02".repeat(20),
+            "03".repeat(32),
+        );
+        assert_eq!(keylog.add_text(&text), 0);
+        assert!(!keylog.has_source());
+    }
+
+    #[test]
+    fn reads_pcapng_decryption_secrets() {
+        let secrets = b"CLIENT_RANDOM 00 11\n";
+        let block = |block_type: u32, body: &[u8]| {
+            let len = 12 + body.len() as u32;
+            [&block_type.to_le_bytes()[..], &len.to_le_bytes(), body, &len.to_le_bytes()].concat()
+        };
+        let header = [&0x1a2b3c4du32.to_le_bytes()[..], &[1, 0, 0, 0], &[0xff; 8]].concat();
+        let mut dsb = DSB_TLS_KEY_LOG.to_le_bytes().to_vec();
+        dsb.extend_from_slice(&(secrets.len() as u32).to_le_bytes());
+        dsb.extend_from_slice(secrets);
+        dsb.resize(dsb.len().next_multiple_of(4), 0);
+        let file = [block(0x0a0d0d0a, &header), block(DSB_BLOCK_TYPE, &dsb)].concat();
+
+        let path = std::env::temp_dir().join(format!("anansi-keylog-{}.pcapng", std::process::id()));
+        std::fs::write(&path, &file).unwrap();
+        let text = read_pcapng_secrets(&path);
+        // A block claiming to be longer than the file is ignored
+        std::fs::write(&path, &file[..file.len() - 4]).unwrap();
+        let truncated = read_pcapng_secrets(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text.unwrap(), "CLIENT_RANDOM 00 11\n\n");
+        assert_eq!(truncated.unwrap(), "");
+    }
+}
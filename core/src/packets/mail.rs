@@ -0,0 +1,430 @@
+use anyhow::Result;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use crate::output::safe_file_name;
+use super::dissector::{Dissection, DissectContext};
+use super::flow::FlowKey;
+use super::imap::ImapProcessor;
+use super::pop3::Pop3Processor;
+use super::reassembly::StreamBuffer;
+use super::smtp::SmtpProcessor;
+
+/// Longest line kept while waiting for its end
+const MAX_LINE: usize = 16 * 1024;
+/// Largest message kept for extraction
+pub(crate) const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+/// Bytes kept of all the messages
+const MAX_STORED_BYTES: usize = 512 * 1024 * 1024;
+
+/// An email sent over SMTP or retrieved over POP3 or IMAP in clear text
+#[derive(Debug, Clone, Serialize)]
+pub struct MailMessage {
+    /// `SMTP`, `POP3` or `IMAP`
+    pub protocol: &'static str,
+    pub client: IpAddr,
+    pub server: IpAddr,
+    /// Envelope sender of an SMTP transaction
+    pub mail_from: Option<String>,
+    /// Envelope recipients of an SMTP transaction
+    pub recipients: Vec<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub subject: Option<String>,
+    pub size: usize,
+    pub captured_at: DateTime<Utc>,
+    /// The content was dropped to stay within the storage limit
+    pub content_dropped: bool,
+    /// Message in Internet Message Format, headers included
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl MailMessage {
+    pub(crate) fn new(protocol: &'static str, client: IpAddr, server: IpAddr, data: Vec<u8>, at: DateTime<Utc>) -> Self {
+        Self {
+            protocol,
+            client,
+            server,
+            mail_from: None,
+            recipients: Vec::new(),
+            from: header(&data, "From"),
+            to: header(&data, "To"),
+            subject: header(&data, "Subject"),
+            size: data.len(),
+            captured_at: at,
+            content_dropped: false,
+            data,
+        }
+    }
+
+    /// Subject with characters unsafe in file names replaced, with the `.eml` extension
+    pub fn file_name(&self) -> String {
+        format!("{}.eml", safe_file_name(self.subject.as_deref().unwrap_or_default(), "message"))
+    }
+
+    pub fn format_info(&self) -> String {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        let sender = self.mail_from.clone().or_else(|| self.from.clone());
+        let recipients = match self.recipients.is_empty() {
+            true => optional(&self.to),
+            false => self.recipients.join(", "),
+        };
+        let mut info = format!(
+            "{} {} -> {} from {} to {} \"{}\", {} bytes",
+            self.protocol,
+            self.client,
+            self.server,
+            optional(&sender),
+            recipients,
+            optional(&self.subject),
+            self.size
+        );
+        if self.content_dropped {
+            info.push_str(" [content dropped]");
+        }
+        info
+    }
+
+    pub(crate) fn add_fields(&self, ctx: &mut DissectContext) {
+        ctx.add_field("imf.size", self.size);
+        for (name, value) in [("imf.from", &self.from), ("imf.to", &self.to), ("imf.subject", &self.subject)] {
+            if let Some(value) = value {
+                ctx.add_field(name, value.clone());
+            }
+        }
+    }
+
+    /// Packet summary of the segment completing the message
+    pub(crate) fn summary(&self) -> String {
+        match &self.subject {
+            Some(subject) => format!("Message, {} bytes, \"{}\"", self.size, subject),
+            None => format!("Message, {} bytes", self.size),
+        }
+    }
+}
+
+/// Value of the first header field called `name`, unfolded
+pub(crate) fn header(message: &[u8], name: &str) -> Option<String> {
+    let text = String::from_utf8_lossy(message);
+    let mut lines = text.split('\n').map(|line| line.trim_end_matches('\r'));
+    let mut value: Option<String> = None;
+    for line in lines.by_ref() {
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = value.as_mut() {
+            if line.starts_with([' ', '\t']) {
+                value.push(' ');
+                value.push_str(line.trim());
+                continue;
+            }
+            break;
+        }
+        // Continuation lines of other fields have no colon
+        let Some((field, rest)) = line.split_once(':') else {
+            continue;
+        };
+        if field.eq_ignore_ascii_case(name) {
+            value = Some(rest.trim().to_string());
+        }
+    }
+    value
+}
+
+/// Both directions of a mail connection
+pub(crate) struct Connection {
+    pub client: IpAddr,
+    pub server: IpAddr,
+    client_port: u16,
+    pub client_stream: LineStream,
+    pub server_stream: LineStream,
+    /// STARTTLS succeeded; the rest of the connection is TLS
+    pub tls: bool,
+}
+
+impl Connection {
+    /// Connection of the flow `client` opened, in its direction
+    pub fn new(client: &FlowKey) -> Self {
+        Self {
+            client: client.src,
+            server: client.dst,
+            client_port: client.src_port,
+            client_stream: LineStream::default(),
+            server_stream: LineStream::default(),
+            tls: false,
+        }
+    }
+
+    pub fn is_client(&self, flow: &FlowKey) -> bool {
+        flow.src == self.client && flow.src_port == self.client_port
+    }
+
+    /// Switches to TLS after a successful STARTTLS
+    pub fn start_tls(&mut self) {
+        self.tls = true;
+        self.client_stream = LineStream::default();
+        self.server_stream = LineStream::default();
+    }
+}
+
+/// One direction of a line-oriented connection
+#[derive(Default)]
+pub(crate) struct LineStream {
+    buffer: StreamBuffer,
+}
+
+impl LineStream {
+    /// Appends a segment and returns `false` if bytes were lost before it
+    pub fn push(&mut self, seq: u32, payload: &[u8]) -> bool {
+        self.buffer.push(seq, payload)
+    }
+
+    /// Removes the next complete line, line ending included
+    pub fn raw_line(&mut self) -> Option<Vec<u8>> {
+        let Some(end) = self.buffer.data().iter().position(|&b| b == b'\n') else {
+            if self.buffer.data().len() > MAX_LINE {
+                self.buffer.clear();
+            }
+            return None;
+        };
+        let line = self.buffer.data()[..=end].to_vec();
+        self.buffer.consume(end + 1);
+        Some(line)
+    }
+
+    /// Removes the next complete line, without its line ending
+    pub fn line(&mut self) -> Option<String> {
+        self.raw_line().map(|line| trim_line(&line))
+    }
+
+    /// Removes up to `len` bytes, for literals and chunks read by size
+    pub fn take(&mut self, len: usize) -> Vec<u8> {
+        let len = len.min(self.buffer.data().len());
+        let data = self.buffer.data()[..len].to_vec();
+        self.buffer.consume(len);
+        data
+    }
+}
+
+pub(crate) fn trim_line(line: &[u8]) -> String {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line)).into_owned()
+}
+
+/// Appends a line of dot-stuffed content (SMTP DATA, POP3 multi-line
+/// responses). Returns `true` on the terminating `.` line.
+pub(crate) fn append_dot_stuffed(content: &mut Vec<u8>, line: &[u8]) -> bool {
+    if trim_line(line) == "." {
+        return true;
+    }
+    let line = line.strip_prefix(b".").unwrap_or(line);
+    if content.len() + line.len() <= MAX_MESSAGE_SIZE {
+        content.extend_from_slice(line);
+    }
+    false
+}
+
+/// SASL exchange started by an AUTH or AUTHENTICATE command
+pub(crate) struct SaslExchange {
+    pub mechanism: String,
+    /// Client responses read so far
+    responses: usize,
+}
+
+impl SaslExchange {
+    pub fn new(mechanism: &str) -> Self {
+        Self {
+            mechanism: mechanism.to_ascii_uppercase(),
+            responses: 0,
+        }
+    }
+
+    /// Reads a base64 client response. Returns the user name it carries, if any;
+    /// passwords are never kept.
+    pub fn response(&mut self, encoded: &str) -> Option<String> {
+        let index = self.responses;
+        self.responses += 1;
+        let decoded = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+        let username = match self.mechanism.as_str() {
+            // authzid NUL authcid NUL passwd
+            "PLAIN" => decoded.split(|&b| b == 0).nth(1)?.to_vec(),
+            "LOGIN" if index == 0 => decoded,
+            _ => return None,
+        };
+        Some(String::from_utf8_lossy(&username).into_owned()).filter(|username| !username.is_empty())
+    }
+}
+
+/// Hands a segment of a connection upgraded with STARTTLS to the TLS dissector
+pub(crate) fn dissect_tls(data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+    let tls = ctx
+        .registry()
+        .by_name("TLS")
+        .ok_or_else(|| anyhow::anyhow!("No TLS dissector for the STARTTLS upgrade"))?;
+    ctx.dissect_with(tls.as_ref(), data)
+}
+
+/// Messages of every mail connection; once the storage limit is reached only
+/// the headers of new messages are kept
+#[derive(Default)]
+pub(crate) struct MailStore {
+    messages: Vec<MailMessage>,
+    /// Bytes of message content kept
+    stored: usize,
+}
+
+impl MailStore {
+    pub fn extend(&mut self, messages: impl IntoIterator<Item = MailMessage>) {
+        for mut message in messages {
+            if message.data.len() > MAX_STORED_BYTES - self.stored {
+                message.data = Vec::new();
+                message.content_dropped = true;
+            }
+            self.stored += message.data.len();
+            self.messages.push(message);
+        }
+    }
+}
+
+/// The SMTP, POP3 and IMAP dissectors, collecting the messages they carry
+pub struct MailProcessor {
+    messages: Arc<Mutex<MailStore>>,
+}
+
+impl Default for MailProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MailProcessor {
+    pub fn new() -> Self {
+        Self {
+            messages: Arc::new(Mutex::new(MailStore::default())),
+        }
+    }
+
+    pub fn smtp_dissector(&self) -> SmtpProcessor {
+        SmtpProcessor::new(self.messages.clone())
+    }
+
+    pub fn pop3_dissector(&self) -> Pop3Processor {
+        Pop3Processor::new(self.messages.clone())
+    }
+
+    pub fn imap_dissector(&self) -> ImapProcessor {
+        ImapProcessor::new(self.messages.clone())
+    }
+
+    /// Messages seen so far, in the order they were completed
+    pub fn messages(&self) -> Vec<MailMessage> {
+        self.messages.lock().unwrap().messages.clone()
+    }
+}
+
+/// Plays both sides of a mail connection in tests
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use crate::alerts::AlertChannel;
+    use pnet::packet::tcp::TcpFlags;
+    use super::super::dissector::{testing, Dissector, DissectorRegistry};
+    use super::super::tree::Field;
+
+    pub(crate) struct Conversation<'a> {
+        registry: &'a DissectorRegistry,
+        dissector: &'a dyn Dissector,
+        client: FlowKey,
+        /// Next sequence number of the client and of the server
+        sequence: [u32; 2],
+        pub at: DateTime<Utc>,
+    }
+
+    impl<'a> Conversation<'a> {
+        pub fn new(registry: &'a DissectorRegistry, dissector: &'a dyn Dissector, port: u16) -> Self {
+            Self {
+                registry,
+                dissector,
+                client: FlowKey::new("10.0.0.1".parse().unwrap(), 50000, "10.0.0.2".parse().unwrap(), port),
+                sequence: [1, 1],
+                at: Utc::now(),
+            }
+        }
+
+        /// Dissects a segment of the client, which must succeed
+        pub fn client(&mut self, data: &[u8]) -> (Dissection, Vec<Field>) {
+            let (dissection, fields) = self.send(true, data, TcpFlags::ACK);
+            (dissection.expect("client segment"), fields)
+        }
+
+        /// Dissects a segment of the server, which must succeed
+        pub fn server(&mut self, data: &[u8]) -> (Dissection, Vec<Field>) {
+            let (dissection, fields) = self.send(false, data, TcpFlags::ACK);
+            (dissection.expect("server segment"), fields)
+        }
+
+        pub fn send(&mut self, from_client: bool, data: &[u8], flags: u8) -> (Result<Dissection>, Vec<Field>) {
+            let flow = if from_client { self.client } else { self.client.reversed() };
+            let sequence = &mut self.sequence[!from_client as usize];
+            let seq = *sequence;
+            *sequence = sequence.wrapping_add(data.len() as u32);
+            let (client, at) = (self.client, self.at);
+            let closed = testing::close_flow(self.registry, &flow, flags, at);
+            testing::dissect_in(self.registry, &AlertChannel::new(), self.dissector, data, |ctx| {
+                ctx.flow = Some(flow);
+                ctx.client_flow = Some(client);
+                ctx.tcp_sequence = Some(seq);
+                ctx.tcp_flags = Some(flags);
+                ctx.flow_closed = closed;
+                ctx.captured_at = at;
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(subject: Option<&str>, data: &[u8]) -> MailMessage {
+        let mut data = data.to_vec();
+        if let Some(subject) = subject {
+            data.splice(0..0, format!("Subject: {}\r\n\r\n", subject).into_bytes());
+        }
+        MailMessage::new("SMTP", "10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap(), data, Utc::now())
+    }
+
+    #[test]
+    fn unfolds_header_fields() {
+        let data = b"From: alice@example.com\r\nSubject: a long\r\n\tsubject\r\nTo: bob\r\n\r\nSubject: body";
+        assert_eq!(header(data, "subject").as_deref(), Some("a long subject"));
+        assert_eq!(header(data, "To").as_deref(), Some("bob"));
+        assert_eq!(header(data, "Cc"), None);
+    }
+
+    #[test]
+    fn names_exported_messages_after_their_subject() {
+        assert_eq!(message(Some("Re: a/b?"), b"").file_name(), "Re_ a_b_.eml");
+        assert_eq!(message(Some(""), b"").file_name(), "message.eml");
+        assert_eq!(message(None, b"no headers").file_name(), "message.eml");
+        assert_eq!(message(Some(&"x".repeat(100)), b"").file_name().len(), 64 + 4);
+    }
+
+    #[test]
+    fn keeps_to_the_storage_limit() {
+        let mut store = MailStore { stored: MAX_STORED_BYTES - 8, ..MailStore::default() };
+        store.extend([message(None, b"too large"), message(None, b"fits")]);
+        let [dropped, kept] = &store.messages[..] else {
+            panic!("expected two messages");
+        };
+        assert!(dropped.content_dropped && dropped.data.is_empty());
+        assert_eq!(dropped.size, 9);
+        assert!(dropped.format_info().ends_with("[content dropped]"));
+        assert!(!kept.content_dropped);
+        assert_eq!(kept.data, b"fits");
+        assert_eq!(store.stored, MAX_STORED_BYTES - 4);
+    }
+}
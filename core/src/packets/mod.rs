@@ -1,309 +1,369 @@
+mod bytes;
+mod flow;
+mod detection;
+mod dissector;
+mod certificate;
 mod tcp;
 mod udp;
 mod icmp;
 mod icmpv6;
+mod neighbors;
 mod ipv4;
 mod ipv6;
 mod ethernet;
 mod arp;
+mod arp_watch;
 mod dns;
+mod dns_stats;
 mod dhcp;
+mod dhcpv6;
+mod dhcp_leases;
 mod http;
+mod http2;
 mod tls;
+mod tls_decrypt;
+mod keylog;
+mod reassembly;
 mod igmp;
+mod multicast;
+mod quic;
+mod ntp;
+mod snmp;
+mod syslog;
+mod ssh;
 mod smb;
+mod smb2;
+mod smb_files;
 mod ftp;
+mod mail;
+mod smtp;
+mod pop3;
+mod imap;
+mod tree;
+mod hexdump;
 
 pub use tcp::TcpProcessor;
 pub use udp::UdpProcessor;
 pub use icmp::IcmpProcessor;
 pub use icmpv6::Icmpv6Processor;
+pub use neighbors::Ipv6Neighbor;
 pub use ipv4::Ipv4Processor;
 pub use ipv6::Ipv6Processor;
 pub use ethernet::EthernetProcessor;
 pub use arp::ArpProcessor;
+pub use arp_watch::ArpBinding;
 pub use dns::DnsProcessor;
+pub use dns_stats::{DnsStatistics, LatencyPercentiles, NameCount, RcodeCount, ServerStatistics};
 pub use dhcp::DhcpProcessor;
+pub use dhcpv6::Dhcpv6Processor;
+pub use dhcp_leases::{DhcpAllowList, DhcpLease, LeaseState};
 pub use http::HttpProcessor;
+pub use http2::Http2Processor;
 pub use tls::TlsProcessor;
+pub use quic::QuicProcessor;
+pub use ntp::NtpProcessor;
+pub use snmp::SnmpProcessor;
+pub use syslog::SyslogProcessor;
+pub use ssh::{SshAlgorithms, SshProcessor, SshSession};
+pub use tls_decrypt::DecryptedData;
+pub use keylog::read_pcapng_secrets;
 pub use igmp::IgmpProcessor;
+pub use multicast::{FilterMode, MembershipState, MulticastMembership};
 pub use smb::SmbProcessor;
-pub use ftp::FtpProcessor;
+pub use smb_files::{SmbEvent, SmbFile, SmbOperation};
+pub use ftp::{FtpDataProcessor, FtpMessage, FtpProcessor, FtpTransfer};
+pub use mail::{MailMessage, MailProcessor};
+pub use smtp::SmtpProcessor;
+pub use pop3::Pop3Processor;
+pub use imap::ImapProcessor;
+pub use flow::FlowKey;
+pub use detection::{Detection, FlowClassifier, ProbeResult};
+pub use dissector::{
+    Dissection, DissectContext, Dissector, DissectorRegistry, DissectorTable, Expectation, FlowRecord, Transport,
+    LINKTYPE_ETHERNET,
+};
+pub use certificate::CertificateInfo;
+pub use tree::{Field, FieldValue, Layer};
 
-use chrono::Local;
+use chrono::{DateTime, Local, Utc};
 use colored::*;
-use pnet::packet::Packet;
-use pnet::packet::ethernet::EtherTypes;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use crate::alerts::AlertChannel;
 
+/// A decoded packet: the summary shown in the packet list and the protocol
+/// tree it was built from
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PacketInfo {
+    /// Position of the packet in the capture, starting at 1
+    pub number: u64,
+    pub captured_at: DateTime<Utc>,
+    /// Capture time formatted for display
     pub timestamp: String,
     pub source_ip: String,
     pub destination_ip: String,
     pub protocol: String,
     pub length: usize,
     pub details: String,
+    /// Decoded layers, outermost first, starting with the `Frame` layer
+    pub layers: Vec<Layer>,
+}
+
+impl PacketInfo {
+    /// Returns every field of every layer
+    pub fn fields(&self) -> impl Iterator<Item = &Field> {
+        self.layers.iter().flat_map(|layer| layer.fields.iter())
+    }
+
+    /// Returns the first value of the named field
+    pub fn field(&self, name: &str) -> Option<&FieldValue> {
+        self.fields().find(|field| field.name == name).map(|field| &field.value)
+    }
+
+    /// Returns all values of the named field; a name may occur several times
+    /// for multi-valued fields
+    pub fn field_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a FieldValue> + 'a {
+        self.fields()
+            .filter(move |field| field.name == name)
+            .map(|field| &field.value)
+    }
+
+    /// Returns the first layer decoded by the named protocol
+    pub fn layer(&self, protocol: &str) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.protocol.eq_ignore_ascii_case(protocol))
+    }
 }
 
 pub struct PacketProcessor {
     debug_mode: bool,
-    tcp_processor: TcpProcessor,
-    udp_processor: UdpProcessor,
-    icmp_processor: IcmpProcessor,
-    icmpv6_processor: icmpv6::Icmpv6Processor,
-    ipv4_processor: ipv4::Ipv4Processor,
-    ipv6_processor: ipv6::Ipv6Processor,
-    ethernet_processor: ethernet::EthernetProcessor,
-    arp_processor: ArpProcessor,
-    dns_processor: DnsProcessor,
-    dhcp_processor: DhcpProcessor,
-    http_processor: HttpProcessor,
-    tls_processor: TlsProcessor,
-    igmp_processor: IgmpProcessor,
-    smb_processor: SmbProcessor,
-    ftp_processor: FtpProcessor,
+    registry: DissectorRegistry,
+    tls_processor: Arc<TlsProcessor>,
+    dns_processor: Arc<DnsProcessor>,
+    dhcp_processor: Arc<DhcpProcessor>,
+    ftp_processor: Arc<FtpProcessor>,
+    mail_processor: Arc<MailProcessor>,
+    smb_processor: Arc<SmbProcessor>,
+    ssh_processor: Arc<SshProcessor>,
+    arp_processor: Arc<ArpProcessor>,
+    icmpv6_processor: Arc<Icmpv6Processor>,
+    igmp_processor: Arc<IgmpProcessor>,
+    alerts: Arc<AlertChannel>,
+    frames: AtomicU64,
 }
 
 impl PacketProcessor {
     pub fn new(debug_mode: bool) -> Self {
-        Self {
+        let mut processor = Self {
             debug_mode,
-            tcp_processor: TcpProcessor::new(),
-            udp_processor: UdpProcessor::new(),
-            icmp_processor: IcmpProcessor::new(),
-            icmpv6_processor: icmpv6::Icmpv6Processor::new(),
-            ipv4_processor: ipv4::Ipv4Processor::new(),
-            ipv6_processor: ipv6::Ipv6Processor::new(),
-            ethernet_processor: ethernet::EthernetProcessor::new(),
-            arp_processor: ArpProcessor::new(),
-            dns_processor: DnsProcessor::new(),
-            dhcp_processor: DhcpProcessor::new(),
-            http_processor: HttpProcessor::new(),
-            tls_processor: TlsProcessor::new(),
-            igmp_processor: IgmpProcessor::new(),
-            smb_processor: SmbProcessor::new(),
-            ftp_processor: FtpProcessor::new(),
-        }
+            registry: DissectorRegistry::new(),
+            tls_processor: Arc::new(TlsProcessor::new()),
+            dns_processor: Arc::new(DnsProcessor::new()),
+            dhcp_processor: Arc::new(DhcpProcessor::new()),
+            ftp_processor: Arc::new(FtpProcessor::new()),
+            mail_processor: Arc::new(MailProcessor::new()),
+            smb_processor: Arc::new(SmbProcessor::new()),
+            ssh_processor: Arc::new(SshProcessor::new()),
+            arp_processor: Arc::new(ArpProcessor::new()),
+            icmpv6_processor: Arc::new(Icmpv6Processor::new()),
+            igmp_processor: Arc::new(IgmpProcessor::new()),
+            alerts: Arc::new(AlertChannel::new()),
+            frames: AtomicU64::new(0),
+        };
+        processor.registry = processor.default_registry();
+        processor
     }
 
-    pub fn process_packet(&self, packet: &pcap::Packet) -> PacketInfo {
-        let timestamp = Local::now().format("%H:%M:%S%.3f").to_string();
-        let ethernet = self.ethernet_processor.process(packet.data).unwrap();
-        
-        let (source_ip, destination_ip, protocol, details) = match ethernet.get_ethertype() {
-            EtherTypes::Ipv4 => {
-                let ipv4 = self.ipv4_processor.process(ethernet.payload()).unwrap();
-                let source = ipv4.get_source();
-                let destination = ipv4.get_destination();
-                
-                let (protocol, details) = match ipv4.get_next_level_protocol() {
-                    pnet::packet::ip::IpNextHeaderProtocols::Tcp => {
-                        let tcp = self.tcp_processor.process(ipv4.payload()).unwrap();
-                        let payload = tcp.payload();
-                        
-                        // Check for TLS/SSL traffic (ports 443, 465, 993, 995)
-                        if tcp.get_destination() == 443 || tcp.get_source() == 443 ||
-                           tcp.get_destination() == 465 || tcp.get_source() == 465 ||
-                           tcp.get_destination() == 993 || tcp.get_source() == 993 ||
-                           tcp.get_destination() == 995 || tcp.get_source() == 995 {
-                            if let Ok(tls) = self.tls_processor.process(payload) {
-                                (
-                                    "TLS".to_string(),
-                                    format!("{} - {}", tls.get_version(), tls.format_info())
-                                )
-                            } else {
-                                (
-                                    "TCP".to_string(),
-                                    TcpProcessor::format_tcp_info(&tcp)
-                                )
-                            }
-                        } else if tcp.get_destination() == 445 || tcp.get_source() == 445 {
-                            // Check for SMB traffic (port 445)
-                            if let Ok(smb) = self.smb_processor.process(payload) {
-                                (
-                                    "SMB".to_string(),
-                                    format!(
-                                        "SMB Packet - Command: {}",
-                                        self.smb_processor.get_command(&smb)
-                                    )
-                                )
-                            } else {
-                                (
-                                    "TCP".to_string(),
-                                    TcpProcessor::format_tcp_info(&tcp)
-                                )
-                            }
-                        } else if tcp.get_destination() == 80 || tcp.get_source() == 80 {
-                            // Check for HTTP traffic
-                            if let Ok(http) = self.http_processor.process(payload) {
-                                (
-                                    "HTTP".to_string(),
-                                    format!(
-                                        "{} {} {}",
-                                        http.get_method(),
-                                        http.get_path(),
-                                        http.get_host()
-                                    )
-                                )
-                            } else {
-                                (
-                                    "TCP".to_string(),
-                                    TcpProcessor::format_tcp_info(&tcp)
-                                )
-                            }
-                        } else {
-                            (
-                                "TCP".to_string(),
-                                TcpProcessor::format_tcp_info(&tcp)
-                            )
-                        }
-                    }
-                    pnet::packet::ip::IpNextHeaderProtocols::Udp => {
-                        let udp = self.udp_processor.process(ipv4.payload()).unwrap();
-                        let payload = udp.payload();
-                        
-                        // Check for DNS traffic
-                        if udp.get_destination() == 53 || udp.get_source() == 53 {
-                            if let Ok(dns) = self.dns_processor.process(payload) {
-                                (
-                                    "DNS".to_string(),
-                                    self.dns_processor.get_query_info(&dns)
-                                )
-                            } else {
-                                (
-                                    "UDP".to_string(),
-                                    format!(
-                                        "{}:{} > {}:{} UDP, length {}",
-                                        source,
-                                        udp.get_source(),
-                                        destination,
-                                        udp.get_destination(),
-                                        ipv4.payload().len()
-                                    )
-                                )
-                            }
-                        } else {
-                            (
-                                "UDP".to_string(),
-                                format!(
-                                    "{}:{} > {}:{} UDP, length {}",
-                                    source,
-                                    udp.get_source(),
-                                    destination,
-                                    udp.get_destination(),
-                                    ipv4.payload().len()
-                                )
-                            )
-                        }
-                    }
-                    pnet::packet::ip::IpNextHeaderProtocols::Icmp => {
-                        let icmp = self.icmp_processor.process(ipv4.payload()).unwrap();
-                        (
-                            "ICMP".to_string(),
-                            format!(
-                                "{} > {}: {}",
-                                source,
-                                destination,
-                                self.icmp_processor.get_icmp_type(&icmp)
-                            )
-                        )
-                    }
-                    pnet::packet::ip::IpNextHeaderProtocols::Igmp => {
-                        if let Ok(igmp) = self.igmp_processor.process(ipv4.payload()) {
-                            (
-                                "IGMP".to_string(),
-                                igmp.format_info()
-                            )
-                        } else {
-                            (
-                                "IGMP".to_string(),
-                                format!("Unknown IGMP packet, length {}", ipv4.payload().len())
-                            )
-                        }
-                    }
-                    _ => (
-                        format!("Unknown({})", ipv4.get_next_level_protocol()),
-                        format!("Unknown protocol, length {}", ipv4.payload().len())
-                    )
-                };
-                
-                (source.to_string(), destination.to_string(), protocol, details)
-            }
-            EtherTypes::Ipv6 => {
-                let ipv6 = self.ipv6_processor.process(ethernet.payload()).unwrap();
-                let source = ipv6.get_source();
-                let destination = ipv6.get_destination();
-                
-                let (protocol, details) = match ipv6.get_next_header() {
-                    pnet::packet::ip::IpNextHeaderProtocols::Tcp => {
-                        let tcp = self.tcp_processor.process(ipv6.payload()).unwrap();
-                        (
-                            "TCP".to_string(),
-                            format!(
-                                "Flags [{}], seq {}, ack {}, win {}, length {}",
-                                tcp.get_flags(),
-                                tcp.get_sequence(),
-                                tcp.get_acknowledgement(),
-                                tcp.get_window(),
-                                ipv6.payload().len()
-                            )
-                        )
-                    }
-                    pnet::packet::ip::IpNextHeaderProtocols::Udp => {
-                        let _udp = self.udp_processor.process(ipv6.payload()).unwrap();
-                        (
-                            "UDP".to_string(),
-                            format!(
-                                "UDP, length {}",
-                                ipv6.payload().len()
-                            )
-                        )
-                    }
-                    pnet::packet::ip::IpNextHeaderProtocols::Icmpv6 => {
-                        let _icmpv6 = self.icmpv6_processor.process(ipv6.payload()).unwrap();
-                        (
-                            "ICMPv6".to_string(),
-                            format!(
-                                "{} > {}: ICMPv6, length {}",
-                                source,
-                                destination,
-                                ipv6.payload().len()
-                            )
-                        )
-                    }
-                    _ => (
-                        format!("Unknown({})", ipv6.get_next_header()),
-                        format!("{} > {}: Unknown protocol", source, destination)
-                    )
-                };
-                
-                (source.to_string(), destination.to_string(), protocol, details)
+    /// Registry with the built-in dissectors
+    fn default_registry(&self) -> DissectorRegistry {
+        let mut registry = DissectorRegistry::new();
+        registry.register(DissectorTable::LinkType(LINKTYPE_ETHERNET), Arc::new(EthernetProcessor::new()));
+        registry.register(DissectorTable::Ethertype(0x0800), Arc::new(Ipv4Processor::new()));
+        registry.register(DissectorTable::Ethertype(0x86dd), Arc::new(Ipv6Processor::new()));
+        registry.register(DissectorTable::Ethertype(0x0806), self.arp_processor.clone());
+        registry.register(DissectorTable::IpProtocol(1), Arc::new(IcmpProcessor::new()));
+        registry.register(DissectorTable::IpProtocol(2), self.igmp_processor.clone());
+        registry.register(DissectorTable::IpProtocol(6), Arc::new(TcpProcessor::new()));
+        registry.register(DissectorTable::IpProtocol(17), Arc::new(UdpProcessor::new()));
+        registry.register(DissectorTable::IpProtocol(58), self.icmpv6_processor.clone());
+
+        // Application protocols are found by their heuristics; the ports are hints
+        let ftp_data = Arc::new(self.ftp_processor.data_dissector());
+        let tcp: [(Arc<dyn Dissector>, &[u16]); 9] = [
+            (Arc::new(Http2Processor::new()), &[]),
+            (Arc::new(HttpProcessor::new()), &[80, 8000, 8008, 8080, 8888]),
+            (self.tls_processor.clone(), &[443, 465, 563, 636, 853, 989, 990, 993, 995, 8443]),
+            (self.smb_processor.clone(), &[139, 445]),
+            (self.ftp_processor.clone(), &[21]),
+            (self.ssh_processor.clone(), &[22, 2222]),
+            (Arc::new(self.mail_processor.smtp_dissector()), &[25, 587, 2525]),
+            (Arc::new(self.mail_processor.pop3_dissector()), &[110]),
+            (Arc::new(self.mail_processor.imap_dissector()), &[143]),
+        ];
+        for (dissector, ports) in tcp {
+            for port in ports {
+                registry.register(DissectorTable::Port(Transport::Tcp, *port), dissector.clone());
             }
-            EtherTypes::Arp => {
-                let arp = self.arp_processor.process(ethernet.payload()).unwrap();
-                let source = format_mac(&ethernet.get_source().octets());
-                let destination = format_mac(&ethernet.get_destination().octets());
-                (
-                    source,
-                    destination,
-                    "ARP".to_string(),
-                    ArpProcessor::format_arp_info(&arp)
-                )
+            registry.register_heuristic(Transport::Tcp, dissector);
+        }
+        // Data connections are announced by the control connection; active mode
+        // servers connect from port 20
+        registry.register(DissectorTable::Port(Transport::Tcp, 20), ftp_data);
+
+        let dns: Arc<dyn Dissector> = self.dns_processor.clone();
+        for port in [53, 5353, 5355] {
+            registry.register(DissectorTable::Port(Transport::Udp, port), dns.clone());
+        }
+        registry.register(DissectorTable::Port(Transport::Tcp, 53), dns.clone());
+        registry.register_heuristic(Transport::Udp, dns.clone());
+        registry.register_heuristic(Transport::Tcp, dns);
+
+        let dhcp: Arc<dyn Dissector> = self.dhcp_processor.clone();
+        for port in [67, 68] {
+            registry.register(DissectorTable::Port(Transport::Udp, port), dhcp.clone());
+        }
+        registry.register_heuristic(Transport::Udp, dhcp);
+        // HTTP/3 and DNS over QUIC
+        let quic: Arc<dyn Dissector> = Arc::new(QuicProcessor::new());
+        for port in [443, 853] {
+            registry.register(DissectorTable::Port(Transport::Udp, port), quic.clone());
+        }
+        registry.register_heuristic(Transport::Udp, quic);
+
+        let dhcpv6: Arc<dyn Dissector> = Arc::new(Dhcpv6Processor::new());
+        for port in [546, 547] {
+            registry.register(DissectorTable::Port(Transport::Udp, port), dhcpv6.clone());
+        }
+
+        // Infrastructure monitoring: time, management and logging
+        let udp: [(Arc<dyn Dissector>, &[u16]); 3] = [
+            (Arc::new(NtpProcessor::new()), &[123]),
+            (Arc::new(SnmpProcessor::new()), &[161, 162]),
+            (Arc::new(SyslogProcessor::new()), &[514]),
+        ];
+        for (dissector, ports) in udp {
+            for port in ports {
+                registry.register(DissectorTable::Port(Transport::Udp, *port), dissector.clone());
             }
-            _ => (
-                "Unknown".to_string(),
-                "Unknown".to_string(),
-                "Unknown".to_string(),
-                format!("Unknown ethertype: {}", ethernet.get_ethertype())
-            )
-        };
+            registry.register_heuristic(Transport::Udp, dissector);
+        }
+
+        registry
+    }
+
+    /// Registers an additional dissector, replacing a built-in one registered for the same key
+    pub fn register_dissector(&mut self, table: DissectorTable, dissector: Arc<dyn Dissector>) {
+        self.registry.register(table, dissector);
+    }
+
+    /// Registers a dissector that is probed on TCP or UDP flows not yet classified
+    pub fn register_heuristic_dissector(&mut self, transport: Transport, dissector: Arc<dyn Dissector>) {
+        self.registry.register_heuristic(transport, dissector);
+    }
+
+    /// Channel receiving the alerts raised while processing packets
+    pub fn alerts(&self) -> Arc<AlertChannel> {
+        self.alerts.clone()
+    }
+
+    /// Returns every distinct TLS certificate seen so far
+    pub fn certificates(&self) -> Vec<CertificateInfo> {
+        self.tls_processor.certificates()
+    }
+
+    /// DNS resolver statistics with the `top` most queried names and types
+    pub fn dns_statistics(&self, top: usize) -> DnsStatistics {
+        self.dns_processor.statistics(top)
+    }
+
+    /// Current DHCP lease of every client seen
+    pub fn dhcp_leases(&self) -> Vec<DhcpLease> {
+        self.dhcp_processor.leases()
+    }
+
+    /// Sets the DHCP servers, routers and DNS servers expected on the network
+    pub fn set_dhcp_allow_list(&self, allow_list: DhcpAllowList) {
+        self.dhcp_processor.set_allow_list(allow_list);
+    }
+
+    /// Files transferred over FTP data connections, including those in progress
+    pub fn ftp_transfers(&self) -> Vec<FtpTransfer> {
+        self.ftp_processor.transfers()
+    }
+
+    /// Messages sent over SMTP or retrieved over POP3 and IMAP in clear text
+    pub fn mail_messages(&self) -> Vec<MailMessage> {
+        self.mail_processor.messages()
+    }
+
+    /// SMB2 logons, tree connects and file operations in the order of their responses
+    pub fn smb_events(&self) -> Vec<SmbEvent> {
+        self.smb_processor.events()
+    }
+
+    /// Files read or written over SMB2, rebuilt from the clear-text data
+    pub fn smb_files(&self) -> Vec<SmbFile> {
+        self.smb_processor.files()
+    }
+
+    /// SSH sessions with the software and algorithms of both ends
+    pub fn ssh_sessions(&self) -> Vec<SshSession> {
+        self.ssh_processor.sessions()
+    }
+
+    /// IPv4 to MAC bindings learned from ARP
+    pub fn arp_bindings(&self) -> Vec<ArpBinding> {
+        self.arp_processor.bindings()
+    }
+
+    /// IPv6 neighbors learned from Neighbor Discovery
+    pub fn ipv6_neighbors(&self) -> Vec<Ipv6Neighbor> {
+        self.icmpv6_processor.neighbors()
+    }
+
+    /// Multicast group memberships reported over IGMP
+    pub fn multicast_memberships(&self) -> Vec<MulticastMembership> {
+        self.igmp_processor.memberships()
+    }
+
+    /// Loads TLS session secrets from an NSS key log file
+    pub fn load_tls_keylog(&self, path: &std::path::Path) -> anyhow::Result<usize> {
+        self.tls_processor.load_keylog(path)
+    }
+
+    /// Adds TLS session secrets given as key log text
+    pub fn add_tls_secrets(&self, keylog: &str) -> usize {
+        self.tls_processor.add_secrets(keylog)
+    }
+
+    pub fn process_packet(&self, packet: &pcap::Packet) -> PacketInfo {
+        let captured_at = DateTime::<Utc>::from_timestamp(
+            packet.header.ts.tv_sec as _,
+            packet.header.ts.tv_usec as u32 * 1000,
+        )
+        .unwrap_or_else(Utc::now);
+        let timestamp = captured_at.with_timezone(&Local).format("%H:%M:%S%.3f").to_string();
+
+        let number = self.frames.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut ctx = DissectContext::new(&self.registry, &self.alerts, packet.data, number, captured_at, timestamp);
+        ctx.add_field("frame.number", number);
+        ctx.add_field("frame.time_epoch", captured_at.timestamp_micros() as f64 / 1e6);
+        ctx.add_field("frame.len", packet.header.len);
+        ctx.add_field("frame.cap_len", packet.data.len());
+        let dissection = ctx
+            .dissect_next(DissectorTable::LinkType(LINKTYPE_ETHERNET), packet.data)
+            .unwrap_or_else(|| Dissection::new("Unknown", "No dissector for the link type"));
 
         PacketInfo {
-            timestamp,
-            source_ip,
-            destination_ip,
-            protocol,
+            number,
+            captured_at,
+            layers: ctx.take_layers(),
+            timestamp: ctx.timestamp,
+            source_ip: ctx.source,
+            destination_ip: ctx.destination,
+            protocol: dissection.protocol,
             length: packet.data.len(),
-            details,
+            details: dissection.info,
         }
     }
 
@@ -314,13 +374,17 @@ impl PacketProcessor {
             "ICMP" => "blue",
             "ICMPv6" => "cyan",
             "ARP" => "magenta",
-            "DNS" => "bright_blue",
-            "DHCP" => "bright_green",
+            "DNS" | "MDNS" | "LLMNR" => "bright_blue",
+            "DHCP" | "DHCPv6" => "bright_green",
+            "NTP" | "SNMP" | "Syslog" => "green",
             "HTTP" => "bright_yellow",
-            "TLS" => "bright_magenta",
+            "HTTP2" => "bright_yellow",
+            "TLS" | "QUIC" => "bright_magenta",
             "IGMP" => "bright_cyan",
-            "SMB" => "bright_purple",
-            "FTP" => "bright_red",
+            "SMB" | "SMB2" | "NBSS" => "bright_purple",
+            "FTP" | "FTP-DATA" => "bright_red",
+            "SSH" => "bright_white",
+            "SMTP" | "POP" | "IMAP" => "red",
             _ => "white",
         };
 
@@ -334,9 +398,36 @@ impl PacketProcessor {
             info.details
         )
     }
+
+    /// Renders the protocol tree of a packet, one layer per block, with the
+    /// position of every field read from the frame
+    pub fn format_packet_tree(&self, info: &PacketInfo) -> String {
+        let mut tree = String::new();
+        for layer in &info.layers {
+            let span = match layer.offset {
+                Some(offset) => format!("{} bytes at {}", layer.length, offset),
+                None => format!("{} bytes, not in frame", layer.length),
+            };
+            tree.push_str(&format!("{} ({})\n", layer.protocol.bold(), span));
+            for field in &layer.fields {
+                let position = match field.offset {
+                    Some(offset) => format!(" [{}..{}]", offset, offset + field.length).dimmed().to_string(),
+                    None => String::new(),
+                };
+                tree.push_str(&format!("    {}: {}{}\n", field.name, field.value, position));
+            }
+        }
+        tree
+    }
+
+    /// Hex and ASCII dump of the frame coloured by layer, optionally limited
+    /// to the bytes of one layer; `None` if that layer is not in the frame
+    pub fn format_hex_dump(&self, info: &PacketInfo, data: &[u8], layer: Option<&str>) -> Option<String> {
+        hexdump::format_hex_dump(data, &info.layers, layer)
+    }
 }
 
-fn format_mac(mac: &[u8]) -> String {
+pub(crate) fn format_mac(mac: &[u8]) -> String {
     if mac.iter().all(|&b| b == 0) {
         return "Broadcast".to_string();
     }
@@ -0,0 +1,387 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::net::Ipv4Addr;
+use super::igmp::{GroupRecord, IgmpPacket, IgmpType};
+
+/// Query interval and robustness variable used until a query says otherwise (RFC 3376)
+const DEFAULT_QUERY_INTERVAL: u32 = 125;
+const DEFAULT_ROBUSTNESS: u8 = 2;
+/// Query response interval, in tenths of a second
+const DEFAULT_RESPONSE_INTERVAL: u32 = 100;
+/// Memberships that expired or were left this long ago are forgotten
+const PURGE_AFTER_SECS: i64 = 3600;
+const MAX_MEMBERSHIPS: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterMode {
+    /// Traffic from the listed sources only
+    Include,
+    /// Traffic from any source but the listed ones
+    Exclude,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MembershipState {
+    Active,
+    /// The host left the group
+    Left,
+    /// No report within the group membership interval
+    Expired,
+}
+
+impl fmt::Display for MembershipState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            MembershipState::Active => "active",
+            MembershipState::Left => "left",
+            MembershipState::Expired => "expired",
+        };
+        f.write_str(state)
+    }
+}
+
+/// Membership of one host interface in a multicast group
+#[derive(Debug, Clone, Serialize)]
+pub struct MulticastMembership {
+    /// Address of the reporting interface
+    pub host: Ipv4Addr,
+    pub group: Ipv4Addr,
+    /// IGMP version of the last report
+    pub version: u8,
+    pub mode: FilterMode,
+    pub sources: Vec<Ipv4Addr>,
+    pub joined_at: DateTime<Utc>,
+    pub last_report: DateTime<Utc>,
+    /// End of the group membership interval after the last report
+    pub expires_at: DateTime<Utc>,
+    pub state: MembershipState,
+}
+
+impl MulticastMembership {
+    /// When the host left the group or the membership runs out
+    fn ended_at(&self) -> DateTime<Utc> {
+        match self.state {
+            MembershipState::Left => self.last_report,
+            _ => self.expires_at,
+        }
+    }
+
+    pub fn format_info(&self) -> String {
+        let sources = match (self.mode, self.sources.is_empty()) {
+            (FilterMode::Exclude, true) => "any".to_string(),
+            (mode, _) => {
+                let sources: Vec<String> = self.sources.iter().map(|s| s.to_string()).collect();
+                let prefix = if mode == FilterMode::Exclude { "all but " } else { "" };
+                format!("{}{}", prefix, sources.join(","))
+            }
+        };
+        format!(
+            "{:<15} {:<15} v{:<2} {:<24} {:<8} {} {}",
+            self.host,
+            self.group,
+            self.version,
+            sources,
+            self.state.to_string(),
+            self.last_report.format("%Y-%m-%d %H:%M:%S"),
+            self.expires_at.format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
+
+/// Membership state of the hosts, rebuilt from their IGMP reports
+#[derive(Default)]
+pub(crate) struct MembershipTable {
+    memberships: HashMap<(Ipv4Addr, Ipv4Addr), MulticastMembership>,
+    /// Robustness, query interval (s) and response interval (1/10 s) of the last general query
+    timers: Option<(u8, u32, u32)>,
+    /// Time of the latest IGMP message, against which memberships expire
+    now: Option<DateTime<Utc>>,
+    purged_at: Option<DateTime<Utc>>,
+}
+
+impl MembershipTable {
+    /// Group membership interval: robustness × query interval + response interval
+    fn membership_interval(&self) -> Duration {
+        let (robustness, interval, response) =
+            self.timers
+                .unwrap_or((DEFAULT_ROBUSTNESS, DEFAULT_QUERY_INTERVAL, DEFAULT_RESPONSE_INTERVAL));
+        Duration::milliseconds(robustness as i64 * interval as i64 * 1000 + response as i64 * 100)
+    }
+
+    /// Applies a message sent by `host`
+    pub fn update(&mut self, packet: &IgmpPacket, host: Ipv4Addr, at: DateTime<Utc>) {
+        let now = self.now.map_or(at, |now| now.max(at));
+        self.now = Some(now);
+        self.purge(now);
+        match packet.get_type() {
+            IgmpType::MembershipQuery => {
+                if packet.group_address.is_unspecified() {
+                    let (robustness, interval) = packet
+                        .query
+                        .as_ref()
+                        .map(|query| (query.robustness, query.query_interval))
+                        .filter(|(robustness, interval)| *robustness > 0 && *interval > 0)
+                        .unwrap_or((DEFAULT_ROBUSTNESS, DEFAULT_QUERY_INTERVAL));
+                    self.timers = Some((robustness, interval, packet.max_response_time()));
+                }
+            }
+            IgmpType::MembershipReportV1 | IgmpType::MembershipReportV2 => {
+                let version = if matches!(packet.get_type(), IgmpType::MembershipReportV1) { 1 } else { 2 };
+                self.apply(host, packet.group_address, version, at, |membership| {
+                    membership.mode = FilterMode::Exclude;
+                    membership.sources.clear();
+                });
+            }
+            IgmpType::LeaveGroup => {
+                if let Some(membership) = self.memberships.get_mut(&(host, packet.group_address)) {
+                    membership.state = MembershipState::Left;
+                    membership.last_report = at;
+                }
+            }
+            IgmpType::MembershipReportV3 => {
+                for record in &packet.records {
+                    self.apply_record(host, record, at);
+                }
+            }
+            IgmpType::Unknown => {}
+        }
+    }
+
+    fn apply_record(&mut self, host: Ipv4Addr, record: &GroupRecord, at: DateTime<Utc>) {
+        let sources: BTreeSet<Ipv4Addr> = record.sources.iter().copied().collect();
+        self.apply(host, record.group, 3, at, |membership| {
+            let mut current: BTreeSet<Ipv4Addr> = membership.sources.iter().copied().collect();
+            match (record.record_type, membership.mode) {
+                // MODE_IS_INCLUDE, CHANGE_TO_INCLUDE_MODE
+                (1 | 3, _) => {
+                    membership.mode = FilterMode::Include;
+                    current = sources.clone();
+                }
+                // MODE_IS_EXCLUDE, CHANGE_TO_EXCLUDE_MODE
+                (2 | 4, _) => {
+                    membership.mode = FilterMode::Exclude;
+                    current = sources.clone();
+                }
+                // ALLOW_NEW_SOURCES
+                (5, FilterMode::Include) => current.extend(&sources),
+                (5, FilterMode::Exclude) => current.retain(|source| !sources.contains(source)),
+                // BLOCK_OLD_SOURCES
+                (6, FilterMode::Include) => current.retain(|source| !sources.contains(source)),
+                (6, FilterMode::Exclude) => current.extend(&sources),
+                _ => {}
+            }
+            membership.sources = current.into_iter().collect();
+            // Including no source at all is how a v3 host leaves
+            if membership.mode == FilterMode::Include && membership.sources.is_empty() {
+                membership.state = MembershipState::Left;
+            }
+        });
+    }
+
+    fn apply(
+        &mut self,
+        host: Ipv4Addr,
+        group: Ipv4Addr,
+        version: u8,
+        at: DateTime<Utc>,
+        change: impl FnOnce(&mut MulticastMembership),
+    ) {
+        let expires_at = at + self.membership_interval();
+        if self.memberships.len() >= MAX_MEMBERSHIPS && !self.memberships.contains_key(&(host, group)) {
+            self.evict();
+        }
+        let membership = self.memberships.entry((host, group)).or_insert_with(|| MulticastMembership {
+            host,
+            group,
+            version,
+            mode: FilterMode::Include,
+            sources: Vec::new(),
+            joined_at: at,
+            last_report: at,
+            expires_at,
+            state: MembershipState::Active,
+        });
+        if membership.state != MembershipState::Active {
+            membership.joined_at = at;
+        }
+        membership.version = version;
+        membership.last_report = at;
+        membership.expires_at = expires_at;
+        membership.state = MembershipState::Active;
+        change(membership);
+    }
+
+    /// Forgets the memberships that ended more than the grace period ago;
+    /// runs at most once per grace period
+    fn purge(&mut self, now: DateTime<Utc>) {
+        let grace = Duration::seconds(PURGE_AFTER_SECS);
+        if self.purged_at.is_some_and(|purged_at| now - purged_at < grace) {
+            return;
+        }
+        self.purged_at = Some(now);
+        self.memberships.retain(|_, membership| now - membership.ended_at() <= grace);
+    }
+
+    /// Drops the quarter of a full table that ended the earliest
+    fn evict(&mut self) {
+        let mut by_end: Vec<(DateTime<Utc>, (Ipv4Addr, Ipv4Addr))> =
+            self.memberships.iter().map(|(key, membership)| (membership.ended_at(), *key)).collect();
+        let count = (by_end.len() / 4).max(1);
+        by_end.select_nth_unstable_by_key(count - 1, |(ended_at, _)| *ended_at);
+        for (_, key) in by_end.into_iter().take(count) {
+            self.memberships.remove(&key);
+        }
+    }
+
+    /// Memberships ordered by group and host, expired ones marked as such
+    pub fn memberships(&self) -> Vec<MulticastMembership> {
+        let mut memberships: Vec<MulticastMembership> = self
+            .memberships
+            .values()
+            .cloned()
+            .map(|mut membership| {
+                if membership.state == MembershipState::Active && self.now.is_some_and(|now| now > membership.expires_at) {
+                    membership.state = MembershipState::Expired;
+                }
+                membership
+            })
+            .collect();
+        memberships.sort_by_key(|membership| (membership.group, membership.host));
+        memberships
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissector::testing::at;
+
+    const GROUP: Ipv4Addr = Ipv4Addr::new(232, 1, 1, 1);
+
+    fn host(index: u32) -> Ipv4Addr {
+        Ipv4Addr::from(0x0a00_0000 + index)
+    }
+
+    fn source(index: u8) -> Ipv4Addr {
+        Ipv4Addr::new(192, 0, 2, index)
+    }
+
+    /// IGMPv3 report with one record for [`GROUP`]
+    fn record(record_type: u8, sources: &[u8]) -> IgmpPacket {
+        let mut data = vec![0x22, 0, 0, 0, 0, 0, 0, 1, record_type, 0, 0, sources.len() as u8];
+        data.extend(GROUP.octets());
+        for &index in sources {
+            data.extend(source(index).octets());
+        }
+        IgmpPacket::from_bytes(&data).unwrap()
+    }
+
+    /// v1 or v2 report (0x12, 0x16) or leave (0x17) for `group`
+    fn message(type_: u8, group: Ipv4Addr) -> IgmpPacket {
+        let mut data = vec![type_, 0, 0, 0];
+        data.extend(group.octets());
+        IgmpPacket::from_bytes(&data).unwrap()
+    }
+
+    fn membership(table: &MembershipTable, host: Ipv4Addr) -> (FilterMode, Vec<Ipv4Addr>, MembershipState) {
+        let membership = table.memberships.get(&(host, GROUP)).unwrap();
+        (membership.mode, membership.sources.clone(), membership.state)
+    }
+
+    #[test]
+    fn applies_group_records() {
+        use FilterMode::*;
+        use MembershipState::*;
+        let mut table = MembershipTable::default();
+        let steps: [(u8, &[u8], FilterMode, &[u8]); 9] = [
+            (2, &[1], Exclude, &[1]),
+            (5, &[1], Exclude, &[]),
+            (6, &[2], Exclude, &[2]),
+            (3, &[1, 2], Include, &[1, 2]),
+            (6, &[1], Include, &[2]),
+            (5, &[3], Include, &[2, 3]),
+            (4, &[], Exclude, &[]),
+            (1, &[4], Include, &[4]),
+            (1, &[], Include, &[]),
+        ];
+        for (step, (record_type, sources, mode, expected)) in steps.into_iter().enumerate() {
+            table.update(&record(record_type, sources), host(1), at(step as i64));
+            let expected: Vec<Ipv4Addr> = expected.iter().map(|&index| source(index)).collect();
+            let state = if step == 8 { Left } else { Active };
+            assert_eq!(membership(&table, host(1)), (mode, expected, state), "record type {}", record_type);
+        }
+
+        // Joining again starts a new membership
+        table.update(&record(5, &[1]), host(1), at(20));
+        let membership = &table.memberships()[0];
+        assert_eq!((membership.state, membership.joined_at, membership.version), (Active, at(20), 3));
+    }
+
+    #[test]
+    fn expires_on_the_querier_timers() {
+        let mut table = MembershipTable::default();
+        table.update(&message(0x16, GROUP), host(1), at(0));
+        assert_eq!(table.memberships()[0].expires_at, at(260));
+
+        // Robustness 3, query interval 60 s, response interval 10 s
+        let query = IgmpPacket::from_bytes(&[0x11, 100, 0, 0, 0, 0, 0, 0, 0x03, 60, 0, 0]).unwrap();
+        table.update(&query, host(9), at(10));
+        table.update(&message(0x16, GROUP), host(1), at(10));
+        assert_eq!(table.memberships()[0].expires_at, at(200));
+        table.update(&message(0x16, GROUP), host(2), at(200));
+        assert_eq!(membership(&table, host(1)).2, MembershipState::Active);
+        table.update(&message(0x16, GROUP), host(2), at(201));
+        let states: Vec<MembershipState> = table.memberships().iter().map(|membership| membership.state).collect();
+        assert_eq!(states, [MembershipState::Expired, MembershipState::Active]);
+    }
+
+    #[test]
+    fn follows_v1_and_v2_hosts() {
+        let mut table = MembershipTable::default();
+        table.update(&record(1, &[1]), host(1), at(0));
+        table.update(&message(0x16, GROUP), host(1), at(1));
+        assert_eq!(membership(&table, host(1)), (FilterMode::Exclude, Vec::new(), MembershipState::Active));
+        assert_eq!(table.memberships()[0].version, 2);
+
+        table.update(&message(0x17, GROUP), host(1), at(2));
+        assert_eq!(membership(&table, host(1)).2, MembershipState::Left);
+        // Leaving a group never joined is ignored
+        table.update(&message(0x17, Ipv4Addr::new(239, 9, 9, 9)), host(1), at(2));
+        assert_eq!(table.memberships().len(), 1);
+
+        table.update(&message(0x12, GROUP), host(1), at(3));
+        let membership = &table.memberships()[0];
+        assert_eq!((membership.version, membership.state, membership.joined_at), (1, MembershipState::Active, at(3)));
+    }
+
+    #[test]
+    fn purges_ended_memberships() {
+        let mut table = MembershipTable::default();
+        table.update(&message(0x16, GROUP), host(1), at(0));
+        table.update(&message(0x16, GROUP), host(2), at(100));
+        table.update(&message(0x17, GROUP), host(2), at(200));
+        table.update(&message(0x16, GROUP), host(3), at(3000));
+        assert_eq!(table.memberships().len(), 3);
+
+        table.update(&message(0x16, GROUP), host(4), at(3861));
+        let hosts: Vec<Ipv4Addr> = table.memberships().iter().map(|membership| membership.host).collect();
+        assert_eq!(hosts, [host(3), host(4)]);
+    }
+
+    #[test]
+    fn bounds_the_table() {
+        let mut table = MembershipTable::default();
+        for index in 0..MAX_MEMBERSHIPS as u32 {
+            table.update(&message(0x16, GROUP), host(index), at(index as i64 / 1000));
+        }
+        assert_eq!(table.memberships.len(), MAX_MEMBERSHIPS);
+        table.update(&message(0x16, GROUP), host(MAX_MEMBERSHIPS as u32), at(100));
+        assert_eq!(table.memberships.len(), MAX_MEMBERSHIPS - MAX_MEMBERSHIPS / 4 + 1);
+        assert!(!table.memberships.contains_key(&(host(0), GROUP)));
+        assert!(table.memberships.contains_key(&(host(MAX_MEMBERSHIPS as u32 - 1), GROUP)));
+    }
+}
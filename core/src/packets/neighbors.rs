@@ -0,0 +1,102 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::net::Ipv6Addr;
+use super::flow::FlowTable;
+
+/// Neighbors not heard from for this long are forgotten
+const NEIGHBOR_TIMEOUT_SECS: i64 = 3600;
+const MAX_NEIGHBORS: usize = 65536;
+
+/// IPv6 address to link-layer address binding learned from Neighbor Discovery
+#[derive(Debug, Clone, Serialize)]
+pub struct Ipv6Neighbor {
+    pub ip: Ipv6Addr,
+    pub mac: String,
+    /// Sent a Router Advertisement or a Neighbor Advertisement with the router flag
+    pub is_router: bool,
+    /// Message the binding was last learned from, e.g. `NA`
+    pub learned_from: &'static str,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+impl Ipv6Neighbor {
+    pub fn format_info(&self) -> String {
+        format!(
+            "{:<39} {:<17} {:<6} {:<4} {} {}",
+            self.ip,
+            self.mac,
+            if self.is_router { "yes" } else { "no" },
+            self.learned_from,
+            self.first_seen.format("%Y-%m-%d %H:%M:%S"),
+            self.last_seen.format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
+
+/// IPv6 neighbor table built from the Neighbor Discovery messages
+pub(crate) struct NeighborTable {
+    neighbors: FlowTable<Ipv6Addr, Ipv6Neighbor>,
+}
+
+impl Default for NeighborTable {
+    fn default() -> Self {
+        Self {
+            neighbors: FlowTable::with_limits(Duration::seconds(NEIGHBOR_TIMEOUT_SECS), MAX_NEIGHBORS),
+        }
+    }
+}
+
+impl NeighborTable {
+    /// Binds `ip` to `mac`; `is_router` is left unchanged when `None`
+    pub fn learn(&mut self, ip: Ipv6Addr, mac: String, is_router: Option<bool>, learned_from: &'static str, at: DateTime<Utc>) {
+        if ip.is_unspecified() || ip.is_multicast() {
+            return;
+        }
+        let neighbor = self.neighbors.entry(ip, at, || Ipv6Neighbor {
+            ip,
+            mac: mac.clone(),
+            is_router: false,
+            learned_from,
+            first_seen: at,
+            last_seen: at,
+        });
+        neighbor.mac = mac;
+        neighbor.learned_from = learned_from;
+        neighbor.last_seen = at;
+        if let Some(is_router) = is_router {
+            neighbor.is_router = is_router;
+        }
+    }
+
+    /// Neighbors ordered by address
+    pub fn neighbors(&self) -> Vec<Ipv6Neighbor> {
+        let mut neighbors: Vec<Ipv6Neighbor> = self.neighbors.values().cloned().collect();
+        neighbors.sort_by_key(|neighbor| neighbor.ip);
+        neighbors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissector::testing;
+
+    #[test]
+    fn forgets_silent_neighbors() {
+        let at = testing::at(0);
+        let mut table = NeighborTable::default();
+        table.learn("fe80::1".parse().unwrap(), "00:11:22:33:44:55".to_string(), Some(true), "RA", at);
+        table.learn("ff02::1".parse().unwrap(), "33:33:00:00:00:01".to_string(), None, "NS", at);
+        table.learn("fe80::2".parse().unwrap(), "00:11:22:33:44:66".to_string(), None, "NS", at);
+        table.learn("fe80::1".parse().unwrap(), "00:11:22:33:44:77".to_string(), None, "NA", at + Duration::minutes(30));
+        let neighbors = table.neighbors();
+        assert_eq!(neighbors.len(), 2);
+        assert_eq!((neighbors[0].mac.as_str(), neighbors[0].is_router), ("00:11:22:33:44:77", true));
+        assert_eq!(neighbors[0].first_seen, at);
+
+        table.learn("fe80::3".parse().unwrap(), "00:11:22:33:44:88".to_string(), None, "NS", at + Duration::minutes(75));
+        let addresses: Vec<String> = table.neighbors().iter().map(|neighbor| neighbor.ip.to_string()).collect();
+        assert_eq!(addresses, ["fe80::1", "fe80::3"]);
+    }
+}
@@ -0,0 +1,364 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Mutex;
+use super::bytes::{to_hex, ByteReader};
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::flow::FlowKey;
+use super::tree::FieldValue;
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch (1970)
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+const HEADER_LEN: usize = 48;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const MODE_CONTROL: u8 = 6;
+const MODE_PRIVATE: u8 = 7;
+/// Client requests kept while waiting for the reply of the server
+const MAX_PENDING: usize = 4096;
+const REPLY_TIMEOUT_SECS: i64 = 60;
+
+/// A 64-bit NTP timestamp: seconds since 1900 and a binary fraction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Timestamp(u64);
+
+impl Timestamp {
+    fn is_null(self) -> bool {
+        self.0 == 0
+    }
+
+    fn to_datetime(self) -> Option<DateTime<Utc>> {
+        let mut seconds = (self.0 >> 32) as i64;
+        // Era 1 starts on 7 February 2036; timestamps with the top bit clear belong to it
+        if seconds & 0x8000_0000 == 0 {
+            seconds += 1 << 32;
+        }
+        let nanos = ((self.0 & 0xffff_ffff) * 1_000_000_000) >> 32;
+        Utc.timestamp_opt(seconds - NTP_UNIX_OFFSET, nanos as u32).single()
+    }
+
+    fn format(self) -> String {
+        match self.to_datetime().filter(|_| !self.is_null()) {
+            Some(time) => time.format("%b %e, %Y %H:%M:%S%.9f UTC").to_string(),
+            None => "NULL".to_string(),
+        }
+    }
+}
+
+/// 16.16 fixed point seconds of the root delay and dispersion
+fn short_format(value: u32) -> f64 {
+    value as f64 / 65536.0
+}
+
+/// Seconds between two times, negative if `to` is earlier
+fn seconds(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_nanoseconds().unwrap_or_default() as f64 / 1e9
+}
+
+/// Client request waiting for the reply of its server
+struct Request {
+    frame: u64,
+    sent_at: DateTime<Utc>,
+}
+
+/// NTP (RFC 5905) packets, with the clock offset and round-trip delay of
+/// every client/server exchange
+pub struct NtpProcessor {
+    /// Requests by client flow and transmit timestamp, which the server echoes
+    /// as origin timestamp
+    requests: Mutex<HashMap<(FlowKey, Timestamp), Request>>,
+}
+
+impl NtpProcessor {
+    pub fn new() -> Self {
+        Self {
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Pairs a server reply with its request. The offset and delay are
+    /// measured against the capture clock: the request and reply capture
+    /// times stand for the client's send and receive times.
+    fn exchange(&self, flow: &FlowKey, origin: Timestamp, receive: Timestamp, transmit: Timestamp, ctx: &mut DissectContext) -> Option<(f64, f64)> {
+        let request = self.requests.lock().unwrap().remove(&(flow.reversed(), origin))?;
+        ctx.add_field("ntp.request_in", request.frame);
+        let (t2, t3) = (receive.to_datetime()?, transmit.to_datetime()?);
+        let (t1, t4) = (request.sent_at, ctx.captured_at);
+        let offset = (seconds(t1, t2) + seconds(t4, t3)) / 2.0;
+        let delay = seconds(t1, t4) - seconds(t2, t3);
+        ctx.add_field("ntp.offset", offset);
+        ctx.add_field("ntp.delay", delay);
+        Some((offset, delay))
+    }
+
+    fn dissect_header(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Vec<String>> {
+        let mut reader = ByteReader::new(data);
+        let flags = reader.read_u8()?;
+        let mode = flags & 7;
+        let stratum = reader.read_u8()?;
+        let poll = reader.read_u8()? as i8;
+        let precision = reader.read_u8()? as i8;
+        let root_delay = reader.read_u32()?;
+        let root_dispersion = reader.read_u32()?;
+        let reference_id = reader.read_bytes(4)?;
+        let mut timestamps = [Timestamp(0); 4];
+        for timestamp in &mut timestamps {
+            let high = reader.read_u32()? as u64;
+            *timestamp = Timestamp(high << 32 | reader.read_u32()? as u64);
+        }
+        let [reference, origin, receive, transmit] = timestamps;
+
+        ctx.add_field_at("ntp.flags.li", 0, 1, leap_indicator_name(flags >> 6));
+        ctx.add_field_at("ntp.stratum", 1, 1, stratum);
+        ctx.add_field_at("ntp.ppoll", 2, 1, poll);
+        ctx.add_field_at("ntp.precision", 3, 1, precision);
+        ctx.add_field_at("ntp.rootdelay", 4, 4, short_format(root_delay));
+        ctx.add_field_at("ntp.rootdispersion", 8, 4, short_format(root_dispersion));
+        let reference_id = format_reference_id(reference_id, stratum, ctx.src_ip);
+        ctx.add_field_at("ntp.refid", 12, 4, reference_id.clone());
+        for (name, offset, timestamp) in [
+            ("ntp.reftime", 16, reference),
+            ("ntp.org", 24, origin),
+            ("ntp.rec", 32, receive),
+            ("ntp.xmt", 40, transmit),
+        ] {
+            ctx.add_field_at(name, offset, 8, timestamp.format());
+        }
+        // Extension fields and the MAC (key id and digest) follow the header
+        if data.len() > HEADER_LEN {
+            let trailer = &data[HEADER_LEN..];
+            if matches!(trailer.len(), 20 | 24) {
+                ctx.add_field_at("ntp.keyid", HEADER_LEN, 4, FieldValue::Hex(u32::from_be_bytes(trailer[..4].try_into()?) as u64));
+                ctx.add_field_at("ntp.mac", HEADER_LEN + 4, trailer.len() - 4, FieldValue::Bytes(trailer[4..].to_vec()));
+            }
+        }
+
+        let mut info = Vec::new();
+        match mode {
+            MODE_CLIENT if !transmit.is_null() => {
+                let mut requests = self.requests.lock().unwrap();
+                if requests.len() >= MAX_PENDING {
+                    let timeout = Duration::seconds(REPLY_TIMEOUT_SECS);
+                    requests.retain(|_, request| ctx.captured_at - request.sent_at <= timeout);
+                }
+                if let Some(flow) = ctx.flow {
+                    requests.insert((flow, transmit), Request { frame: ctx.number, sent_at: ctx.captured_at });
+                }
+            }
+            MODE_SERVER => {
+                info.push(format!("stratum {}", stratum));
+                // Stratum 0 replies carry a kiss code instead of time
+                if stratum == 0 {
+                    info.push(format!("Kiss-o'-Death {}", reference_id));
+                } else {
+                    info.push(format!("ref {}", reference_id));
+                    if let Some((offset, delay)) = ctx.flow.and_then(|flow| self.exchange(&flow, origin, receive, transmit, ctx)) {
+                        info.push(format!("offset {:+.6} s, delay {:.6} s", offset, delay));
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(info)
+    }
+}
+
+impl Default for NtpProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dissector for NtpProcessor {
+    fn name(&self) -> &'static str {
+        "NTP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let flags = *data.first().ok_or_else(|| anyhow::anyhow!("No NTP payload"))?;
+        let version = (flags >> 3) & 7;
+        let mode = flags & 7;
+        ctx.add_field_at("ntp.flags.vn", 0, 1, version);
+        ctx.add_field_at("ntp.flags.mode", 0, 1, mode_name(mode));
+
+        let mut info = vec![format!("NTP Version {}, {}", version, mode_name(mode))];
+        match mode {
+            MODE_CONTROL => {
+                let mut reader = ByteReader::new(data);
+                reader.read_u8()?;
+                let opcode = reader.read_u8()?;
+                let sequence = reader.read_u16()?;
+                let status = reader.read_u16()?;
+                let association = reader.read_u16()?;
+                ctx.add_field_at("ntp.ctrl.flags2.r", 1, 1, opcode & 0x80 != 0);
+                ctx.add_field_at("ntp.ctrl.flags2.opcode", 1, 1, control_opcode_name(opcode & 0x1f));
+                ctx.add_field_at("ntp.ctrl.sequence", 2, 2, sequence);
+                ctx.add_field_at("ntp.ctrl.status", 4, 2, FieldValue::Hex(status as u64));
+                ctx.add_field_at("ntp.ctrl.associd", 6, 2, association);
+                info.push(control_opcode_name(opcode & 0x1f));
+                if opcode & 0x80 != 0 {
+                    info.push("response".to_string());
+                }
+            }
+            MODE_PRIVATE => {
+                let mut reader = ByteReader::new(data);
+                reader.read_u8()?;
+                reader.read_u8()?;
+                let implementation = reader.read_u8()?;
+                let request = reader.read_u8()?;
+                ctx.add_field_at("ntppriv.impl", 2, 1, implementation);
+                ctx.add_field_at("ntppriv.reqcode", 3, 1, request);
+                // MON_GETLIST is the monlist query abused for traffic amplification
+                info.push(match request {
+                    20 | 42 => "MON_GETLIST (monlist)".to_string(),
+                    request => format!("request code {}", request),
+                });
+            }
+            _ => {
+                if data.len() < HEADER_LEN {
+                    anyhow::bail!("NTP packet of {} bytes is shorter than the header", data.len());
+                }
+                info.extend(self.dissect_header(data, ctx)?);
+            }
+        }
+        Ok(Dissection::new("NTP", info.join(", ")))
+    }
+
+    /// Heuristic probe: a header of a known version and mode, with no or a MAC trailer
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        let first = *payload.first()?;
+        let version = (first >> 3) & 7;
+        let mode = first & 7;
+        if (1..=4).contains(&version) && (1..=5).contains(&mode) && matches!(payload.len(), 48 | 68 | 72) {
+            return Some(ProbeResult::new(40, "NTP header"));
+        }
+        None
+    }
+}
+
+fn leap_indicator_name(leap: u8) -> &'static str {
+    match leap {
+        0 => "no warning",
+        1 => "last minute of the day has 61 seconds",
+        2 => "last minute of the day has 59 seconds",
+        _ => "unknown (clock unsynchronized)",
+    }
+}
+
+fn mode_name(mode: u8) -> String {
+    let name = match mode {
+        1 => "symmetric active",
+        2 => "symmetric passive",
+        MODE_CLIENT => "client",
+        MODE_SERVER => "server",
+        5 => "broadcast",
+        MODE_CONTROL => "control",
+        MODE_PRIVATE => "private",
+        _ => return format!("reserved ({})", mode),
+    };
+    name.to_string()
+}
+
+fn control_opcode_name(opcode: u8) -> String {
+    let name = match opcode {
+        1 => "read status",
+        2 => "read variables",
+        3 => "write variables",
+        4 => "read clock variables",
+        5 => "write clock variables",
+        6 => "set trap",
+        7 => "trap response",
+        8 => "configure",
+        9 => "save configuration",
+        10 => "read MRU list",
+        _ => return format!("opcode {}", opcode),
+    };
+    name.to_string()
+}
+
+/// Reference ID: a kiss code or clock source below stratum 2, otherwise the
+/// upstream server's IPv4 address or, over IPv6, a hash of its address
+fn format_reference_id(id: &[u8], stratum: u8, source: Option<IpAddr>) -> String {
+    if stratum <= 1 {
+        return String::from_utf8_lossy(id).trim_end_matches('\0').to_string();
+    }
+    match source {
+        Some(IpAddr::V6(_)) => to_hex(id),
+        _ => Ipv4Addr::new(id[0], id[1], id[2], id[3]).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissector::testing::{self, field};
+    use super::super::tree::Field;
+
+    /// NTP timestamp of a Unix time in milliseconds
+    fn timestamp(millis: i64) -> u64 {
+        let seconds = (millis / 1000 + NTP_UNIX_OFFSET) as u64;
+        (seconds << 32) | ((((millis % 1000) as u64) << 32) / 1000)
+    }
+
+    /// Version 4 header of `mode` with the origin, receive and transmit timestamps
+    fn packet(mode: u8, stratum: u8, [origin, receive, transmit]: [u64; 3]) -> Vec<u8> {
+        let mut data = vec![0x20 | mode, stratum, 6, 0xe9];
+        data.extend_from_slice(&[0, 0, 0x80, 0, 0, 0, 0x40, 0]);
+        data.extend_from_slice(&[192, 0, 2, 1]);
+        for timestamp in [0, origin, receive, transmit] {
+            data.extend_from_slice(&timestamp.to_be_bytes());
+        }
+        data
+    }
+
+    fn dissect(ntp: &NtpProcessor, flow: FlowKey, data: &[u8], millis: i64) -> (Dissection, Vec<Field>) {
+        let (dissection, fields) = testing::dissect(ntp, data, |ctx| {
+            ctx.captured_at = Utc.timestamp_millis_opt(millis).unwrap();
+            ctx.flow = Some(flow);
+        });
+        (dissection.unwrap(), fields)
+    }
+
+    #[test]
+    fn converts_timestamps() {
+        assert_eq!(Timestamp(timestamp(0)).to_datetime(), Utc.timestamp_opt(0, 0).single());
+        assert_eq!(Timestamp(timestamp(1_500)).to_datetime(), Utc.timestamp_millis_opt(1_500).single());
+        // Era 1 starts in 2036
+        assert_eq!(Timestamp(1 << 32).format(), "Feb  7, 2036 06:28:17.000000000 UTC");
+        assert_eq!(Timestamp(0).format(), "NULL");
+    }
+
+    #[test]
+    fn measures_offset_and_delay() {
+        let ntp = NtpProcessor::default();
+        let sent = 1_700_000_000_000;
+        let transmit = timestamp(sent - 5_000);
+        let (_, fields) = dissect(&ntp, testing::flow(123), &packet(MODE_CLIENT, 0, [0, 0, transmit]), sent);
+        assert_eq!(field(&fields, "ntp.ppoll"), Some(&FieldValue::Int(6)));
+        assert_eq!(field(&fields, "ntp.precision"), Some(&FieldValue::Int(-23)));
+
+        // The server clock is one second ahead and takes 100 ms to answer
+        let reply = packet(MODE_SERVER, 2, [transmit, timestamp(sent + 1_050), timestamp(sent + 1_150)]);
+        let (dissection, fields) = dissect(&ntp, testing::flow(123).reversed(), &reply, sent + 200);
+        assert_eq!(dissection.info, "NTP Version 4, server, stratum 2, ref 192.0.2.1, offset +1.000000 s, delay 0.100000 s");
+        assert_eq!(field(&fields, "ntp.request_in"), Some(&FieldValue::Uint(1)));
+        assert_eq!(field(&fields, "ntp.rootdelay"), Some(&FieldValue::Float(0.5)));
+
+        // The request was answered
+        let (_, fields) = dissect(&ntp, testing::flow(123).reversed(), &reply, sent + 300);
+        assert_eq!(field(&fields, "ntp.request_in"), None);
+    }
+
+    #[test]
+    fn rejects_short_packets() {
+        let ntp = NtpProcessor::new();
+        let data = packet(MODE_CLIENT, 0, [0, 0, 1]);
+        for len in [0, 1, 47] {
+            assert!(testing::dissect(&ntp, &data[..len], |_| {}).0.is_err(), "{} bytes", len);
+        }
+        assert!(ntp.probe(&data).is_some());
+        assert!(ntp.probe(&data[..47]).is_none());
+    }
+}
@@ -0,0 +1,272 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::flow::{FlowKey, FlowTable};
+use super::mail::{self, Connection, MailMessage, MailStore, SaslExchange};
+
+/// Multi-line response being read, up to the line with a single dot
+struct Listing {
+    command: String,
+    content: Vec<u8>,
+}
+
+struct Session {
+    conn: Connection,
+    /// Commands waiting for their response, oldest first
+    pending: VecDeque<(String, String)>,
+    listing: Option<Listing>,
+    auth: Option<SaslExchange>,
+}
+
+impl Session {
+    fn read_client(&mut self, ctx: &mut DissectContext, info: &mut Vec<String>) {
+        while let Some(line) = self.conn.client_stream.line() {
+            if let Some(auth) = &mut self.auth {
+                match auth.response(&line) {
+                    Some(username) => {
+                        ctx.add_field("pop.request.username", username.clone());
+                        info.push(format!("Request: User: {}", username));
+                    }
+                    None if line == "*" => info.push("Request: * (cancel)".to_string()),
+                    None => info.push(format!("Request: {} response", auth.mechanism)),
+                }
+                continue;
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (command, parameter) = match line.split_once(' ') {
+                Some((command, parameter)) => (command.to_ascii_uppercase(), parameter.trim().to_string()),
+                None => (line.to_ascii_uppercase(), String::new()),
+            };
+            ctx.add_field("pop.request.command", command.clone());
+            let shown = match command.as_str() {
+                // Passwords are never shown
+                "PASS" => String::new(),
+                "APOP" => parameter.split_whitespace().next().unwrap_or_default().to_string(),
+                "AUTH" if !parameter.is_empty() => {
+                    let mut parts = parameter.split_whitespace();
+                    let mut auth = SaslExchange::new(parts.next().unwrap_or_default());
+                    ctx.add_field("pop.auth.mechanism", auth.mechanism.clone());
+                    if let Some(username) = parts.next().and_then(|initial| auth.response(initial)) {
+                        ctx.add_field("pop.request.username", username);
+                    }
+                    let mechanism = auth.mechanism.clone();
+                    self.auth = Some(auth);
+                    mechanism
+                }
+                _ => parameter.clone(),
+            };
+            if command == "USER" {
+                ctx.add_field("pop.request.username", parameter.clone());
+            }
+            if !shown.is_empty() {
+                ctx.add_field("pop.request.parameter", shown.clone());
+            }
+            info.push(format!("Request: {} {}", command, shown).trim_end().to_string());
+            self.pending.push_back((command, parameter));
+        }
+    }
+
+    fn read_server(&mut self, ctx: &mut DissectContext, info: &mut Vec<String>) -> Vec<MailMessage> {
+        let mut messages = Vec::new();
+        while let Some(raw) = self.conn.server_stream.raw_line() {
+            if let Some(listing) = &mut self.listing {
+                if !mail::append_dot_stuffed(&mut listing.content, &raw) {
+                    continue;
+                }
+                ctx.add_field("pop.data.size", listing.content.len());
+                if listing.command == "RETR" {
+                    let data = std::mem::take(&mut listing.content);
+                    messages.push(MailMessage::new("POP3", self.conn.client, self.conn.server, data, ctx.captured_at));
+                }
+                self.listing = None;
+                continue;
+            }
+
+            let line = mail::trim_line(&raw);
+            let (indicator, description) = match line.split_once(' ') {
+                Some((indicator, description)) => (indicator.to_string(), description.to_string()),
+                None => (line.clone(), String::new()),
+            };
+            // SASL challenge, the command goes on
+            if indicator == "+" {
+                info.push(format!("Response: {}", line));
+                continue;
+            }
+            ctx.add_field("pop.response.indicator", indicator.clone());
+            if !description.is_empty() {
+                ctx.add_field("pop.response.description", description);
+            }
+            info.push(format!("Response: {}", line));
+
+            let ok = indicator.eq_ignore_ascii_case("+OK");
+            let Some((command, parameter)) = self.pending.pop_front() else {
+                continue;
+            };
+            let multi_line = match command.as_str() {
+                "RETR" | "TOP" | "CAPA" => true,
+                "LIST" | "UIDL" | "AUTH" => parameter.is_empty(),
+                _ => false,
+            };
+            if ok && multi_line {
+                self.listing = Some(Listing { command, content: Vec::new() });
+            } else if command == "AUTH" {
+                self.auth = None;
+            } else if ok && command == "STLS" {
+                ctx.add_field("pop.starttls", true);
+                self.conn.start_tls();
+                break;
+            }
+        }
+        messages
+    }
+}
+
+/// POP3 commands and responses over reassembled streams, with the messages
+/// retrieved by RETR
+pub struct Pop3Processor {
+    sessions: Mutex<FlowTable<FlowKey, Session>>,
+    messages: Arc<Mutex<MailStore>>,
+}
+
+impl Pop3Processor {
+    pub(crate) fn new(messages: Arc<Mutex<MailStore>>) -> Self {
+        Self {
+            sessions: Mutex::new(FlowTable::new()),
+            messages,
+        }
+    }
+}
+
+impl Dissector for Pop3Processor {
+    fn name(&self) -> &'static str {
+        "POP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let flow = ctx.flow.ok_or_else(|| anyhow::anyhow!("POP outside of a TCP flow"))?;
+        let key = flow.canonical();
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.entry(key, ctx.captured_at, || Session {
+            conn: Connection::new(&ctx.client_flow.unwrap_or(flow)),
+            pending: VecDeque::new(),
+            listing: None,
+            auth: None,
+        });
+        let from_client = session.conn.is_client(&flow);
+        let tls = session.conn.tls;
+        let closed = ctx.flow_closed;
+        if tls {
+            if closed {
+                sessions.remove(&key);
+            }
+            drop(sessions);
+            if data.is_empty() {
+                anyhow::bail!("No POP payload");
+            }
+            return mail::dissect_tls(data, ctx);
+        }
+
+        let mut info = Vec::new();
+        let mut messages = Vec::new();
+        let sequence = ctx.tcp_sequence.unwrap_or_default();
+        if from_client {
+            session.conn.client_stream.push(sequence, data);
+            session.read_client(ctx, &mut info);
+        } else {
+            if !session.conn.server_stream.push(sequence, data) {
+                session.listing = None;
+            }
+            messages = session.read_server(ctx, &mut info);
+        }
+        if closed {
+            sessions.remove(&key);
+        }
+        drop(sessions);
+        if data.is_empty() {
+            anyhow::bail!("No POP payload");
+        }
+
+        for message in &messages {
+            message.add_fields(ctx);
+            info.push(format!("Response: {}", message.summary()));
+        }
+        self.messages.lock().unwrap().extend(messages);
+        let info = match info.len() {
+            0 => "Continuation".to_string(),
+            _ => info.join(" | "),
+        };
+        Ok(Dissection::new("POP", info))
+    }
+
+    /// Heuristic probe: the greeting of a POP3 server
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        let line_end = payload.iter().position(|&b| b == b'\n').unwrap_or(payload.len());
+        let line = String::from_utf8_lossy(&payload[..line_end]).to_ascii_uppercase();
+        if !line.starts_with("+OK") {
+            return None;
+        }
+        match line.contains("POP") {
+            true => Some(ProbeResult::new(85, "POP3 server greeting")),
+            false => Some(ProbeResult::new(45, "+OK greeting")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::dissector::{testing, DissectorRegistry};
+    use super::super::tree::FieldValue;
+    use super::super::mail::{testing::Conversation, MailProcessor};
+
+    #[test]
+    fn extracts_retrieved_messages() {
+        let mail = MailProcessor::new();
+        let registry = DissectorRegistry::new();
+        let pop3 = mail.pop3_dissector();
+        let mut conversation = Conversation::new(&registry, &pop3, 110);
+        conversation.server(b"+OK POP3 server ready\r\n");
+        conversation.client(b"USER alice\r\n");
+        conversation.server(b"+OK\r\n");
+        let (dissection, _) = conversation.client(b"PASS secret\r\n");
+        assert_eq!(dissection.info, "Request: PASS");
+        conversation.server(b"+OK Logged in\r\n");
+        conversation.client(b"LIST\r\n");
+        conversation.server(b"+OK 1 messages\r\n1 35\r\n.\r\n");
+        assert!(mail.messages().is_empty());
+
+        conversation.client(b"RETR 1\r\n");
+        conversation.server(b"+OK 35 octets\r\nSubject: hi\r\n\r\n");
+        let (dissection, _) = conversation.server(b"..leading dot\r\n.\r\n");
+        assert_eq!(dissection.info, "Response: Message, 29 bytes, \"hi\"");
+
+        let messages = mail.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].protocol, "POP3");
+        assert_eq!(messages[0].data, b"Subject: hi\r\n\r\n.leading dot\r\n");
+        assert_eq!(messages[0].client.to_string(), "10.0.0.1");
+
+        // The session goes on after the message
+        let (dissection, _) = conversation.client(b"QUIT\r\n");
+        assert_eq!(dissection.info, "Request: QUIT");
+    }
+
+    #[test]
+    fn extracts_auth_users() {
+        let mail = MailProcessor::new();
+        let registry = DissectorRegistry::new();
+        let pop3 = mail.pop3_dissector();
+        let mut conversation = Conversation::new(&registry, &pop3, 110);
+        let (dissection, fields) = conversation.client(b"AUTH PLAIN AGFsaWNlAHNlY3JldA==\r\n");
+        assert_eq!(dissection.info, "Request: AUTH PLAIN");
+        assert_eq!(testing::field(&fields, "pop.auth.mechanism"), Some(&FieldValue::Text("PLAIN".to_string())));
+        assert_eq!(testing::field(&fields, "pop.request.username"), Some(&FieldValue::Text("alice".to_string())));
+        conversation.server(b"+OK\r\n");
+        let (dissection, _) = conversation.client(b"STAT\r\n");
+        assert_eq!(dissection.info, "Request: STAT");
+    }
+}
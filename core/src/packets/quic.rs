@@ -0,0 +1,802 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::aes::cipher::{generic_array::GenericArray, BlockEncrypt};
+use aes_gcm::aes::Aes128;
+use aes_gcm::{Aes128Gcm, Nonce};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use super::bytes::{ByteReader, to_hex};
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::flow::FlowTable;
+use super::tls::{ClientHello, ServerHello, cipher_suite_name};
+use super::tls_decrypt::{hkdf_expand_label, HashAlgorithm};
+use super::tree::FieldValue;
+
+pub const QUIC_V1: u32 = 0x0000_0001;
+pub const QUIC_V2: u32 = 0x6b33_43cf;
+
+/// Salts of the Initial secrets (RFC 9001, section 5.2 and RFC 9369, section 3.3.1)
+const INITIAL_SALT_V1: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad, 0xcc, 0xbb, 0x7f,
+    0x0a,
+];
+const INITIAL_SALT_V2: [u8; 20] = [
+    0x0d, 0xed, 0xe3, 0xde, 0xf7, 0x00, 0xa6, 0xdb, 0x81, 0x93, 0x81, 0xbe, 0x6e, 0x26, 0x9d, 0xcb, 0xf9, 0xbd, 0x2e,
+    0xd9,
+];
+/// Largest CRYPTO stream kept per direction of a connection
+const MAX_CRYPTO_LEN: u64 = 64 * 1024;
+/// Connection IDs looked up per connection; later ones are ignored
+const MAX_CIDS_PER_CONNECTION: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LongPacketType {
+    Initial,
+    ZeroRtt,
+    Handshake,
+    Retry,
+}
+
+impl LongPacketType {
+    fn from_bits(version: u32, bits: u8) -> Self {
+        // QUIC v2 shifts the type codes by one (RFC 9369, section 3.2)
+        let bits = if version == QUIC_V2 { bits.wrapping_sub(1) & 0x03 } else { bits };
+        match bits {
+            0 => LongPacketType::Initial,
+            1 => LongPacketType::ZeroRtt,
+            2 => LongPacketType::Handshake,
+            _ => LongPacketType::Retry,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LongPacketType::Initial => "Initial",
+            LongPacketType::ZeroRtt => "0-RTT",
+            LongPacketType::Handshake => "Handshake",
+            LongPacketType::Retry => "Retry",
+        }
+    }
+}
+
+pub fn version_name(version: u32) -> String {
+    match version {
+        0 => "Version Negotiation".to_string(),
+        QUIC_V1 => "1".to_string(),
+        QUIC_V2 => "2".to_string(),
+        0xff00_0000..=0xff00_00ff => format!("draft-{}", version & 0xff),
+        // Reserved versions that force version negotiation
+        _ if version & 0x0f0f_0f0f == 0x0a0a_0a0a => format!("0x{:08x} (forcing negotiation)", version),
+        _ => format!("0x{:08x}", version),
+    }
+}
+
+/// Header of one QUIC packet of a datagram
+#[derive(Debug, Clone)]
+pub struct QuicHeader {
+    pub first_byte: u8,
+    /// `None` for short headers
+    pub version: Option<u32>,
+    /// `None` for short headers and Version Negotiation
+    pub packet_type: Option<LongPacketType>,
+    pub dcid: Vec<u8>,
+    pub scid: Vec<u8>,
+    pub token: Vec<u8>,
+    /// Versions offered by a Version Negotiation packet
+    pub versions: Vec<u32>,
+    /// Offset of the protected packet number
+    pub pn_offset: usize,
+    /// Length of the packet in the datagram, coalesced packets follow
+    pub packet_len: usize,
+}
+
+impl QuicHeader {
+    pub fn is_long(&self) -> bool {
+        self.first_byte & 0x80 != 0
+    }
+
+    pub fn parse_long(data: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new(data);
+        let first_byte = reader.read_u8()?;
+        let version = reader.read_u32()?;
+        let dcid = reader.read_vec8()?.to_vec();
+        let scid = reader.read_vec8()?.to_vec();
+        let mut header = Self {
+            first_byte,
+            version: Some(version),
+            packet_type: None,
+            dcid,
+            scid,
+            token: Vec::new(),
+            versions: Vec::new(),
+            pn_offset: 0,
+            packet_len: data.len(),
+        };
+        if version == 0 {
+            while reader.remaining() >= 4 {
+                header.versions.push(reader.read_u32()?);
+            }
+            return Ok(header);
+        }
+
+        let packet_type = LongPacketType::from_bits(version, (first_byte >> 4) & 0x03);
+        header.packet_type = Some(packet_type);
+        match packet_type {
+            // The token runs up to the 16-byte integrity tag
+            LongPacketType::Retry => {
+                let token_len = reader.remaining().saturating_sub(16);
+                header.token = reader.read_bytes(token_len)?.to_vec();
+                return Ok(header);
+            }
+            LongPacketType::Initial => {
+                let token_len = reader.read_varint()? as usize;
+                header.token = reader.read_bytes(token_len)?.to_vec();
+            }
+            _ => {}
+        }
+        let length = reader.read_varint()? as usize;
+        header.pn_offset = reader.position();
+        if length > reader.remaining() {
+            return Err(anyhow::anyhow!("QUIC packet truncated: length {} exceeds {} bytes", length, reader.remaining()));
+        }
+        header.packet_len = header.pn_offset + length;
+        Ok(header)
+    }
+
+    /// Short headers do not carry the length of the connection ID, which is
+    /// known from the connection
+    pub fn parse_short(data: &[u8], dcid_len: usize) -> Result<Self> {
+        let dcid = data
+            .get(1..1 + dcid_len)
+            .ok_or_else(|| anyhow::anyhow!("QUIC short header truncated"))?;
+        Ok(Self {
+            first_byte: data[0],
+            version: None,
+            packet_type: None,
+            dcid: dcid.to_vec(),
+            scid: Vec::new(),
+            token: Vec::new(),
+            versions: Vec::new(),
+            pn_offset: 1 + dcid_len,
+            packet_len: data.len(),
+        })
+    }
+}
+
+/// Packet protection keys of one direction
+struct InitialKeys {
+    aead: Aes128Gcm,
+    iv: [u8; 12],
+    hp: Aes128,
+}
+
+impl InitialKeys {
+    /// Client and server Initial keys derived from the client's first destination connection ID
+    fn derive(version: u32, dcid: &[u8]) -> Option<(Self, Self)> {
+        let (salt, prefix) = match version {
+            QUIC_V1 => (&INITIAL_SALT_V1, "quic"),
+            QUIC_V2 => (&INITIAL_SALT_V2, "quicv2"),
+            _ => return None,
+        };
+        let (initial_secret, _) = hkdf::Hkdf::<Sha256>::extract(Some(salt), dcid);
+        let keys = |label: &[u8]| {
+            let secret = hkdf_expand_label(HashAlgorithm::Sha256, &initial_secret, label, 32)?;
+            let expand = |name: &str, len| {
+                hkdf_expand_label(HashAlgorithm::Sha256, &secret, format!("{} {}", prefix, name).as_bytes(), len)
+            };
+            Some(Self {
+                aead: Aes128Gcm::new_from_slice(&expand("key", 16)?).ok()?,
+                iv: expand("iv", 12)?.try_into().ok()?,
+                hp: Aes128::new_from_slice(&expand("hp", 16)?).ok()?,
+            })
+        };
+        Some((keys(b"client in")?, keys(b"server in")?))
+    }
+
+    /// Removes the header protection in place. Returns the truncated packet
+    /// number and its length.
+    fn unprotect(&self, packet: &mut [u8], pn_offset: usize) -> Option<(u64, usize)> {
+        let sample = packet.get(pn_offset + 4..pn_offset + 20)?;
+        let mut mask = GenericArray::clone_from_slice(sample);
+        self.hp.encrypt_block(&mut mask);
+        packet[0] ^= mask[0] & if packet[0] & 0x80 != 0 { 0x0f } else { 0x1f };
+        let pn_len = (packet[0] & 0x03) as usize + 1;
+        let mut packet_number = 0;
+        for (index, byte) in packet.get_mut(pn_offset..pn_offset + pn_len)?.iter_mut().enumerate() {
+            *byte ^= mask[1 + index];
+            packet_number = packet_number << 8 | *byte as u64;
+        }
+        Some((packet_number, pn_len))
+    }
+
+    fn decrypt(&self, header: &[u8], payload: &[u8], packet_number: u64) -> Option<Vec<u8>> {
+        let mut nonce = self.iv;
+        for (index, byte) in packet_number.to_be_bytes().iter().enumerate() {
+            nonce[4 + index] ^= byte;
+        }
+        self.aead.decrypt(Nonce::from_slice(&nonce), Payload { msg: payload, aad: header }).ok()
+    }
+}
+
+/// Full packet number from its truncated encoding (RFC 9000, appendix A.3)
+fn decode_packet_number(largest: Option<u64>, truncated: u64, pn_len: usize) -> u64 {
+    let expected = largest.map_or(0, |largest| largest + 1);
+    let window = 1u64 << (pn_len * 8);
+    let half_window = window / 2;
+    let candidate = (expected & !(window - 1)) | truncated;
+    if candidate + half_window <= expected && candidate < (1 << 62) - window {
+        candidate + window
+    } else if candidate > expected + half_window && candidate >= window {
+        candidate - window
+    } else {
+        candidate
+    }
+}
+
+/// CRYPTO frame data of one direction, reassembled from offset 0
+#[derive(Default)]
+struct CryptoStream {
+    fragments: BTreeMap<u64, Vec<u8>>,
+    data: Vec<u8>,
+    /// The first handshake message was returned
+    complete: bool,
+}
+
+impl CryptoStream {
+    /// Adds a CRYPTO frame. Returns the type and body of the first handshake
+    /// message once it is complete.
+    fn add(&mut self, offset: u64, data: &[u8]) -> Option<(u8, Vec<u8>)> {
+        if self.complete || offset + data.len() as u64 > MAX_CRYPTO_LEN {
+            return None;
+        }
+        self.fragments.insert(offset, data.to_vec());
+        while let Some(entry) = self.fragments.first_entry() {
+            let start = *entry.key() as usize;
+            if start > self.data.len() {
+                break;
+            }
+            let fragment = entry.remove();
+            if start + fragment.len() > self.data.len() {
+                self.data.extend_from_slice(&fragment[self.data.len() - start..]);
+            }
+        }
+
+        let header = self.data.get(..4)?;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let body = self.data.get(4..4 + length)?;
+        self.complete = true;
+        Some((header[0], body.to_vec()))
+    }
+}
+
+struct QuicConnection {
+    version: u32,
+    /// Address and port of the endpoint that sent the first Initial
+    client: (IpAddr, u16),
+    /// Client and server Initial keys
+    keys: Option<(InitialKeys, InitialKeys)>,
+    /// Client and server CRYPTO streams of the Initial packets
+    crypto: [CryptoStream; 2],
+    largest_pn: [Option<u64>; 2],
+    /// Connection IDs registered for the connection
+    cids: usize,
+}
+
+/// Connections by the connection IDs either endpoint chose
+struct QuicState {
+    /// Connections by number, dropped once idle
+    connections: FlowTable<usize, QuicConnection>,
+    next_number: usize,
+    by_cid: HashMap<Vec<u8>, usize>,
+    cid_lengths: BTreeSet<usize>,
+}
+
+impl Default for QuicState {
+    fn default() -> Self {
+        Self {
+            connections: FlowTable::new(),
+            next_number: 0,
+            by_cid: HashMap::new(),
+            cid_lengths: BTreeSet::new(),
+        }
+    }
+}
+
+impl QuicState {
+    fn register(&mut self, cid: &[u8], number: usize) {
+        if cid.is_empty() || self.by_cid.contains_key(cid) {
+            return;
+        }
+        let Some(connection) = self.connections.get_mut(&number).filter(|c| c.cids < MAX_CIDS_PER_CONNECTION) else {
+            return;
+        };
+        connection.cids += 1;
+        self.by_cid.insert(cid.to_vec(), number);
+        self.cid_lengths.insert(cid.len());
+    }
+
+    /// Connection of a short header packet and the length of its connection ID
+    fn find_short(&mut self, data: &[u8], at: DateTime<Utc>) -> Option<(usize, usize)> {
+        let (number, len) = self.cid_lengths.iter().rev().find_map(|len| {
+            let cid = data.get(1..1 + len)?;
+            self.by_cid.get(cid).map(|number| (*number, *len))
+        })?;
+        self.connections.seen(&number, at)?;
+        Some((number, len))
+    }
+
+    /// Finds the connection of a long header packet, creating it for a new
+    /// client Initial
+    fn connection(&mut self, header: &QuicHeader, sender: (IpAddr, u16), at: DateTime<Utc>) -> usize {
+        let known = [&header.dcid, &header.scid]
+            .into_iter()
+            .filter_map(|cid| self.by_cid.get(cid))
+            .find(|number| self.connections.get(number).is_some())
+            .copied();
+        let number = match known {
+            Some(number) => {
+                self.connections.seen(&number, at);
+                number
+            }
+            None => {
+                let version = header.version.unwrap_or_default();
+                let is_initial = header.packet_type == Some(LongPacketType::Initial);
+                let number = self.next_number;
+                self.next_number += 1;
+                let count = self.connections.len();
+                self.connections.insert(
+                    number,
+                    at,
+                    QuicConnection {
+                        version,
+                        client: sender,
+                        keys: is_initial.then(|| InitialKeys::derive(version, &header.dcid)).flatten(),
+                        crypto: Default::default(),
+                        largest_pn: [None, None],
+                        cids: 0,
+                    },
+                );
+                // The connection IDs of idle connections go with them
+                if self.connections.len() <= count {
+                    let connections = &self.connections;
+                    self.by_cid.retain(|_, number| connections.get(number).is_some());
+                }
+                self.register(&header.dcid, number);
+                number
+            }
+        };
+        self.register(&header.scid, number);
+        number
+    }
+}
+
+/// A frame of a decrypted Initial packet
+enum Frame<'a> {
+    Padding(usize),
+    Ping,
+    Ack { largest: u64 },
+    Crypto { offset: u64, data: &'a [u8] },
+    ConnectionClose { error: u64, reason: String },
+    Other(u64),
+}
+
+impl Frame<'_> {
+    fn name(&self) -> String {
+        match self {
+            Frame::Padding(_) => "PADDING".to_string(),
+            Frame::Ping => "PING".to_string(),
+            Frame::Ack { .. } => "ACK".to_string(),
+            Frame::Crypto { .. } => "CRYPTO".to_string(),
+            Frame::ConnectionClose { .. } => "CONNECTION_CLOSE".to_string(),
+            Frame::Other(frame_type) => format!("0x{:x}", frame_type),
+        }
+    }
+}
+
+/// Frames allowed in Initial packets; decoding stops at any other frame
+fn parse_frames(payload: &[u8]) -> Vec<Frame<'_>> {
+    let mut frames = Vec::new();
+    let mut reader = ByteReader::new(payload);
+    while !reader.is_empty() {
+        let start = reader.position();
+        let frame = (|| -> Result<Frame> {
+            Ok(match reader.read_varint()? {
+                0x00 => {
+                    while payload.get(reader.position()) == Some(&0) {
+                        reader.read_u8()?;
+                    }
+                    Frame::Padding(reader.position() - start)
+                }
+                0x01 => Frame::Ping,
+                frame_type @ (0x02 | 0x03) => {
+                    let largest = reader.read_varint()?;
+                    reader.read_varint()?;
+                    let ranges = reader.read_varint()?;
+                    reader.read_varint()?;
+                    for _ in 0..ranges * 2 {
+                        reader.read_varint()?;
+                    }
+                    if frame_type == 0x03 {
+                        for _ in 0..3 {
+                            reader.read_varint()?;
+                        }
+                    }
+                    Frame::Ack { largest }
+                }
+                0x06 => {
+                    let offset = reader.read_varint()?;
+                    let length = reader.read_varint()? as usize;
+                    Frame::Crypto { offset, data: reader.read_bytes(length)? }
+                }
+                frame_type @ (0x1c | 0x1d) => {
+                    let error = reader.read_varint()?;
+                    if frame_type == 0x1c {
+                        reader.read_varint()?;
+                    }
+                    let length = reader.read_varint()? as usize;
+                    let reason = String::from_utf8_lossy(reader.read_bytes(length)?).into_owned();
+                    Frame::ConnectionClose { error, reason }
+                }
+                frame_type => Frame::Other(frame_type),
+            })
+        })();
+        match frame {
+            Ok(Frame::Other(frame_type)) => {
+                frames.push(Frame::Other(frame_type));
+                break;
+            }
+            Ok(frame) => frames.push(frame),
+            Err(_) => break,
+        }
+    }
+    frames
+}
+
+/// QUIC, decrypting the Initial packets to read the TLS hellos
+pub struct QuicProcessor {
+    state: Mutex<QuicState>,
+}
+
+impl QuicProcessor {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(QuicState::default()),
+        }
+    }
+
+    /// Decodes one packet of the datagram starting at `offset`. Returns its
+    /// summary and length.
+    fn dissect_packet(
+        &self,
+        state: &mut QuicState,
+        data: &[u8],
+        offset: usize,
+        sender: (IpAddr, u16),
+        ctx: &mut DissectContext,
+    ) -> Result<(String, usize)> {
+        let packet = &data[offset..];
+        let (header, number) = if packet[0] & 0x80 != 0 {
+            let header = QuicHeader::parse_long(packet)?;
+            let number = state.connection(&header, sender, ctx.captured_at);
+            (header, Some(number))
+        } else {
+            match state.find_short(packet, ctx.captured_at) {
+                Some((number, len)) => (QuicHeader::parse_short(packet, len)?, Some(number)),
+                None => (QuicHeader::parse_short(packet, 0)?, None),
+            }
+        };
+
+        ctx.add_field_at("quic.header_form", offset, 1, header.is_long() as u8);
+        ctx.add_field_at("quic.fixed_bit", offset, 1, header.first_byte & 0x40 != 0);
+        if let Some(number) = number {
+            ctx.add_field("quic.connection.number", number);
+        }
+        let mut info = match header.packet_type {
+            Some(packet_type) => packet_type.name().to_string(),
+            None if header.is_long() => "Version Negotiation".to_string(),
+            None => "Protected Payload".to_string(),
+        };
+        if let Some(version) = header.version {
+            ctx.add_field_at("quic.version", offset + 1, 4, version_name(version));
+            ctx.add_field_at("quic.dcil", offset + 5, 1, header.dcid.len());
+            ctx.add_field_at("quic.dcid", offset + 6, header.dcid.len(), FieldValue::Bytes(header.dcid.clone()));
+            ctx.add_field_at("quic.scil", offset + 6 + header.dcid.len(), 1, header.scid.len());
+            ctx.add_field_at(
+                "quic.scid",
+                offset + 7 + header.dcid.len(),
+                header.scid.len(),
+                FieldValue::Bytes(header.scid.clone()),
+            );
+            info.push_str(&format!(", DCID={}, SCID={}", to_hex(&header.dcid), to_hex(&header.scid)));
+        } else if !header.dcid.is_empty() {
+            ctx.add_field_at("quic.dcid", offset + 1, header.dcid.len(), FieldValue::Bytes(header.dcid.clone()));
+            info.push_str(&format!(", DCID={}", to_hex(&header.dcid)));
+        }
+        if let Some(packet_type) = header.packet_type {
+            ctx.add_field_at("quic.long.packet_type", offset, 1, packet_type.name());
+        }
+
+        match header.packet_type {
+            None if header.is_long() => {
+                let versions: Vec<String> = header.versions.iter().map(|version| version_name(*version)).collect();
+                for version in &versions {
+                    ctx.add_field("quic.supported_version", version.clone());
+                }
+                info.push_str(&format!(", versions {}", versions.join(", ")));
+            }
+            Some(LongPacketType::Retry) => {
+                ctx.add_field("quic.retry_token", FieldValue::Bytes(header.token.clone()));
+                info.push_str(&format!(", token {} bytes", header.token.len()));
+                // The client derives new Initial keys from the connection ID the server chose
+                if let Some(connection) = number.and_then(|number| state.connections.get_mut(&number)) {
+                    connection.keys = InitialKeys::derive(connection.version, &header.scid);
+                    connection.crypto = Default::default();
+                    connection.largest_pn = [None, None];
+                }
+            }
+            Some(LongPacketType::Initial) => {
+                ctx.add_field("quic.token_length", header.token.len());
+                ctx.add_field("quic.length", header.packet_len - header.pn_offset);
+                if let Some(connection) = number.and_then(|number| state.connections.get_mut(&number)) {
+                    let from_client = connection.client == sender;
+                    let packet = &packet[..header.packet_len];
+                    match decrypt_initial(connection, packet, header.pn_offset, from_client) {
+                        Some((packet_number, payload)) => {
+                            ctx.add_field("quic.packet_number", packet_number);
+                            info.push_str(&format!(", PKN: {}", packet_number));
+                            info.push_str(&Self::dissect_frames(connection, &payload, from_client, ctx));
+                        }
+                        None => {
+                            ctx.add_field("quic.decryption_failed", true);
+                            info.push_str(", encrypted");
+                        }
+                    }
+                }
+            }
+            _ => ctx.add_field("quic.length", header.packet_len - header.pn_offset),
+        }
+        Ok((info, header.packet_len))
+    }
+
+    /// Adds the frames of a decrypted Initial packet and the TLS hello they complete
+    fn dissect_frames(connection: &mut QuicConnection, payload: &[u8], from_client: bool, ctx: &mut DissectContext) -> String {
+        let mut names: Vec<String> = Vec::new();
+        let mut hello = String::new();
+        for frame in parse_frames(payload) {
+            let name = frame.name();
+            ctx.add_field("quic.frame_type", name.clone());
+            if names.last() != Some(&name) {
+                names.push(name);
+            }
+            match frame {
+                Frame::Padding(length) => ctx.add_field("quic.padding_length", length),
+                Frame::Ack { largest } => ctx.add_field("quic.ack.largest_acknowledged", largest),
+                Frame::ConnectionClose { error, reason } => {
+                    ctx.add_field("quic.cc.error_code", error);
+                    ctx.add_field("quic.cc.reason_phrase", reason);
+                }
+                Frame::Crypto { offset, data } => {
+                    ctx.add_field("quic.crypto.offset", offset);
+                    ctx.add_field("quic.crypto.length", data.len());
+                    let stream = &mut connection.crypto[if from_client { 0 } else { 1 }];
+                    if let Some((handshake_type, body)) = stream.add(offset, data) {
+                        hello = Self::dissect_hello(handshake_type, &body, ctx);
+                    }
+                }
+                Frame::Ping | Frame::Other(_) => {}
+            }
+        }
+        format!(", {}{}", names.join(", "), hello)
+    }
+
+    fn dissect_hello(handshake_type: u8, body: &[u8], ctx: &mut DissectContext) -> String {
+        match handshake_type {
+            1 => {
+                let Ok(hello) = ClientHello::parse(body) else {
+                    return ", malformed ClientHello".to_string();
+                };
+                ctx.add_field("tls.handshake.type", "ClientHello");
+                let mut info = ", ClientHello".to_string();
+                if let Some(sni) = &hello.server_name {
+                    ctx.add_field("tls.sni", sni.clone());
+                    info.push_str(&format!(", SNI={}", sni));
+                }
+                for alpn in &hello.alpn {
+                    ctx.add_field("tls.alpn", alpn.clone());
+                }
+                if !hello.alpn.is_empty() {
+                    info.push_str(&format!(", ALPN={}", hello.alpn.join(",")));
+                }
+                let ja4 = hello.ja4('q');
+                info.push_str(&format!(", JA4={}", ja4));
+                ctx.add_field("tls.ja4", ja4);
+                info
+            }
+            2 => {
+                let Ok(hello) = ServerHello::parse(body) else {
+                    return ", malformed ServerHello".to_string();
+                };
+                ctx.add_field("tls.handshake.type", "ServerHello");
+                let cipher = cipher_suite_name(hello.cipher_suite);
+                ctx.add_field("tls.cipher_suite", cipher.clone());
+                format!(", ServerHello, Cipher={}", cipher)
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+impl Default for QuicProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Removes the header protection of an Initial packet and decrypts it.
+/// Returns the packet number and the plaintext frames.
+fn decrypt_initial(connection: &mut QuicConnection, packet: &[u8], pn_offset: usize, from_client: bool) -> Option<(u64, Vec<u8>)> {
+    let (client_keys, server_keys) = connection.keys.as_ref()?;
+    let keys = if from_client { client_keys } else { server_keys };
+    let mut packet = packet.to_vec();
+    let (truncated, pn_len) = keys.unprotect(&mut packet, pn_offset)?;
+    let direction = if from_client { 0 } else { 1 };
+    let packet_number = decode_packet_number(connection.largest_pn[direction], truncated, pn_len);
+    let (header, payload) = packet.split_at(pn_offset + pn_len);
+    let plaintext = keys.decrypt(header, payload, packet_number)?;
+    connection.largest_pn[direction] = Some(connection.largest_pn[direction].map_or(packet_number, |pn| pn.max(packet_number)));
+    Some((packet_number, plaintext))
+}
+
+impl Dissector for QuicProcessor {
+    fn name(&self) -> &'static str {
+        "QUIC"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let flow = ctx.flow.ok_or_else(|| anyhow::anyhow!("QUIC needs a UDP flow"))?;
+        if data.is_empty() || data[0] & 0x40 == 0 && data[0] & 0x80 == 0 {
+            return Err(anyhow::anyhow!("Not a QUIC packet: fixed bit is clear"));
+        }
+        let mut state = self.state.lock().unwrap();
+        let mut summaries = Vec::new();
+        let mut offset = 0;
+        // Coalesced packets share the datagram; padding may follow the last one
+        while offset < data.len() && data[offset] != 0 {
+            let (info, length) = self.dissect_packet(&mut state, data, offset, (flow.src, flow.src_port), ctx)?;
+            summaries.push(info);
+            offset += length;
+        }
+        Ok(Dissection::new("QUIC", summaries.join("; ")))
+    }
+
+    /// Heuristic probe: a long header with the fixed bit and a known version
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        let header = QuicHeader::parse_long(payload).ok().filter(|_| payload[0] & 0xc0 == 0xc0)?;
+        match (header.version?, header.packet_type) {
+            (QUIC_V1 | QUIC_V2, Some(LongPacketType::Initial)) => Some(ProbeResult::new(90, "QUIC Initial")),
+            (QUIC_V1 | QUIC_V2 | 0xff00_0000..=0xff00_00ff, _) => Some(ProbeResult::new(75, "QUIC long header")),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::bytes::from_hex;
+    use super::super::dissector::testing::at;
+
+    const DCID: [u8; 8] = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+    /// Server Initial of RFC 9001, appendix A.3
+    const SERVER_INITIAL: &str = "cf000000010008f067a5502a4262b5004075c0d95a482cd0991cd25b0aac406a5816b6394100f37a1c69797554780bb38cc5a99f5ede4cf73c3ec2493a1839b3dbcba3f6ea46c5b7684df3548e7ddeb9c3bf9c73cc3f3bded74b562bfb19fb84022f8ef4cdd93795d77d06edbb7aaf2f58891850abbdca3d20398c276456cbc42158407dd074ee";
+
+    fn hex(text: &str) -> Vec<u8> {
+        from_hex(text).unwrap()
+    }
+
+    fn assert_keys(keys: &InitialKeys, key: &str, iv: &str, hp: &str) {
+        let nonce = Nonce::from_slice(&[0; 12]);
+        let expected = Aes128Gcm::new_from_slice(&hex(key)).unwrap();
+        assert_eq!(keys.aead.encrypt(nonce, &b"quic"[..]).unwrap(), expected.encrypt(nonce, &b"quic"[..]).unwrap());
+        assert_eq!(keys.iv.to_vec(), hex(iv));
+        let (mut block, mut expected_block) = (GenericArray::default(), GenericArray::default());
+        keys.hp.encrypt_block(&mut block);
+        Aes128::new_from_slice(&hex(hp)).unwrap().encrypt_block(&mut expected_block);
+        assert_eq!(block, expected_block);
+    }
+
+    /// Header of a v1 client Initial with a one byte payload
+    fn initial(dcid: &[u8]) -> QuicHeader {
+        let mut packet = vec![0xc0, 0, 0, 0, 1, dcid.len() as u8];
+        packet.extend_from_slice(dcid);
+        packet.extend_from_slice(&[0, 0, 1, 0]);
+        QuicHeader::parse_long(&packet).unwrap()
+    }
+
+    #[test]
+    fn derives_the_initial_keys() {
+        // RFC 9001, appendix A.1
+        let (client, server) = InitialKeys::derive(QUIC_V1, &DCID).unwrap();
+        assert_keys(&client, "1f369613dd76d5467730efcbe3b1a22d", "fa044b2f42a3fd3b46fb255c", "9f50449e04a0e810283a1e9933adedd2");
+        assert_keys(&server, "cf3a5331653c364c88f0f379b6067e37", "0ac1493ca1905853b0bba03e", "c206b8d9b9f0f37644430b490eeaa314");
+        // RFC 9369, appendix A.1
+        let (client, server) = InitialKeys::derive(QUIC_V2, &DCID).unwrap();
+        assert_keys(&client, "8b1a0bc121284290a29e0971b5cd045d", "91f73e2351d8fa91660e909f", "45b95e15235d6f45a6b19cbcb0294ba9");
+        assert_keys(&server, "82db637861d55e1d011f19ea71d5d2a7", "dd13c276499c0249d3310652", "edf6d05c83121201b436e16877593c3a");
+        assert!(InitialKeys::derive(0xff00_001d, &DCID).is_none());
+    }
+
+    #[test]
+    fn removes_the_header_protection() {
+        // Client Initials of RFC 9001, appendix A.2 and RFC 9369, appendix A.2,
+        // up to the end of the header protection sample
+        for (version, protected, sample, header) in [
+            (
+                QUIC_V1,
+                "c000000001088394c8f03e5157080000449e7b9aec34",
+                "d1b1c98dd7689fb8ec11d242b123dc9b",
+                "c300000001088394c8f03e5157080000449e00000002",
+            ),
+            (
+                QUIC_V2,
+                "d76b3343cf088394c8f03e5157080000449ea0c95e82",
+                "ffe67b6abcdb4298b485dd04de806071",
+                "d36b3343cf088394c8f03e5157080000449e00000002",
+            ),
+        ] {
+            let (client, _) = InitialKeys::derive(version, &DCID).unwrap();
+            let mut packet = hex(&format!("{}{}", protected, sample));
+            assert_eq!(client.unprotect(&mut packet, 18), Some((2, 4)));
+            assert_eq!(packet[..22], hex(header)[..]);
+        }
+    }
+
+    #[test]
+    fn decrypts_the_server_initial() {
+        let mut connection = QuicConnection {
+            version: QUIC_V1,
+            client: ("192.0.2.1".parse().unwrap(), 50000),
+            keys: InitialKeys::derive(QUIC_V1, &DCID),
+            crypto: Default::default(),
+            largest_pn: [None, None],
+            cids: 0,
+        };
+        let packet = hex(SERVER_INITIAL);
+        let header = QuicHeader::parse_long(&packet).unwrap();
+        assert_eq!(header.packet_type, Some(LongPacketType::Initial));
+        assert_eq!(header.packet_len, packet.len());
+
+        let (packet_number, payload) = decrypt_initial(&mut connection, &packet, header.pn_offset, false).unwrap();
+        assert_eq!(packet_number, 1);
+        assert_eq!(connection.largest_pn, [None, Some(1)]);
+        let frames = parse_frames(&payload);
+        assert!(matches!(frames[0], Frame::Ack { largest: 0 }));
+        let Frame::Crypto { offset: 0, data } = frames[1] else { panic!("expected a CRYPTO frame") };
+        assert_eq!(data.len(), 90);
+        assert!(ServerHello::parse(&data[4..]).is_ok());
+
+        // The client keys do not open it
+        assert!(decrypt_initial(&mut connection, &packet, header.pn_offset, true).is_none());
+    }
+
+    #[test]
+    fn forgets_idle_connections() {
+        let mut state = QuicState::default();
+        let sender = ("192.0.2.1".parse().unwrap(), 50000);
+        assert_eq!(state.connection(&initial(&DCID), sender, at(0)), 0);
+        assert_eq!(state.connection(&initial(&DCID), sender, at(100)), 0);
+        let mut short = vec![0x40];
+        short.extend_from_slice(&DCID);
+        assert_eq!(state.find_short(&short, at(200)), Some((0, 8)));
+
+        assert_eq!(state.connection(&initial(&[1; 8]), sender, at(1000)), 1);
+        assert!(state.find_short(&short, at(1000)).is_none());
+        assert!(!state.by_cid.contains_key(&DCID[..]));
+        assert_eq!(state.connection(&initial(&DCID), sender, at(1001)), 2);
+    }
+}
@@ -0,0 +1,101 @@
+/// In-order reassembly buffer for one direction of a TCP stream.
+///
+/// Retransmitted bytes are dropped; when a gap is detected (a segment was
+/// lost or captured out of order) the buffered data is discarded and the
+/// stream resynchronises on the new segment.
+#[derive(Debug, Default)]
+pub(crate) struct StreamBuffer {
+    next_seq: Option<u32>,
+    data: Vec<u8>,
+}
+
+impl StreamBuffer {
+    /// Appends a segment and returns `false` if the stream lost synchronisation
+    pub fn push(&mut self, seq: u32, payload: &[u8]) -> bool {
+        if payload.is_empty() {
+            return true;
+        }
+
+        let mut in_sync = true;
+        let (start, payload) = match self.next_seq {
+            None => (seq, payload),
+            Some(next) => {
+                let offset = seq.wrapping_sub(next) as i32;
+                if offset > 0 {
+                    self.data.clear();
+                    in_sync = false;
+                    (seq, payload)
+                } else {
+                    let overlap = offset.unsigned_abs() as usize;
+                    if overlap >= payload.len() {
+                        return true;
+                    }
+                    (next, &payload[overlap..])
+                }
+            }
+        };
+
+        self.next_seq = Some(start.wrapping_add(payload.len() as u32));
+        self.data.extend_from_slice(payload);
+        in_sync
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Drops `len` bytes from the front of the buffer
+    pub fn consume(&mut self, len: usize) {
+        self.data.drain(..len.min(self.data.len()));
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_in_order_segments() {
+        let mut buffer = StreamBuffer::default();
+        assert!(buffer.push(1000, b"hello "));
+        assert!(buffer.push(1006, b"world"));
+        assert!(buffer.push(1011, b""));
+        assert_eq!(buffer.data(), b"hello world");
+        buffer.consume(6);
+        assert_eq!(buffer.data(), b"world");
+        buffer.consume(100);
+        assert!(buffer.data().is_empty());
+    }
+
+    #[test]
+    fn drops_retransmitted_bytes() {
+        let mut buffer = StreamBuffer::default();
+        buffer.push(1, b"abcd");
+        assert!(buffer.push(1, b"abcd"));
+        assert!(buffer.push(3, b"cdef"));
+        assert_eq!(buffer.data(), b"abcdef");
+    }
+
+    #[test]
+    fn resynchronises_after_a_gap() {
+        let mut buffer = StreamBuffer::default();
+        buffer.push(1, b"abc");
+        assert!(!buffer.push(10, b"xyz"));
+        assert_eq!(buffer.data(), b"xyz");
+        assert!(buffer.push(13, b"!"));
+        assert_eq!(buffer.data(), b"xyz!");
+    }
+
+    #[test]
+    fn follows_sequence_wraparound() {
+        let mut buffer = StreamBuffer::default();
+        buffer.push(u32::MAX - 1, b"ab");
+        assert!(buffer.push(0, b"cd"));
+        assert!(buffer.push(u32::MAX, b"bc"));
+        assert_eq!(buffer.data(), b"abcd");
+    }
+}
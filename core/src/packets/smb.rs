@@ -1,3 +1,26 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::flow::{FlowKey, FlowTable};
+use super::reassembly::StreamBuffer;
+use super::smb2::{
+    self, FileId, Smb2Body, Smb2Message, TransformHeader, COMPRESSION_PROTOCOL_ID, SMB2_PROTOCOL_ID,
+    STATUS_PENDING, STATUS_SUCCESS, TRANSFORM_HEADER_LEN, TRANSFORM_PROTOCOL_ID,
+};
+use super::smb_files::{ConnectionFiles, SmbActivity, SmbEvent, SmbFile, SmbOperation};
+use super::tree::FieldValue;
+
+/// Length of the NetBIOS session service / direct TCP header
+const NBSS_HEADER_LEN: usize = 4;
+/// Largest SMB message accepted; anything larger means the stream lost sync
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+/// Requests of a connection waiting for their response; the oldest is
+/// dropped beyond it
+const MAX_PENDING_REQUESTS: usize = 4096;
+
 #[derive(Debug)]
 pub struct SmbPacket {
     pub command: u8,
@@ -13,14 +36,75 @@ pub struct SmbPacket {
     pub mid: u16,
 }
 
-pub struct SmbProcessor;
+/// Request waiting for its response
+#[derive(Clone)]
+struct PendingRequest {
+    frame: u64,
+    at: DateTime<Utc>,
+    /// Share path of a TREE_CONNECT, file name of the other commands
+    name: Option<String>,
+    /// Share the request was sent on
+    share: Option<String>,
+    user: Option<String>,
+    file_id: Option<FileId>,
+    /// Offset of a READ or WRITE, and the data written
+    offset: u64,
+    data: Vec<u8>,
+    delete_on_close: bool,
+}
+
+/// What the earlier messages of a compound tell the related ones
+#[derive(Default)]
+struct Compound {
+    name: Option<String>,
+    share: Option<String>,
+    user: Option<String>,
+    /// File opened by the CREATE response of the compound
+    file_id: Option<FileId>,
+}
+
+/// Requests in flight and the names of the shares and open files of one
+/// connection, to label messages that only carry their ids
+#[derive(Default)]
+struct SmbConnection {
+    pending: HashMap<u64, PendingRequest>,
+    trees: HashMap<u32, String>,
+    files: HashMap<FileId, String>,
+    activity: ConnectionFiles,
+}
+
+struct Smb2State {
+    /// Keyed by the canonical flow
+    connections: FlowTable<FlowKey, SmbConnection>,
+    activity: SmbActivity,
+}
+
+/// One direction of an SMB connection
+#[derive(Default)]
+struct SmbStream {
+    messages: StreamBuffer,
+}
+
+/// SMB over NetBIOS session service (port 139) or direct TCP (port 445):
+/// SMB1 headers, SMB2/3 messages and compounds, and encrypted SMB3
+pub struct SmbProcessor {
+    /// Messages split over several segments, per direction
+    streams: Mutex<FlowTable<FlowKey, SmbStream>>,
+    state: Mutex<Smb2State>,
+}
 
 impl SmbProcessor {
     pub fn new() -> Self {
-        Self
+        Self {
+            streams: Mutex::new(FlowTable::new()),
+            state: Mutex::new(Smb2State {
+                connections: FlowTable::new(),
+                activity: SmbActivity::default(),
+            }),
+        }
     }
 
-    pub fn process(&self, payload: &[u8]) -> Result<SmbPacket, String> {
+    pub fn process(&self, payload: &[u8]) -> std::result::Result<SmbPacket, String> {
         if payload.len() < 32 {
             return Err("Packet too short for SMB header".to_string());
         }
@@ -129,4 +213,622 @@ impl SmbProcessor {
             _ => format!("Unknown SMB command: 0x{:02X}", packet.command),
         }
     }
-} 
\ No newline at end of file
+}
+
+impl SmbProcessor {
+    /// Feeds a TCP segment and returns the complete NetBIOS messages it
+    /// finishes (type and payload), each with the offset of its payload in
+    /// the segment if it lies entirely within it
+    fn process_segment(
+        &self,
+        flow: &FlowKey,
+        seq: u32,
+        payload: &[u8],
+        at: DateTime<Utc>,
+    ) -> Vec<(u8, Vec<u8>, Option<usize>)> {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = &mut streams.entry(*flow, at, SmbStream::default).messages;
+        let fresh = stream.data().is_empty();
+        stream.push(seq, payload);
+        // Offsets in the segment are only known while the buffer mirrors it
+        let in_segment = fresh && stream.data().len() == payload.len();
+
+        let mut messages = Vec::new();
+        let mut consumed = 0;
+        loop {
+            let data = stream.data();
+            if data.len() < NBSS_HEADER_LEN {
+                break;
+            }
+            let kind = data[0];
+            let len = u32::from_be_bytes([0, data[1], data[2], data[3]]) as usize;
+            // A segment lost in the middle of a message leaves garbage in
+            // front of the next header
+            let plausible = matches!(kind, 0x00 | 0x81..=0x85)
+                && len <= MAX_MESSAGE_LEN
+                && (kind != 0x00 || data.len() < 8 || is_smb(&data[4..8]));
+            if !plausible {
+                stream.clear();
+                break;
+            }
+            if data.len() < NBSS_HEADER_LEN + len {
+                break;
+            }
+            let offset = in_segment.then_some(consumed + NBSS_HEADER_LEN);
+            messages.push((kind, data[NBSS_HEADER_LEN..NBSS_HEADER_LEN + len].to_vec(), offset));
+            stream.consume(NBSS_HEADER_LEN + len);
+            consumed += NBSS_HEADER_LEN + len;
+        }
+        messages
+    }
+
+    /// Decodes one SMB message; returns the protocol shown and the summary
+    fn dissect_message(&self, data: &[u8], base: Option<usize>, ctx: &mut DissectContext) -> Result<(&'static str, String)> {
+        let field = |ctx: &mut DissectContext, name: &str, at: Option<(usize, usize)>, value: FieldValue| match at {
+            Some((offset, len)) => ctx.add_field_at(name, offset, len, value),
+            None => ctx.add_field(name, value),
+        };
+        let at = |offset: usize, len: usize| base.map(|base| (base + offset, len));
+
+        match data.get(..4) {
+            Some(id) if id == SMB2_PROTOCOL_ID => {
+                let messages = Smb2Message::parse_compound(data)?;
+                let mut infos = Vec::new();
+                let mut compound = Compound::default();
+                for message in &messages {
+                    for field in message.fields(base.map(|base| base + message.offset)) {
+                        match field.offset {
+                            Some(offset) => ctx.add_field_at(&field.name, offset, field.length, field.value),
+                            None => ctx.add_field(&field.name, field.value),
+                        }
+                    }
+                    let mut info = message.format_info();
+                    if let Some(name) = self.track(message, &mut compound, ctx) {
+                        info.push_str(&format!(" File: {}", name));
+                    }
+                    infos.push(info);
+                }
+                Ok(("SMB2", infos.join("; ")))
+            }
+            Some(id) if id == TRANSFORM_PROTOCOL_ID => {
+                let transform = TransformHeader::parse(data)?;
+                field(ctx, "smb2.tf.signature", at(4, 16), FieldValue::Bytes(transform.signature.to_vec()));
+                field(ctx, "smb2.tf.nonce", at(20, 16), FieldValue::Bytes(transform.nonce.to_vec()));
+                field(ctx, "smb2.tf.msg_size", at(36, 4), transform.original_size.into());
+                field(ctx, "smb2.tf.flags", at(42, 2), FieldValue::Hex(transform.flags as u64));
+                field(ctx, "smb2.tf.sesid", at(44, 8), FieldValue::Hex(transform.session_id));
+                field(ctx, "smb2.encrypted", at(TRANSFORM_HEADER_LEN, data.len() - TRANSFORM_HEADER_LEN), true.into());
+                Ok((
+                    "SMB2",
+                    format!(
+                        "Encrypted SMB3 SessionId 0x{:016x} ({} bytes)",
+                        transform.session_id, transform.original_size
+                    ),
+                ))
+            }
+            Some(id) if id == COMPRESSION_PROTOCOL_ID => {
+                let original_size = data
+                    .get(4..8)
+                    .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .ok_or_else(|| anyhow::anyhow!("Truncated SMB3 compression header"))?;
+                field(ctx, "smb2.comp_transform.original_size", at(4, 4), original_size.into());
+                Ok(("SMB2", format!("Compressed SMB3 ({} bytes uncompressed)", original_size)))
+            }
+            _ => {
+                let smb = self.process(data).map_err(anyhow::Error::msg)?;
+                field(ctx, "smb.cmd", at(4, 1), FieldValue::Hex(smb.command as u64));
+                field(ctx, "smb.nt_status", at(5, 4), FieldValue::Hex(smb.status as u64));
+                Ok(("SMB", format!("SMB Packet - Command: {}", self.get_command(&smb))))
+            }
+        }
+    }
+
+    /// Pairs requests with their responses, names the shares and files the
+    /// message refers to and records the file activity. Returns the file
+    /// name shown in the summary.
+    fn track(&self, message: &Smb2Message, compound: &mut Compound, ctx: &mut DissectContext) -> Option<String> {
+        let key = ctx.flow?.canonical();
+        let header = &message.header;
+        let mut state = self.state.lock().unwrap();
+        let Smb2State { connections, activity } = &mut *state;
+        // Files left open by connections that went idle keep their data
+        let connection = connections.entry_with(key, ctx.captured_at, SmbConnection::default, |_, ended| {
+            activity.end(ended.activity)
+        });
+
+        let share = header
+            .tree_id
+            .and_then(|tree_id| connection.trees.get(&tree_id).cloned())
+            .or_else(|| compound.share.clone().filter(|_| header.is_related()));
+        if let Some(path) = &share {
+            ctx.add_field("smb2.share", path.clone());
+        }
+
+        if !header.is_response() {
+            let file_id = match &message.body {
+                Smb2Body::CloseRequest { file_id }
+                | Smb2Body::ReadRequest { file_id, .. }
+                | Smb2Body::WriteRequest { file_id, .. }
+                | Smb2Body::IoctlRequest { file_id, .. } => Some(*file_id),
+                _ => None,
+            };
+            let name = match &message.body {
+                Smb2Body::TreeConnectRequest { path } => Some(path.clone()),
+                Smb2Body::CreateRequest { name, .. } => {
+                    compound.name = Some(name.clone());
+                    Some(name.clone())
+                }
+                _ => file_id.and_then(|file_id| {
+                    if file_id.is_related() {
+                        compound.name.clone()
+                    } else {
+                        connection.files.get(&file_id).cloned()
+                    }
+                }),
+            };
+            let user = match &message.body {
+                Smb2Body::SessionSetupRequest { ntlm: Some(ntlm), .. } => Some(ntlm.account()),
+                _ => connection
+                    .activity
+                    .user(header.session_id)
+                    .or_else(|| compound.user.clone().filter(|_| header.is_related())),
+            };
+            let (offset, data) = match &message.body {
+                Smb2Body::ReadRequest { offset, .. } => (*offset, Vec::new()),
+                Smb2Body::WriteRequest { offset, data, .. } => (*offset, data.clone()),
+                _ => (0, Vec::new()),
+            };
+            let delete_on_close = matches!(
+                message.body,
+                Smb2Body::CreateRequest { options, .. } if options & smb2::FILE_DELETE_ON_CLOSE != 0
+            );
+            compound.share = share.clone();
+            compound.user = user.clone();
+            let pending = PendingRequest {
+                frame: ctx.number,
+                at: ctx.captured_at,
+                name: name.clone(),
+                share,
+                user,
+                file_id,
+                offset,
+                data,
+                delete_on_close,
+            };
+            if connection.pending.len() >= MAX_PENDING_REQUESTS {
+                let oldest = connection.pending.iter().min_by_key(|(_, request)| request.frame).map(|(id, _)| *id);
+                if let Some(id) = oldest {
+                    connection.pending.remove(&id);
+                }
+            }
+            connection.pending.insert(header.message_id, pending);
+            return match message.body {
+                Smb2Body::TreeConnectRequest { .. } | Smb2Body::CreateRequest { .. } => None,
+                _ => name,
+            };
+        }
+
+        // An interim response leaves the request waiting for the final one
+        let interim = header.status == STATUS_PENDING;
+        let request = if interim {
+            connection.pending.get(&header.message_id).cloned()
+        } else {
+            connection.pending.remove(&header.message_id)
+        };
+        let request = request?;
+        ctx.add_field("smb2.response_to", request.frame);
+        let time = (ctx.captured_at - request.at).num_microseconds().unwrap_or_default() as f64 / 1e6;
+        ctx.add_field("smb2.time", time);
+        if !interim {
+            self.record(activity, &mut connection.activity, message, &request, compound, ctx);
+        }
+
+        let success = header.status == STATUS_SUCCESS;
+        match &message.body {
+            Smb2Body::TreeConnectResponse { .. } if success => {
+                if let (Some(tree_id), Some(path)) = (header.tree_id, &request.name) {
+                    connection.trees.insert(tree_id, path.clone());
+                    ctx.add_field("smb2.share", path.clone());
+                }
+                None
+            }
+            Smb2Body::CreateResponse { file_id, .. } => {
+                if let Some(name) = &request.name {
+                    connection.files.insert(*file_id, name.clone());
+                    ctx.add_field("smb2.filename", name.clone());
+                }
+                request.name
+            }
+            Smb2Body::CloseResponse { .. } => {
+                if let Some(name) = &request.name {
+                    connection.files.retain(|_, file| file != name);
+                }
+                request.name
+            }
+            _ if header.command == smb2::TREE_DISCONNECT && success => {
+                if let Some(tree_id) = header.tree_id {
+                    connection.trees.remove(&tree_id);
+                }
+                None
+            }
+            Smb2Body::TreeConnectResponse { .. } | Smb2Body::SessionSetupResponse { .. } => None,
+            _ => request.name,
+        }
+    }
+
+    /// Adds the final response to a request to the file activity
+    fn record(
+        &self,
+        activity: &mut SmbActivity,
+        connection: &mut ConnectionFiles,
+        message: &Smb2Message,
+        request: &PendingRequest,
+        compound: &mut Compound,
+        ctx: &DissectContext,
+    ) {
+        let header = &message.header;
+        let (Some(client), Some(server)) = (ctx.dst_ip, ctx.src_ip) else {
+            return;
+        };
+        let success = header.status == STATUS_SUCCESS;
+        let file_id = request
+            .file_id
+            .and_then(|file_id| if file_id.is_related() { compound.file_id } else { Some(file_id) });
+        let user = request.user.clone().or_else(|| connection.user(header.session_id));
+
+        let (operation, bytes) = match (&message.body, header.command) {
+            (_, smb2::SESSION_SETUP) if header.status == smb2::STATUS_MORE_PROCESSING_REQUIRED => return,
+            (_, smb2::SESSION_SETUP) => {
+                if let (true, Some(user)) = (success, &request.user) {
+                    connection.logon(header.session_id, user.clone());
+                }
+                (SmbOperation::Logon, None)
+            }
+            (_, smb2::TREE_CONNECT) => (SmbOperation::TreeConnect, None),
+            (Smb2Body::CreateResponse { file_id, action, end_of_file, .. }, _) => {
+                compound.file_id = Some(*file_id);
+                let file = SmbFile {
+                    client,
+                    server,
+                    user: user.clone(),
+                    share: request.share.clone(),
+                    path: request.name.clone().unwrap_or_default(),
+                    size: Some(*end_of_file),
+                    captured: 0,
+                    bytes_read: 0,
+                    bytes_written: 0,
+                    opened_at: ctx.captured_at,
+                    closed_at: None,
+                    chunks: BTreeMap::new(),
+                };
+                activity.open(connection, *file_id, file);
+                let operation = if request.delete_on_close {
+                    SmbOperation::Delete
+                } else if *action == smb2::FILE_CREATED {
+                    SmbOperation::Create
+                } else {
+                    SmbOperation::Open
+                };
+                (operation, Some(*end_of_file))
+            }
+            (_, smb2::CREATE) if request.delete_on_close => (SmbOperation::Delete, None),
+            (_, smb2::CREATE) => (SmbOperation::Open, None),
+            (Smb2Body::ReadResponse { data, .. }, _) => {
+                if let Some(file_id) = file_id {
+                    activity.read(connection, file_id, request.offset, data);
+                }
+                (SmbOperation::Read, Some(data.len() as u64))
+            }
+            (_, smb2::READ) => (SmbOperation::Read, None),
+            (Smb2Body::WriteResponse { count }, _) => {
+                if let Some(file_id) = file_id {
+                    let written = request.data.len().min(*count as usize);
+                    activity.write(connection, file_id, request.offset, &request.data[..written]);
+                }
+                (SmbOperation::Write, Some(*count as u64))
+            }
+            (_, smb2::WRITE) => (SmbOperation::Write, None),
+            (body, smb2::CLOSE) => {
+                let end_of_file = match body {
+                    Smb2Body::CloseResponse { end_of_file } if *end_of_file > 0 => Some(*end_of_file),
+                    _ => None,
+                };
+                if let (true, Some(file_id)) = (success, file_id) {
+                    activity.close(connection, file_id, ctx.captured_at, end_of_file);
+                }
+                (SmbOperation::Close, None)
+            }
+            _ => return,
+        };
+        activity.record(SmbEvent {
+            at: ctx.captured_at,
+            client,
+            server,
+            user,
+            share: match operation {
+                SmbOperation::TreeConnect => request.name.clone(),
+                _ => request.share.clone(),
+            },
+            path: match operation {
+                SmbOperation::Logon | SmbOperation::TreeConnect => None,
+                _ => request.name.clone(),
+            },
+            operation,
+            bytes,
+            status: smb2::format_status(header.status),
+        });
+    }
+
+    pub fn events(&self) -> Vec<SmbEvent> {
+        self.state.lock().unwrap().activity.events()
+    }
+
+    pub fn files(&self) -> Vec<SmbFile> {
+        let state = self.state.lock().unwrap();
+        let open = state.connections.values().flat_map(|connection| connection.activity.open_files());
+        state.activity.files(open)
+    }
+
+    /// Drops both directions of a closed connection, keeping the data of
+    /// its open files
+    fn close(&self, flow: &FlowKey) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.remove(flow);
+        streams.remove(&flow.reversed());
+        drop(streams);
+        let mut state = self.state.lock().unwrap();
+        if let Some(connection) = state.connections.remove(&flow.canonical()) {
+            state.activity.end(connection.activity);
+        }
+    }
+
+    /// Decodes the NetBIOS messages a TCP segment completes
+    fn dissect_segment(&self, flow: &FlowKey, seq: u32, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        if data.is_empty() {
+            return Err(anyhow::anyhow!("No SMB data"));
+        }
+        let messages = self.process_segment(flow, seq, data, ctx.captured_at);
+        if messages.is_empty() {
+            return Err(anyhow::anyhow!("No complete SMB message"));
+        }
+
+        let mut protocol = "NBSS";
+        let mut infos = Vec::new();
+        for (kind, payload, offset) in &messages {
+            match offset {
+                Some(offset) => {
+                    ctx.add_field_at("nbss.type", offset - NBSS_HEADER_LEN, 1, FieldValue::Hex(*kind as u64));
+                    ctx.add_field_at("nbss.length", offset - 3, 3, payload.len());
+                }
+                None => {
+                    ctx.add_field("nbss.type", FieldValue::Hex(*kind as u64));
+                    ctx.add_field("nbss.length", payload.len());
+                }
+            }
+            let info = match kind {
+                0x00 => {
+                    let (smb_protocol, info) = self.dissect_message(payload, *offset, ctx)?;
+                    protocol = smb_protocol;
+                    info
+                }
+                0x81 => "Session request".to_string(),
+                0x82 => "Positive session response".to_string(),
+                0x83 => "Negative session response".to_string(),
+                0x84 => "Retarget session response".to_string(),
+                _ => "Session keep-alive".to_string(),
+            };
+            infos.push(info);
+        }
+        Ok(Dissection::new(protocol, infos.join("; ")))
+    }
+}
+
+impl Dissector for SmbProcessor {
+    fn name(&self) -> &'static str {
+        "SMB"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let (Some(flow), Some(seq)) = (ctx.flow, ctx.tcp_sequence) else {
+            let (protocol, info) = self.dissect_message(data, Some(0), ctx)?;
+            return Ok(Dissection::new(protocol, info));
+        };
+        let dissection = self.dissect_segment(&flow, seq, data, ctx);
+        if ctx.flow_closed {
+            self.close(&flow);
+        }
+        dissection
+    }
+
+    /// Heuristic probe: an SMB1, SMB2 or SMB3 transform protocol id,
+    /// optionally behind a NetBIOS session header
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        if payload.len() >= 8 && payload[0] == 0x00 && is_smb(&payload[4..]) {
+            Some(ProbeResult::new(95, "NetBIOS session message with SMB header"))
+        } else if is_smb(payload) {
+            Some(ProbeResult::new(80, "SMB protocol id"))
+        } else {
+            None
+        }
+    }
+}
+
+fn is_smb(id: &[u8]) -> bool {
+    id.len() >= 4 && matches!(id[0], 0xfc..=0xff) && &id[1..4] == b"SMB"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::AlertChannel;
+    use pnet::packet::tcp::TcpFlags;
+    use super::super::dissector::testing::{self, at, flow};
+    use super::super::dissector::DissectorRegistry;
+
+    fn session_message(payload: &[u8]) -> Vec<u8> {
+        let mut message = (payload.len() as u32).to_be_bytes().to_vec();
+        message.extend(payload);
+        message
+    }
+
+    #[test]
+    fn frames_netbios_messages() {
+        let smb = SmbProcessor::new();
+        let mut payload = b"\xfeSMB".to_vec();
+        payload.resize(64, 0);
+        let mut stream = session_message(&payload);
+        stream.extend(session_message(&payload));
+
+        assert!(smb.process_segment(&flow(445), 1, &stream[..50], at(0)).is_empty());
+        let messages = smb.process_segment(&flow(445), 51, &stream[50..], at(0));
+        assert_eq!(messages, [(0, payload.clone(), None), (0, payload.clone(), None)]);
+        let messages = smb.process_segment(&flow(445), 1 + stream.len() as u32, &session_message(&payload), at(0));
+        assert_eq!(messages, [(0, payload, Some(4))]);
+    }
+
+    #[test]
+    fn drops_implausible_headers() {
+        let smb = SmbProcessor::new();
+        assert!(smb.process_segment(&flow(445), 1, b"\x00\x00\x00\x40GET / HTTP/1.1", at(0)).is_empty());
+        assert!(smb.streams.lock().unwrap().get(&flow(445)).unwrap().messages.data().is_empty());
+        assert!(smb.process_segment(&flow(445), 100, &[0x90, 0x00, 0x00, 0x04, 0xfe], at(0)).is_empty());
+        assert!(smb.streams.lock().unwrap().get(&flow(445)).unwrap().messages.data().is_empty());
+        assert!(smb.process(&[0xff, b'S', b'M', b'B']).is_err());
+    }
+
+    fn header(command: u16, flags: u32, message_id: u64) -> Vec<u8> {
+        let mut header = SMB2_PROTOCOL_ID.to_vec();
+        header.extend(64u16.to_le_bytes());
+        header.resize(12, 0);
+        header.extend(command.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(flags.to_le_bytes());
+        header.extend(0u32.to_le_bytes());
+        header.extend(message_id.to_le_bytes());
+        header.resize(smb2::HEADER_LEN, 0);
+        header
+    }
+
+    const FILE_ID: [u8; 16] = [1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0];
+
+    /// CREATE and WRITE of `report.txt`: the client's messages, then the server's
+    fn write_file() -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        let name: Vec<u8> = "report.txt".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let mut create = header(smb2::CREATE, 0, 1);
+        let mut body = vec![0; 56];
+        body[44..46].copy_from_slice(&120u16.to_le_bytes());
+        body[46..48].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        create.extend(body);
+        create.extend(name);
+
+        let mut created = header(smb2::CREATE, smb2::FLAG_RESPONSE, 1);
+        let mut body = vec![0; 88];
+        body[64..80].copy_from_slice(&FILE_ID);
+        created.extend(body);
+
+        let mut write = header(smb2::WRITE, 0, 2);
+        let mut body = vec![0; 48];
+        body[2..4].copy_from_slice(&112u16.to_le_bytes());
+        body[4..8].copy_from_slice(&5u32.to_le_bytes());
+        body[16..32].copy_from_slice(&FILE_ID);
+        write.extend(body);
+        write.extend(b"hello");
+
+        let mut written = header(smb2::WRITE, smb2::FLAG_RESPONSE, 2);
+        let mut body = vec![0; 16];
+        body[4..8].copy_from_slice(&5u32.to_le_bytes());
+        written.extend(body);
+
+        (vec![session_message(&create), session_message(&write)], vec![session_message(&created), session_message(&written)])
+    }
+
+    /// Dissects `data` sent on `flow` at sequence `seq`, tracking the flow in
+    /// `registry` as the TCP dissector does
+    fn send(
+        smb: &SmbProcessor,
+        registry: &DissectorRegistry,
+        flow: FlowKey,
+        seq: u32,
+        data: &[u8],
+        flags: u8,
+        at: DateTime<Utc>,
+    ) {
+        let closed = testing::close_flow(registry, &flow, flags, at);
+        let _ = testing::dissect_in(registry, &AlertChannel::new(), smb, data, |ctx| {
+            ctx.flow = Some(flow);
+            ctx.tcp_sequence = Some(seq);
+            ctx.tcp_flags = Some(flags);
+            ctx.flow_closed = closed;
+            ctx.captured_at = at;
+            ctx.src_ip = Some(flow.src);
+            ctx.dst_ip = Some(flow.dst);
+        });
+    }
+
+    /// Runs the CREATE and WRITE exchange and returns the next sequence
+    /// numbers of the client and the server
+    fn exchange(smb: &SmbProcessor, registry: &DissectorRegistry, at: DateTime<Utc>) -> (u32, u32) {
+        let (requests, responses) = write_file();
+        let (mut client, mut server) = (1, 1);
+        for (request, response) in requests.iter().zip(&responses) {
+            send(smb, registry, flow(445), client, request, TcpFlags::ACK, at);
+            client += request.len() as u32;
+            send(smb, registry, flow(445).reversed(), server, response, TcpFlags::ACK, at);
+            server += response.len() as u32;
+        }
+        (client, server)
+    }
+
+    #[test]
+    fn forgets_connections_once_closed() {
+        let smb = SmbProcessor::new();
+        let registry = DissectorRegistry::new();
+        let (client, server) = exchange(&smb, &registry, at(0));
+        assert_eq!(smb.state.lock().unwrap().connections.len(), 1);
+        assert_eq!(smb.files()[0].data(), b"hello");
+
+        send(&smb, &registry, flow(445), client, &[], TcpFlags::FIN | TcpFlags::ACK, at(0));
+        assert_eq!(smb.streams.lock().unwrap().len(), 2);
+        send(&smb, &registry, flow(445).reversed(), server, &[], TcpFlags::FIN | TcpFlags::ACK, at(0));
+        assert_eq!(smb.streams.lock().unwrap().len(), 0);
+        assert_eq!(smb.state.lock().unwrap().connections.len(), 0);
+
+        // The file left open keeps its data
+        let files = smb.files();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "report.txt");
+        assert_eq!(files[0].data(), b"hello");
+    }
+
+    #[test]
+    fn forgets_connections_on_reset() {
+        let smb = SmbProcessor::new();
+        let registry = DissectorRegistry::new();
+        let (_, server) = exchange(&smb, &registry, at(0));
+        send(&smb, &registry, flow(445).reversed(), server, &[], TcpFlags::RST, at(0));
+        assert_eq!(smb.streams.lock().unwrap().len(), 0);
+        assert_eq!(smb.state.lock().unwrap().connections.len(), 0);
+        assert_eq!(smb.files().len(), 1);
+    }
+
+    #[test]
+    fn keeps_the_files_of_idle_connections() {
+        let smb = SmbProcessor::new();
+        let registry = DissectorRegistry::new();
+        exchange(&smb, &registry, at(0));
+
+        // Another connection much later expires the first one
+        let later = at(3600);
+        let other = FlowKey::new("10.0.0.3".parse().unwrap(), 50001, "10.0.0.2".parse().unwrap(), 445);
+        let (requests, _) = write_file();
+        send(&smb, &registry, other, 1, &requests[0], TcpFlags::ACK, later);
+
+        let state = smb.state.lock().unwrap();
+        assert_eq!(state.connections.len(), 1);
+        assert!(state.connections.get(&other.canonical()).is_some());
+        drop(state);
+        let files = smb.files();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].data(), b"hello");
+    }
+}
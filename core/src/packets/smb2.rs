@@ -0,0 +1,903 @@
+use anyhow::Result;
+use std::fmt;
+use super::tree::{Field, FieldValue};
+
+pub const SMB2_PROTOCOL_ID: &[u8; 4] = b"\xfeSMB";
+pub const TRANSFORM_PROTOCOL_ID: &[u8; 4] = b"\xfdSMB";
+pub const COMPRESSION_PROTOCOL_ID: &[u8; 4] = b"\xfcSMB";
+
+pub const HEADER_LEN: usize = 64;
+pub const TRANSFORM_HEADER_LEN: usize = 52;
+
+pub const NEGOTIATE: u16 = 0x00;
+pub const SESSION_SETUP: u16 = 0x01;
+pub const LOGOFF: u16 = 0x02;
+pub const TREE_CONNECT: u16 = 0x03;
+pub const TREE_DISCONNECT: u16 = 0x04;
+pub const CREATE: u16 = 0x05;
+pub const CLOSE: u16 = 0x06;
+pub const FLUSH: u16 = 0x07;
+pub const READ: u16 = 0x08;
+pub const WRITE: u16 = 0x09;
+pub const LOCK: u16 = 0x0a;
+pub const IOCTL: u16 = 0x0b;
+pub const CANCEL: u16 = 0x0c;
+pub const ECHO: u16 = 0x0d;
+pub const QUERY_DIRECTORY: u16 = 0x0e;
+pub const CHANGE_NOTIFY: u16 = 0x0f;
+pub const QUERY_INFO: u16 = 0x10;
+pub const SET_INFO: u16 = 0x11;
+pub const OPLOCK_BREAK: u16 = 0x12;
+
+pub const FLAG_RESPONSE: u32 = 0x01;
+pub const FLAG_ASYNC: u32 = 0x02;
+pub const FLAG_RELATED: u32 = 0x04;
+pub const FLAG_SIGNED: u32 = 0x08;
+
+/// CREATE action telling that the file did not exist
+pub const FILE_CREATED: u32 = 2;
+/// CREATE option deleting the file when its last handle is closed
+pub const FILE_DELETE_ON_CLOSE: u32 = 0x1000;
+
+pub const STATUS_SUCCESS: u32 = 0x0000_0000;
+pub const STATUS_PENDING: u32 = 0x0000_0103;
+pub const STATUS_BUFFER_OVERFLOW: u32 = 0x8000_0005;
+pub const STATUS_MORE_PROCESSING_REQUIRED: u32 = 0xc000_0016;
+
+pub fn command_name(command: u16) -> &'static str {
+    match command {
+        NEGOTIATE => "Negotiate Protocol",
+        SESSION_SETUP => "Session Setup",
+        LOGOFF => "Session Logoff",
+        TREE_CONNECT => "Tree Connect",
+        TREE_DISCONNECT => "Tree Disconnect",
+        CREATE => "Create",
+        CLOSE => "Close",
+        FLUSH => "Flush",
+        READ => "Read",
+        WRITE => "Write",
+        LOCK => "Lock",
+        IOCTL => "Ioctl",
+        CANCEL => "Cancel",
+        ECHO => "KeepAlive",
+        QUERY_DIRECTORY => "Find",
+        CHANGE_NOTIFY => "Notify",
+        QUERY_INFO => "GetInfo",
+        SET_INFO => "SetInfo",
+        OPLOCK_BREAK => "Break",
+        _ => "Unknown",
+    }
+}
+
+/// Name of an NT status code, `None` for codes not listed
+pub fn status_name(status: u32) -> Option<&'static str> {
+    Some(match status {
+        0x0000_0000 => "STATUS_SUCCESS",
+        0x0000_0103 => "STATUS_PENDING",
+        0x0000_010c => "STATUS_NOTIFY_ENUM_DIR",
+        0x8000_0005 => "STATUS_BUFFER_OVERFLOW",
+        0x8000_0006 => "STATUS_NO_MORE_FILES",
+        0xc000_0004 => "STATUS_INFO_LENGTH_MISMATCH",
+        0xc000_0008 => "STATUS_INVALID_HANDLE",
+        0xc000_000d => "STATUS_INVALID_PARAMETER",
+        0xc000_000f => "STATUS_NO_SUCH_FILE",
+        0xc000_0010 => "STATUS_INVALID_DEVICE_REQUEST",
+        0xc000_0011 => "STATUS_END_OF_FILE",
+        0xc000_0016 => "STATUS_MORE_PROCESSING_REQUIRED",
+        0xc000_0022 => "STATUS_ACCESS_DENIED",
+        0xc000_0023 => "STATUS_BUFFER_TOO_SMALL",
+        0xc000_0033 => "STATUS_OBJECT_NAME_INVALID",
+        0xc000_0034 => "STATUS_OBJECT_NAME_NOT_FOUND",
+        0xc000_0035 => "STATUS_OBJECT_NAME_COLLISION",
+        0xc000_003a => "STATUS_OBJECT_PATH_NOT_FOUND",
+        0xc000_0043 => "STATUS_SHARING_VIOLATION",
+        0xc000_0056 => "STATUS_DELETE_PENDING",
+        0xc000_0061 => "STATUS_PRIVILEGE_NOT_HELD",
+        0xc000_0064 => "STATUS_NO_SUCH_USER",
+        0xc000_006a => "STATUS_WRONG_PASSWORD",
+        0xc000_006d => "STATUS_LOGON_FAILURE",
+        0xc000_006e => "STATUS_ACCOUNT_RESTRICTION",
+        0xc000_0071 => "STATUS_PASSWORD_EXPIRED",
+        0xc000_0072 => "STATUS_ACCOUNT_DISABLED",
+        0xc000_007f => "STATUS_DISK_FULL",
+        0xc000_009a => "STATUS_INSUFFICIENT_RESOURCES",
+        0xc000_00b0 => "STATUS_PIPE_DISCONNECTED",
+        0xc000_00b5 => "STATUS_IO_TIMEOUT",
+        0xc000_00ba => "STATUS_FILE_IS_A_DIRECTORY",
+        0xc000_00bb => "STATUS_NOT_SUPPORTED",
+        0xc000_00c9 => "STATUS_NETWORK_NAME_DELETED",
+        0xc000_00cc => "STATUS_BAD_NETWORK_NAME",
+        0xc000_0101 => "STATUS_DIRECTORY_NOT_EMPTY",
+        0xc000_0103 => "STATUS_NOT_A_DIRECTORY",
+        0xc000_0120 => "STATUS_CANCELLED",
+        0xc000_0128 => "STATUS_FILE_CLOSED",
+        0xc000_014b => "STATUS_PIPE_BROKEN",
+        0xc000_019c => "STATUS_FS_DRIVER_REQUIRED",
+        0xc000_0203 => "STATUS_USER_SESSION_DELETED",
+        0xc000_0225 => "STATUS_NOT_FOUND",
+        0xc000_0234 => "STATUS_ACCOUNT_LOCKED_OUT",
+        0xc000_0257 => "STATUS_PATH_NOT_COVERED",
+        0xc000_035c => "STATUS_NETWORK_SESSION_EXPIRED",
+        _ => return None,
+    })
+}
+
+pub fn format_status(status: u32) -> String {
+    status_name(status).map(str::to_string).unwrap_or_else(|| format!("0x{:08x}", status))
+}
+
+pub fn dialect_name(dialect: u16) -> String {
+    match dialect {
+        0x0202 => "SMB 2.0.2".to_string(),
+        0x0210 => "SMB 2.1".to_string(),
+        0x0300 => "SMB 3.0".to_string(),
+        0x0302 => "SMB 3.0.2".to_string(),
+        0x0311 => "SMB 3.1.1".to_string(),
+        0x02ff => "SMB2 wildcard".to_string(),
+        _ => format!("0x{:04x}", dialect),
+    }
+}
+
+fn cipher_name(cipher: u16) -> String {
+    match cipher {
+        1 => "AES-128-CCM".to_string(),
+        2 => "AES-128-GCM".to_string(),
+        3 => "AES-256-CCM".to_string(),
+        4 => "AES-256-GCM".to_string(),
+        _ => format!("0x{:04x}", cipher),
+    }
+}
+
+pub fn ioctl_name(code: u32) -> Option<&'static str> {
+    Some(match code {
+        0x0006_0194 => "FSCTL_DFS_GET_REFERRALS",
+        0x0011_0018 => "FSCTL_PIPE_WAIT",
+        0x0011_400c => "FSCTL_PIPE_PEEK",
+        0x0011_c017 => "FSCTL_PIPE_TRANSCEIVE",
+        0x0014_0078 => "FSCTL_SRV_REQUEST_RESUME_KEY",
+        0x0014_01fc => "FSCTL_QUERY_NETWORK_INTERFACE_INFO",
+        0x0014_0204 => "FSCTL_VALIDATE_NEGOTIATE_INFO",
+        0x0014_4064 => "FSCTL_SRV_ENUMERATE_SNAPSHOTS",
+        0x0014_40f2 => "FSCTL_SRV_COPYCHUNK",
+        0x0018_00f2 => "FSCTL_SRV_COPYCHUNK_WRITE",
+        0x0009_00c4 => "FSCTL_SET_SPARSE",
+        0x0009_00a8 => "FSCTL_GET_REPARSE_POINT",
+        _ => return None,
+    })
+}
+
+fn share_type_name(share_type: u8) -> &'static str {
+    match share_type {
+        1 => "Disk",
+        2 => "Named pipe",
+        3 => "Printer",
+        _ => "Unknown",
+    }
+}
+
+fn create_action_name(action: u32) -> &'static str {
+    match action {
+        0 => "Superseded",
+        1 => "Opened",
+        2 => "Created",
+        3 => "Overwritten",
+        _ => "Unknown",
+    }
+}
+
+fn disposition_name(disposition: u32) -> &'static str {
+    match disposition {
+        0 => "Supersede",
+        1 => "Open",
+        2 => "Create",
+        3 => "Open If",
+        4 => "Overwrite",
+        5 => "Overwrite If",
+        _ => "Unknown",
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    let b = bytes_at(data, offset, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    let b = bytes_at(data, offset, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64> {
+    let b = bytes_at(data, offset, 8)?;
+    Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+}
+
+fn bytes_at(data: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    data.get(offset..offset + len).ok_or_else(|| {
+        anyhow::anyhow!("Unexpected end of SMB2 data: need {} bytes at offset {}, have {}", len, offset, data.len())
+    })
+}
+
+/// UTF-16LE string of `len` bytes at `offset`
+fn utf16_at(data: &[u8], offset: usize, len: usize) -> Result<String> {
+    let units: Vec<u16> = bytes_at(data, offset, len)?
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+    Ok(String::from_utf16_lossy(&units))
+}
+
+/// Persistent and volatile halves of an open file handle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId {
+    pub persistent: u64,
+    pub volatile: u64,
+}
+
+impl FileId {
+    fn parse(data: &[u8], offset: usize) -> Result<Self> {
+        Ok(Self {
+            persistent: u64_at(data, offset)?,
+            volatile: u64_at(data, offset + 8)?,
+        })
+    }
+
+    /// The id standing for the file opened earlier in a compound request
+    pub fn is_related(&self) -> bool {
+        self.persistent == u64::MAX && self.volatile == u64::MAX
+    }
+}
+
+impl fmt::Display for FileId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}{:016x}", self.persistent, self.volatile)
+    }
+}
+
+/// SMB2 message header
+#[derive(Debug, Clone)]
+pub struct Smb2Header {
+    pub credit_charge: u16,
+    pub status: u32,
+    pub command: u16,
+    /// Credits requested or granted
+    pub credits: u16,
+    pub flags: u32,
+    /// Offset of the next message of a compound, 0 for the last one
+    pub next_command: u32,
+    pub message_id: u64,
+    pub async_id: Option<u64>,
+    pub tree_id: Option<u32>,
+    pub session_id: u64,
+    pub signature: [u8; 16],
+}
+
+impl Smb2Header {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if bytes_at(data, 0, 4)? != SMB2_PROTOCOL_ID {
+            return Err(anyhow::anyhow!("Invalid SMB2 protocol id"));
+        }
+        let flags = u32_at(data, 16)?;
+        let async_header = flags & FLAG_ASYNC != 0;
+        let mut signature = [0; 16];
+        signature.copy_from_slice(bytes_at(data, 48, 16)?);
+        Ok(Self {
+            credit_charge: u16_at(data, 6)?,
+            status: u32_at(data, 8)?,
+            command: u16_at(data, 12)?,
+            credits: u16_at(data, 14)?,
+            flags,
+            next_command: u32_at(data, 20)?,
+            message_id: u64_at(data, 24)?,
+            async_id: async_header.then(|| u64_at(data, 32)).transpose()?,
+            tree_id: (!async_header).then(|| u32_at(data, 36)).transpose()?,
+            session_id: u64_at(data, 40)?,
+            signature,
+        })
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags & FLAG_RESPONSE != 0
+    }
+
+    pub fn is_related(&self) -> bool {
+        self.flags & FLAG_RELATED != 0
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.flags & FLAG_SIGNED != 0
+    }
+
+    /// The response carries an error body rather than the one of its command
+    fn is_error(&self, structure_size: u16) -> bool {
+        let expected = matches!(
+            self.status,
+            STATUS_SUCCESS | STATUS_PENDING | STATUS_MORE_PROCESSING_REQUIRED | STATUS_BUFFER_OVERFLOW
+        );
+        self.is_response() && !expected && structure_size == 9
+    }
+}
+
+/// User, domain and host of an NTLMSSP AUTHENTICATE message
+#[derive(Debug, Clone)]
+pub struct NtlmAuth {
+    pub domain: String,
+    pub user: String,
+    pub host: String,
+}
+
+impl NtlmAuth {
+    /// Finds the NTLMSSP message in a security blob (usually wrapped in SPNEGO)
+    fn find(blob: &[u8]) -> Option<Self> {
+        let start = blob.windows(8).position(|w| w == b"NTLMSSP\0")?;
+        let message = &blob[start..];
+        if u32_at(message, 8).ok()? != 3 {
+            return None;
+        }
+        let string = |offset: usize| -> Option<String> {
+            let len = u16_at(message, offset).ok()? as usize;
+            let at = u32_at(message, offset + 4).ok()? as usize;
+            utf16_at(message, at, len).ok()
+        };
+        Some(Self {
+            domain: string(28)?,
+            user: string(36)?,
+            host: string(44)?,
+        })
+    }
+
+    pub fn account(&self) -> String {
+        if self.domain.is_empty() {
+            self.user.clone()
+        } else {
+            format!("{}\\{}", self.domain, self.user)
+        }
+    }
+}
+
+/// Body of an SMB2 message, for the commands decoded
+#[derive(Debug, Clone)]
+pub enum Smb2Body {
+    NegotiateRequest {
+        dialects: Vec<u16>,
+        security_mode: u16,
+        capabilities: u32,
+        client_guid: [u8; 16],
+        ciphers: Vec<u16>,
+    },
+    NegotiateResponse {
+        dialect: u16,
+        security_mode: u16,
+        capabilities: u32,
+        server_guid: [u8; 16],
+        max_read_size: u32,
+        max_write_size: u32,
+        ciphers: Vec<u16>,
+    },
+    SessionSetupRequest {
+        security_mode: u8,
+        previous_session_id: u64,
+        ntlm: Option<NtlmAuth>,
+    },
+    SessionSetupResponse {
+        session_flags: u16,
+    },
+    TreeConnectRequest {
+        path: String,
+    },
+    TreeConnectResponse {
+        share_type: u8,
+        share_flags: u32,
+        maximal_access: u32,
+    },
+    CreateRequest {
+        name: String,
+        desired_access: u32,
+        disposition: u32,
+        options: u32,
+    },
+    CreateResponse {
+        file_id: FileId,
+        action: u32,
+        end_of_file: u64,
+        attributes: u32,
+    },
+    CloseRequest {
+        file_id: FileId,
+    },
+    CloseResponse {
+        end_of_file: u64,
+    },
+    ReadRequest {
+        file_id: FileId,
+        offset: u64,
+        length: u32,
+    },
+    ReadResponse {
+        data: Vec<u8>,
+        remaining: u32,
+    },
+    WriteRequest {
+        file_id: FileId,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    WriteResponse {
+        count: u32,
+    },
+    IoctlRequest {
+        code: u32,
+        file_id: FileId,
+        input_len: u32,
+    },
+    IoctlResponse {
+        code: u32,
+        file_id: FileId,
+        output_len: u32,
+    },
+    Error,
+    Other,
+}
+
+/// One message of an SMB2 compound, with its position in the NetBIOS payload
+#[derive(Debug, Clone)]
+pub struct Smb2Message {
+    pub header: Smb2Header,
+    pub body: Smb2Body,
+    pub offset: usize,
+}
+
+impl Smb2Message {
+    /// Parses every message of a compound chained by `NextCommand`
+    pub fn parse_compound(data: &[u8]) -> Result<Vec<Self>> {
+        let mut messages = Vec::new();
+        let mut offset = 0;
+        loop {
+            let message = &data[offset..];
+            let header = Smb2Header::parse(message)?;
+            let len = match header.next_command as usize {
+                0 => message.len(),
+                next if next >= HEADER_LEN && next <= message.len() => next,
+                next => return Err(anyhow::anyhow!("Invalid SMB2 next command offset {}", next)),
+            };
+            let body = parse_body(&header, &message[..len])?;
+            let next = header.next_command as usize;
+            messages.push(Self { header, body, offset });
+            if next == 0 {
+                return Ok(messages);
+            }
+            offset += next;
+        }
+    }
+
+    pub fn format_info(&self) -> String {
+        let header = &self.header;
+        let mut info = format!(
+            "{} {}",
+            command_name(header.command),
+            if header.is_response() { "Response" } else { "Request" }
+        );
+        if header.is_response() && header.status != STATUS_SUCCESS {
+            info.push_str(&format!(", Error: {}", format_status(header.status)));
+        }
+        match &self.body {
+            Smb2Body::NegotiateRequest { dialects, .. } => {
+                let names: Vec<String> = dialects.iter().map(|&d| dialect_name(d)).collect();
+                info.push_str(&format!(" Dialects: {}", names.join(", ")));
+            }
+            Smb2Body::NegotiateResponse { dialect, .. } => {
+                info.push_str(&format!(" Dialect: {}", dialect_name(*dialect)));
+            }
+            Smb2Body::SessionSetupRequest { ntlm: Some(ntlm), .. } => {
+                info.push_str(&format!(" User: {}", ntlm.account()));
+            }
+            Smb2Body::TreeConnectRequest { path } => info.push_str(&format!(" Tree: {}", path)),
+            Smb2Body::CreateRequest { name, .. } => info.push_str(&format!(" File: {}", name)),
+            Smb2Body::ReadRequest { offset, length, .. } => {
+                info.push_str(&format!(" Len:{} Off:{}", length, offset));
+            }
+            Smb2Body::WriteRequest { offset, data, .. } => {
+                info.push_str(&format!(" Len:{} Off:{}", data.len(), offset));
+            }
+            Smb2Body::IoctlRequest { code, .. } | Smb2Body::IoctlResponse { code, .. } => {
+                info.push_str(&format!(" {}", ioctl_name(*code).map_or_else(|| format!("0x{:08x}", code), str::to_string)));
+            }
+            _ => {}
+        }
+        info
+    }
+
+    /// Fields of the message. Their offsets are relative to the data handed
+    /// to the dissector and only set when `base`, the offset of the message
+    /// in that data, is known.
+    pub fn fields(&self, base: Option<usize>) -> Vec<Field> {
+        let header = &self.header;
+        let at = |offset: usize, len: usize| base.map(|base| (base + offset, len));
+        let field = |name: &str, position: Option<(usize, usize)>, value: FieldValue| Field {
+            offset: position.map(|(offset, _)| offset),
+            length: position.map_or(0, |(_, len)| len),
+            ..Field::new(name, value)
+        };
+        let mut fields = vec![
+            field("smb2.cmd", at(12, 2), header.command.into()),
+            field("smb2.nt_status", at(8, 4), FieldValue::Hex(header.status as u64)),
+            field("smb2.flags.response", at(16, 4), header.is_response().into()),
+            field("smb2.msg_id", at(24, 8), header.message_id.into()),
+            field("smb2.sesid", at(40, 8), FieldValue::Hex(header.session_id)),
+            field("smb2.credit.charge", at(6, 2), header.credit_charge.into()),
+            field("smb2.credits", at(14, 2), header.credits.into()),
+        ];
+        if let Some(status) = status_name(header.status).filter(|_| header.is_response()) {
+            fields.push(field("smb2.nt_status.name", None, status.into()));
+        }
+        if let Some(tree_id) = header.tree_id {
+            fields.push(field("smb2.tid", at(36, 4), FieldValue::Hex(tree_id as u64)));
+        }
+        if let Some(async_id) = header.async_id {
+            fields.push(field("smb2.aid", at(32, 8), FieldValue::Hex(async_id)));
+        }
+        if header.is_signed() {
+            fields.push(field("smb2.signature", at(48, 16), FieldValue::Bytes(header.signature.to_vec())));
+        }
+        if header.is_related() {
+            fields.push(field("smb2.flags.chained", at(16, 4), true.into()));
+        }
+
+        let body = |offset: usize, len: usize| at(HEADER_LEN + offset, len);
+        let hex = |value: u32| FieldValue::Hex(value as u64);
+        match &self.body {
+            Smb2Body::NegotiateRequest { dialects, security_mode, capabilities, client_guid, ciphers } => {
+                fields.push(field("smb2.sec_mode", body(4, 2), hex(*security_mode as u32)));
+                fields.push(field("smb2.capabilities", body(8, 4), hex(*capabilities)));
+                fields.push(field("smb2.client_guid", body(12, 16), FieldValue::Bytes(client_guid.to_vec())));
+                for (i, dialect) in dialects.iter().enumerate() {
+                    fields.push(field("smb2.dialect", body(36 + 2 * i, 2), dialect_name(*dialect).into()));
+                }
+                for cipher in ciphers {
+                    fields.push(field("smb2.cipher", None, cipher_name(*cipher).into()));
+                }
+            }
+            Smb2Body::NegotiateResponse {
+                dialect,
+                security_mode,
+                capabilities,
+                server_guid,
+                max_read_size,
+                max_write_size,
+                ciphers,
+            } => {
+                fields.push(field("smb2.sec_mode", body(2, 2), hex(*security_mode as u32)));
+                fields.push(field("smb2.dialect", body(4, 2), dialect_name(*dialect).into()));
+                fields.push(field("smb2.server_guid", body(8, 16), FieldValue::Bytes(server_guid.to_vec())));
+                fields.push(field("smb2.capabilities", body(24, 4), hex(*capabilities)));
+                fields.push(field("smb2.max_read_size", body(32, 4), (*max_read_size).into()));
+                fields.push(field("smb2.max_write_size", body(36, 4), (*max_write_size).into()));
+                for cipher in ciphers {
+                    fields.push(field("smb2.cipher", None, cipher_name(*cipher).into()));
+                }
+            }
+            Smb2Body::SessionSetupRequest { security_mode, previous_session_id, ntlm } => {
+                fields.push(field("smb2.sec_mode", body(3, 1), hex(*security_mode as u32)));
+                fields.push(field("smb2.previous_sesid", body(16, 8), FieldValue::Hex(*previous_session_id)));
+                if let Some(ntlm) = ntlm {
+                    fields.push(field("ntlmssp.auth.domain", None, ntlm.domain.clone().into()));
+                    fields.push(field("ntlmssp.auth.username", None, ntlm.user.clone().into()));
+                    fields.push(field("ntlmssp.auth.hostname", None, ntlm.host.clone().into()));
+                }
+            }
+            Smb2Body::SessionSetupResponse { session_flags } => {
+                fields.push(field("smb2.session_flags", body(2, 2), hex(*session_flags as u32)));
+            }
+            Smb2Body::TreeConnectRequest { path } => fields.push(field("smb2.tree", None, path.clone().into())),
+            Smb2Body::TreeConnectResponse { share_type, share_flags, maximal_access } => {
+                fields.push(field("smb2.share_type", body(2, 1), share_type_name(*share_type).into()));
+                fields.push(field("smb2.share_flags", body(4, 4), hex(*share_flags)));
+                fields.push(field("smb2.max_access", body(12, 4), hex(*maximal_access)));
+            }
+            Smb2Body::CreateRequest { name, desired_access, disposition, options } => {
+                fields.push(field("smb2.filename", None, name.clone().into()));
+                fields.push(field("smb2.access_mask", body(24, 4), hex(*desired_access)));
+                fields.push(field("smb2.create.disposition", body(36, 4), disposition_name(*disposition).into()));
+                fields.push(field("smb2.create_options", body(40, 4), hex(*options)));
+            }
+            Smb2Body::CreateResponse { file_id, action, end_of_file, attributes } => {
+                fields.push(field("smb2.create.action", body(4, 4), create_action_name(*action).into()));
+                fields.push(field("smb2.end_of_file", body(48, 8), (*end_of_file).into()));
+                fields.push(field("smb2.file_attribute", body(56, 4), hex(*attributes)));
+                fields.push(field("smb2.fid", body(64, 16), file_id.to_string().into()));
+            }
+            Smb2Body::CloseRequest { file_id } => fields.push(field("smb2.fid", body(8, 16), file_id.to_string().into())),
+            Smb2Body::CloseResponse { end_of_file } => {
+                fields.push(field("smb2.end_of_file", body(48, 8), (*end_of_file).into()));
+            }
+            Smb2Body::ReadRequest { file_id, offset, length } => {
+                fields.push(field("smb2.read_length", body(4, 4), (*length).into()));
+                fields.push(field("smb2.file_offset", body(8, 8), (*offset).into()));
+                fields.push(field("smb2.fid", body(16, 16), file_id.to_string().into()));
+            }
+            Smb2Body::ReadResponse { data, remaining } => {
+                fields.push(field("smb2.read_length", body(4, 4), data.len().into()));
+                fields.push(field("smb2.read_remaining", body(8, 4), (*remaining).into()));
+            }
+            Smb2Body::WriteRequest { file_id, offset, data } => {
+                fields.push(field("smb2.write_length", body(4, 4), data.len().into()));
+                fields.push(field("smb2.file_offset", body(8, 8), (*offset).into()));
+                fields.push(field("smb2.fid", body(16, 16), file_id.to_string().into()));
+            }
+            Smb2Body::WriteResponse { count } => fields.push(field("smb2.write_count", body(4, 4), (*count).into())),
+            Smb2Body::IoctlRequest { code, file_id, input_len } => {
+                fields.push(field("smb2.ioctl.function", body(4, 4), hex(*code)));
+                fields.push(field("smb2.fid", body(8, 16), file_id.to_string().into()));
+                fields.push(field("smb2.ioctl.in.count", body(28, 4), (*input_len).into()));
+            }
+            Smb2Body::IoctlResponse { code, file_id, output_len } => {
+                fields.push(field("smb2.ioctl.function", body(4, 4), hex(*code)));
+                fields.push(field("smb2.fid", body(8, 16), file_id.to_string().into()));
+                fields.push(field("smb2.ioctl.out.count", body(36, 4), (*output_len).into()));
+            }
+            Smb2Body::Error | Smb2Body::Other => {}
+        }
+        fields
+    }
+}
+
+fn parse_body(header: &Smb2Header, message: &[u8]) -> Result<Smb2Body> {
+    let body = message.get(HEADER_LEN..).unwrap_or_default();
+    let structure_size = u16_at(body, 0)?;
+    if header.is_error(structure_size) {
+        return Ok(Smb2Body::Error);
+    }
+    // Buffers are located by offsets from the start of the header
+    let buffer = |offset: usize, len: usize| bytes_at(message, offset, len);
+
+    Ok(match (header.command, header.is_response()) {
+        (NEGOTIATE, false) => {
+            let count = u16_at(body, 2)? as usize;
+            let dialects = (0..count).map(|i| u16_at(body, 36 + 2 * i)).collect::<Result<Vec<_>>>()?;
+            let ciphers = if dialects.contains(&0x0311) {
+                negotiate_ciphers(message, u32_at(body, 28)? as usize, u16_at(body, 32)? as usize)
+            } else {
+                Vec::new()
+            };
+            Smb2Body::NegotiateRequest {
+                dialects,
+                security_mode: u16_at(body, 4)?,
+                capabilities: u32_at(body, 8)?,
+                client_guid: bytes_at(body, 12, 16)?.try_into()?,
+                ciphers,
+            }
+        }
+        (NEGOTIATE, true) => {
+            let dialect = u16_at(body, 4)?;
+            let ciphers = if dialect == 0x0311 {
+                negotiate_ciphers(message, u32_at(body, 60)? as usize, u16_at(body, 6)? as usize)
+            } else {
+                Vec::new()
+            };
+            Smb2Body::NegotiateResponse {
+                dialect,
+                security_mode: u16_at(body, 2)?,
+                capabilities: u32_at(body, 24)?,
+                server_guid: bytes_at(body, 8, 16)?.try_into()?,
+                max_read_size: u32_at(body, 32)?,
+                max_write_size: u32_at(body, 36)?,
+                ciphers,
+            }
+        }
+        (SESSION_SETUP, false) => {
+            let blob = buffer(u16_at(body, 12)? as usize, u16_at(body, 14)? as usize).unwrap_or_default();
+            Smb2Body::SessionSetupRequest {
+                security_mode: body.get(3).copied().unwrap_or_default(),
+                previous_session_id: u64_at(body, 16)?,
+                ntlm: NtlmAuth::find(blob),
+            }
+        }
+        (SESSION_SETUP, true) => Smb2Body::SessionSetupResponse { session_flags: u16_at(body, 2)? },
+        (TREE_CONNECT, false) => {
+            let (offset, len) = (u16_at(body, 4)? as usize, u16_at(body, 6)? as usize);
+            Smb2Body::TreeConnectRequest { path: utf16_at(message, offset, len)? }
+        }
+        (TREE_CONNECT, true) => Smb2Body::TreeConnectResponse {
+            share_type: bytes_at(body, 2, 1)?[0],
+            share_flags: u32_at(body, 4)?,
+            maximal_access: u32_at(body, 12)?,
+        },
+        (CREATE, false) => {
+            let (offset, len) = (u16_at(body, 44)? as usize, u16_at(body, 46)? as usize);
+            let name = if len == 0 { String::new() } else { utf16_at(message, offset, len)? };
+            Smb2Body::CreateRequest {
+                name,
+                desired_access: u32_at(body, 24)?,
+                disposition: u32_at(body, 36)?,
+                options: u32_at(body, 40)?,
+            }
+        }
+        (CREATE, true) => Smb2Body::CreateResponse {
+            action: u32_at(body, 4)?,
+            end_of_file: u64_at(body, 48)?,
+            attributes: u32_at(body, 56)?,
+            file_id: FileId::parse(body, 64)?,
+        },
+        (CLOSE, false) => Smb2Body::CloseRequest { file_id: FileId::parse(body, 8)? },
+        (CLOSE, true) => Smb2Body::CloseResponse { end_of_file: u64_at(body, 48)? },
+        (READ, false) => Smb2Body::ReadRequest {
+            length: u32_at(body, 4)?,
+            offset: u64_at(body, 8)?,
+            file_id: FileId::parse(body, 16)?,
+        },
+        (READ, true) => {
+            let offset = bytes_at(body, 2, 1)?[0] as usize;
+            let len = u32_at(body, 4)? as usize;
+            Smb2Body::ReadResponse {
+                data: buffer(offset, len)?.to_vec(),
+                remaining: u32_at(body, 8)?,
+            }
+        }
+        (WRITE, false) => {
+            let offset = u16_at(body, 2)? as usize;
+            let len = u32_at(body, 4)? as usize;
+            Smb2Body::WriteRequest {
+                offset: u64_at(body, 8)?,
+                file_id: FileId::parse(body, 16)?,
+                data: buffer(offset, len)?.to_vec(),
+            }
+        }
+        (WRITE, true) => Smb2Body::WriteResponse { count: u32_at(body, 4)? },
+        (IOCTL, false) => Smb2Body::IoctlRequest {
+            code: u32_at(body, 4)?,
+            file_id: FileId::parse(body, 8)?,
+            input_len: u32_at(body, 28)?,
+        },
+        (IOCTL, true) => Smb2Body::IoctlResponse {
+            code: u32_at(body, 4)?,
+            file_id: FileId::parse(body, 8)?,
+            output_len: u32_at(body, 36)?,
+        },
+        _ => Smb2Body::Other,
+    })
+}
+
+/// Ciphers of the encryption capabilities negotiate context (SMB 3.1.1)
+fn negotiate_ciphers(message: &[u8], offset: usize, count: usize) -> Vec<u16> {
+    let mut ciphers = Vec::new();
+    let mut position = offset;
+    for _ in 0..count {
+        let (Ok(context_type), Ok(len)) = (u16_at(message, position), u16_at(message, position + 2)) else {
+            break;
+        };
+        let data = position + 8;
+        if context_type == 2 {
+            let cipher_count = u16_at(message, data).unwrap_or_default() as usize;
+            ciphers.extend((0..cipher_count).filter_map(|i| u16_at(message, data + 2 + 2 * i).ok()));
+        }
+        // Contexts are 8-byte aligned
+        position = (data + len as usize + 7) & !7;
+    }
+    ciphers
+}
+
+/// Header of an encrypted SMB3 message
+#[derive(Debug, Clone)]
+pub struct TransformHeader {
+    pub signature: [u8; 16],
+    pub nonce: [u8; 16],
+    pub original_size: u32,
+    pub flags: u16,
+    pub session_id: u64,
+}
+
+impl TransformHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if bytes_at(data, 0, 4)? != TRANSFORM_PROTOCOL_ID {
+            return Err(anyhow::anyhow!("Invalid SMB3 transform protocol id"));
+        }
+        Ok(Self {
+            signature: bytes_at(data, 4, 16)?.try_into()?,
+            nonce: bytes_at(data, 20, 16)?.try_into()?,
+            original_size: u32_at(data, 36)?,
+            flags: u16_at(data, 42)?,
+            session_id: u64_at(data, 44)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(command: u16, flags: u32, next_command: u32) -> Vec<u8> {
+        let mut header = SMB2_PROTOCOL_ID.to_vec();
+        header.extend(64u16.to_le_bytes());
+        header.resize(12, 0);
+        header.extend(command.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(flags.to_le_bytes());
+        header.extend(next_command.to_le_bytes());
+        header.resize(HEADER_LEN, 0);
+        header
+    }
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    /// TREE_CONNECT request for `\\server\share`
+    fn tree_connect() -> Vec<u8> {
+        let path = utf16("\\\\server\\share");
+        let mut message = header(TREE_CONNECT, 0, 0);
+        message.extend(9u16.to_le_bytes());
+        message.extend(0u16.to_le_bytes());
+        message.extend(72u16.to_le_bytes());
+        message.extend((path.len() as u16).to_le_bytes());
+        message.extend(path);
+        message
+    }
+
+    #[test]
+    fn parses_a_compound() {
+        let mut data = header(ECHO, 0, 72);
+        data.extend([4, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend(tree_connect());
+        let messages = Smb2Message::parse_compound(&data).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(matches!(messages[0].body, Smb2Body::Other));
+        assert_eq!(messages[1].offset, 72);
+        assert_eq!(messages[1].format_info(), "Tree Connect Request Tree: \\\\server\\share");
+    }
+
+    #[test]
+    fn rejects_invalid_next_command_offsets() {
+        for next in [1, 63, 1000] {
+            let mut data = header(ECHO, 0, next);
+            data.extend([4, 0, 0, 0]);
+            assert!(Smb2Message::parse_compound(&data).is_err(), "next command {}", next);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        let message = tree_connect();
+        for len in 0..message.len() {
+            assert!(Smb2Message::parse_compound(&message[..len]).is_err(), "{} bytes", len);
+        }
+        // A path beyond the end of the message
+        let mut message = message;
+        message[HEADER_LEN + 4..HEADER_LEN + 6].copy_from_slice(&0xfff0u16.to_le_bytes());
+        assert!(Smb2Message::parse_compound(&message).is_err());
+        assert!(TransformHeader::parse(&TRANSFORM_PROTOCOL_ID[..]).is_err());
+    }
+
+    #[test]
+    fn finds_the_ntlm_account() {
+        let (domain, user, host) = (utf16("CORP"), utf16("alice"), utf16("WS01"));
+        let mut message = b"NTLMSSP\0".to_vec();
+        message.extend(3u32.to_le_bytes());
+        message.resize(64, 0);
+        let mut position = 64;
+        for (field, value) in [(28, &domain), (36, &user), (44, &host)] {
+            message[field..field + 2].copy_from_slice(&(value.len() as u16).to_le_bytes());
+            message[field + 4..field + 8].copy_from_slice(&(position as u32).to_le_bytes());
+            position += value.len();
+        }
+        message.extend(domain.iter().chain(&user).chain(&host));
+
+        let mut blob = vec![0x60, 0x48, 0x06, 0x06];
+        blob.extend(&message);
+        assert_eq!(NtlmAuth::find(&blob).unwrap().account(), "CORP\\alice");
+        assert!(NtlmAuth::find(&blob[..blob.len() - 1]).is_none());
+        message[8] = 1;
+        assert!(NtlmAuth::find(&message).is_none());
+    }
+
+    #[test]
+    fn reads_negotiated_ciphers() {
+        // Preauth integrity context, then encryption with AES-128-GCM and AES-256-GCM
+        let mut contexts = vec![1, 0, 2, 0, 0, 0, 0, 0, 0xaa, 0xbb];
+        contexts.resize(16, 0);
+        contexts.extend([2, 0, 6, 0, 0, 0, 0, 0, 2, 0, 2, 0, 4, 0]);
+        assert_eq!(negotiate_ciphers(&contexts, 0, 2), [2, 4]);
+        // More contexts announced than present
+        assert_eq!(negotiate_ciphers(&contexts, 0, u16::MAX as usize), [2, 4]);
+        assert!(negotiate_ciphers(&contexts, 1000, 5).is_empty());
+    }
+}
@@ -0,0 +1,353 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use crate::output::safe_file_name;
+use super::smb2::FileId;
+
+/// Largest file rebuilt from READ and WRITE data; bytes beyond it are dropped
+const MAX_FILE_SIZE: u64 = 256 * 1024 * 1024;
+/// File data kept across all files; bytes beyond it are dropped
+const MAX_STORED_BYTES: u64 = 512 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmbOperation {
+    Logon,
+    TreeConnect,
+    Open,
+    Create,
+    /// Open with delete-on-close
+    Delete,
+    Read,
+    Write,
+    Close,
+}
+
+impl fmt::Display for SmbOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operation = match self {
+            SmbOperation::Logon => "logon",
+            SmbOperation::TreeConnect => "tree_connect",
+            SmbOperation::Open => "open",
+            SmbOperation::Create => "create",
+            SmbOperation::Delete => "delete",
+            SmbOperation::Read => "read",
+            SmbOperation::Write => "write",
+            SmbOperation::Close => "close",
+        };
+        f.write_str(operation)
+    }
+}
+
+/// One completed SMB2 operation: who did what on which share and file
+#[derive(Debug, Clone, Serialize)]
+pub struct SmbEvent {
+    /// Time of the response
+    pub at: DateTime<Utc>,
+    pub client: IpAddr,
+    pub server: IpAddr,
+    /// `DOMAIN\user` of the session, when its NTLM authentication was seen
+    pub user: Option<String>,
+    pub share: Option<String>,
+    pub path: Option<String>,
+    pub operation: SmbOperation,
+    pub bytes: Option<u64>,
+    /// NT status of the response, e.g. `STATUS_SUCCESS`
+    pub status: String,
+}
+
+impl SmbEvent {
+    pub fn format_info(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        format!(
+            "{} {:<15} {:<15} {:<20} {:<12} {:<24} {:<32} {:>10} {}",
+            self.at.format("%Y-%m-%d %H:%M:%S%.3f"),
+            self.client,
+            self.server,
+            optional(self.user.clone()),
+            self.operation.to_string(),
+            optional(self.share.clone()),
+            optional(self.path.clone()),
+            optional(self.bytes.map(|bytes| bytes.to_string())),
+            self.status
+        )
+    }
+}
+
+/// A file opened over SMB2, rebuilt from the READ and WRITE data seen
+#[derive(Debug, Clone, Serialize)]
+pub struct SmbFile {
+    pub client: IpAddr,
+    pub server: IpAddr,
+    pub user: Option<String>,
+    pub share: Option<String>,
+    pub path: String,
+    /// End of file reported by the server at open or close
+    pub size: Option<u64>,
+    /// Distinct bytes read or written
+    pub captured: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: Option<DateTime<Utc>>,
+    /// Byte ranges received, by offset; they neither overlap nor touch
+    #[serde(skip)]
+    pub(crate) chunks: BTreeMap<u64, Vec<u8>>,
+}
+
+impl SmbFile {
+    /// Every byte of the file went over the wire in clear
+    pub fn is_complete(&self) -> bool {
+        self.size.is_some_and(|size| size == self.captured && size == self.end())
+    }
+
+    /// Content of the file up to the last byte received, with zeros where
+    /// nothing was seen
+    pub fn data(&self) -> Vec<u8> {
+        let mut data = vec![0; self.end() as usize];
+        for (&offset, chunk) in &self.chunks {
+            data[offset as usize..offset as usize + chunk.len()].copy_from_slice(chunk);
+        }
+        data
+    }
+
+    /// End of the last byte range received
+    fn end(&self) -> u64 {
+        self.chunks.last_key_value().map_or(0, |(&offset, chunk)| offset + chunk.len() as u64)
+    }
+
+    /// Last component of the path with characters unsafe in file names replaced
+    pub fn file_name(&self) -> String {
+        let base = self.path.rsplit(['/', '\\']).next().unwrap_or_default();
+        safe_file_name(base, "smb-object")
+    }
+
+    /// Stores `data` at `offset` unless that takes more than `budget` new
+    /// bytes; returns the bytes added
+    fn add(&mut self, offset: u64, data: &[u8], budget: u64) -> u64 {
+        let end = offset.saturating_add(data.len() as u64).min(MAX_FILE_SIZE);
+        if offset >= end {
+            return 0;
+        }
+        let data = &data[..(end - offset) as usize];
+
+        // The range is merged with the chunks it overlaps or touches
+        let touching: Vec<u64> = self
+            .chunks
+            .range(..=end)
+            .rev()
+            .take_while(|(&start, chunk)| start + chunk.len() as u64 >= offset)
+            .map(|(&start, _)| start)
+            .collect();
+        let start = touching.last().map_or(offset, |&first| first.min(offset));
+        let stop = touching.first().map_or(end, |&last| end.max(last + self.chunks[&last].len() as u64));
+        let kept: u64 = touching.iter().map(|start| self.chunks[start].len() as u64).sum();
+        let added = stop - start - kept;
+        if added > budget {
+            return 0;
+        }
+
+        // Appending to a chunk extends it in place
+        let mut merged = if touching.last().is_some_and(|&first| first <= offset) {
+            self.chunks.remove(&start).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        merged.resize((stop - start) as usize, 0);
+        for chunk_start in touching {
+            if let Some(chunk) = self.chunks.remove(&chunk_start) {
+                let at = (chunk_start - start) as usize;
+                merged[at..at + chunk.len()].copy_from_slice(&chunk);
+            }
+        }
+        let at = (offset - start) as usize;
+        merged[at..at + data.len()].copy_from_slice(data);
+        self.chunks.insert(start, merged);
+        self.captured += added;
+        added
+    }
+
+    pub fn format_info(&self) -> String {
+        let share = self.share.as_deref().unwrap_or_default();
+        let mut info = format!("{}\\{} on {}", share, self.path, self.server);
+        if let Some(user) = &self.user {
+            info.push_str(&format!(" by {}", user));
+        }
+        info.push_str(&format!(
+            " {} bytes read, {} written",
+            self.bytes_read, self.bytes_written
+        ));
+        if !self.is_complete() {
+            match self.size {
+                Some(size) => info.push_str(&format!(" [incomplete: {} of {} bytes]", self.captured, size)),
+                None => info.push_str(" [incomplete]"),
+            }
+        }
+        info
+    }
+}
+
+/// Authenticated sessions and open files of one SMB2 connection
+#[derive(Default)]
+pub(crate) struct ConnectionFiles {
+    /// Account of every authenticated session
+    users: HashMap<u64, String>,
+    open: HashMap<FileId, SmbFile>,
+}
+
+impl ConnectionFiles {
+    pub fn user(&self, session_id: u64) -> Option<String> {
+        self.users.get(&session_id).cloned()
+    }
+
+    pub fn logon(&mut self, session_id: u64, user: String) {
+        self.users.insert(session_id, user);
+    }
+
+    /// Files open with some data seen
+    pub fn open_files(&self) -> impl Iterator<Item = &SmbFile> {
+        self.open.values().filter(|file| !file.chunks.is_empty())
+    }
+}
+
+/// Timeline and file data of every SMB2 connection
+#[derive(Default)]
+pub(crate) struct SmbActivity {
+    events: Vec<SmbEvent>,
+    /// Files closed with some data seen
+    files: Vec<SmbFile>,
+    /// Bytes of file data kept, open files included
+    stored: u64,
+}
+
+impl SmbActivity {
+    pub fn record(&mut self, event: SmbEvent) {
+        self.events.push(event);
+    }
+
+    pub fn open(&mut self, connection: &mut ConnectionFiles, file_id: FileId, file: SmbFile) {
+        if let Some(previous) = connection.open.insert(file_id, file) {
+            self.keep(previous);
+        }
+    }
+
+    pub fn read(&mut self, connection: &mut ConnectionFiles, file_id: FileId, offset: u64, data: &[u8]) {
+        if let Some(file) = connection.open.get_mut(&file_id) {
+            file.bytes_read += data.len() as u64;
+            self.stored += file.add(offset, data, MAX_STORED_BYTES - self.stored);
+        }
+    }
+
+    pub fn write(&mut self, connection: &mut ConnectionFiles, file_id: FileId, offset: u64, data: &[u8]) {
+        if let Some(file) = connection.open.get_mut(&file_id) {
+            file.bytes_written += data.len() as u64;
+            self.stored += file.add(offset, data, MAX_STORED_BYTES - self.stored);
+        }
+    }
+
+    pub fn close(
+        &mut self,
+        connection: &mut ConnectionFiles,
+        file_id: FileId,
+        at: DateTime<Utc>,
+        end_of_file: Option<u64>,
+    ) {
+        if let Some(mut file) = connection.open.remove(&file_id) {
+            file.closed_at = Some(at);
+            file.size = end_of_file.or(file.size);
+            self.keep(file);
+        }
+    }
+
+    /// Keeps the data of the files left open by a connection that ended
+    pub fn end(&mut self, connection: ConnectionFiles) {
+        for file in connection.open.into_values() {
+            self.keep(file);
+        }
+    }
+
+    fn keep(&mut self, file: SmbFile) {
+        if !file.chunks.is_empty() {
+            self.files.push(file);
+        }
+    }
+
+    pub fn events(&self) -> Vec<SmbEvent> {
+        self.events.clone()
+    }
+
+    /// Files with data, those still `open` included, in the order they were opened
+    pub fn files<'a>(&'a self, open: impl Iterator<Item = &'a SmbFile>) -> Vec<SmbFile> {
+        let mut files = self.files.clone();
+        files.extend(open.cloned());
+        files.sort_by_key(|file| file.opened_at);
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: Option<u64>) -> SmbFile {
+        SmbFile {
+            client: "10.0.0.1".parse().unwrap(),
+            server: "10.0.0.2".parse().unwrap(),
+            user: None,
+            share: None,
+            path: path.to_string(),
+            size,
+            captured: 0,
+            bytes_read: 0,
+            bytes_written: 0,
+            opened_at: Utc::now(),
+            closed_at: None,
+            chunks: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn merges_ranges() {
+        let mut file = file("a.txt", Some(10));
+        assert_eq!(file.add(4, b"efg", u64::MAX), 3);
+        assert_eq!(file.add(0, b"ab", u64::MAX), 2);
+        assert_eq!(file.chunks.len(), 2);
+        assert_eq!(file.add(1, b"BCD", u64::MAX), 2);
+        assert_eq!(file.chunks.len(), 1);
+        assert_eq!(file.add(7, b"hij", u64::MAX), 3);
+        assert_eq!(file.data(), b"aBCDefghij");
+        assert_eq!(file.captured, 10);
+        assert!(file.is_complete());
+        assert_eq!(file.add(2, b"cd", u64::MAX), 0);
+        assert_eq!(file.data(), b"aBcdefghij");
+    }
+
+    #[test]
+    fn stores_only_the_bytes_received() {
+        let mut file = file("a.bin", None);
+        file.add(MAX_FILE_SIZE - 2, b"tail", u64::MAX);
+        file.add(0, b"head", u64::MAX);
+        assert_eq!(file.captured, 6);
+        assert_eq!(file.chunks.values().map(Vec::len).sum::<usize>(), 6);
+        assert_eq!(file.chunks[&(MAX_FILE_SIZE - 2)], b"ta");
+        assert!(!file.is_complete());
+    }
+
+    #[test]
+    fn keeps_to_the_budget() {
+        let mut file = file("a.bin", None);
+        assert_eq!(file.add(0, b"abcd", 4), 4);
+        assert_eq!(file.add(2, b"cdef", 1), 0);
+        assert_eq!(file.add(2, b"cdef", 2), 2);
+        assert_eq!(file.data(), b"abcdef");
+    }
+
+    #[test]
+    fn names_files_after_the_last_path_component() {
+        assert_eq!(file("dir\\sub\\report.docx", None).file_name(), "report.docx");
+        assert_eq!(file("dir\\..", None).file_name(), "smb-object");
+        assert_eq!(file("a:b", None).file_name(), "a_b");
+    }
+}
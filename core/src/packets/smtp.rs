@@ -0,0 +1,462 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::flow::{FlowKey, FlowTable};
+use super::mail::{self, Connection, MailMessage, MailStore, SaslExchange, MAX_MESSAGE_SIZE};
+
+/// Message content being sent
+enum Transfer {
+    /// After DATA, until the line with a single dot
+    Data,
+    /// BDAT chunk with the bytes left and whether it is the last one
+    Chunk { remaining: usize, last: bool },
+}
+
+struct Session {
+    conn: Connection,
+    /// Multi-line reply being read
+    reply: Option<(u16, Vec<String>)>,
+    /// Commands waiting for their reply, oldest first (PIPELINING)
+    pending: VecDeque<String>,
+    mail_from: Option<String>,
+    recipients: Vec<String>,
+    transfer: Option<Transfer>,
+    content: Vec<u8>,
+    auth: Option<SaslExchange>,
+}
+
+impl Session {
+    fn new(client: &FlowKey) -> Self {
+        Self {
+            conn: Connection::new(client),
+            reply: None,
+            pending: VecDeque::new(),
+            mail_from: None,
+            recipients: Vec::new(),
+            transfer: None,
+            content: Vec::new(),
+            auth: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.mail_from = None;
+        self.recipients.clear();
+        self.transfer = None;
+        self.content.clear();
+    }
+
+    fn read_client(&mut self, ctx: &mut DissectContext, info: &mut Vec<String>) -> Vec<MailMessage> {
+        let mut messages = Vec::new();
+        loop {
+            if let Some(Transfer::Chunk { remaining, last }) = &mut self.transfer {
+                if *remaining > 0 {
+                    let chunk = self.conn.client_stream.take(*remaining);
+                    if chunk.is_empty() {
+                        break;
+                    }
+                    *remaining -= chunk.len();
+                    if self.content.len() + chunk.len() <= MAX_MESSAGE_SIZE {
+                        self.content.extend_from_slice(&chunk);
+                    }
+                    continue;
+                }
+                let last = *last;
+                self.transfer = None;
+                if last {
+                    messages.push(self.finish(ctx));
+                }
+                continue;
+            }
+
+            let Some(raw) = self.conn.client_stream.raw_line() else {
+                break;
+            };
+            if matches!(self.transfer, Some(Transfer::Data)) {
+                if mail::append_dot_stuffed(&mut self.content, &raw) {
+                    self.transfer = None;
+                    messages.push(self.finish(ctx));
+                }
+                continue;
+            }
+
+            let line = mail::trim_line(&raw);
+            if let Some(auth) = &mut self.auth {
+                match auth.response(&line) {
+                    Some(username) => {
+                        ctx.add_field("smtp.auth.username", username.clone());
+                        info.push(format!("C: User: {}", username));
+                    }
+                    None if line == "*" => info.push("C: * (cancel)".to_string()),
+                    None => info.push(format!("C: {} response", auth.mechanism)),
+                }
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            self.command(&line, ctx, info);
+        }
+        messages
+    }
+
+    fn command(&mut self, line: &str, ctx: &mut DissectContext, info: &mut Vec<String>) {
+        let (command, parameter) = match line.trim().split_once(' ') {
+            Some((command, parameter)) => (command.to_ascii_uppercase(), parameter.trim()),
+            None => (line.trim().to_ascii_uppercase(), ""),
+        };
+        ctx.add_field("smtp.req.command", command.clone());
+        match command.as_str() {
+            "MAIL" => {
+                self.reset();
+                self.mail_from = path(parameter, "FROM:");
+                if let Some(from) = &self.mail_from {
+                    ctx.add_field("smtp.mail_from", from.clone());
+                }
+            }
+            "RCPT" => {
+                if let Some(to) = path(parameter, "TO:") {
+                    ctx.add_field("smtp.rcpt_to", to.clone());
+                    self.recipients.push(to);
+                }
+            }
+            "DATA" => {
+                self.transfer = Some(Transfer::Data);
+                self.content.clear();
+            }
+            "BDAT" => {
+                let mut parts = parameter.split_whitespace();
+                if let Some(size) = parts.next().and_then(|size| size.parse().ok()) {
+                    let last = parts.next().is_some_and(|last| last.eq_ignore_ascii_case("LAST"));
+                    self.transfer = Some(Transfer::Chunk { remaining: size, last });
+                }
+            }
+            "AUTH" => {
+                let mut parts = parameter.split_whitespace();
+                let mut auth = SaslExchange::new(parts.next().unwrap_or_default());
+                ctx.add_field("smtp.auth.mechanism", auth.mechanism.clone());
+                let mut summary = format!("C: AUTH {}", auth.mechanism);
+                // The initial response carries credentials and is not shown
+                if let Some(username) = parts.next().and_then(|initial| auth.response(initial)) {
+                    ctx.add_field("smtp.auth.username", username.clone());
+                    summary.push_str(&format!(" (User: {})", username));
+                }
+                self.auth = Some(auth);
+                self.pending.push_back(command);
+                info.push(summary);
+                return;
+            }
+            "HELO" | "EHLO" | "RSET" => self.reset(),
+            _ => {}
+        }
+        if !parameter.is_empty() {
+            ctx.add_field("smtp.req.parameter", parameter.to_string());
+        }
+        info.push(format!("C: {} {}", command, parameter).trim_end().to_string());
+        self.pending.push_back(command);
+    }
+
+    fn finish(&mut self, ctx: &DissectContext) -> MailMessage {
+        let data = std::mem::take(&mut self.content);
+        let mut message = MailMessage::new("SMTP", self.conn.client, self.conn.server, data, ctx.captured_at);
+        message.mail_from = self.mail_from.clone();
+        message.recipients = self.recipients.clone();
+        message
+    }
+
+    fn read_server(&mut self, ctx: &mut DissectContext, info: &mut Vec<String>) {
+        while let Some(line) = self.conn.server_stream.line() {
+            let code = line
+                .get(..3)
+                .filter(|code| code.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|code| code.parse::<u16>().ok());
+            let text = line.get(4..).unwrap_or_default().to_string();
+            let Some(code) = code else {
+                if let Some((_, lines)) = &mut self.reply {
+                    lines.push(line);
+                }
+                continue;
+            };
+            if line.as_bytes().get(3) == Some(&b'-') {
+                self.reply.get_or_insert_with(|| (code, Vec::new())).1.push(text);
+                continue;
+            }
+            let mut lines = self.reply.take().map(|(_, lines)| lines).unwrap_or_default();
+            lines.push(text);
+            self.reply(code, lines, ctx, info);
+            if self.conn.tls {
+                break;
+            }
+        }
+    }
+
+    fn reply(&mut self, code: u16, lines: Vec<String>, ctx: &mut DissectContext, info: &mut Vec<String>) {
+        ctx.add_field("smtp.response.code", code);
+        for line in &lines {
+            ctx.add_field("smtp.rsp.parameter", line.clone());
+        }
+        info.push(format!("S: {} {}", code, lines[0]).trim_end().to_string());
+
+        // 334 and 354 ask the client to go on with the same command
+        let command = match code {
+            334 | 354 => self.pending.front().cloned(),
+            _ => self.pending.pop_front(),
+        };
+        match (command.as_deref(), code) {
+            (Some("EHLO"), 250) => {
+                let offered = lines.iter().find_map(|line| {
+                    let upper = line.to_ascii_uppercase();
+                    upper.strip_prefix("AUTH").filter(|rest| rest.starts_with([' ', '='])).map(|rest| rest[1..].to_string())
+                });
+                for mechanism in offered.iter().flat_map(|mechanisms| mechanisms.split_whitespace()) {
+                    ctx.add_field("smtp.auth.offered", mechanism.to_string());
+                }
+            }
+            (Some("STARTTLS"), 220) => {
+                ctx.add_field("smtp.starttls", true);
+                self.conn.start_tls();
+            }
+            (Some("AUTH"), 334) => {}
+            (Some("AUTH"), _) => {
+                self.auth = None;
+            }
+            (Some("DATA"), 354) => {}
+            (Some("DATA"), _) if matches!(self.transfer, Some(Transfer::Data)) => {
+                // The server refused the content
+                self.transfer = None;
+                self.content.clear();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `FROM:<address> params` or `TO:<address> params`; the null reverse-path is `<>`
+fn path(parameter: &str, prefix: &str) -> Option<String> {
+    let rest = parameter
+        .get(..prefix.len())
+        .filter(|head| head.eq_ignore_ascii_case(prefix))
+        .map(|_| parameter[prefix.len()..].trim_start())?;
+    let address = match rest.strip_prefix('<') {
+        Some(rest) => rest.split('>').next().unwrap_or_default(),
+        None => rest.split_whitespace().next().unwrap_or_default(),
+    };
+    Some(if address.is_empty() { "<>".to_string() } else { address.to_string() })
+}
+
+/// SMTP commands and replies over reassembled streams, with the messages they carry
+pub struct SmtpProcessor {
+    sessions: Mutex<FlowTable<FlowKey, Session>>,
+    messages: Arc<Mutex<MailStore>>,
+}
+
+impl SmtpProcessor {
+    pub(crate) fn new(messages: Arc<Mutex<MailStore>>) -> Self {
+        Self {
+            sessions: Mutex::new(FlowTable::new()),
+            messages,
+        }
+    }
+}
+
+impl Dissector for SmtpProcessor {
+    fn name(&self) -> &'static str {
+        "SMTP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let flow = ctx.flow.ok_or_else(|| anyhow::anyhow!("SMTP outside of a TCP flow"))?;
+        let key = flow.canonical();
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.entry(key, ctx.captured_at, || Session::new(&ctx.client_flow.unwrap_or(flow)));
+        let from_client = session.conn.is_client(&flow);
+        let tls = session.conn.tls;
+        let closed = ctx.flow_closed;
+        if tls {
+            if closed {
+                sessions.remove(&key);
+            }
+            drop(sessions);
+            if data.is_empty() {
+                anyhow::bail!("No SMTP payload");
+            }
+            return mail::dissect_tls(data, ctx);
+        }
+
+        let mut info = Vec::new();
+        let mut messages = Vec::new();
+        let sequence = ctx.tcp_sequence.unwrap_or_default();
+        if from_client {
+            if !session.conn.client_stream.push(sequence, data) {
+                session.transfer = None;
+                session.content.clear();
+            }
+            messages = session.read_client(ctx, &mut info);
+        } else {
+            if !session.conn.server_stream.push(sequence, data) {
+                session.reply = None;
+            }
+            session.read_server(ctx, &mut info);
+        }
+        if session.transfer.is_some() && info.is_empty() && messages.is_empty() && !data.is_empty() {
+            info.push(format!("C: DATA fragment, {} bytes", data.len()));
+        }
+        if closed {
+            sessions.remove(&key);
+        }
+        drop(sessions);
+        if data.is_empty() {
+            anyhow::bail!("No SMTP payload");
+        }
+
+        for message in &messages {
+            message.add_fields(ctx);
+            info.push(format!("C: {}", message.summary()));
+        }
+        self.messages.lock().unwrap().extend(messages);
+        let info = match info.len() {
+            0 => "Continuation".to_string(),
+            _ => info.join(" | "),
+        };
+        Ok(Dissection::new("SMTP", info))
+    }
+
+    /// Heuristic probe: the greeting of an SMTP server or of a client
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        let line_end = payload.iter().position(|&b| b == b'\n').unwrap_or(payload.len());
+        let line = String::from_utf8_lossy(&payload[..line_end]).to_ascii_uppercase();
+        if line.starts_with("220") && line.contains("SMTP") {
+            return Some(ProbeResult::new(85, "SMTP server greeting"));
+        }
+        if line.starts_with("EHLO ") || line.starts_with("HELO ") {
+            return Some(ProbeResult::new(70, "SMTP client greeting"));
+        }
+        if line.starts_with("220 ") || line.starts_with("220-") {
+            return Some(ProbeResult::new(45, "220 greeting"));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::tcp::TcpFlags;
+    use super::super::dissector::{testing, DissectorRegistry};
+    use super::super::mail::{testing::Conversation, MailProcessor};
+    use super::super::PacketProcessor;
+    use super::super::tree::FieldValue;
+
+    fn strings<'a>(fields: &'a [super::super::tree::Field], name: &str) -> Vec<&'a str> {
+        fields
+            .iter()
+            .filter(|field| field.name == name)
+            .filter_map(|field| match &field.value {
+                FieldValue::Text(value) => Some(value.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_envelope_paths() {
+        assert_eq!(path("FROM:<alice@example.com> SIZE=10", "FROM:").as_deref(), Some("alice@example.com"));
+        assert_eq!(path("from: <>", "FROM:").as_deref(), Some("<>"));
+        assert_eq!(path("to:bob@example.com", "TO:").as_deref(), Some("bob@example.com"));
+        assert_eq!(path("<bob@example.com>", "TO:"), None);
+    }
+
+    #[test]
+    fn extracts_sent_messages() {
+        let mail = MailProcessor::new();
+        let registry = DissectorRegistry::new();
+        let smtp = mail.smtp_dissector();
+        let mut conversation = Conversation::new(&registry, &smtp, 25);
+        conversation.server(b"220 mail.example.com ESMTP\r\n");
+        conversation.client(b"EHLO client\r\n");
+        conversation.server(b"250 mail.example.com\r\n");
+        let (_, fields) = conversation.client(b"MAIL FROM:<alice@example.com> SIZE=64\r\n");
+        assert_eq!(strings(&fields, "smtp.mail_from"), ["alice@example.com"]);
+        conversation.server(b"250 OK\r\n");
+        let (_, fields) = conversation.client(b"RCPT TO:<bob@example.com>\r\nRCPT TO:<carol@example.com>\r\n");
+        assert_eq!(strings(&fields, "smtp.rcpt_to"), ["bob@example.com", "carol@example.com"]);
+        conversation.server(b"250 OK\r\n250 OK\r\n");
+        conversation.client(b"DATA\r\n");
+        conversation.server(b"354 Go ahead\r\n");
+
+        let (dissection, _) = conversation.client(b"Subject: hi\r\n\r\n..leading dot\r\nbody\r\n.");
+        assert_eq!(dissection.info, "C: DATA fragment, 37 bytes");
+        assert!(mail.messages().is_empty());
+        let (dissection, fields) = conversation.client(b"\r\nQUIT\r\n");
+        assert_eq!(dissection.info, "C: QUIT | C: Message, 35 bytes, \"hi\"");
+        assert_eq!(testing::field(&fields, "imf.subject"), Some(&FieldValue::Text("hi".to_string())));
+
+        let messages = mail.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data, b"Subject: hi\r\n\r\n.leading dot\r\nbody\r\n");
+        assert_eq!(messages[0].mail_from.as_deref(), Some("alice@example.com"));
+        assert_eq!(messages[0].recipients, ["bob@example.com", "carol@example.com"]);
+        assert_eq!(messages[0].file_name(), "hi.eml");
+    }
+
+    #[test]
+    fn extracts_auth_mechanisms_and_users() {
+        let mail = MailProcessor::new();
+        let registry = DissectorRegistry::new();
+        let smtp = mail.smtp_dissector();
+        let mut conversation = Conversation::new(&registry, &smtp, 587);
+        conversation.client(b"EHLO client\r\n");
+        let (_, fields) = conversation.server(b"250-mail.example.com\r\n250-AUTH PLAIN LOGIN\r\n250 OK\r\n");
+        assert_eq!(strings(&fields, "smtp.auth.offered"), ["PLAIN", "LOGIN"]);
+
+        let (dissection, fields) = conversation.client(b"AUTH PLAIN AGFsaWNlAHNlY3JldA==\r\n");
+        assert_eq!(dissection.info, "C: AUTH PLAIN (User: alice)");
+        assert_eq!(strings(&fields, "smtp.auth.mechanism"), ["PLAIN"]);
+        assert_eq!(strings(&fields, "smtp.auth.username"), ["alice"]);
+        conversation.server(b"535 Authentication failed\r\n");
+
+        conversation.client(b"AUTH LOGIN\r\n");
+        conversation.server(b"334 VXNlcm5hbWU6\r\n");
+        let (dissection, fields) = conversation.client(b"YWxpY2U=\r\n");
+        assert_eq!(dissection.info, "C: User: alice");
+        assert_eq!(strings(&fields, "smtp.auth.username"), ["alice"]);
+        conversation.server(b"334 UGFzc3dvcmQ6\r\n");
+        // The password is neither shown nor kept
+        let (dissection, fields) = conversation.client(b"c2VjcmV0\r\n");
+        assert_eq!(dissection.info, "C: LOGIN response");
+        assert!(fields.iter().all(|field| !field.name.starts_with("smtp.auth")));
+        conversation.server(b"235 Authenticated\r\n");
+        let (dissection, _) = conversation.client(b"QUIT\r\n");
+        assert_eq!(dissection.info, "C: QUIT");
+    }
+
+    #[test]
+    fn hands_over_to_tls_after_starttls() {
+        let processor = PacketProcessor::new(false);
+        let smtp = processor.mail_processor.smtp_dissector();
+        let mut conversation = Conversation::new(&processor.registry, &smtp, 25);
+        conversation.client(b"STARTTLS\r\n");
+        let (dissection, fields) = conversation.server(b"220 Ready to start TLS\r\n");
+        assert_eq!(dissection.protocol, "SMTP");
+        assert_eq!(testing::field(&fields, "smtp.starttls"), Some(&FieldValue::Bool(true)));
+
+        let alert = [0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28];
+        let (dissection, _) = conversation.client(&alert);
+        assert_eq!(dissection.protocol, "TLS");
+    }
+
+    #[test]
+    fn forgets_sessions_once_closed() {
+        let mail = MailProcessor::new();
+        let registry = DissectorRegistry::new();
+        let smtp = mail.smtp_dissector();
+        let mut conversation = Conversation::new(&registry, &smtp, 25);
+        conversation.client(b"QUIT\r\n");
+        let _ = conversation.send(true, b"", TcpFlags::FIN | TcpFlags::ACK);
+        assert_eq!(smtp.sessions.lock().unwrap().len(), 1);
+        let _ = conversation.send(false, b"", TcpFlags::FIN | TcpFlags::ACK);
+        assert_eq!(smtp.sessions.lock().unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,496 @@
+use anyhow::Result;
+use std::net::Ipv4Addr;
+use super::bytes::to_hex;
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::tree::FieldValue;
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const IP_ADDRESS: u8 = 0x40;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIME_TICKS: u8 = 0x43;
+const OPAQUE: u8 = 0x44;
+const COUNTER64: u8 = 0x46;
+
+const GET_BULK_REQUEST: u8 = 0xa5;
+const TRAP_V1: u8 = 0xa4;
+
+/// Variable bindings listed in the packet summary
+const MAX_INFO_NAMES: usize = 8;
+
+/// A BER type-length-value with the offset of its value in the dissected data
+#[derive(Clone, Copy)]
+struct Tlv<'a> {
+    tag: u8,
+    offset: usize,
+    value: &'a [u8],
+}
+
+impl Tlv<'_> {
+    fn integer(&self) -> Result<i64> {
+        if self.tag != INTEGER || self.value.is_empty() || self.value.len() > 8 {
+            anyhow::bail!("Expected a BER INTEGER, found tag 0x{:02x} of {} bytes", self.tag, self.value.len());
+        }
+        Ok(signed(self.value))
+    }
+
+    fn octets(&self) -> Result<&[u8]> {
+        if self.tag != OCTET_STRING {
+            anyhow::bail!("Expected a BER OCTET STRING, found tag 0x{:02x}", self.tag);
+        }
+        Ok(self.value)
+    }
+
+    /// Reader over the elements of a constructed value
+    fn elements(&self) -> BerReader<'_> {
+        BerReader { data: self.value, position: 0, base: self.offset }
+    }
+}
+
+/// Definite-length BER, as SNMP uses it
+struct BerReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    /// Offset of `data` in the dissected data
+    base: usize,
+}
+
+impl<'a> BerReader<'a> {
+    fn new(data: &'a [u8], base: usize) -> Self {
+        Self { data, position: 0, base }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn read(&mut self) -> Result<Tlv<'a>> {
+        let data = &self.data[self.position..];
+        let (&tag, rest) = data.split_first().ok_or_else(|| anyhow::anyhow!("BER element missing"))?;
+        if tag & 0x1f == 0x1f {
+            anyhow::bail!("BER tag 0x{:02x} with a multi-byte number is not used by SNMP", tag);
+        }
+        let (&first, rest) = rest.split_first().ok_or_else(|| anyhow::anyhow!("BER length missing"))?;
+        let (len, header) = match first {
+            0..=0x7f => (first as usize, 2),
+            0x81..=0x84 => {
+                let count = (first & 0x7f) as usize;
+                let bytes = rest.get(..count).ok_or_else(|| anyhow::anyhow!("BER length truncated"))?;
+                (bytes.iter().fold(0usize, |len, &b| len << 8 | b as usize), 2 + count)
+            }
+            _ => anyhow::bail!("Unsupported BER length 0x{:02x}", first),
+        };
+        let value = data
+            .get(header..header + len)
+            .ok_or_else(|| anyhow::anyhow!("BER element of {} bytes runs past the message", len))?;
+        let offset = self.base + self.position + header;
+        self.position += header + len;
+        Ok(Tlv { tag, offset, value })
+    }
+
+    fn expect(&mut self, tag: u8, what: &str) -> Result<Tlv<'a>> {
+        let tlv = self.read()?;
+        if tlv.tag != tag {
+            anyhow::bail!("Expected {} (tag 0x{:02x}), found tag 0x{:02x}", what, tag, tlv.tag);
+        }
+        Ok(tlv)
+    }
+}
+
+fn signed(bytes: &[u8]) -> i64 {
+    let initial = if bytes.first().is_some_and(|&b| b & 0x80 != 0) { -1 } else { 0 };
+    bytes.iter().fold(initial, |value, &b| value << 8 | b as i64)
+}
+
+fn unsigned(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, &b| value << 8 | b as u64)
+}
+
+/// Dotted form of a BER object identifier
+fn oid(bytes: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut value: u64 = 0;
+    for &b in bytes {
+        value = value << 7 | (b & 0x7f) as u64;
+        if b & 0x80 != 0 {
+            continue;
+        }
+        if arcs.is_empty() {
+            // The first subidentifier packs the first two arcs
+            let first = (value / 40).min(2);
+            arcs.push(first);
+            arcs.push(value - first * 40);
+        } else {
+            arcs.push(value);
+        }
+        value = 0;
+    }
+    arcs.iter().map(u64::to_string).collect::<Vec<_>>().join(".")
+}
+
+fn format_octets(bytes: &[u8]) -> String {
+    let text = std::str::from_utf8(bytes).ok().filter(|text| {
+        !text.is_empty() && text.chars().all(|c| !c.is_control() || matches!(c, '\r' | '\n' | '\t'))
+    });
+    match text {
+        Some(text) => text.to_string(),
+        None => to_hex(bytes),
+    }
+}
+
+/// Value of a variable binding in the syntax of its type
+fn format_value(value: &Tlv) -> String {
+    match value.tag {
+        INTEGER => signed(value.value).to_string(),
+        OCTET_STRING => format_octets(value.value),
+        NULL => "NULL".to_string(),
+        OBJECT_IDENTIFIER => oid(value.value),
+        IP_ADDRESS if value.value.len() == 4 => {
+            Ipv4Addr::new(value.value[0], value.value[1], value.value[2], value.value[3]).to_string()
+        }
+        COUNTER32 | GAUGE32 | COUNTER64 => unsigned(value.value).to_string(),
+        TIME_TICKS => {
+            let ticks = unsigned(value.value);
+            let seconds = ticks / 100;
+            format!(
+                "{} ({}d {:02}:{:02}:{:02}.{:02})",
+                ticks,
+                seconds / 86400,
+                seconds / 3600 % 24,
+                seconds / 60 % 60,
+                seconds % 60,
+                ticks % 100
+            )
+        }
+        OPAQUE => to_hex(value.value),
+        0x80 => "noSuchObject".to_string(),
+        0x81 => "noSuchInstance".to_string(),
+        0x82 => "endOfMibView".to_string(),
+        tag => format!("[tag 0x{:02x}] {}", tag, to_hex(value.value)),
+    }
+}
+
+fn version_name(version: i64) -> String {
+    match version {
+        0 => "v1".to_string(),
+        1 => "v2c".to_string(),
+        3 => "v3".to_string(),
+        version => format!("unknown ({})", version),
+    }
+}
+
+fn pdu_name(tag: u8) -> Option<&'static str> {
+    let name = match tag {
+        0xa0 => "get-request",
+        0xa1 => "get-next-request",
+        0xa2 => "get-response",
+        0xa3 => "set-request",
+        TRAP_V1 => "trap",
+        GET_BULK_REQUEST => "getBulkRequest",
+        0xa6 => "informRequest",
+        0xa7 => "snmpV2-trap",
+        0xa8 => "report",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn error_status_name(status: i64) -> String {
+    let name = match status {
+        0 => "noError",
+        1 => "tooBig",
+        2 => "noSuchName",
+        3 => "badValue",
+        4 => "readOnly",
+        5 => "genErr",
+        6 => "noAccess",
+        7 => "wrongType",
+        8 => "wrongLength",
+        9 => "wrongEncoding",
+        10 => "wrongValue",
+        11 => "noCreation",
+        12 => "inconsistentValue",
+        13 => "resourceUnavailable",
+        14 => "commitFailed",
+        15 => "undoFailed",
+        16 => "authorizationError",
+        17 => "notWritable",
+        18 => "inconsistentName",
+        _ => return format!("unknown ({})", status),
+    };
+    name.to_string()
+}
+
+fn generic_trap_name(trap: i64) -> String {
+    let name = match trap {
+        0 => "coldStart",
+        1 => "warmStart",
+        2 => "linkDown",
+        3 => "linkUp",
+        4 => "authenticationFailure",
+        5 => "egpNeighborLoss",
+        6 => "enterpriseSpecific",
+        _ => return format!("unknown ({})", trap),
+    };
+    name.to_string()
+}
+
+/// SNMP v1, v2c and v3 (RFC 1157, 3416, 3412): community or USM security
+/// parameters, PDUs and their variable bindings
+#[derive(Default)]
+pub struct SnmpProcessor;
+
+impl SnmpProcessor {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn dissect_pdu(&self, pdu: Tlv, ctx: &mut DissectContext, info: &mut Vec<String>) -> Result<()> {
+        let name = pdu_name(pdu.tag).ok_or_else(|| anyhow::anyhow!("Unknown SNMP PDU type 0x{:02x}", pdu.tag))?;
+        ctx.add_field_at("snmp.data", pdu.offset, pdu.value.len(), name);
+        info.push(name.to_string());
+        let mut fields = pdu.elements();
+
+        if pdu.tag == TRAP_V1 {
+            let enterprise = fields.expect(OBJECT_IDENTIFIER, "enterprise")?;
+            let agent = fields.expect(IP_ADDRESS, "agent-addr")?;
+            let generic = fields.read()?;
+            let specific = fields.read()?;
+            let timestamp = fields.expect(TIME_TICKS, "time-stamp")?;
+            ctx.add_field_at("snmp.enterprise", enterprise.offset, enterprise.value.len(), oid(enterprise.value));
+            ctx.add_field_at("snmp.agent_addr", agent.offset, agent.value.len(), format_value(&agent));
+            let generic_name = generic_trap_name(generic.integer()?);
+            ctx.add_field_at("snmp.generic_trap", generic.offset, generic.value.len(), generic_name.clone());
+            ctx.add_field_at("snmp.specific_trap", specific.offset, specific.value.len(), specific.integer()?);
+            ctx.add_field_at("snmp.time_stamp", timestamp.offset, timestamp.value.len(), unsigned(timestamp.value));
+            info.push(oid(enterprise.value));
+            info.push(generic_name);
+        } else {
+            let request_id = fields.read()?;
+            let error_status = fields.read()?;
+            let error_index = fields.read()?;
+            ctx.add_field_at("snmp.request_id", request_id.offset, request_id.value.len(), request_id.integer()?);
+            if pdu.tag == GET_BULK_REQUEST {
+                ctx.add_field_at("snmp.non_repeaters", error_status.offset, error_status.value.len(), error_status.integer()?);
+                ctx.add_field_at("snmp.max_repetitions", error_index.offset, error_index.value.len(), error_index.integer()?);
+            } else {
+                let status = error_status.integer()?;
+                let index = error_index.integer()?;
+                ctx.add_field_at("snmp.error_status", error_status.offset, error_status.value.len(), error_status_name(status));
+                ctx.add_field_at("snmp.error_index", error_index.offset, error_index.value.len(), index);
+                if status != 0 {
+                    info.push(format!("{} at index {}", error_status_name(status), index));
+                }
+            }
+        }
+
+        let list = fields.expect(SEQUENCE, "variable-bindings")?;
+        let mut bindings = list.elements();
+        let mut names = Vec::new();
+        while !bindings.is_empty() {
+            let binding = bindings.expect(SEQUENCE, "VarBind")?;
+            let mut binding = binding.elements();
+            let name = binding.expect(OBJECT_IDENTIFIER, "VarBind name")?;
+            let value = binding.read()?;
+            let name_text = oid(name.value);
+            ctx.add_field_at("snmp.name", name.offset, name.value.len(), name_text.clone());
+            ctx.add_field_at("snmp.value", value.offset, value.value.len(), format_value(&value));
+            names.push(name_text);
+        }
+        if names.len() > MAX_INFO_NAMES {
+            let more = names.len() - MAX_INFO_NAMES;
+            names.truncate(MAX_INFO_NAMES);
+            names.push(format!("(+{} more)", more));
+        }
+        info.extend(names);
+        Ok(())
+    }
+
+    /// SNMPv3: header data, USM security parameters and the scoped or encrypted PDU
+    fn dissect_v3(&self, fields: &mut BerReader, ctx: &mut DissectContext, info: &mut Vec<String>) -> Result<()> {
+        let global = fields.expect(SEQUENCE, "msgGlobalData")?;
+        let mut global = global.elements();
+        let id = global.read()?;
+        let max_size = global.read()?;
+        let flags = global.read()?;
+        let model = global.read()?;
+        ctx.add_field_at("snmp.msgID", id.offset, id.value.len(), id.integer()?);
+        ctx.add_field_at("snmp.msgMaxSize", max_size.offset, max_size.value.len(), max_size.integer()?);
+        let flags_byte = flags.octets()?.first().copied().unwrap_or_default();
+        ctx.add_field_at("snmp.msgFlags", flags.offset, flags.value.len(), FieldValue::Hex(flags_byte as u64));
+        ctx.add_field_at("snmp.v3.flags.auth", flags.offset, 1, flags_byte & 1 != 0);
+        ctx.add_field_at("snmp.v3.flags.crypt", flags.offset, 1, flags_byte & 2 != 0);
+        ctx.add_field_at("snmp.v3.flags.report", flags.offset, 1, flags_byte & 4 != 0);
+        let model_id = model.integer()?;
+        let model_name = if model_id == 3 { "USM".to_string() } else { model_id.to_string() };
+        ctx.add_field_at("snmp.msgSecurityModel", model.offset, model.value.len(), model_name);
+
+        let parameters = fields.expect(OCTET_STRING, "msgSecurityParameters")?;
+        // The user-based security model encodes its parameters as a BER sequence
+        if model_id == 3 && !parameters.value.is_empty() {
+            let mut outer = BerReader::new(parameters.value, parameters.offset);
+            let usm = outer.expect(SEQUENCE, "UsmSecurityParameters")?;
+            let mut usm = usm.elements();
+            let engine = usm.read()?;
+            let boots = usm.read()?;
+            let time = usm.read()?;
+            let user = usm.read()?;
+            let auth = usm.read()?;
+            let privacy = usm.read()?;
+            ctx.add_field_at("snmp.msgAuthoritativeEngineID", engine.offset, engine.value.len(), FieldValue::Bytes(engine.octets()?.to_vec()));
+            ctx.add_field_at("snmp.msgAuthoritativeEngineBoots", boots.offset, boots.value.len(), boots.integer()?);
+            ctx.add_field_at("snmp.msgAuthoritativeEngineTime", time.offset, time.value.len(), time.integer()?);
+            let user_name = String::from_utf8_lossy(user.octets()?).into_owned();
+            ctx.add_field_at("snmp.msgUserName", user.offset, user.value.len(), user_name.clone());
+            ctx.add_field_at("snmp.msgAuthenticationParameters", auth.offset, auth.value.len(), FieldValue::Bytes(auth.octets()?.to_vec()));
+            ctx.add_field_at("snmp.msgPrivacyParameters", privacy.offset, privacy.value.len(), FieldValue::Bytes(privacy.octets()?.to_vec()));
+            if !user_name.is_empty() {
+                info.push(format!("user {}", user_name));
+            }
+        }
+
+        let data = fields.read()?;
+        match data.tag {
+            OCTET_STRING => {
+                ctx.add_field_at("snmp.encryptedPDU", data.offset, data.value.len(), FieldValue::Bytes(data.value.to_vec()));
+                info.push(format!("encryptedPDU, {} bytes", data.value.len()));
+                Ok(())
+            }
+            SEQUENCE => {
+                let mut scoped = data.elements();
+                let engine = scoped.expect(OCTET_STRING, "contextEngineID")?;
+                let context = scoped.expect(OCTET_STRING, "contextName")?;
+                ctx.add_field_at("snmp.contextEngineID", engine.offset, engine.value.len(), FieldValue::Bytes(engine.value.to_vec()));
+                ctx.add_field_at("snmp.contextName", context.offset, context.value.len(), format_octets(context.value));
+                let pdu = scoped.read()?;
+                self.dissect_pdu(pdu, ctx, info)
+            }
+            tag => anyhow::bail!("Unexpected SNMPv3 msgData tag 0x{:02x}", tag),
+        }
+    }
+}
+
+impl Dissector for SnmpProcessor {
+    fn name(&self) -> &'static str {
+        "SNMP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let message = BerReader::new(data, 0).expect(SEQUENCE, "SNMP message")?;
+        let mut fields = message.elements();
+        let version_tlv = fields.read()?;
+        let version = version_tlv.integer()?;
+        ctx.add_field_at("snmp.version", version_tlv.offset, version_tlv.value.len(), version_name(version));
+
+        let mut info = vec![version_name(version)];
+        match version {
+            0 | 1 => {
+                let community = fields.read()?;
+                let text = String::from_utf8_lossy(community.octets()?).into_owned();
+                ctx.add_field_at("snmp.community", community.offset, community.value.len(), text);
+                let pdu = fields.read()?;
+                self.dissect_pdu(pdu, ctx, &mut info)?;
+            }
+            3 => self.dissect_v3(&mut fields, ctx, &mut info)?,
+            version => anyhow::bail!("Unknown SNMP version {}", version),
+        }
+        Ok(Dissection::new("SNMP", info.join(" ")))
+    }
+
+    /// Heuristic probe: a BER message starting with a known version and a
+    /// community followed by a PDU, or the SNMPv3 header data
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        if payload.first() != Some(&SEQUENCE) {
+            return None;
+        }
+        let message = BerReader::new(payload, 0).read().ok()?;
+        let mut fields = message.elements();
+        let version = fields.read().ok()?.integer().ok()?;
+        let next = fields.read().ok()?;
+        match (version, next.tag) {
+            (0 | 1, OCTET_STRING) => {
+                let pdu = fields.read().ok()?;
+                pdu_name(pdu.tag).map(|_| ProbeResult::new(80, "SNMP community message"))
+            }
+            (3, SEQUENCE) => Some(ProbeResult::new(70, "SNMPv3 message")),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dissector::testing::{self, field};
+    use super::super::tree::Field;
+
+    /// v2c get-response with request ID -2 binding sysUpTime.0 to one day
+    const RESPONSE: &[u8] = &[
+        0x30, 0x2d, 0x02, 0x01, 0x01, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', //
+        0xa2, 0x20, 0x02, 0x04, 0xff, 0xff, 0xff, 0xfe, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, //
+        0x30, 0x12, 0x30, 0x10, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x03, 0x00, //
+        0x43, 0x04, 0x00, 0x83, 0xd6, 0x00,
+    ];
+
+    fn dissect(data: &[u8]) -> (Result<Dissection>, Vec<Field>) {
+        testing::dissect(&SnmpProcessor::new(), data, |_| {})
+    }
+
+    #[test]
+    fn reads_ber_elements() {
+        let mut reader = BerReader::new(&[0x02, 0x02, 0xff, 0x7f, 0x04, 0x81, 0x01, 0x61], 10);
+        let integer = reader.read().unwrap();
+        assert_eq!((integer.offset, integer.integer().unwrap()), (12, -129));
+        let octets = reader.read().unwrap();
+        assert_eq!((octets.offset, octets.octets().unwrap()), (17, &b"a"[..]));
+        assert!(reader.is_empty());
+
+        assert_eq!(signed(&[0x00, 0x80]), 128);
+        assert_eq!(signed(&[0x80, 0, 0, 0, 0, 0, 0, 0]), i64::MIN);
+        assert_eq!(unsigned(&[0xff, 0xff, 0xff, 0xff]), 0xffff_ffff);
+        assert_eq!(oid(&[0x2b, 0x06, 0x01, 0x04, 0x01, 0x86, 0x48, 0x00]), "1.3.6.1.4.1.840.0");
+        assert_eq!(oid(&[0x88, 0x37, 0x01]), "2.999.1");
+    }
+
+    #[test]
+    fn rejects_malformed_ber() {
+        for data in [
+            &[][..],
+            &[0x02],
+            &[0x02, 0x03, 0x01],
+            &[0x02, 0x82, 0x01],
+            // Indefinite length
+            &[0x30, 0x80, 0x00, 0x00],
+            // Multi-byte tag number
+            &[0x1f, 0x01, 0x00],
+        ] {
+            assert!(BerReader::new(data, 0).read().is_err(), "{:02x?}", data);
+        }
+        let long = BerReader::new(&[0x02, 0x09, 1, 2, 3, 4, 5, 6, 7, 8, 9], 0).read().unwrap();
+        assert!(long.integer().is_err());
+    }
+
+    #[test]
+    fn dissects_a_response() {
+        let (dissection, fields) = dissect(RESPONSE);
+        assert_eq!(dissection.unwrap().info, "v2c get-response 1.3.6.1.2.1.1.3.0");
+        assert_eq!(field(&fields, "snmp.community"), Some(&FieldValue::Text("public".to_string())));
+        assert_eq!(field(&fields, "snmp.request_id"), Some(&FieldValue::Int(-2)));
+        assert_eq!(field(&fields, "snmp.error_index"), Some(&FieldValue::Int(0)));
+        assert_eq!(field(&fields, "snmp.value").map(ToString::to_string).as_deref(), Some("8640000 (1d 00:00:00.00)"));
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        for len in 0..RESPONSE.len() {
+            assert!(dissect(&RESPONSE[..len]).0.is_err(), "{} bytes", len);
+        }
+        assert!(SnmpProcessor::new().probe(RESPONSE).is_some());
+        assert!(SnmpProcessor::new().probe(&RESPONSE[..20]).is_none());
+    }
+}
@@ -0,0 +1,662 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use md5::{Digest, Md5};
+use pnet::packet::tcp::TcpFlags;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use crate::alerts::{Alert, AlertSeverity};
+use super::bytes::{ByteReader, to_hex};
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::flow::FlowKey;
+use super::reassembly::StreamBuffer;
+
+/// Longest banner line (RFC 4253, section 4.2)
+const MAX_BANNER: usize = 255;
+/// Largest binary packet an implementation must accept (RFC 4253, section 6.1)
+const MAX_PACKET_LEN: usize = 35000;
+
+const MSG_KEXINIT: u8 = 20;
+const MSG_NEWKEYS: u8 = 21;
+
+/// Algorithm name-lists of a KEXINIT message
+#[derive(Debug, Clone, Default)]
+pub struct KexInit {
+    pub kex_algorithms: Vec<String>,
+    pub server_host_key_algorithms: Vec<String>,
+    pub encryption_client_to_server: Vec<String>,
+    pub encryption_server_to_client: Vec<String>,
+    pub mac_client_to_server: Vec<String>,
+    pub mac_server_to_client: Vec<String>,
+    pub compression_client_to_server: Vec<String>,
+    pub compression_server_to_client: Vec<String>,
+    pub first_kex_packet_follows: bool,
+}
+
+impl KexInit {
+    /// Parses the payload of a KEXINIT message, message code included
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new(payload);
+        reader.read_u8()?;
+        reader.read_bytes(16)?;
+        let mut lists = Vec::new();
+        for _ in 0..10 {
+            let len = reader.read_u32()? as usize;
+            let list = String::from_utf8_lossy(reader.read_bytes(len)?).into_owned();
+            lists.push(list.split(',').filter(|name| !name.is_empty()).map(str::to_string).collect::<Vec<_>>());
+        }
+        let mut lists = lists.into_iter();
+        let mut next = || lists.next().unwrap_or_default();
+        Ok(Self {
+            kex_algorithms: next(),
+            server_host_key_algorithms: next(),
+            encryption_client_to_server: next(),
+            encryption_server_to_client: next(),
+            mac_client_to_server: next(),
+            mac_server_to_client: next(),
+            compression_client_to_server: next(),
+            compression_server_to_client: next(),
+            first_kex_packet_follows: reader.read_u8()? != 0,
+        })
+    }
+
+    /// Input of the HASSH (client) or HASSHServer fingerprint
+    pub fn hassh_string(&self, client: bool) -> String {
+        let (encryption, mac, compression) = if client {
+            (&self.encryption_client_to_server, &self.mac_client_to_server, &self.compression_client_to_server)
+        } else {
+            (&self.encryption_server_to_client, &self.mac_server_to_client, &self.compression_server_to_client)
+        };
+        [&self.kex_algorithms, encryption, mac, compression]
+            .iter()
+            .map(|list| list.join(","))
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+
+    pub fn hassh(&self, client: bool) -> String {
+        to_hex(&Md5::digest(self.hassh_string(client).as_bytes()))
+    }
+}
+
+/// Algorithms agreed on by the two KEXINIT messages
+#[derive(Debug, Clone, Serialize)]
+pub struct SshAlgorithms {
+    pub kex: Option<String>,
+    pub host_key: Option<String>,
+    pub cipher_client_to_server: Option<String>,
+    pub cipher_server_to_client: Option<String>,
+    /// `implicit` for the AEAD ciphers, which carry their own MAC
+    pub mac_client_to_server: Option<String>,
+    pub mac_server_to_client: Option<String>,
+    pub compression_client_to_server: Option<String>,
+    pub compression_server_to_client: Option<String>,
+}
+
+impl SshAlgorithms {
+    /// The first client algorithm the server also supports (RFC 4253, section 7.1)
+    fn negotiate(client: &KexInit, server: &KexInit) -> Self {
+        let choose = |client: &[String], server: &[String]| client.iter().find(|name| server.contains(name)).cloned();
+        let cipher_client_to_server = choose(&client.encryption_client_to_server, &server.encryption_client_to_server);
+        let cipher_server_to_client = choose(&client.encryption_server_to_client, &server.encryption_server_to_client);
+        let mac = |cipher: &Option<String>, client: &[String], server: &[String]| match cipher {
+            Some(cipher) if is_aead(cipher) => Some("implicit".to_string()),
+            _ => choose(client, server),
+        };
+        Self {
+            kex: choose(&client.kex_algorithms, &server.kex_algorithms),
+            host_key: choose(&client.server_host_key_algorithms, &server.server_host_key_algorithms),
+            mac_client_to_server: mac(&cipher_client_to_server, &client.mac_client_to_server, &server.mac_client_to_server),
+            mac_server_to_client: mac(&cipher_server_to_client, &client.mac_server_to_client, &server.mac_server_to_client),
+            cipher_client_to_server,
+            cipher_server_to_client,
+            compression_client_to_server: choose(
+                &client.compression_client_to_server,
+                &server.compression_client_to_server,
+            ),
+            compression_server_to_client: choose(
+                &client.compression_server_to_client,
+                &server.compression_server_to_client,
+            ),
+        }
+    }
+
+    /// Negotiated algorithms that are considered broken, as (kind, name)
+    pub fn weak(&self) -> Vec<(&'static str, String)> {
+        [
+            ("key exchange", &self.kex),
+            ("host key", &self.host_key),
+            ("cipher", &self.cipher_client_to_server),
+            ("cipher", &self.cipher_server_to_client),
+            ("MAC", &self.mac_client_to_server),
+            ("MAC", &self.mac_server_to_client),
+        ]
+        .into_iter()
+        .filter_map(|(kind, name)| name.clone().filter(|name| is_weak(name)).map(|name| (kind, name)))
+        .fold(Vec::new(), |mut weak, entry| {
+            if !weak.contains(&entry) {
+                weak.push(entry);
+            }
+            weak
+        })
+    }
+}
+
+fn is_aead(cipher: &str) -> bool {
+    cipher.contains("-gcm") || cipher.contains("poly1305")
+}
+
+/// SHA-1 key exchanges and signatures, DSA, CBC mode and RC4 ciphers, MD5
+/// and truncated SHA-1 MACs, and no protection at all
+pub fn is_weak(algorithm: &str) -> bool {
+    let name = algorithm.split('@').next().unwrap_or(algorithm);
+    matches!(
+        name,
+        "diffie-hellman-group1-sha1"
+            | "diffie-hellman-group14-sha1"
+            | "diffie-hellman-group-exchange-sha1"
+            | "rsa1024-sha1"
+            | "ssh-dss"
+            | "ssh-rsa"
+            | "ssh-dss-cert-v01"
+            | "ssh-rsa-cert-v01"
+            | "none"
+    ) || name.ends_with("-cbc")
+        || name.starts_with("arcfour")
+        || name.starts_with("hmac-md5")
+        || name.starts_with("hmac-sha1-96")
+}
+
+/// One SSH connection: the software of both ends and what they agreed on
+#[derive(Debug, Clone, Serialize)]
+pub struct SshSession {
+    pub client: IpAddr,
+    pub client_port: u16,
+    pub server: IpAddr,
+    pub server_port: u16,
+    /// Banner without the `SSH-2.0-` prefix, e.g. `OpenSSH_9.6p1 Ubuntu-3`
+    pub client_software: Option<String>,
+    pub server_software: Option<String>,
+    pub protocol_version: Option<String>,
+    pub hassh: Option<String>,
+    pub hassh_server: Option<String>,
+    pub algorithms: Option<SshAlgorithms>,
+    pub weak_algorithms: Vec<String>,
+    /// Both sides sent NEWKEYS
+    pub encrypted: bool,
+    pub started_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+impl SshSession {
+    pub fn format_info(&self) -> String {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        let algorithms = self.algorithms.as_ref();
+        format!(
+            "{:<21} {:<21} {:<28} {:<28} {:<30} {:<30} {:<32} {}",
+            format!("{}:{}", self.client, self.client_port),
+            format!("{}:{}", self.server, self.server_port),
+            optional(&self.client_software),
+            optional(&self.server_software),
+            optional(&algorithms.and_then(|a| a.kex.clone())),
+            optional(&algorithms.and_then(|a| a.cipher_client_to_server.clone())),
+            optional(&self.hassh),
+            optional(&self.hassh_server)
+        )
+    }
+}
+
+/// One direction of a connection
+#[derive(Default)]
+struct SshStream {
+    buffer: StreamBuffer,
+    banner_seen: bool,
+    /// NEWKEYS was sent: the following packets are encrypted
+    encrypted: bool,
+}
+
+struct Session {
+    info: SshSession,
+    client_kexinit: Option<KexInit>,
+    server_kexinit: Option<KexInit>,
+    /// NEWKEYS sent by the server and by the client
+    newkeys: [bool; 2],
+}
+
+#[derive(Default)]
+struct SshState {
+    streams: HashMap<FlowKey, SshStream>,
+    sessions: HashMap<FlowKey, Session>,
+    /// Connections in the order they were seen
+    order: Vec<FlowKey>,
+}
+
+/// A message read from one direction of the stream
+enum SshMessage {
+    Banner(String),
+    Packet { length: usize, padding: usize, payload: Vec<u8> },
+    Encrypted(usize),
+}
+
+impl SshStream {
+    fn push(&mut self, seq: u32, payload: &[u8]) -> Vec<SshMessage> {
+        if !self.buffer.push(seq, payload) && self.banner_seen {
+            // Packet boundaries are lost with the missing segment
+            self.encrypted = true;
+        }
+        let mut messages = Vec::new();
+        while !self.banner_seen {
+            let Some(end) = self.buffer.data().iter().position(|&b| b == b'\n') else {
+                if self.buffer.data().len() > MAX_BANNER {
+                    self.buffer.clear();
+                }
+                return messages;
+            };
+            let line = String::from_utf8_lossy(&self.buffer.data()[..end]).trim_end_matches('\r').to_string();
+            self.buffer.consume(end + 1);
+            // Servers may send other lines before the banner
+            if line.starts_with("SSH-") {
+                self.banner_seen = true;
+                messages.push(SshMessage::Banner(line));
+            }
+        }
+
+        while !self.encrypted && self.buffer.data().len() >= 5 {
+            let data = self.buffer.data();
+            let length = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            let padding = data[4] as usize;
+            if !(padding + 1..=MAX_PACKET_LEN).contains(&length) {
+                self.encrypted = true;
+                break;
+            }
+            if data.len() < 4 + length {
+                return messages;
+            }
+            let payload = data[5..4 + length - padding].to_vec();
+            self.buffer.consume(4 + length);
+            if payload.first() == Some(&MSG_NEWKEYS) {
+                self.encrypted = true;
+            }
+            messages.push(SshMessage::Packet { length, padding, payload });
+        }
+        if self.encrypted && !self.buffer.data().is_empty() {
+            messages.push(SshMessage::Encrypted(self.buffer.data().len()));
+            self.buffer.clear();
+        }
+        messages
+    }
+}
+
+fn message_name(code: u8, kex: Option<&str>) -> String {
+    let gex = kex.is_some_and(|kex| kex.starts_with("diffie-hellman-group-exchange"));
+    let ecdh = kex.is_some_and(|kex| !kex.starts_with("diffie-hellman"));
+    let name = match code {
+        1 => "Disconnect",
+        2 => "Ignore",
+        3 => "Unimplemented",
+        4 => "Debug",
+        5 => "Service Request",
+        6 => "Service Accept",
+        7 => "Extension Info",
+        MSG_KEXINIT => "Key Exchange Init",
+        MSG_NEWKEYS => "New Keys",
+        30 if gex => "Diffie-Hellman Group Exchange Request (old)",
+        31 if gex => "Diffie-Hellman Group Exchange Group",
+        32 if gex => "Diffie-Hellman Group Exchange Init",
+        33 if gex => "Diffie-Hellman Group Exchange Reply",
+        34 if gex => "Diffie-Hellman Group Exchange Request",
+        30 if ecdh => "Elliptic Curve Diffie-Hellman Key Exchange Init",
+        31 if ecdh => "Elliptic Curve Diffie-Hellman Key Exchange Reply",
+        30 => "Diffie-Hellman Key Exchange Init",
+        31 => "Diffie-Hellman Key Exchange Reply",
+        _ => return format!("Message Code {}", code),
+    };
+    name.to_string()
+}
+
+/// SSH: version banners, key exchange and HASSH fingerprints
+pub struct SshProcessor {
+    state: Mutex<SshState>,
+}
+
+impl SshProcessor {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(SshState::default()),
+        }
+    }
+
+    /// Sessions in the order they started
+    pub fn sessions(&self) -> Vec<SshSession> {
+        let state = self.state.lock().unwrap();
+        state.order.iter().filter_map(|key| state.sessions.get(key)).map(|session| session.info.clone()).collect()
+    }
+
+    /// Reads the KEXINIT of one side. Returns alerts for the weak algorithms
+    /// once both sides sent theirs, once per algorithm and session.
+    fn key_exchange(session: &mut Session, kexinit: KexInit, from_client: bool, timestamp: &str) -> Vec<Alert> {
+        if from_client {
+            session.info.hassh = Some(kexinit.hassh(true));
+            session.client_kexinit = Some(kexinit);
+        } else {
+            session.info.hassh_server = Some(kexinit.hassh(false));
+            session.server_kexinit = Some(kexinit);
+        }
+        let (Some(client), Some(server)) = (&session.client_kexinit, &session.server_kexinit) else {
+            return Vec::new();
+        };
+        let algorithms = SshAlgorithms::negotiate(client, server);
+        let info = &mut session.info;
+        let mut alerts = Vec::new();
+        for (kind, name) in algorithms.weak() {
+            // Rekeying repeats the negotiation
+            if info.weak_algorithms.contains(&name) {
+                continue;
+            }
+            info.weak_algorithms.push(name.clone());
+            alerts.push(Alert::new(
+                timestamp,
+                AlertSeverity::Warning,
+                "ssh.weak_algorithm",
+                format!(
+                    "SSH session {}:{} > {}:{} negotiated weak {} {}",
+                    info.client, info.client_port, info.server, info.server_port, kind, name
+                ),
+            ));
+        }
+        info.algorithms = Some(algorithms);
+        alerts
+    }
+}
+
+impl Default for SshProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dissector for SshProcessor {
+    fn name(&self) -> &'static str {
+        "SSH"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let flow = ctx.flow.ok_or_else(|| anyhow::anyhow!("SSH outside of a TCP flow"))?;
+        let mut state = self.state.lock().unwrap();
+        let messages = state
+            .streams
+            .entry(flow)
+            .or_default()
+            .push(ctx.tcp_sequence.unwrap_or_default(), data);
+        if ctx.tcp_flags.is_some_and(|flags| flags & (TcpFlags::FIN | TcpFlags::RST) != 0) {
+            state.streams.remove(&flow);
+        }
+        if data.is_empty() {
+            anyhow::bail!("No SSH payload");
+        }
+
+        let key = flow.canonical();
+        if !state.sessions.contains_key(&key) {
+            state.order.push(key);
+        }
+        let client = ctx.client_flow.unwrap_or(flow);
+        let server = client.reversed();
+        let session = state.sessions.entry(key).or_insert_with(|| Session {
+            info: SshSession {
+                client: client.src,
+                client_port: client.src_port,
+                server: server.src,
+                server_port: server.src_port,
+                client_software: None,
+                server_software: None,
+                protocol_version: None,
+                hassh: None,
+                hassh_server: None,
+                algorithms: None,
+                weak_algorithms: Vec::new(),
+                encrypted: false,
+                started_at: ctx.captured_at,
+                last_seen: ctx.captured_at,
+            },
+            client_kexinit: None,
+            server_kexinit: None,
+            newkeys: [false, false],
+        });
+        session.info.last_seen = ctx.captured_at;
+        let from_client = flow == client;
+        ctx.add_field("ssh.direction", if from_client { "client" } else { "server" });
+
+        let mut info = Vec::new();
+        for message in messages {
+            match message {
+                SshMessage::Banner(banner) => {
+                    ctx.add_field("ssh.protocol", banner.clone());
+                    let mut parts = banner.splitn(3, '-').skip(1);
+                    let version = parts.next().unwrap_or_default().to_string();
+                    let software = parts.next().unwrap_or_default().to_string();
+                    ctx.add_field("ssh.protocol_version", version.clone());
+                    ctx.add_field("ssh.software", software.clone());
+                    // 1.99 announces support for both protocol versions
+                    if version.starts_with("1.") && version != "1.99" {
+                        ctx.raise(Alert::new(
+                            &ctx.timestamp,
+                            AlertSeverity::Warning,
+                            "ssh.protocol_v1",
+                            format!("{} uses the obsolete SSH protocol {}", flow.src, version),
+                        ));
+                    }
+                    // The client picks the version a 1.99 server offers
+                    if from_client || session.info.protocol_version.is_none() {
+                        session.info.protocol_version = Some(version);
+                    }
+                    if from_client {
+                        session.info.client_software = Some(software);
+                    } else {
+                        session.info.server_software = Some(software);
+                    }
+                    info.push(format!("Protocol ({})", banner));
+                }
+                SshMessage::Packet { length, padding, payload } => {
+                    let Some(&code) = payload.first() else {
+                        continue;
+                    };
+                    ctx.add_field("ssh.packet_length", length);
+                    ctx.add_field("ssh.padding_length", padding);
+                    ctx.add_field("ssh.message_code", code);
+                    let kex = session.info.algorithms.as_ref().and_then(|algorithms| algorithms.kex.as_deref());
+                    let mut summary = message_name(code, kex);
+                    if code == MSG_KEXINIT {
+                        let kexinit = KexInit::parse(&payload)?;
+                        let lists = [
+                            ("ssh.kex_algorithms", &kexinit.kex_algorithms),
+                            ("ssh.server_host_key_algorithms", &kexinit.server_host_key_algorithms),
+                            ("ssh.encryption_algorithms_client_to_server", &kexinit.encryption_client_to_server),
+                            ("ssh.encryption_algorithms_server_to_client", &kexinit.encryption_server_to_client),
+                            ("ssh.mac_algorithms_client_to_server", &kexinit.mac_client_to_server),
+                            ("ssh.mac_algorithms_server_to_client", &kexinit.mac_server_to_client),
+                            ("ssh.compression_algorithms_client_to_server", &kexinit.compression_client_to_server),
+                            ("ssh.compression_algorithms_server_to_client", &kexinit.compression_server_to_client),
+                        ];
+                        for (name, list) in lists {
+                            ctx.add_field(name, list.join(","));
+                        }
+                        ctx.add_field("ssh.first_kex_packet_follows", kexinit.first_kex_packet_follows);
+                        let (hassh_name, hassh) = if from_client {
+                            ("ssh.kex.hassh", kexinit.hassh(true))
+                        } else {
+                            ("ssh.kex.hasshserver", kexinit.hassh(false))
+                        };
+                        ctx.add_field(&format!("{}_algorithms", hassh_name), kexinit.hassh_string(from_client));
+                        ctx.add_field(hassh_name, hassh.clone());
+                        summary.push_str(&format!(", {}={}", if from_client { "HASSH" } else { "HASSHServer" }, hassh));
+                        for alert in Self::key_exchange(session, kexinit, from_client, &ctx.timestamp) {
+                            ctx.raise(alert);
+                        }
+                        if let Some(algorithms) = &session.info.algorithms {
+                            let negotiated = [
+                                ("ssh.negotiated.kex", &algorithms.kex),
+                                ("ssh.negotiated.host_key", &algorithms.host_key),
+                                ("ssh.negotiated.cipher_client_to_server", &algorithms.cipher_client_to_server),
+                                ("ssh.negotiated.cipher_server_to_client", &algorithms.cipher_server_to_client),
+                                ("ssh.negotiated.mac_client_to_server", &algorithms.mac_client_to_server),
+                                ("ssh.negotiated.mac_server_to_client", &algorithms.mac_server_to_client),
+                            ];
+                            for (name, value) in negotiated {
+                                if let Some(value) = value {
+                                    ctx.add_field(name, value.clone());
+                                }
+                            }
+                        }
+                    }
+                    if code == MSG_NEWKEYS {
+                        session.newkeys[from_client as usize] = true;
+                        session.info.encrypted = session.newkeys == [true, true];
+                    }
+                    info.push(summary);
+                }
+                SshMessage::Encrypted(length) => {
+                    ctx.add_field("ssh.encrypted_packet", length);
+                    info.push(format!("Encrypted packet (len={})", length));
+                }
+            }
+        }
+
+        let info = match info.len() {
+            0 => "Continuation".to_string(),
+            _ => format!("{}: {}", if from_client { "Client" } else { "Server" }, info.join(", ")),
+        };
+        Ok(Dissection::new("SSH", info))
+    }
+
+    /// Heuristic probe: the version banner both ends send first
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        match payload {
+            [b'S', b'S', b'H', b'-', major, b'.', ..] if major.is_ascii_digit() => {
+                Some(ProbeResult::new(95, "SSH version banner"))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::alerts::AlertChannel;
+    use super::super::dissector::{testing, DissectorRegistry};
+
+    const KEX: &str = "curve25519-sha256,curve25519-sha256@libssh.org,ecdh-sha2-nistp256,diffie-hellman-group14-sha256,ext-info-c";
+
+    /// KEXINIT payload with the same algorithms in both directions of each pair
+    fn kexinit(kex: &str, host_key: &str, encryption: [&str; 2], mac: [&str; 2], compression: [&str; 2]) -> Vec<u8> {
+        let mut payload = vec![MSG_KEXINIT];
+        payload.extend_from_slice(&[0x5a; 16]);
+        let lists = [kex, host_key, encryption[0], encryption[1], mac[0], mac[1], compression[0], compression[1], "", ""];
+        for list in lists {
+            payload.extend_from_slice(&(list.len() as u32).to_be_bytes());
+            payload.extend_from_slice(list.as_bytes());
+        }
+        payload.extend_from_slice(&[0, 0, 0, 0, 0]);
+        payload
+    }
+
+    fn packet(payload: &[u8]) -> Vec<u8> {
+        let padding = 8 - (payload.len() + 5) % 8 + 4;
+        let mut packet = ((payload.len() + padding + 1) as u32).to_be_bytes().to_vec();
+        packet.push(padding as u8);
+        packet.extend_from_slice(payload);
+        packet.extend(std::iter::repeat_n(0, padding));
+        packet
+    }
+
+    /// Sends the next segment of one direction of a connection opened by `client`
+    fn send(ssh: &SshProcessor, alerts: &AlertChannel, client: FlowKey, from_client: bool, seq: &mut u32, data: &[u8]) {
+        let (dissection, _) = testing::dissect_in(&DissectorRegistry::new(), alerts, ssh, data, |ctx| {
+            ctx.captured_at = Utc.timestamp_opt(0, 0).unwrap();
+            ctx.flow = Some(if from_client { client } else { client.reversed() });
+            ctx.client_flow = Some(client);
+            ctx.tcp_sequence = Some(*seq);
+            ctx.tcp_flags = Some(TcpFlags::ACK);
+        });
+        dissection.unwrap();
+        *seq += data.len() as u32;
+    }
+
+    #[test]
+    fn computes_hassh() {
+        let client = KexInit::parse(&kexinit(
+            KEX,
+            "ssh-ed25519",
+            ["chacha20-poly1305@openssh.com,aes128-ctr,aes256-gcm@openssh.com"; 2],
+            ["umac-64-etm@openssh.com,hmac-sha2-256-etm@openssh.com,hmac-sha1"; 2],
+            ["none,zlib@openssh.com"; 2],
+        ))
+        .unwrap();
+        assert_eq!(
+            client.hassh_string(true),
+            format!(
+                "{};chacha20-poly1305@openssh.com,aes128-ctr,aes256-gcm@openssh.com;\
+                 umac-64-etm@openssh.com,hmac-sha2-256-etm@openssh.com,hmac-sha1;none,zlib@openssh.com",
+                KEX
+            )
+        );
+        assert_eq!(client.hassh(true), "3c94a89f75edcad9cc0a0c18839bd0a5");
+
+        // HASSHServer reads the server to client lists
+        let mut server = client.clone();
+        server.encryption_server_to_client = vec!["aes256-ctr".to_string()];
+        server.mac_server_to_client = vec!["hmac-sha2-512".to_string()];
+        server.compression_server_to_client = vec!["none".to_string()];
+        assert_eq!(server.hassh(true), client.hassh(true));
+        assert_eq!(server.hassh(false), "1d9970d5a8dfcc5d70a9842cc53b0264");
+    }
+
+    #[test]
+    fn takes_the_client_from_the_flow() {
+        let ssh = SshProcessor::new();
+        let alerts = AlertChannel::new();
+        // A client on a privileged port connecting to a server on a high one
+        let client = FlowKey::new("10.0.0.1".parse().unwrap(), 1022, "10.0.0.2".parse().unwrap(), 2222);
+        let (mut client_seq, mut server_seq) = (1, 1);
+        send(&ssh, &alerts, client, false, &mut server_seq, b"SSH-2.0-OpenSSH_9.6\r\n");
+        send(&ssh, &alerts, client, true, &mut client_seq, b"SSH-2.0-PuTTY_Release_0.80\r\n");
+
+        let session = &ssh.sessions()[0];
+        assert_eq!((session.client, session.client_port), (client.src, 1022));
+        assert_eq!((session.server, session.server_port), (client.dst, 2222));
+        assert_eq!(session.client_software.as_deref(), Some("PuTTY_Release_0.80"));
+        assert_eq!(session.server_software.as_deref(), Some("OpenSSH_9.6"));
+    }
+
+    #[test]
+    fn alerts_on_weak_algorithms_once() {
+        let ssh = SshProcessor::default();
+        let alerts = AlertChannel::new();
+        let client = FlowKey::new("10.0.0.1".parse().unwrap(), 50000, "10.0.0.2".parse().unwrap(), 22);
+        let (mut client_seq, mut server_seq) = (1, 1);
+        let kexinit = packet(&kexinit(KEX, "ssh-rsa", ["aes128-cbc"; 2], ["hmac-sha2-256"; 2], ["none"; 2]));
+        send(&ssh, &alerts, client, true, &mut client_seq, b"SSH-2.0-OpenSSH_9.6\r\n");
+        send(&ssh, &alerts, client, false, &mut server_seq, b"SSH-2.0-OpenSSH_7.4\r\n");
+        send(&ssh, &alerts, client, true, &mut client_seq, &kexinit);
+        send(&ssh, &alerts, client, false, &mut server_seq, &kexinit);
+
+        let mut messages: Vec<String> = alerts.drain().into_iter().map(|alert| alert.message).collect();
+        messages.sort();
+        assert_eq!(
+            messages,
+            [
+                "SSH session 10.0.0.1:50000 > 10.0.0.2:22 negotiated weak cipher aes128-cbc",
+                "SSH session 10.0.0.1:50000 > 10.0.0.2:22 negotiated weak host key ssh-rsa",
+            ]
+        );
+
+        // A second key exchange agreeing on the same algorithms
+        send(&ssh, &alerts, client, true, &mut client_seq, &kexinit);
+        send(&ssh, &alerts, client, false, &mut server_seq, &kexinit);
+        assert!(alerts.drain().is_empty());
+        assert_eq!(ssh.sessions()[0].weak_algorithms, ["ssh-rsa", "aes128-cbc"]);
+    }
+}
@@ -0,0 +1,295 @@
+use anyhow::Result;
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+
+/// The header of a syslog message in either format
+#[derive(Debug, Default)]
+struct Header<'a> {
+    version: Option<u8>,
+    timestamp: Option<&'a str>,
+    hostname: Option<&'a str>,
+    app_name: Option<&'a str>,
+    proc_id: Option<&'a str>,
+    msg_id: Option<&'a str>,
+    structured_data: Option<&'a str>,
+    message: &'a str,
+}
+
+/// `<PRI>` at the start of the message: the priority and the length of the part
+fn priority(text: &str) -> Option<(u8, usize)> {
+    let rest = text.strip_prefix('<')?;
+    let end = rest.find('>')?;
+    let digits = &rest[..end];
+    if digits.is_empty() || digits.len() > 3 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let priority = digits.parse::<u8>().ok().filter(|&priority| priority <= 191)?;
+    Some((priority, end + 2))
+}
+
+/// `-` stands for a missing value in RFC 5424
+fn nil(value: &str) -> Option<&str> {
+    (value != "-").then_some(value)
+}
+
+/// Splits the next space-separated word off `text`
+fn word(text: &str) -> (&str, &str) {
+    match text.split_once(' ') {
+        Some((word, rest)) => (word, rest),
+        None => (text, ""),
+    }
+}
+
+/// RFC 5424: `VERSION SP TIMESTAMP SP HOSTNAME SP APP-NAME SP PROCID SP MSGID SP STRUCTURED-DATA [SP MSG]`
+fn parse_rfc5424(text: &str) -> Option<Header<'_>> {
+    let (version, rest) = word(text);
+    let version = version.parse::<u8>().ok().filter(|version| (1..=99).contains(version))?;
+    let (timestamp, rest) = word(rest);
+    let (hostname, rest) = word(rest);
+    let (app_name, rest) = word(rest);
+    let (proc_id, rest) = word(rest);
+    let (msg_id, rest) = word(rest);
+    let (structured_data, message) = match rest.strip_prefix('-') {
+        Some(message) => (None, message),
+        None => {
+            let end = structured_data_end(rest)?;
+            (Some(&rest[..end]), &rest[end..])
+        }
+    };
+    let message = message.strip_prefix(' ').unwrap_or(message);
+    Some(Header {
+        version: Some(version),
+        timestamp: nil(timestamp),
+        hostname: nil(hostname),
+        app_name: nil(app_name),
+        proc_id: nil(proc_id),
+        msg_id: nil(msg_id),
+        structured_data,
+        // MSG may start with a byte order mark to announce UTF-8
+        message: message.strip_prefix('\u{feff}').unwrap_or(message),
+    })
+}
+
+/// Length of the `[id param="value" ...]` elements at the start of `text`;
+/// quoted values may contain escaped `"`, `\` and `]`
+fn structured_data_end(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut position = 0;
+    while bytes.get(position) == Some(&b'[') {
+        let mut quoted = false;
+        position += 1;
+        loop {
+            match *bytes.get(position)? {
+                b'\\' if quoted => position += 1,
+                b'"' => quoted = !quoted,
+                b']' if !quoted => break,
+                _ => {}
+            }
+            position += 1;
+        }
+        position += 1;
+    }
+    (position > 0).then_some(position)
+}
+
+/// RFC 3164: `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`. Senders often leave
+/// parts out, so anything not matching is kept as the message.
+fn parse_rfc3164(text: &str) -> Header<'_> {
+    let mut header = Header { message: text, ..Header::default() };
+    let is_timestamp = text.len() >= 16
+        && text.is_char_boundary(7)
+        && text.is_char_boundary(15)
+        && text.as_bytes()[15] == b' '
+        && MONTHS.iter().any(|month| text.starts_with(month))
+        && text[7..15].bytes().enumerate().all(|(i, b)| if i % 3 == 2 { b == b':' } else { b.is_ascii_digit() });
+    if !is_timestamp {
+        return header;
+    }
+    header.timestamp = Some(&text[..15]);
+    let (hostname, rest) = word(&text[16..]);
+    header.hostname = Some(hostname);
+    header.message = rest;
+
+    // The tag is alphanumeric, optionally followed by the PID in brackets, and ends with a colon
+    let tag_end = rest.find(|c: char| !c.is_ascii_alphanumeric() && !matches!(c, '-' | '_' | '.' | '/')).unwrap_or(rest.len());
+    if tag_end == 0 || tag_end > 48 {
+        return header;
+    }
+    let after = &rest[tag_end..];
+    let (proc_id, after) = match after.strip_prefix('[').and_then(|after| after.split_once(']')) {
+        Some((proc_id, after)) => (Some(proc_id), after),
+        None => (None, after),
+    };
+    if let Some(message) = after.strip_prefix(':') {
+        header.app_name = Some(&rest[..tag_end]);
+        header.proc_id = proc_id;
+        header.message = message.strip_prefix(' ').unwrap_or(message);
+    }
+    header
+}
+
+const MONTHS: [&str; 12] = ["Jan ", "Feb ", "Mar ", "Apr ", "May ", "Jun ", "Jul ", "Aug ", "Sep ", "Oct ", "Nov ", "Dec "];
+
+fn facility_name(facility: u8) -> &'static str {
+    match facility {
+        0 => "KERN",
+        1 => "USER",
+        2 => "MAIL",
+        3 => "DAEMON",
+        4 => "AUTH",
+        5 => "SYSLOG",
+        6 => "LPR",
+        7 => "NEWS",
+        8 => "UUCP",
+        9 => "CRON",
+        10 => "AUTHPRIV",
+        11 => "FTP",
+        12 => "NTP",
+        13 => "AUDIT",
+        14 => "ALERT",
+        15 => "CLOCK",
+        16 => "LOCAL0",
+        17 => "LOCAL1",
+        18 => "LOCAL2",
+        19 => "LOCAL3",
+        20 => "LOCAL4",
+        21 => "LOCAL5",
+        22 => "LOCAL6",
+        _ => "LOCAL7",
+    }
+}
+
+fn severity_name(severity: u8) -> &'static str {
+    match severity {
+        0 => "EMERG",
+        1 => "ALERT",
+        2 => "CRIT",
+        3 => "ERR",
+        4 => "WARNING",
+        5 => "NOTICE",
+        6 => "INFO",
+        _ => "DEBUG",
+    }
+}
+
+/// Syslog messages (RFC 3164 and RFC 5424): priority, header and message
+#[derive(Default)]
+pub struct SyslogProcessor;
+
+impl SyslogProcessor {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Dissector for SyslogProcessor {
+    fn name(&self) -> &'static str {
+        "Syslog"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let text = String::from_utf8_lossy(data);
+        let text = text.trim_end_matches(['\r', '\n', '\0']);
+        let (priority, start) = priority(text).ok_or_else(|| anyhow::anyhow!("Syslog message without a priority"))?;
+        let (facility, severity) = (priority >> 3, priority & 7);
+        ctx.add_field_at("syslog.facility", 1, start - 2, facility_name(facility));
+        ctx.add_field_at("syslog.level", 1, start - 2, severity_name(severity));
+
+        let body = &text[start..];
+        let header = parse_rfc5424(body).unwrap_or_else(|| parse_rfc3164(body));
+        let fields = [
+            ("syslog.timestamp", header.timestamp),
+            ("syslog.hostname", header.hostname),
+            ("syslog.appname", header.app_name),
+            ("syslog.procid", header.proc_id),
+            ("syslog.msgid", header.msg_id),
+            ("syslog.sd", header.structured_data),
+        ];
+        if let Some(version) = header.version {
+            ctx.add_field("syslog.version", version);
+        }
+        for (name, value) in fields {
+            if let Some(value) = value {
+                ctx.add_field(name, value);
+            }
+        }
+        ctx.add_field("syslog.msg", header.message);
+
+        let mut info = format!("{}.{}: ", facility_name(facility), severity_name(severity));
+        if let Some(hostname) = header.hostname {
+            info.push_str(&format!("{} ", hostname));
+        }
+        match (header.app_name, header.proc_id) {
+            (Some(app), Some(pid)) => info.push_str(&format!("{}[{}]: ", app, pid)),
+            (Some(app), None) => info.push_str(&format!("{}: ", app)),
+            _ => {}
+        }
+        info.push_str(header.message);
+        Ok(Dissection::new("Syslog", info))
+    }
+
+    /// Heuristic probe: a priority in angle brackets, then an RFC 5424 version
+    /// or printable text
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        let head = String::from_utf8_lossy(&payload[..payload.len().min(64)]);
+        let (_, start) = priority(&head)?;
+        let rest = &head[start..];
+        if rest.starts_with("1 ") {
+            return Some(ProbeResult::new(80, "RFC 5424 syslog header"));
+        }
+        if MONTHS.iter().any(|month| rest.starts_with(month)) {
+            return Some(ProbeResult::new(75, "RFC 3164 syslog header"));
+        }
+        rest.trim_end().chars().all(|c| !c.is_control()).then(|| ProbeResult::new(45, "Syslog priority"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_priority() {
+        assert_eq!(priority("<34>1 -"), Some((34, 4)));
+        assert_eq!(priority("<0>Oct"), Some((0, 3)));
+        for text in ["<192>x", "<>x", "<0034>x", "<3a>x", "34>x", "<34"] {
+            assert_eq!(priority(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_rfc5424_headers() {
+        // RFC 5424, section 6.5
+        let header = parse_rfc5424("1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - 'su root' failed").unwrap();
+        assert_eq!(header.version, Some(1));
+        assert_eq!(header.timestamp, Some("2003-10-11T22:14:15.003Z"));
+        assert_eq!(header.hostname, Some("mymachine.example.com"));
+        assert_eq!((header.app_name, header.proc_id, header.msg_id), (Some("su"), None, Some("ID47")));
+        assert_eq!((header.structured_data, header.message), (None, "'su root' failed"));
+
+        let header = parse_rfc5424(
+            "1 - host app 42 - [exampleSDID@32473 iut=\"3\" note=\"a \\\"]\\\" b\"][other@1 x=\"y\"] \u{feff}event",
+        )
+        .unwrap();
+        assert_eq!(header.structured_data, Some("[exampleSDID@32473 iut=\"3\" note=\"a \\\"]\\\" b\"][other@1 x=\"y\"]"));
+        assert_eq!((header.timestamp, header.message), (None, "event"));
+
+        assert!(parse_rfc5424("1 - host app 42 - [unterminated x=\"]").is_none());
+        assert!(parse_rfc5424("Oct 11 22:14:15 host su: message").is_none());
+    }
+
+    #[test]
+    fn parses_rfc3164_headers() {
+        let header = parse_rfc3164("Oct 11 22:14:15 mymachine sshd[1234]: Accepted publickey");
+        assert_eq!((header.timestamp, header.hostname), (Some("Oct 11 22:14:15"), Some("mymachine")));
+        assert_eq!((header.app_name, header.proc_id, header.message), (Some("sshd"), Some("1234"), "Accepted publickey"));
+
+        // No tag: the rest is the message
+        let header = parse_rfc3164("Oct  1 02:04:06 router link down on ge-0/0/1");
+        assert_eq!((header.hostname, header.app_name, header.message), (Some("router"), None, "link down on ge-0/0/1"));
+
+        let header = parse_rfc3164("kernel: no header at all");
+        assert_eq!((header.timestamp, header.message), (None, "kernel: no header at all"));
+        assert_eq!(parse_rfc3164("Oct 11 22:14:1\u{e9} host").timestamp, None);
+    }
+}
@@ -1,11 +1,20 @@
+use anyhow::Result;
 use pnet::packet::tcp::{TcpPacket, TcpFlags, TcpOptionNumber};
 use pnet::packet::Packet;
+use super::detection::{Detection, FlowClassifier};
+use super::dissector::{Dissection, DissectContext, Dissector, Transport};
+use super::flow::FlowKey;
+use super::tree::FieldValue;
 
-pub struct TcpProcessor;
+pub struct TcpProcessor {
+    classifier: FlowClassifier,
+}
 
 impl TcpProcessor {
     pub fn new() -> Self {
-        Self
+        Self {
+            classifier: FlowClassifier::new(Transport::Tcp),
+        }
     }
 
     pub fn process<'a>(&self, data: &'a [u8]) -> Option<TcpPacket<'a>> {
@@ -58,4 +67,58 @@ impl TcpProcessor {
             tsecr
         )
     }
-} 
\ No newline at end of file
+}
+
+impl Dissector for TcpProcessor {
+    fn name(&self) -> &'static str {
+        "TCP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let tcp = self.process(data).ok_or_else(|| anyhow::anyhow!("Segment too short for TCP"))?;
+        ctx.add_field_at("tcp.srcport", 0, 2, tcp.get_source());
+        ctx.add_field_at("tcp.dstport", 2, 2, tcp.get_destination());
+        ctx.add_field_at("tcp.port", 0, 2, tcp.get_source());
+        ctx.add_field_at("tcp.port", 2, 2, tcp.get_destination());
+        ctx.add_field_at("tcp.seq", 4, 4, tcp.get_sequence());
+        ctx.add_field_at("tcp.ack", 8, 4, tcp.get_acknowledgement());
+        ctx.add_field_at("tcp.hdr_len", 12, 1, tcp.get_data_offset() as usize * 4);
+        ctx.add_field_at("tcp.flags", 13, 1, Self::get_tcp_flags(tcp.get_flags()));
+        ctx.add_field_at("tcp.window_size_value", 14, 2, tcp.get_window());
+        ctx.add_field_at("tcp.checksum", 16, 2, FieldValue::Hex(tcp.get_checksum() as u64));
+        ctx.add_field("tcp.len", tcp.payload().len());
+
+        let tcp_info = || Dissection::new("TCP", Self::format_tcp_info(&tcp));
+        let (Some(src), Some(dst)) = (ctx.src_ip, ctx.dst_ip) else {
+            return Ok(tcp_info());
+        };
+        let flow = FlowKey::new(src, tcp.get_source(), dst, tcp.get_destination());
+        ctx.flow = Some(flow);
+        ctx.tcp_sequence = Some(tcp.get_sequence());
+        ctx.tcp_flags = Some(tcp.get_flags());
+
+        // The application protocol is detected once per flow from its payload
+        let payload = tcp.payload();
+        let detection = self.classifier.classify(&flow, ctx.registry(), payload, ctx.captured_at);
+        let protocol = detection.as_ref().map(Detection::protocol);
+        let record = ctx.registry().note_flow(Transport::Tcp, &flow, ctx.tcp_flags, ctx.number, protocol, ctx.captured_at);
+        ctx.client_flow = Some(record.client);
+        // The state of a flow goes with its last FIN or a reset
+        ctx.flow_closed = ctx.registry().close_flow(&flow, tcp.get_flags());
+        if ctx.flow_closed {
+            self.classifier.forget(&flow);
+        }
+        let dissection = detection.map(|detection| {
+            ctx.add_fields(detection.fields());
+            let mut dissection = ctx
+                .dissect_with(detection.dissector.as_ref(), payload)
+                .unwrap_or_else(|_| tcp_info());
+            if detection.new {
+                dissection.info.push_str(&detection.format_note());
+            }
+            dissection
+        });
+
+        Ok(dissection.unwrap_or_else(tcp_info))
+    }
+}
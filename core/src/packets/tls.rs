@@ -1,96 +1,1237 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::Mutex;
+use crate::alerts::{Alert, AlertSeverity};
+use super::bytes::{ByteReader, to_hex};
+use super::certificate::CertificateInfo;
+use super::detection::ProbeResult;
+use super::dissector::{Dissection, DissectContext, Dissector};
+use super::flow::{FlowKey, FlowTable};
+use super::http2::Http2Processor;
+use super::reassembly::StreamBuffer;
+use super::tls_decrypt::{DecryptedData, TlsDecryptor};
+use super::tree::{Field, FieldValue};
 
-pub struct TlsProcessor;
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_EC_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_ALPN: u16 = 0x0010;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+const EXT_KEY_SHARE: u16 = 0x0033;
+
+const CONTENT_CHANGE_CIPHER_SPEC: u8 = 0x14;
+const CONTENT_HANDSHAKE: u8 = 0x16;
+
+/// Largest record allowed on the wire (2^14 plus expansion for protection)
+const MAX_RECORD_LEN: usize = 16384 + 2048;
+/// Largest handshake message buffered across records; certificate chains
+/// are the longest in practice
+const MAX_HANDSHAKE_LEN: usize = 256 * 1024;
+
+/// Per-direction state of a TLS connection
+#[derive(Default)]
+struct TlsStream {
+    records: StreamBuffer,
+    /// Start of a handshake message that continues in the next record
+    handshake: Vec<u8>,
+    /// ChangeCipherSpec was sent, later handshake records are encrypted
+    encrypted: bool,
+}
+
+pub struct TlsProcessor {
+    /// Record reassembly state, keyed by the flow of each direction
+    streams: Mutex<FlowTable<FlowKey, TlsStream>>,
+    /// Negotiated version, keyed by the canonical flow
+    versions: Mutex<FlowTable<FlowKey, u16>>,
+    /// SNI requested by the client, keyed by the canonical flow
+    server_names: Mutex<FlowTable<FlowKey, String>>,
+    /// Every distinct certificate seen so far, in order of appearance
+    certificates: Mutex<Vec<CertificateInfo>>,
+    decryptor: Mutex<TlsDecryptor>,
+}
 
 impl TlsProcessor {
     pub fn new() -> Self {
-        Self
+        Self {
+            streams: Mutex::new(FlowTable::new()),
+            versions: Mutex::new(FlowTable::new()),
+            server_names: Mutex::new(FlowTable::new()),
+            certificates: Mutex::new(Vec::new()),
+            decryptor: Mutex::new(TlsDecryptor::new()),
+        }
     }
 
-    pub fn process(&self, payload: &[u8]) -> Result<TlsPacket> {
-        if payload.len() < 5 {
-            return Err(anyhow::anyhow!("Packet too short for TLS"));
-        }
+    /// Loads session secrets from an NSS key log file (`SSLKEYLOGFILE`)
+    pub fn load_keylog(&self, path: &Path) -> Result<usize> {
+        self.decryptor.lock().unwrap().load_keylog(path)
+    }
 
-        let content_type = payload[0];
-        let version = u16::from_be_bytes([payload[1], payload[2]]);
-        let length = u16::from_be_bytes([payload[3], payload[4]]) as usize;
+    /// Adds session secrets given as key log text, e.g. from a pcapng Decryption Secrets Block
+    pub fn add_secrets(&self, keylog: &str) -> usize {
+        self.decryptor.lock().unwrap().add_secrets(keylog)
+    }
+
+    /// Feeds a TCP segment of a TLS connection and decodes every record it
+    /// completes. Records split over several segments are buffered per
+    /// direction; an error means the segment did not complete any record.
+    /// `at` is the capture time of the segment.
+    pub fn process_segment(&self, flow: &FlowKey, seq: u32, payload: &[u8], at: DateTime<Utc>) -> Result<TlsPacket> {
+        if payload.is_empty() {
+            return Err(anyhow::anyhow!("No TLS payload"));
+        }
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.entry(*flow, at, TlsStream::default);
+        if !stream.records.push(seq, payload) {
+            stream.handshake.clear();
+        }
 
-        if payload.len() < 5 + length {
-            return Err(anyhow::anyhow!("Packet truncated"));
+        let records = take_records(&mut stream.records);
+        if records.is_empty() {
+            return Err(anyhow::anyhow!("No complete TLS record"));
         }
 
-        let tls_version = match version {
-            0x0301 => "TLS 1.0",
-            0x0302 => "TLS 1.1",
-            0x0303 => "TLS 1.2",
-            0x0304 => "TLS 1.3",
-            _ => "Unknown TLS version",
+        let mut packet = TlsPacket {
+            version: String::new(),
+            record_version: u16::from_be_bytes([records[0][1], records[0][2]]),
+            records: Vec::new(),
+            handshake_types: Vec::new(),
+            messages: Vec::new(),
+            client_hello: None,
+            server_hello: None,
+            certificates: Vec::new(),
+            decrypted: Vec::new(),
         };
 
-        let content_type_str = match content_type {
-            0x14 => "Change Cipher Spec",
-            0x15 => "Alert",
-            0x16 => "Handshake",
-            0x17 => "Application Data",
-            0x18 => "Heartbeat",
-            _ => "Unknown",
+        for record in &records {
+            let content_type = record[0];
+            let body = &record[5..];
+            packet.records.push(TlsRecord {
+                content_type,
+                version: u16::from_be_bytes([record[1], record[2]]),
+                length: body.len(),
+            });
+
+            match content_type {
+                CONTENT_HANDSHAKE if stream.encrypted => {
+                    packet.messages.push("Encrypted Handshake Message");
+                }
+                CONTENT_HANDSHAKE => {
+                    let completed = packet.handshake_types.len();
+                    stream.handshake.extend_from_slice(body);
+                    let consumed = packet.parse_handshake(&stream.handshake);
+                    stream.handshake.drain(..consumed);
+                    if stream.handshake.len() > MAX_HANDSHAKE_LEN {
+                        stream.handshake.clear();
+                    }
+                    packet.messages.extend_from_slice(&packet.handshake_types[completed..]);
+                }
+                _ => {
+                    if content_type == CONTENT_CHANGE_CIPHER_SPEC {
+                        stream.encrypted = true;
+                    }
+                    packet.messages.push(content_type_name(content_type));
+                }
+            }
+        }
+        drop(streams);
+
+        let connection = flow.canonical();
+        let mut versions = self.versions.lock().unwrap();
+        if let Some(hello) = &packet.server_hello {
+            versions.insert(connection, at, hello.version());
+        }
+        // The record version is frozen at TLS 1.2 for TLS 1.3, so prefer the
+        // version negotiated through the supported_versions extension
+        let version = match (&packet.client_hello, versions.get(&connection)) {
+            (Some(hello), _) => hello.version(),
+            (None, Some(version)) => *version,
+            (None, None) => packet.record_version,
         };
+        packet.version = version_name(version).to_string();
+        drop(versions);
+
+        let mut decryptor = self.decryptor.lock().unwrap();
+        if decryptor.is_enabled() {
+            packet.decrypted = decryptor.process_records(flow, &records, at);
+        }
+
+        Ok(packet)
+    }
+
+    /// Correlates a decoded record with earlier records of the same connection
+    /// and returns alerts for suspicious certificates.
+    ///
+    /// `at` is the capture time used to judge certificate validity.
+    pub fn inspect(&self, flow: &FlowKey, packet: &TlsPacket, at: DateTime<Utc>, timestamp: &str) -> Vec<Alert> {
+        let mut alerts = Vec::new();
 
-        // Check for SSL/TLS handshake
-        let handshake_type = if content_type == 0x16 && payload.len() > 5 {
-            match payload[5] {
-                0x01 => "Client Hello",
-                0x02 => "Server Hello",
-                0x0b => "Certificate",
-                0x0c => "Server Key Exchange",
-                0x0e => "Server Hello Done",
-                0x10 => "Client Key Exchange",
-                0x14 => "Finished",
-                _ => "Unknown handshake type",
+        if let Some(sni) = packet.client_hello.as_ref().and_then(|h| h.server_name.clone()) {
+            self.server_names.lock().unwrap().insert(flow.canonical(), at, sni);
+        }
+
+        if packet.certificates.is_empty() {
+            return alerts;
+        }
+
+        let server_name = self.server_names.lock().unwrap().remove(&flow.canonical());
+        let server = format!("{}:{}", flow.src, flow.src_port);
+
+        for (index, cert) in packet.certificates.iter().enumerate() {
+            let name = cert.common_name.as_deref().unwrap_or(&cert.subject);
+            if cert.is_expired(at) {
+                alerts.push(Alert::new(
+                    timestamp,
+                    AlertSeverity::Warning,
+                    "tls.certificate.expired",
+                    format!("{} presented certificate '{}' that expired on {}", server, name, cert.not_after),
+                ));
             }
-        } else {
-            "Continuation Data"
+            if cert.is_not_yet_valid(at) {
+                alerts.push(Alert::new(
+                    timestamp,
+                    AlertSeverity::Warning,
+                    "tls.certificate.not_yet_valid",
+                    format!("{} presented certificate '{}' that is not valid before {}", server, name, cert.not_before),
+                ));
+            }
+            // A self-signed root at the end of a chain is normal, a self-signed leaf is not
+            if index == 0 && cert.self_signed {
+                alerts.push(Alert::new(
+                    timestamp,
+                    AlertSeverity::Warning,
+                    "tls.certificate.self_signed",
+                    format!("{} presented self-signed certificate '{}'", server, name),
+                ));
+            }
+        }
+
+        if let (Some(sni), Some(leaf)) = (server_name, packet.certificates.first()) {
+            if !leaf.matches_hostname(&sni) {
+                alerts.push(Alert::new(
+                    timestamp,
+                    AlertSeverity::Critical,
+                    "tls.certificate.sni_mismatch",
+                    format!(
+                        "{} presented certificate for '{}' but the client requested '{}'",
+                        server,
+                        leaf.common_name.as_deref().unwrap_or(&leaf.subject),
+                        sni
+                    ),
+                ));
+            }
+        }
+
+        let mut certificates = self.certificates.lock().unwrap();
+        for cert in &packet.certificates {
+            if !certificates.iter().any(|known| known.sha256 == cert.sha256) {
+                certificates.push(cert.clone());
+            }
+        }
+
+        alerts
+    }
+
+    /// Returns every distinct certificate seen so far
+    pub fn certificates(&self) -> Vec<CertificateInfo> {
+        self.certificates.lock().unwrap().clone()
+    }
+
+    /// Drops the state of both directions of a closed connection
+    fn close(&self, flow: &FlowKey) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.remove(flow);
+        streams.remove(&flow.reversed());
+        drop(streams);
+        let connection = flow.canonical();
+        self.versions.lock().unwrap().remove(&connection);
+        self.server_names.lock().unwrap().remove(&connection);
+        self.decryptor.lock().unwrap().close(&connection);
+    }
+}
+
+impl Dissector for TlsProcessor {
+    fn name(&self) -> &'static str {
+        "TLS"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let (Some(flow), Some(seq)) = (ctx.flow, ctx.tcp_sequence) else {
+            return Err(anyhow::anyhow!("TLS needs a TCP flow"));
         };
+        let tls = self.process_segment(&flow, seq, data, ctx.captured_at);
+        if ctx.flow_closed {
+            self.close(&flow);
+            // The HTTP/2 state of the decrypted data goes with the connection;
+            // the HTTP/2 dissector drops it on seeing the closing segment
+            if let Some(http2) = ctx.registry().by_name("HTTP2") {
+                let _ = ctx.dissect_with(http2.as_ref(), &[]);
+            }
+        }
+        let tls = tls?;
+        for alert in self.inspect(&flow, &tls, ctx.captured_at, &ctx.timestamp) {
+            ctx.raise(alert);
+        }
+        ctx.add_fields(tls.fields());
+
+        if tls.decrypted.is_empty() {
+            return Ok(Dissection::new("TLS", tls.format_info()));
+        }
+
+        // Decrypted application data goes to the HTTP/1 and HTTP/2 dissectors
+        ctx.add_field("tls.decrypted", true);
+        let registry = ctx.registry();
+        let mut protocol = "TLS".to_string();
+        let mut summaries = Vec::new();
+        for record in &tls.decrypted {
+            let is_http2 = record.alpn.as_deref() == Some("h2") || Http2Processor::is_preface(&record.data);
+            let dissection = is_http2
+                .then(|| registry.by_name("HTTP2"))
+                .flatten()
+                .and_then(|http2| ctx.dissect_with(http2.as_ref(), &record.data).ok())
+                .or_else(|| {
+                    registry
+                        .by_name("HTTP")
+                        .and_then(|http| ctx.dissect_with(http.as_ref(), &record.data).ok())
+                });
+            match dissection {
+                Some(dissection) => {
+                    protocol = dissection.protocol;
+                    summaries.push(dissection.info);
+                }
+                None => summaries.push(format!("Application Data, {} bytes", record.data.len())),
+            }
+        }
+
+        let version = version_name(tls.decrypted[0].version);
+        Ok(Dissection::new(protocol, format!("{} [decrypted {}]", summaries.join(", "), version)))
+    }
+
+    /// Heuristic probe: a record header with a known content type and an
+    /// SSL 3.0 to TLS 1.3 version, most confident for a hello message
+    fn probe(&self, payload: &[u8]) -> Option<ProbeResult> {
+        if payload.len() < 5 || payload[1] != 0x03 || payload[2] > 0x04 {
+            return None;
+        }
+        let length = u16::from_be_bytes([payload[3], payload[4]]) as usize;
+        if length == 0 || length > MAX_RECORD_LEN {
+            return None;
+        }
+        match payload[0] {
+            CONTENT_HANDSHAKE if matches!(payload.get(5), Some(0x01 | 0x02)) => {
+                Some(ProbeResult::new(90, "TLS hello record"))
+            }
+            0x14..=0x18 => Some(ProbeResult::new(55, "TLS record header")),
+            _ => None,
+        }
+    }
+}
 
-        Ok(TlsPacket {
-            version: tls_version.to_string(),
-            content_type: content_type_str.to_string(),
-            handshake_type: handshake_type.to_string(),
-            length,
-        })
+/// Header of one TLS record
+#[derive(Debug, Clone)]
+pub struct TlsRecord {
+    pub content_type: u8,
+    pub version: u16,
+    pub length: usize,
+}
+
+impl TlsRecord {
+    pub fn content_type_name(&self) -> &'static str {
+        content_type_name(self.content_type)
     }
 }
 
+/// The TLS records completed by one TCP segment
 pub struct TlsPacket {
     version: String,
-    content_type: String,
-    handshake_type: String,
-    length: usize,
+    record_version: u16,
+    records: Vec<TlsRecord>,
+    /// Every handshake message completed in these records, in order
+    handshake_types: Vec<&'static str>,
+    /// Handshake messages and the types of the other records, in order
+    messages: Vec<&'static str>,
+    client_hello: Option<ClientHello>,
+    server_hello: Option<ServerHello>,
+    certificates: Vec<CertificateInfo>,
+    decrypted: Vec<DecryptedData>,
 }
 
 impl TlsPacket {
+    /// Decodes the complete handshake messages at the start of `data` and
+    /// returns the number of bytes consumed
+    fn parse_handshake(&mut self, data: &[u8]) -> usize {
+        let mut reader = ByteReader::new(data);
+        let mut consumed = 0;
+        while let (Ok(msg_type), Ok(body)) = (reader.read_u8(), reader.read_vec24()) {
+            self.handshake_types.push(handshake_type_name(msg_type));
+            match msg_type {
+                0x01 => self.client_hello = ClientHello::parse(body).ok(),
+                0x02 => self.server_hello = ServerHello::parse(body).ok(),
+                0x0b => self.certificates = parse_certificate_list(body).unwrap_or_default(),
+                _ => {}
+            }
+            consumed = data.len() - reader.remaining();
+        }
+        consumed
+    }
+
     pub fn get_version(&self) -> &str {
         &self.version
     }
 
+    pub fn get_record_version(&self) -> u16 {
+        self.record_version
+    }
+
+    pub fn get_records(&self) -> &[TlsRecord] {
+        &self.records
+    }
+
     pub fn get_content_type(&self) -> &str {
-        &self.content_type
+        self.records[0].content_type_name()
     }
 
-    pub fn get_handshake_type(&self) -> &str {
-        &self.handshake_type
+    pub fn get_handshake_types(&self) -> &[&'static str] {
+        &self.handshake_types
     }
 
     pub fn get_length(&self) -> usize {
-        self.length
+        self.records.iter().map(|r| r.length).sum()
+    }
+
+    pub fn get_client_hello(&self) -> Option<&ClientHello> {
+        self.client_hello.as_ref()
+    }
+
+    pub fn get_server_hello(&self) -> Option<&ServerHello> {
+        self.server_hello.as_ref()
+    }
+
+    /// Certificate chain from a Certificate message, leaf first
+    pub fn get_certificates(&self) -> &[CertificateInfo] {
+        &self.certificates
+    }
+
+    /// Application data of the records that could be decrypted
+    pub fn get_decrypted(&self) -> &[DecryptedData] {
+        &self.decrypted
+    }
+
+    /// Lists the messages in the order they were sent, collapsing repeats
+    fn summary(&self) -> String {
+        let mut messages = self.messages.clone();
+        messages.dedup();
+        if messages.is_empty() {
+            // Handshake records holding only the start of a message
+            return "Handshake Fragment".to_string();
+        }
+        messages.join(", ")
     }
 
     pub fn format_info(&self) -> String {
-        if self.content_type == "Handshake" {
-            format!("{} {}", self.version, self.handshake_type)
+        let mut info = format!("{} {}", self.version, self.summary());
+        if let Some(hello) = &self.client_hello {
+            if let Some(sni) = &hello.server_name {
+                info.push_str(&format!(", SNI={}", sni));
+            }
+            if !hello.alpn.is_empty() {
+                info.push_str(&format!(", ALPN={}", hello.alpn.join(",")));
+            }
+            info.push_str(&format!(", JA3={}, JA4={}", hello.ja3(), hello.ja4('t')));
+        }
+        if let Some(hello) = &self.server_hello {
+            info.push_str(&format!(", Cipher={}", cipher_suite_name(hello.cipher_suite)));
+            if let Some(alpn) = &hello.alpn {
+                info.push_str(&format!(", ALPN={}", alpn));
+            }
+            info.push_str(&format!(", JA3S={}", hello.ja3s()));
+        }
+        if let Some(leaf) = self.certificates.first() {
+            info.push_str(&format!(", {} cert(s), {}", self.certificates.len(), leaf.format_info()));
+        }
+        info
+    }
+
+    /// Returns the decoded TLS fields as `(name, value)` pairs for display filtering
+    pub fn fields(&self) -> Vec<Field> {
+        let mut fields = vec![
+            Field::new("tls.record.version", FieldValue::Hex(self.record_version as u64)),
+            Field::new("tls.version", self.version.clone()),
+        ];
+        for record in &self.records {
+            fields.push(Field::new("tls.record.content_type", record.content_type_name()));
+            fields.push(Field::new("tls.record.length", record.length));
+        }
+        for handshake_type in &self.handshake_types {
+            fields.push(Field::new("tls.handshake.type", *handshake_type));
+        }
+
+        if let Some(hello) = &self.client_hello {
+            if let Some(sni) = &hello.server_name {
+                fields.push(Field::new("tls.sni", sni.clone()));
+            }
+            for alpn in &hello.alpn {
+                fields.push(Field::new("tls.alpn", alpn.clone()));
+            }
+            fields.push(Field::new("tls.session_id", FieldValue::Bytes(hello.session_id.clone())));
+            for suite in &hello.cipher_suites {
+                fields.push(Field::new("tls.cipher_suite", cipher_suite_name(*suite)));
+            }
+            for ext in &hello.extensions {
+                fields.push(Field::new("tls.extension", ext.to_string()));
+            }
+            for version in &hello.supported_versions {
+                fields.push(Field::new("tls.supported_version", version_name(*version)));
+            }
+            for group in &hello.key_share_groups {
+                fields.push(Field::new("tls.key_share_group", group_name(*group)));
+            }
+            fields.push(Field::new("tls.ja3_full", hello.ja3_string()));
+            fields.push(Field::new("tls.ja3", hello.ja3()));
+            fields.push(Field::new("tls.ja4", hello.ja4('t')));
+        }
+
+        if let Some(hello) = &self.server_hello {
+            fields.push(Field::new("tls.session_id", FieldValue::Bytes(hello.session_id.clone())));
+            fields.push(Field::new("tls.cipher_suite", cipher_suite_name(hello.cipher_suite)));
+            if let Some(alpn) = &hello.alpn {
+                fields.push(Field::new("tls.alpn", alpn.clone()));
+            }
+            for ext in &hello.extensions {
+                fields.push(Field::new("tls.extension", ext.to_string()));
+            }
+            if let Some(group) = hello.key_share_group {
+                fields.push(Field::new("tls.key_share_group", group_name(group)));
+            }
+            fields.push(Field::new("tls.ja3s_full", hello.ja3s_string()));
+            fields.push(Field::new("tls.ja3s", hello.ja3s()));
+        }
+
+        for cert in &self.certificates {
+            fields.push(Field::new("tls.cert.subject", cert.subject.clone()));
+            fields.push(Field::new("tls.cert.issuer", cert.issuer.clone()));
+            for san in &cert.subject_alt_names {
+                fields.push(Field::new("tls.cert.san", san.clone()));
+            }
+            fields.push(Field::new("tls.cert.not_before", cert.not_before.to_rfc3339()));
+            fields.push(Field::new("tls.cert.not_after", cert.not_after.to_rfc3339()));
+            fields.push(Field::new("tls.cert.key_type", cert.key_type.clone()));
+            fields.push(Field::new("tls.cert.key_bits", cert.key_bits));
+            fields.push(Field::new("tls.cert.sha256", cert.sha256.clone()));
+        }
+
+        fields
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientHello {
+    pub legacy_version: u16,
+    pub random: [u8; 32],
+    pub session_id: Vec<u8>,
+    pub cipher_suites: Vec<u16>,
+    pub compression_methods: Vec<u8>,
+    /// Extension types in the order they were sent
+    pub extensions: Vec<u16>,
+    pub server_name: Option<String>,
+    pub alpn: Vec<String>,
+    pub supported_versions: Vec<u16>,
+    pub supported_groups: Vec<u16>,
+    pub ec_point_formats: Vec<u8>,
+    pub signature_algorithms: Vec<u16>,
+    pub key_share_groups: Vec<u16>,
+}
+
+impl ClientHello {
+    /// Parses the body of a ClientHello handshake message (without the handshake header)
+    pub fn parse(body: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new(body);
+        let legacy_version = reader.read_u16()?;
+        let random: [u8; 32] = reader.read_bytes(32)?.try_into()?;
+        let session_id = reader.read_vec8()?.to_vec();
+        let cipher_suites = read_u16_list(reader.read_vec16()?);
+        let compression_methods = reader.read_vec8()?.to_vec();
+
+        let mut hello = Self {
+            legacy_version,
+            random,
+            session_id,
+            cipher_suites,
+            compression_methods,
+            extensions: Vec::new(),
+            server_name: None,
+            alpn: Vec::new(),
+            supported_versions: Vec::new(),
+            supported_groups: Vec::new(),
+            ec_point_formats: Vec::new(),
+            signature_algorithms: Vec::new(),
+            key_share_groups: Vec::new(),
+        };
+
+        // Extensions are optional in pre-TLS 1.2 hellos
+        if reader.is_empty() {
+            return Ok(hello);
+        }
+
+        let mut extensions = ByteReader::new(reader.read_vec16()?);
+        while !extensions.is_empty() {
+            let ext_type = extensions.read_u16()?;
+            let mut data = ByteReader::new(extensions.read_vec16()?);
+            hello.extensions.push(ext_type);
+
+            match ext_type {
+                EXT_SERVER_NAME => {
+                    let mut list = ByteReader::new(data.read_vec16()?);
+                    while !list.is_empty() {
+                        let name_type = list.read_u8()?;
+                        let name = list.read_vec16()?;
+                        if name_type == 0 {
+                            hello.server_name = Some(String::from_utf8_lossy(name).to_string());
+                        }
+                    }
+                }
+                EXT_ALPN => hello.alpn = read_alpn_list(&mut data)?,
+                EXT_SUPPORTED_VERSIONS => {
+                    hello.supported_versions = read_u16_list(data.read_vec8()?);
+                }
+                EXT_SUPPORTED_GROUPS => {
+                    hello.supported_groups = read_u16_list(data.read_vec16()?);
+                }
+                EXT_EC_POINT_FORMATS => {
+                    hello.ec_point_formats = data.read_vec8()?.to_vec();
+                }
+                EXT_SIGNATURE_ALGORITHMS => {
+                    hello.signature_algorithms = read_u16_list(data.read_vec16()?);
+                }
+                EXT_KEY_SHARE => {
+                    let mut shares = ByteReader::new(data.read_vec16()?);
+                    while !shares.is_empty() {
+                        let group = shares.read_u16()?;
+                        shares.read_vec16()?;
+                        hello.key_share_groups.push(group);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(hello)
+    }
+
+    /// Highest offered version, taking supported_versions into account
+    pub fn version(&self) -> u16 {
+        self.supported_versions
+            .iter()
+            .copied()
+            .filter(|v| !is_grease(*v))
+            .max()
+            .unwrap_or(self.legacy_version)
+    }
+
+    pub fn ja3_string(&self) -> String {
+        format!(
+            "{},{},{},{},{}",
+            self.legacy_version,
+            join_decimal(&self.cipher_suites),
+            join_decimal(&self.extensions),
+            join_decimal(&self.supported_groups),
+            self.ec_point_formats
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>()
+                .join("-")
+        )
+    }
+
+    pub fn ja3(&self) -> String {
+        to_hex(&Md5::digest(self.ja3_string().as_bytes()))
+    }
+
+    /// Computes the JA4 fingerprint; `transport` is `'t'` for TCP or `'q'` for QUIC
+    pub fn ja4(&self, transport: char) -> String {
+        let version = match self.version() {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            0x0200 => "s2",
+            _ => "00",
+        };
+        let sni = if self.server_name.is_some() { 'd' } else { 'i' };
+
+        let ciphers: Vec<u16> = self.cipher_suites.iter().copied().filter(|c| !is_grease(*c)).collect();
+        let extensions: Vec<u16> = self.extensions.iter().copied().filter(|e| !is_grease(*e)).collect();
+
+        let alpn = match self.alpn.first().map(|a| a.as_bytes()) {
+            Some(value) if !value.is_empty() => {
+                let first = value[0];
+                let last = value[value.len() - 1];
+                if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+                    format!("{}{}", first as char, last as char)
+                } else {
+                    let hex = to_hex(value);
+                    format!("{}{}", &hex[..1], &hex[hex.len() - 1..])
+                }
+            }
+            _ => "00".to_string(),
+        };
+
+        let mut sorted_ciphers = ciphers.clone();
+        sorted_ciphers.sort_unstable();
+        let cipher_hash = if sorted_ciphers.is_empty() {
+            "000000000000".to_string()
+        } else {
+            truncated_sha256(&join_hex(&sorted_ciphers))
+        };
+
+        let mut sorted_extensions: Vec<u16> = extensions
+            .iter()
+            .copied()
+            .filter(|e| *e != EXT_SERVER_NAME && *e != EXT_ALPN)
+            .collect();
+        sorted_extensions.sort_unstable();
+        let extension_hash = if sorted_extensions.is_empty() {
+            "000000000000".to_string()
         } else {
-            format!("{} {}", self.version, self.content_type)
+            let mut input = join_hex(&sorted_extensions);
+            if !self.signature_algorithms.is_empty() {
+                input.push('_');
+                input.push_str(&join_hex(&self.signature_algorithms));
+            }
+            truncated_sha256(&input)
+        };
+
+        format!(
+            "{}{}{}{:02}{:02}{}_{}_{}",
+            transport,
+            version,
+            sni,
+            ciphers.len().min(99),
+            extensions.len().min(99),
+            alpn,
+            cipher_hash,
+            extension_hash
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerHello {
+    pub legacy_version: u16,
+    pub random: [u8; 32],
+    pub session_id: Vec<u8>,
+    pub cipher_suite: u16,
+    pub compression_method: u8,
+    /// Extension types in the order they were sent
+    pub extensions: Vec<u16>,
+    pub supported_version: Option<u16>,
+    pub alpn: Option<String>,
+    pub key_share_group: Option<u16>,
+}
+
+impl ServerHello {
+    /// Parses the body of a ServerHello handshake message (without the handshake header)
+    pub fn parse(body: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new(body);
+        let mut hello = Self {
+            legacy_version: reader.read_u16()?,
+            random: reader.read_bytes(32)?.try_into()?,
+            session_id: reader.read_vec8()?.to_vec(),
+            cipher_suite: reader.read_u16()?,
+            compression_method: reader.read_u8()?,
+            extensions: Vec::new(),
+            supported_version: None,
+            alpn: None,
+            key_share_group: None,
+        };
+
+        if reader.is_empty() {
+            return Ok(hello);
+        }
+
+        let mut extensions = ByteReader::new(reader.read_vec16()?);
+        while !extensions.is_empty() {
+            let ext_type = extensions.read_u16()?;
+            let mut data = ByteReader::new(extensions.read_vec16()?);
+            hello.extensions.push(ext_type);
+
+            match ext_type {
+                EXT_SUPPORTED_VERSIONS => hello.supported_version = Some(data.read_u16()?),
+                EXT_ALPN => hello.alpn = read_alpn_list(&mut data)?.into_iter().next(),
+                EXT_KEY_SHARE => hello.key_share_group = Some(data.read_u16()?),
+                _ => {}
+            }
+        }
+
+        Ok(hello)
+    }
+
+    /// Negotiated version, taking supported_versions into account
+    pub fn version(&self) -> u16 {
+        self.supported_version.unwrap_or(self.legacy_version)
+    }
+
+    pub fn ja3s_string(&self) -> String {
+        format!(
+            "{},{},{}",
+            self.legacy_version,
+            self.cipher_suite,
+            join_decimal(&self.extensions)
+        )
+    }
+
+    pub fn ja3s(&self) -> String {
+        to_hex(&Md5::digest(self.ja3s_string().as_bytes()))
+    }
+}
+
+/// Parses the TLS 1.2 Certificate message body into its chain of certificates
+fn parse_certificate_list(body: &[u8]) -> Result<Vec<CertificateInfo>> {
+    let mut list = ByteReader::new(ByteReader::new(body).read_vec24()?);
+    let mut certificates = Vec::new();
+    while !list.is_empty() {
+        certificates.push(CertificateInfo::parse(list.read_vec24()?)?);
+    }
+    Ok(certificates)
+}
+
+/// Splits the complete records off the front of a reassembled stream. Data
+/// that does not start with a record header is dropped.
+fn take_records(buffer: &mut StreamBuffer) -> Vec<Vec<u8>> {
+    let mut records = Vec::new();
+    loop {
+        let data = buffer.data();
+        if data.len() < 5 {
+            break;
+        }
+        let length = u16::from_be_bytes([data[3], data[4]]) as usize;
+        if !(0x14..=0x18).contains(&data[0]) || data[1] != 0x03 || length > MAX_RECORD_LEN {
+            // Not aligned on a record boundary; wait for the stream to resynchronise
+            buffer.clear();
+            break;
+        }
+        if data.len() < 5 + length {
+            break;
+        }
+        records.push(data[..5 + length].to_vec());
+        buffer.consume(5 + length);
+    }
+    records
+}
+
+fn content_type_name(content_type: u8) -> &'static str {
+    match content_type {
+        0x14 => "Change Cipher Spec",
+        0x15 => "Alert",
+        0x16 => "Handshake",
+        0x17 => "Application Data",
+        0x18 => "Heartbeat",
+        _ => "Unknown",
+    }
+}
+
+fn handshake_type_name(handshake_type: u8) -> &'static str {
+    match handshake_type {
+        0x00 => "Hello Request",
+        0x01 => "Client Hello",
+        0x02 => "Server Hello",
+        0x04 => "New Session Ticket",
+        0x08 => "Encrypted Extensions",
+        0x0b => "Certificate",
+        0x0c => "Server Key Exchange",
+        0x0d => "Certificate Request",
+        0x0e => "Server Hello Done",
+        0x0f => "Certificate Verify",
+        0x10 => "Client Key Exchange",
+        0x14 => "Finished",
+        _ => "Unknown handshake type",
+    }
+}
+
+pub fn version_name(version: u16) -> &'static str {
+    match version {
+        0x0300 => "SSL 3.0",
+        0x0301 => "TLS 1.0",
+        0x0302 => "TLS 1.1",
+        0x0303 => "TLS 1.2",
+        0x0304 => "TLS 1.3",
+        _ => "Unknown TLS version",
+    }
+}
+
+pub fn cipher_suite_name(suite: u16) -> String {
+    let name = match suite {
+        0x1301 => "TLS_AES_128_GCM_SHA256",
+        0x1302 => "TLS_AES_256_GCM_SHA384",
+        0x1303 => "TLS_CHACHA20_POLY1305_SHA256",
+        0x1304 => "TLS_AES_128_CCM_SHA256",
+        0x1305 => "TLS_AES_128_CCM_8_SHA256",
+        0x002f => "TLS_RSA_WITH_AES_128_CBC_SHA",
+        0x0035 => "TLS_RSA_WITH_AES_256_CBC_SHA",
+        0x003c => "TLS_RSA_WITH_AES_128_CBC_SHA256",
+        0x003d => "TLS_RSA_WITH_AES_256_CBC_SHA256",
+        0x009c => "TLS_RSA_WITH_AES_128_GCM_SHA256",
+        0x009d => "TLS_RSA_WITH_AES_256_GCM_SHA384",
+        0x009e => "TLS_DHE_RSA_WITH_AES_128_GCM_SHA256",
+        0x009f => "TLS_DHE_RSA_WITH_AES_256_GCM_SHA384",
+        0xc009 => "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA",
+        0xc00a => "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA",
+        0xc013 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA",
+        0xc014 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA",
+        0xc023 => "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256",
+        0xc024 => "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384",
+        0xc027 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256",
+        0xc028 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384",
+        0xc02b => "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+        0xc02c => "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+        0xc02f => "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+        0xc030 => "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+        0xcca8 => "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xcca9 => "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xccaa => "TLS_DHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+        0x00ff => "TLS_EMPTY_RENEGOTIATION_INFO_SCSV",
+        0x5600 => "TLS_FALLBACK_SCSV",
+        s if is_grease(s) => "GREASE",
+        _ => return format!("0x{:04x}", suite),
+    };
+    name.to_string()
+}
+
+pub fn group_name(group: u16) -> String {
+    let name = match group {
+        0x0017 => "secp256r1",
+        0x0018 => "secp384r1",
+        0x0019 => "secp521r1",
+        0x001d => "x25519",
+        0x001e => "x448",
+        0x0100 => "ffdhe2048",
+        0x0101 => "ffdhe3072",
+        0x11ec => "X25519MLKEM768",
+        0x6399 => "X25519Kyber768Draft00",
+        g if is_grease(g) => "GREASE",
+        _ => return format!("0x{:04x}", group),
+    };
+    name.to_string()
+}
+
+/// GREASE values (RFC 8701) are 0x?a?a with identical bytes
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn read_u16_list(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect()
+}
+
+/// Returns the protocol selected in a TLS 1.3 EncryptedExtensions message
+pub(crate) fn encrypted_extensions_alpn(body: &[u8]) -> Option<String> {
+    let mut extensions = ByteReader::new(ByteReader::new(body).read_vec16().ok()?);
+    while !extensions.is_empty() {
+        let ext_type = extensions.read_u16().ok()?;
+        let mut data = ByteReader::new(extensions.read_vec16().ok()?);
+        if ext_type == EXT_ALPN {
+            return read_alpn_list(&mut data).ok()?.into_iter().next();
+        }
+    }
+    None
+}
+
+fn read_alpn_list(data: &mut ByteReader) -> Result<Vec<String>> {
+    let mut list = ByteReader::new(data.read_vec16()?);
+    let mut protocols = Vec::new();
+    while !list.is_empty() {
+        protocols.push(String::from_utf8_lossy(list.read_vec8()?).to_string());
+    }
+    Ok(protocols)
+}
+
+fn join_decimal(values: &[u16]) -> String {
+    values
+        .iter()
+        .filter(|v| !is_grease(**v))
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn join_hex(values: &[u16]) -> String {
+    values
+        .iter()
+        .map(|v| format!("{:04x}", v))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn truncated_sha256(input: &str) -> String {
+    to_hex(&Sha256::digest(input.as_bytes()))[..12].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::AlertChannel;
+    use pnet::packet::tcp::TcpFlags;
+    use super::super::certificate::testing::{LEAF, ROOT};
+    use super::super::dissector::{testing, DissectorRegistry};
+
+    fn hello() -> ClientHello {
+        ClientHello {
+            legacy_version: 0x0303,
+            random: [0; 32],
+            session_id: Vec::new(),
+            cipher_suites: Vec::new(),
+            compression_methods: vec![0],
+            extensions: Vec::new(),
+            server_name: None,
+            alpn: Vec::new(),
+            supported_versions: Vec::new(),
+            supported_groups: Vec::new(),
+            ec_point_formats: Vec::new(),
+            signature_algorithms: Vec::new(),
+            key_share_groups: Vec::new(),
         }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn ja3_matches_reference() {
+        // Example of the JA3 README
+        let hello = ClientHello {
+            legacy_version: 769,
+            cipher_suites: vec![47, 53, 5, 10, 49161, 49162, 49171, 49172, 50, 56, 19, 4],
+            extensions: vec![0, 10, 11],
+            supported_groups: vec![23, 24, 25],
+            ec_point_formats: vec![0],
+            ..hello()
+        };
+        assert_eq!(hello.ja3_string(), "769,47-53-5-10-49161-49162-49171-49172-50-56-19-4,0-10-11,23-24-25,0");
+        assert_eq!(hello.ja3(), "ada70206e40642a3e4461f35503241d5");
+    }
+
+    #[test]
+    fn ja3_skips_grease() {
+        let hello = ClientHello {
+            cipher_suites: vec![0x0a0a, 0x1301],
+            extensions: vec![0x1a1a, 0],
+            supported_groups: vec![0x2a2a, 29],
+            ..hello()
+        };
+        assert_eq!(hello.ja3_string(), "771,4865,0,29,");
+    }
+
+    #[test]
+    fn ja4_matches_reference() {
+        // Example of the JA4 technical details
+        let hello = ClientHello {
+            cipher_suites: vec![
+                0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013, 0xc014, 0x009c,
+                0x009d, 0x002f, 0x0035,
+            ],
+            extensions: vec![
+                0x0a0a, 0x001b, 0x0000, 0x0033, 0x0010, 0x4469, 0x0017, 0x002d, 0x000d, 0x0005, 0x0023, 0x0012,
+                0x002b, 0xff01, 0x000b, 0x000a, 0x0015,
+            ],
+            server_name: Some("example.com".to_string()),
+            alpn: vec!["h2".to_string(), "http/1.1".to_string()],
+            supported_versions: vec![0x3a3a, 0x0304, 0x0303],
+            signature_algorithms: vec![0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601],
+            ..hello()
+        };
+        assert_eq!(hello.ja4('t'), "t13d1516h2_8daaf6152771_e5627efa2ab1");
+        assert_eq!(hello.ja4('q'), "q13d1516h2_8daaf6152771_e5627efa2ab1");
+    }
+
+    #[test]
+    fn ja4_without_ciphers_or_extensions() {
+        assert_eq!(hello().ja4('t'), "t12i000000_000000000000_000000000000");
+    }
+
+    #[test]
+    fn parses_client_hello() {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[7; 32]);
+        body.push(0);
+        body.extend_from_slice(&[0x00, 0x04, 0x13, 0x01, 0x13, 0x02]);
+        body.extend_from_slice(&[0x01, 0x00]);
+        let extensions = [
+            // server_name: host_name "a.example"
+            &[0x00, 0x00, 0x00, 0x0e, 0x00, 0x0c, 0x00, 0x00, 0x09][..],
+            b"a.example",
+            // ALPN: h2
+            &[0x00, 0x10, 0x00, 0x05, 0x00, 0x03, 0x02, b'h', b'2'],
+            // supported_versions: TLS 1.3
+            &[0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04],
+        ]
+        .concat();
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend_from_slice(&extensions);
+
+        let hello = ClientHello::parse(&body).unwrap();
+        assert_eq!(hello.cipher_suites, [0x1301, 0x1302]);
+        assert_eq!(hello.extensions, [0x0000, 0x0010, 0x002b]);
+        assert_eq!(hello.server_name.as_deref(), Some("a.example"));
+        assert_eq!(hello.alpn, ["h2"]);
+        assert_eq!(hello.version(), 0x0304);
+
+        // Every truncation is an error, not a panic
+        for end in 0..body.len() {
+            let _ = ClientHello::parse(&body[..end]);
+        }
+        assert!(ClientHello::parse(&body[..40]).is_err());
+    }
+
+    #[test]
+    fn forgets_connections_once_closed() {
+        let tls = TlsProcessor::new();
+        let registry = DissectorRegistry::new();
+        let (client, server) = (testing::flow(443), testing::flow(443).reversed());
+        let alert = [0x15, 0x03, 0x03, 0x00, 0x02, 0x01, 0x00];
+        // A segment as the TCP dissector hands it on
+        let segment = |flow: FlowKey, seq: u32, flags: u8, data: &[u8]| {
+            let closed = testing::close_flow(&registry, &flow, flags, Utc::now());
+            let _ = testing::dissect_in(&registry, &AlertChannel::new(), &tls, data, |ctx| {
+                ctx.flow = Some(flow);
+                ctx.tcp_sequence = Some(seq);
+                ctx.tcp_flags = Some(flags);
+                ctx.flow_closed = closed;
+            });
+        };
+        segment(client, 1, TcpFlags::ACK, &alert);
+        segment(server, 1, TcpFlags::ACK, &alert);
+        segment(client, 8, TcpFlags::FIN | TcpFlags::ACK, &[]);
+        assert!(tls.streams.lock().unwrap().get(&client).is_some());
+        segment(server, 8, TcpFlags::FIN | TcpFlags::ACK, &[]);
+        assert!(tls.streams.lock().unwrap().get(&client).is_none());
+        assert!(tls.streams.lock().unwrap().get(&server).is_none());
+
+        segment(client, 8, TcpFlags::ACK, &alert);
+        segment(server, 15, TcpFlags::RST, &[]);
+        assert!(tls.streams.lock().unwrap().get(&client).is_none());
+        // Segments without payload do not bring the state back
+        assert!(tls.process_segment(&client, 15, &[], Utc::now()).is_err());
+        assert!(tls.streams.lock().unwrap().get(&client).is_none());
+    }
+
+    #[test]
+    fn caps_buffered_handshake_messages() {
+        let tls = TlsProcessor::new();
+        // A Certificate message announcing 16 MiB, sent in full records
+        let mut seq = 1;
+        let mut body = vec![0x0b, 0xff, 0xff, 0xff];
+        body.resize(16384, 0);
+        for _ in 0..20 {
+            let record = [&[0x16, 0x03, 0x03, 0x40, 0x00][..], &body].concat();
+            tls.process_segment(&testing::flow(443), seq, &record, Utc::now()).unwrap();
+            seq += record.len() as u32;
+            body = vec![0; 16384];
+        }
+        let streams = tls.streams.lock().unwrap();
+        assert!(streams.get(&testing::flow(443)).unwrap().handshake.len() <= MAX_HANDSHAKE_LEN);
+    }
+
+    #[test]
+    fn resynchronises_on_garbage() {
+        let tls = TlsProcessor::new();
+        assert!(tls.process_segment(&testing::flow(443), 1, b"GET / HTTP/1.1\r\n", Utc::now()).is_err());
+        let record = [0x16, 0x03, 0x03, 0x00, 0x04, 0x0e, 0x00, 0x00, 0x00];
+        let packet = tls.process_segment(&testing::flow(443), 100, &record, Utc::now()).unwrap();
+        assert_eq!(packet.get_handshake_types(), ["Server Hello Done"]);
+    }
+
+    /// Record with one handshake message of type `msg_type`
+    fn handshake(msg_type: u8, body: &[u8]) -> Vec<u8> {
+        let length = (body.len() as u32).to_be_bytes();
+        let mut record = vec![0x16, 0x03, 0x03];
+        record.extend_from_slice(&(body.len() as u16 + 4).to_be_bytes());
+        record.extend_from_slice(&[msg_type, length[1], length[2], length[3]]);
+        record.extend_from_slice(body);
+        record
+    }
+
+    /// Minimal ClientHello asking for `server_name`
+    fn client_hello(server_name: &str) -> Vec<u8> {
+        let name = server_name.as_bytes();
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[7; 32]);
+        body.extend_from_slice(&[0x00, 0x00, 0x02, 0x13, 0x01, 0x01, 0x00]);
+        body.extend_from_slice(&(name.len() as u16 + 9).to_be_bytes());
+        body.extend_from_slice(&[0x00, 0x00]);
+        body.extend_from_slice(&(name.len() as u16 + 5).to_be_bytes());
+        body.extend_from_slice(&(name.len() as u16 + 3).to_be_bytes());
+        body.push(0);
+        body.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        body.extend_from_slice(name);
+        handshake(0x01, &body)
+    }
+
+    /// Certificate message with the chain `certificates`
+    fn certificate(certificates: &[&[u8]]) -> Vec<u8> {
+        let list: Vec<u8> = certificates
+            .iter()
+            .flat_map(|der| [&(der.len() as u32).to_be_bytes()[1..], der].concat())
+            .collect();
+        handshake(0x0b, &[&(list.len() as u32).to_be_bytes()[1..], &list].concat())
+    }
+
+    /// Categories of the alerts raised on a handshake for `server_name`
+    /// presenting `certificates` at `date`
+    fn certificate_alerts(server_name: &str, certificates: &[&[u8]], date: &str) -> Vec<String> {
+        let tls = TlsProcessor::new();
+        let at: DateTime<Utc> = date.parse().unwrap();
+        let hello = tls.process_segment(&testing::flow(443), 1, &client_hello(server_name), at).unwrap();
+        assert!(tls.inspect(&testing::flow(443), &hello, at, "").is_empty());
+        let packet = tls.process_segment(&testing::flow(443).reversed(), 1, &certificate(certificates), at).unwrap();
+        assert_eq!(packet.get_certificates().len(), certificates.len());
+        let alerts = tls.inspect(&testing::flow(443).reversed(), &packet, at, "");
+        assert_eq!(tls.certificates().len(), certificates.len());
+        alerts.into_iter().map(|alert| alert.category).collect()
+    }
+
+    #[test]
+    fn parses_certificate_chains() {
+        let tls = TlsProcessor::new();
+        let chain = certificate(&[LEAF, ROOT]);
+        let packet = tls.process_segment(&testing::flow(443).reversed(), 1, &chain, Utc::now()).unwrap();
+        assert_eq!(packet.get_handshake_types(), ["Certificate"]);
+        let names: Vec<_> = packet.get_certificates().iter().map(|cert| cert.common_name.clone().unwrap()).collect();
+        assert_eq!(names, ["www.example.com", "Test Root CA"]);
+
+        // A chain that does not parse yields no certificates
+        let truncated = certificate(&[LEAF, &ROOT[..100]]);
+        let packet = tls.process_segment(&testing::flow(443), 1, &truncated, Utc::now()).unwrap();
+        assert!(packet.get_certificates().is_empty());
+    }
+
+    #[test]
+    fn alerts_on_certificate_anomalies() {
+        let chain: &[&[u8]] = &[LEAF, ROOT];
+        assert!(certificate_alerts("api.example.com", chain, "2024-06-01T00:00:00Z").is_empty());
+        assert_eq!(certificate_alerts("www.example.com", chain, "2025-06-01T00:00:00Z"), ["tls.certificate.expired"]);
+        assert_eq!(
+            certificate_alerts("www.example.com", chain, "2023-06-01T00:00:00Z"),
+            ["tls.certificate.not_yet_valid"]
+        );
+        assert_eq!(
+            certificate_alerts("www.example.org", chain, "2024-06-01T00:00:00Z"),
+            ["tls.certificate.sni_mismatch"]
+        );
+        // Only a self-signed leaf is suspicious
+        assert_eq!(
+            certificate_alerts("www.example.com", &[ROOT], "2024-06-01T00:00:00Z"),
+            ["tls.certificate.self_signed", "tls.certificate.sni_mismatch"]
+        );
+    }
+
+    #[test]
+    fn recognises_grease() {
+        assert!(is_grease(0x0a0a));
+        assert!(is_grease(0xfafa));
+        assert!(!is_grease(0x0a1a));
+        assert!(!is_grease(0x1301));
+    }
+}
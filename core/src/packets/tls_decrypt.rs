@@ -0,0 +1,609 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use anyhow::Result;
+use chacha20poly1305::ChaCha20Poly1305;
+use hmac::{Hmac, Mac};
+use chrono::{DateTime, Utc};
+use sha2::{Sha256, Sha384};
+use std::path::Path;
+use super::bytes::ByteReader;
+use super::flow::{FlowKey, FlowTable};
+use super::keylog::{KeyLog, SessionSecrets};
+use super::tls::{ClientHello, ServerHello, encrypted_extensions_alpn};
+
+const CONTENT_CHANGE_CIPHER_SPEC: u8 = 0x14;
+const CONTENT_HANDSHAKE: u8 = 0x16;
+const CONTENT_APPLICATION_DATA: u8 = 0x17;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum HashAlgorithm {
+    Sha256,
+    Sha384,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AeadAlgorithm {
+    Aes128Gcm,
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl AeadAlgorithm {
+    fn key_len(&self) -> usize {
+        match self {
+            AeadAlgorithm::Aes128Gcm => 16,
+            AeadAlgorithm::Aes256Gcm | AeadAlgorithm::ChaCha20Poly1305 => 32,
+        }
+    }
+
+    /// Length of the implicit IV derived from the key block in TLS 1.2
+    fn tls12_iv_len(&self) -> usize {
+        match self {
+            AeadAlgorithm::Aes128Gcm | AeadAlgorithm::Aes256Gcm => 4,
+            AeadAlgorithm::ChaCha20Poly1305 => 12,
+        }
+    }
+}
+
+/// Returns the AEAD and PRF hash of a supported cipher suite
+fn suite_parameters(suite: u16) -> Option<(AeadAlgorithm, HashAlgorithm)> {
+    use AeadAlgorithm::*;
+    use HashAlgorithm::*;
+    match suite {
+        0x1301 => Some((Aes128Gcm, Sha256)),
+        0x1302 => Some((Aes256Gcm, Sha384)),
+        0x1303 => Some((ChaCha20Poly1305, Sha256)),
+        0x009c | 0x009e | 0xc02b | 0xc02f => Some((Aes128Gcm, Sha256)),
+        0x009d | 0x009f | 0xc02c | 0xc030 => Some((Aes256Gcm, Sha384)),
+        0xcca8..=0xccaa => Some((ChaCha20Poly1305, Sha256)),
+        _ => None,
+    }
+}
+
+enum AeadCipher {
+    Aes128Gcm(Box<Aes128Gcm>),
+    Aes256Gcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(Box<ChaCha20Poly1305>),
+}
+
+/// Record protection state for one direction
+struct RecordCipher {
+    aead: AeadCipher,
+    algorithm: AeadAlgorithm,
+    iv: Vec<u8>,
+    seq: u64,
+    tls13: bool,
+}
+
+impl RecordCipher {
+    fn new(algorithm: AeadAlgorithm, key: &[u8], iv: Vec<u8>, tls13: bool) -> Result<Self> {
+        let invalid = |_| anyhow::anyhow!("Invalid key length");
+        let aead = match algorithm {
+            AeadAlgorithm::Aes128Gcm => AeadCipher::Aes128Gcm(Box::new(Aes128Gcm::new_from_slice(key).map_err(invalid)?)),
+            AeadAlgorithm::Aes256Gcm => AeadCipher::Aes256Gcm(Box::new(Aes256Gcm::new_from_slice(key).map_err(invalid)?)),
+            AeadAlgorithm::ChaCha20Poly1305 => {
+                AeadCipher::ChaCha20Poly1305(Box::new(ChaCha20Poly1305::new_from_slice(key).map_err(invalid)?))
+            }
+        };
+        Ok(Self { aead, algorithm, iv, seq: 0, tls13 })
+    }
+
+    /// Decrypts one record body; `header` is the 5-byte record header
+    fn decrypt(&mut self, header: &[u8], body: &[u8]) -> Result<Vec<u8>> {
+        let (nonce, ciphertext) = if !self.tls13 && self.algorithm != AeadAlgorithm::ChaCha20Poly1305 {
+            // TLS 1.2 AES-GCM: 4-byte implicit salt followed by the 8-byte explicit nonce
+            if body.len() < 8 {
+                return Err(anyhow::anyhow!("Record too short"));
+            }
+            let mut nonce = self.iv.clone();
+            nonce.extend_from_slice(&body[..8]);
+            (nonce, &body[8..])
+        } else {
+            let mut nonce = self.iv.clone();
+            let seq = self.seq.to_be_bytes();
+            for i in 0..8 {
+                nonce[4 + i] ^= seq[i];
+            }
+            (nonce, body)
+        };
+
+        let aad = if self.tls13 {
+            header.to_vec()
+        } else {
+            let plaintext_len = ciphertext.len().saturating_sub(16) as u16;
+            let mut aad = self.seq.to_be_bytes().to_vec();
+            aad.extend_from_slice(&header[..3]);
+            aad.extend_from_slice(&plaintext_len.to_be_bytes());
+            aad
+        };
+
+        let payload = Payload { msg: ciphertext, aad: &aad };
+        let plaintext = match &self.aead {
+            AeadCipher::Aes128Gcm(cipher) => cipher.decrypt(nonce.as_slice().into(), payload),
+            AeadCipher::Aes256Gcm(cipher) => cipher.decrypt(nonce.as_slice().into(), payload),
+            AeadCipher::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce.as_slice().into(), payload),
+        }
+        .map_err(|_| anyhow::anyhow!("Record authentication failed"))?;
+
+        self.seq += 1;
+        Ok(plaintext)
+    }
+}
+
+#[derive(Default)]
+struct Direction {
+    cipher: Option<RecordCipher>,
+    /// TLS 1.3: the handshake keys were replaced by application keys
+    application_keys: bool,
+}
+
+struct Session {
+    /// Direction from the client, known once a hello message is seen
+    client: Option<FlowKey>,
+    client_random: Option<[u8; 32]>,
+    server_random: Option<[u8; 32]>,
+    cipher_suite: Option<u16>,
+    version: u16,
+    alpn: Option<String>,
+    secrets: Option<SessionSecrets>,
+    to_server: Direction,
+    to_client: Direction,
+}
+
+/// Application data recovered from a TLS connection
+#[derive(Debug, Clone)]
+pub struct DecryptedData {
+    pub version: u16,
+    pub alpn: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Decrypts TLS sessions whose secrets are known from a key log
+pub(crate) struct TlsDecryptor {
+    keylog: KeyLog,
+    /// Sessions by canonical flow
+    sessions: FlowTable<FlowKey, Session>,
+}
+
+impl TlsDecryptor {
+    pub fn new() -> Self {
+        Self {
+            keylog: KeyLog::new(),
+            sessions: FlowTable::new(),
+        }
+    }
+
+    pub fn load_keylog(&mut self, path: &Path) -> Result<usize> {
+        self.keylog.load_file(path)
+    }
+
+    pub fn add_secrets(&mut self, text: &str) -> usize {
+        self.keylog.add_text(text)
+    }
+
+    /// A key log file counts even while empty: a live capture may start
+    /// before the browser writes the first secrets
+    pub fn is_enabled(&self) -> bool {
+        self.keylog.has_source()
+    }
+
+    /// Feeds the complete records of one direction of a TLS connection and
+    /// returns the application data of every record that could be decrypted
+    pub fn process_records(&mut self, flow: &FlowKey, records: &[Vec<u8>], at: DateTime<Utc>) -> Vec<DecryptedData> {
+        let key = flow.canonical();
+        let session = self.sessions.entry(key, at, || Session {
+            client: None,
+            client_random: None,
+            server_random: None,
+            cipher_suite: None,
+            version: 0,
+            alpn: None,
+            secrets: None,
+            to_server: Direction::default(),
+            to_client: Direction::default(),
+        });
+        if session.client.is_none() {
+            // The client is the side that sends the ClientHello
+            session.client = records.iter().find_map(|record| match (record[0], record.get(5)) {
+                (CONTENT_HANDSHAKE, Some(0x01)) => Some(*flow),
+                (CONTENT_HANDSHAKE, Some(0x02)) => Some(flow.reversed()),
+                _ => None,
+            });
+        }
+        let Some(client) = session.client else {
+            return Vec::new();
+        };
+        let from_client = *flow == client;
+
+        records
+            .iter()
+            .filter_map(|record| self.process_record(&key, from_client, record))
+            .collect()
+    }
+
+    fn process_record(&mut self, key: &FlowKey, from_client: bool, record: &[u8]) -> Option<DecryptedData> {
+        let session = self.sessions.get_mut(key)?;
+        let (header, body) = record.split_at(5);
+
+        match header[0] {
+            CONTENT_HANDSHAKE if Self::direction(session, from_client).cipher.is_none() => {
+                Self::observe_handshake(session, body);
+                if session.version == 0x0304 && !from_client && session.server_random.is_some() {
+                    Self::install_tls13_handshake_keys(&mut self.keylog, session);
+                }
+                None
+            }
+            CONTENT_CHANGE_CIPHER_SPEC if session.version != 0x0304 => {
+                Self::install_tls12_keys(&mut self.keylog, session, from_client);
+                None
+            }
+            CONTENT_CHANGE_CIPHER_SPEC => None,
+            _ => {
+                let version = session.version;
+                let direction = Self::direction(session, from_client);
+                let application_keys = direction.application_keys;
+                let mut plaintext = direction.cipher.as_mut()?.decrypt(header, body).ok()?;
+
+                let mut content_type = header[0];
+                if version == 0x0304 {
+                    // TLSInnerPlaintext: content, real content type, zero padding
+                    let end = plaintext.iter().rposition(|&b| b != 0)?;
+                    content_type = plaintext[end];
+                    plaintext.truncate(end);
+
+                    if content_type == CONTENT_HANDSHAKE && !application_keys {
+                        // ALPN of TLS 1.3 is only visible in the encrypted handshake
+                        Self::observe_handshake(session, &plaintext);
+                        if contains_finished(&plaintext) {
+                            Self::install_tls13_application_key(&mut self.keylog, session, from_client);
+                        }
+                    }
+                }
+
+                if content_type == CONTENT_APPLICATION_DATA && !plaintext.is_empty() {
+                    Some(DecryptedData { version, alpn: session.alpn.clone(), data: plaintext })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Forgets the session of a closed connection, keyed by its canonical flow
+    pub fn close(&mut self, connection: &FlowKey) {
+        self.sessions.remove(connection);
+    }
+
+    fn direction(session: &mut Session, from_client: bool) -> &mut Direction {
+        if from_client {
+            &mut session.to_server
+        } else {
+            &mut session.to_client
+        }
+    }
+
+    fn observe_handshake(session: &mut Session, body: &[u8]) {
+        let mut reader = ByteReader::new(body);
+        while let (Ok(msg_type), Ok(message)) = (reader.read_u8(), reader.read_vec24()) {
+            match msg_type {
+                0x01 => {
+                    if let Ok(hello) = ClientHello::parse(message) {
+                        session.client_random = Some(hello.random);
+                    }
+                }
+                0x02 => {
+                    if let Ok(hello) = ServerHello::parse(message) {
+                        session.server_random = Some(hello.random);
+                        session.cipher_suite = Some(hello.cipher_suite);
+                        session.version = hello.version();
+                        session.alpn = hello.alpn.clone();
+                    }
+                }
+                0x08 => {
+                    if let Some(alpn) = encrypted_extensions_alpn(message) {
+                        session.alpn = Some(alpn);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn secrets(keylog: &mut KeyLog, session: &mut Session) -> Option<SessionSecrets> {
+        if session.secrets.is_none() {
+            session.secrets = keylog.lookup(&session.client_random?);
+        }
+        session.secrets.clone()
+    }
+
+    /// Installs the TLS 1.2 key for the direction that just sent ChangeCipherSpec
+    fn install_tls12_keys(keylog: &mut KeyLog, session: &mut Session, from_client: bool) {
+        let (Some(suite), Some(client_random), Some(server_random)) =
+            (session.cipher_suite, session.client_random, session.server_random)
+        else {
+            return;
+        };
+        let Some((aead, hash)) = suite_parameters(suite) else {
+            return;
+        };
+        let Some(master_secret) = Self::secrets(keylog, session).and_then(|s| s.master_secret) else {
+            return;
+        };
+
+        let key_len = aead.key_len();
+        let iv_len = aead.tls12_iv_len();
+        let mut seed = server_random.to_vec();
+        seed.extend_from_slice(&client_random);
+        let key_block = prf(hash, &master_secret, b"key expansion", &seed, 2 * key_len + 2 * iv_len);
+
+        let (client_key, rest) = key_block.split_at(key_len);
+        let (server_key, rest) = rest.split_at(key_len);
+        let (client_iv, server_iv) = rest.split_at(iv_len);
+
+        let (key, iv) = if from_client {
+            (client_key, client_iv)
+        } else {
+            (server_key, server_iv)
+        };
+        Self::direction(session, from_client).cipher = RecordCipher::new(aead, key, iv.to_vec(), false).ok();
+    }
+
+    /// Installs the TLS 1.3 handshake keys of both directions
+    fn install_tls13_handshake_keys(keylog: &mut KeyLog, session: &mut Session) {
+        let Some(secrets) = Self::secrets(keylog, session) else {
+            return;
+        };
+        session.to_server.cipher = tls13_cipher(session, &secrets.client_handshake_traffic_secret);
+        session.to_client.cipher = tls13_cipher(session, &secrets.server_handshake_traffic_secret);
+    }
+
+    /// Switches the direction that sent its Finished message to the application key
+    fn install_tls13_application_key(keylog: &mut KeyLog, session: &mut Session, from_client: bool) {
+        let Some(secrets) = Self::secrets(keylog, session) else {
+            return;
+        };
+        let secret = if from_client {
+            &secrets.client_traffic_secret_0
+        } else {
+            &secrets.server_traffic_secret_0
+        };
+        let cipher = tls13_cipher(session, secret);
+        let direction = Self::direction(session, from_client);
+        direction.cipher = cipher;
+        direction.application_keys = true;
+    }
+}
+
+fn tls13_cipher(session: &Session, secret: &Option<Vec<u8>>) -> Option<RecordCipher> {
+    let (aead, hash) = session.cipher_suite.and_then(suite_parameters)?;
+    let secret = secret.as_ref()?;
+    let key = hkdf_expand_label(hash, secret, b"key", aead.key_len())?;
+    let iv = hkdf_expand_label(hash, secret, b"iv", 12)?;
+    RecordCipher::new(aead, &key, iv, true).ok()
+}
+
+fn contains_finished(handshake: &[u8]) -> bool {
+    let mut reader = ByteReader::new(handshake);
+    while let (Ok(msg_type), Ok(_)) = (reader.read_u8(), reader.read_vec24()) {
+        if msg_type == 0x14 {
+            return true;
+        }
+    }
+    false
+}
+
+/// TLS 1.2 PRF (RFC 5246, section 5)
+fn prf(hash: HashAlgorithm, secret: &[u8], label: &[u8], seed: &[u8], len: usize) -> Vec<u8> {
+    let mut label_seed = label.to_vec();
+    label_seed.extend_from_slice(seed);
+    match hash {
+        HashAlgorithm::Sha256 => p_hash::<Hmac<Sha256>>(secret, &label_seed, len),
+        HashAlgorithm::Sha384 => p_hash::<Hmac<Sha384>>(secret, &label_seed, len),
+    }
+}
+
+fn p_hash<M: Mac + KeyInit + Clone>(secret: &[u8], seed: &[u8], len: usize) -> Vec<u8> {
+    let mac = <M as Mac>::new_from_slice(secret).expect("HMAC accepts any key length");
+    let mut output = Vec::with_capacity(len);
+    let mut a = seed.to_vec();
+    while output.len() < len {
+        let mut round = mac.clone();
+        round.update(&a);
+        a = round.finalize().into_bytes().to_vec();
+
+        let mut round = mac.clone();
+        round.update(&a);
+        round.update(seed);
+        output.extend_from_slice(&round.finalize().into_bytes());
+    }
+    output.truncate(len);
+    output
+}
+
+/// HKDF-Expand-Label (RFC 8446, section 7.1) with an empty context. `None`
+/// if the secret is shorter than the hash output, e.g. a key log secret of
+/// another cipher suite, or `len` is too long for the hash.
+pub(crate) fn hkdf_expand_label(hash: HashAlgorithm, secret: &[u8], label: &[u8], len: usize) -> Option<Vec<u8>> {
+    let full_label = [b"tls13 ".as_slice(), label].concat();
+    let mut info = (len as u16).to_be_bytes().to_vec();
+    info.push(full_label.len() as u8);
+    info.extend_from_slice(&full_label);
+    info.push(0);
+
+    let mut output = vec![0u8; len];
+    let expanded = match hash {
+        HashAlgorithm::Sha256 => hkdf::Hkdf::<Sha256>::from_prk(secret)
+            .map(|hk| hk.expand(&info, &mut output)),
+        HashAlgorithm::Sha384 => hkdf::Hkdf::<Sha384>::from_prk(secret)
+            .map(|hk| hk.expand(&info, &mut output)),
+    };
+    matches!(expanded, Ok(Ok(()))).then_some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::bytes::{from_hex, to_hex};
+
+    fn hex(text: &str) -> Vec<u8> {
+        from_hex(&text.replace(' ', "")).unwrap()
+    }
+
+    fn record(content_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut record = vec![content_type, 0x03, 0x03];
+        record.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        record.extend_from_slice(body);
+        record
+    }
+
+    fn handshake(msg_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![msg_type];
+        message.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        message.extend_from_slice(body);
+        record(CONTENT_HANDSHAKE, &message)
+    }
+
+    #[test]
+    fn prf_sha256_matches_reference() {
+        let output = prf(
+            HashAlgorithm::Sha256,
+            &hex("9bbe436ba940f017b17652849a71db35"),
+            b"test label",
+            &hex("a0ba9f936cda311827a6f796ffd5198c"),
+            100,
+        );
+        assert_eq!(
+            to_hex(&output),
+            "e3f229ba727be17b8d122620557cd453c2aab21d07c3d495329b52d4e61edb5a6b301791e90d35c9c9a46b4e14baf9af\
+             0fa022f7077def17abfd3797c0564bab4fbc91666e9def9b97fce34f796789baa48082d122ee42c5a72e5a5110fff70187\
+             347b66"
+        );
+    }
+
+    #[test]
+    fn hkdf_expand_label_matches_rfc8448() {
+        // RFC 8448, section 3: handshake traffic keys of the simple 1-RTT handshake
+        let server = hex("b67b7d690cc16c4e75e54213cb2d37b4e9c912bcded9105d42befd59d391ad38");
+        let expand = |secret: &[u8], label: &[u8], len| to_hex(&hkdf_expand_label(HashAlgorithm::Sha256, secret, label, len).unwrap());
+        assert_eq!(expand(&server, b"key", 16), "3fce516009c21727d0f2e4e86ee403bc");
+        assert_eq!(expand(&server, b"iv", 12), "5d313eb2671276ee13000b30");
+        let client = hex("b3eddb126e067f35a780b3abf45e2d8f3b1a950738f52e9600746a0e27a55a21");
+        assert_eq!(expand(&client, b"key", 16), "dbfaa693d1762c5b666af5d950258d01");
+        assert_eq!(expand(&client, b"iv", 12), "5bd3c71b836e0b76bb73265f");
+    }
+
+    #[test]
+    fn rejects_secrets_shorter_than_the_hash() {
+        // A 32-byte secret logged for a SHA-384 suite
+        let secret = vec![0x44; 32];
+        assert_eq!(hkdf_expand_label(HashAlgorithm::Sha384, &secret, b"key", 32), None);
+        assert!(hkdf_expand_label(HashAlgorithm::Sha384, &[0x44; 48], b"key", 32).is_some());
+
+        let session = |cipher_suite| Session {
+            client: None,
+            client_random: None,
+            server_random: None,
+            cipher_suite: Some(cipher_suite),
+            version: 0x0304,
+            alpn: None,
+            secrets: None,
+            to_server: Direction::default(),
+            to_client: Direction::default(),
+        };
+        assert!(tls13_cipher(&session(0x1302), &Some(secret.clone())).is_none());
+        assert!(tls13_cipher(&session(0x1301), &Some(secret)).is_some());
+    }
+
+    const CLIENT_RANDOM: [u8; 32] = [0x11; 32];
+    const MASTER_SECRET: [u8; 48] = [0x33; 48];
+
+    /// TLS 1.2 connection with AES-128-GCM from a client on port 443 to a
+    /// server listening on 50000
+    struct Tls12Connection {
+        client: FlowKey,
+        /// Records up to the client's ChangeCipherSpec, with the flow each is sent on
+        handshake: Vec<(FlowKey, Vec<u8>)>,
+        /// Application data record the client sends next
+        request: Vec<u8>,
+    }
+
+    fn tls12_connection(plaintext: &[u8]) -> Tls12Connection {
+        let server_random = [0x22; 32];
+        let client = FlowKey::new("10.0.0.1".parse().unwrap(), 443, "10.0.0.2".parse().unwrap(), 50000);
+        let client_hello = [&[0x03, 0x03][..], &CLIENT_RANDOM, &[0x00, 0x00, 0x02, 0x00, 0x9c, 0x01, 0x00]].concat();
+        let server_hello = [&[0x03, 0x03][..], &server_random, &[0x00, 0x00, 0x9c, 0x00]].concat();
+        let handshake = vec![
+            (client, handshake(0x01, &client_hello)),
+            (client.reversed(), handshake(0x02, &server_hello)),
+            (client, record(CONTENT_CHANGE_CIPHER_SPEC, &[1])),
+        ];
+
+        // Client write key and implicit IV are the start of the key block
+        let seed = [server_random, CLIENT_RANDOM].concat();
+        let key_block = prf(HashAlgorithm::Sha256, &MASTER_SECRET, b"key expansion", &seed, 40);
+        let cipher = Aes128Gcm::new_from_slice(&key_block[..16]).unwrap();
+        let explicit_nonce = [0, 0, 0, 0, 0, 0, 0, 1];
+        let nonce = [&key_block[32..36], &explicit_nonce[..]].concat();
+        let mut aad = 0u64.to_be_bytes().to_vec();
+        aad.extend_from_slice(&[CONTENT_APPLICATION_DATA, 0x03, 0x03]);
+        aad.extend_from_slice(&(plaintext.len() as u16).to_be_bytes());
+        let ciphertext = cipher
+            .encrypt(nonce.as_slice().into(), Payload { msg: plaintext, aad: &aad })
+            .unwrap();
+        let body = [&explicit_nonce[..], &ciphertext].concat();
+        Tls12Connection { client, handshake, request: record(CONTENT_APPLICATION_DATA, &body) }
+    }
+
+    fn keylog_line() -> String {
+        format!("CLIENT_RANDOM {} {}\n", to_hex(&CLIENT_RANDOM), to_hex(&MASTER_SECRET))
+    }
+
+    #[test]
+    fn decrypts_tls12_with_client_on_lower_port() {
+        let mut decryptor = TlsDecryptor::new();
+        decryptor.add_secrets(&keylog_line());
+
+        let plaintext = b"GET / HTTP/1.1\r\n\r\n";
+        let Tls12Connection { client, handshake, request } = tls12_connection(plaintext);
+        for (flow, record) in handshake {
+            assert!(decryptor.process_records(&flow, &[record], Utc::now()).is_empty());
+        }
+        let decrypted = decryptor.process_records(&client, &[request], Utc::now());
+        assert_eq!(decrypted.len(), 1);
+        assert_eq!(decrypted[0].data, plaintext);
+        assert_eq!(decrypted[0].version, 0x0303);
+    }
+
+    #[test]
+    fn picks_up_secrets_written_during_the_capture() {
+        let path = std::env::temp_dir().join(format!("anansi-keylog-{}.txt", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut decryptor = TlsDecryptor::new();
+        assert_eq!(decryptor.load_keylog(&path).unwrap(), 0);
+        assert!(decryptor.is_enabled());
+
+        // The browser logs the secrets of a connection made after the capture started
+        let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        std::io::Write::write_all(&mut file, keylog_line().as_bytes()).unwrap();
+        file.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(1)).unwrap();
+        drop(file);
+
+        let plaintext = b"GET / HTTP/1.1\r\n\r\n";
+        let Tls12Connection { client, handshake, request } = tls12_connection(plaintext);
+        for (flow, record) in handshake {
+            decryptor.process_records(&flow, &[record], Utc::now());
+        }
+        let decrypted = decryptor.process_records(&client, &[request], Utc::now());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(decrypted.len(), 1);
+        assert_eq!(decrypted[0].data, plaintext);
+    }
+
+    #[test]
+    fn waits_for_a_hello_to_tell_the_client() {
+        let mut decryptor = TlsDecryptor::new();
+        let flow = FlowKey::new("10.0.0.1".parse().unwrap(), 50000, "10.0.0.2".parse().unwrap(), 443);
+        assert!(decryptor.process_records(&flow, &[record(CONTENT_APPLICATION_DATA, &[0; 32])], Utc::now()).is_empty());
+        assert_eq!(decryptor.sessions.get(&flow.canonical()).unwrap().client, None);
+    }
+}
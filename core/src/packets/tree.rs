@@ -0,0 +1,194 @@
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use super::bytes::{from_hex, to_hex};
+
+/// Typed value of a decoded field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum FieldValue {
+    Bool(bool),
+    Uint(u64),
+    Int(i64),
+    /// Unsigned integer conventionally shown in hexadecimal, e.g. an ethertype
+    Hex(u64),
+    Float(f64),
+    Text(String),
+    Ip(IpAddr),
+    Mac(#[serde(with = "mac_string")] [u8; 6]),
+    Bytes(#[serde(with = "hex_string")] Vec<u8>),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::Bool(value) => write!(f, "{}", value),
+            FieldValue::Uint(value) => write!(f, "{}", value),
+            FieldValue::Int(value) => write!(f, "{}", value),
+            FieldValue::Hex(value) => write!(f, "{:#06x}", value),
+            FieldValue::Float(value) => write!(f, "{}", value),
+            FieldValue::Text(value) => f.write_str(value),
+            FieldValue::Ip(value) => write!(f, "{}", value),
+            FieldValue::Mac(value) => write!(f, "{}", MacAddr::from(*value)),
+            FieldValue::Bytes(value) => f.write_str(&to_hex(value)),
+        }
+    }
+}
+
+macro_rules! uint_field_value {
+    ($($ty:ty),*) => {
+        $(impl From<$ty> for FieldValue {
+            fn from(value: $ty) -> Self {
+                FieldValue::Uint(value as u64)
+            }
+        })*
+    };
+}
+
+uint_field_value!(u8, u16, u32, u64, usize);
+
+macro_rules! int_field_value {
+    ($($ty:ty),*) => {
+        $(impl From<$ty> for FieldValue {
+            fn from(value: $ty) -> Self {
+                FieldValue::Int(value as i64)
+            }
+        })*
+    };
+}
+
+int_field_value!(i8, i16, i32, i64);
+
+impl From<f64> for FieldValue {
+    fn from(value: f64) -> Self {
+        FieldValue::Float(value)
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(value: bool) -> Self {
+        FieldValue::Bool(value)
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        FieldValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        FieldValue::Text(value)
+    }
+}
+
+impl From<&String> for FieldValue {
+    fn from(value: &String) -> Self {
+        FieldValue::Text(value.clone())
+    }
+}
+
+impl From<IpAddr> for FieldValue {
+    fn from(value: IpAddr) -> Self {
+        FieldValue::Ip(value)
+    }
+}
+
+impl From<Ipv4Addr> for FieldValue {
+    fn from(value: Ipv4Addr) -> Self {
+        FieldValue::Ip(IpAddr::V4(value))
+    }
+}
+
+impl From<Ipv6Addr> for FieldValue {
+    fn from(value: Ipv6Addr) -> Self {
+        FieldValue::Ip(IpAddr::V6(value))
+    }
+}
+
+impl From<MacAddr> for FieldValue {
+    fn from(value: MacAddr) -> Self {
+        FieldValue::Mac(value.octets())
+    }
+}
+
+/// A named field of a layer, e.g. `tcp.srcport`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Field {
+    pub name: String,
+    pub value: FieldValue,
+    /// Offset of the field in the frame; `None` when the value was not read
+    /// from the frame bytes, e.g. decrypted, reassembled or derived values
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    pub length: usize,
+}
+
+impl Field {
+    /// Field without a position in the frame
+    pub fn new(name: &str, value: impl Into<FieldValue>) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.into(),
+            offset: None,
+            length: 0,
+        }
+    }
+}
+
+/// The bytes of a frame decoded by one dissector
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Layer {
+    /// Name of the dissector, e.g. `"IPv4"`
+    pub protocol: String,
+    /// Offset of the layer in the frame; `None` for decrypted or reassembled data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    /// Bytes handed to the dissector, payload included
+    pub length: usize,
+    pub fields: Vec<Field>,
+}
+
+impl Layer {
+    pub fn new(protocol: &str, offset: Option<usize>, length: usize) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            offset,
+            length,
+            fields: Vec::new(),
+        }
+    }
+}
+
+/// MAC addresses are serialized as `aa:bb:cc:dd:ee:ff`
+mod mac_string {
+    use pnet::util::MacAddr;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(mac: &[u8; 6], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&MacAddr::from(*mac))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 6], D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse::<MacAddr>()
+            .map(|mac| mac.octets())
+            .map_err(|_| serde::de::Error::custom(format!("invalid MAC address '{}'", text)))
+    }
+}
+
+/// Byte strings are serialized as lowercase hex
+mod hex_string {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::to_hex(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        super::from_hex(&text).ok_or_else(|| serde::de::Error::custom(format!("invalid hex string '{}'", text)))
+    }
+}
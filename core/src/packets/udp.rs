@@ -1,13 +1,82 @@
+use anyhow::Result;
 use pnet::packet::udp::UdpPacket;
+use pnet::packet::Packet;
+use std::net::IpAddr;
+use super::detection::{Detection, FlowClassifier};
+use super::dissector::{Dissection, DissectContext, Dissector, Transport};
+use super::flow::FlowKey;
+use super::tree::FieldValue;
 
-pub struct UdpProcessor;
+pub struct UdpProcessor {
+    classifier: FlowClassifier,
+}
 
 impl UdpProcessor {
     pub fn new() -> Self {
-        Self
+        Self {
+            classifier: FlowClassifier::new(Transport::Udp),
+        }
     }
 
     pub fn process<'a>(&self, data: &'a [u8]) -> Option<UdpPacket<'a>> {
         UdpPacket::new(data)
     }
-} 
\ No newline at end of file
+}
+
+impl Dissector for UdpProcessor {
+    fn name(&self) -> &'static str {
+        "UDP"
+    }
+
+    fn dissect(&self, data: &[u8], ctx: &mut DissectContext) -> Result<Dissection> {
+        let udp = self.process(data).ok_or_else(|| anyhow::anyhow!("Datagram too short for UDP"))?;
+        ctx.add_field_at("udp.srcport", 0, 2, udp.get_source());
+        ctx.add_field_at("udp.dstport", 2, 2, udp.get_destination());
+        ctx.add_field_at("udp.port", 0, 2, udp.get_source());
+        ctx.add_field_at("udp.port", 2, 2, udp.get_destination());
+        ctx.add_field_at("udp.length", 4, 2, udp.get_length());
+        ctx.add_field_at("udp.checksum", 6, 2, FieldValue::Hex(udp.get_checksum() as u64));
+
+        let udp_info = || {
+            Dissection::new(
+                "UDP",
+                format!(
+                    "{} > {} UDP, length {}",
+                    endpoint(ctx.src_ip, udp.get_source()),
+                    endpoint(ctx.dst_ip, udp.get_destination()),
+                    data.len()
+                ),
+            )
+        };
+        let (Some(src), Some(dst)) = (ctx.src_ip, ctx.dst_ip) else {
+            return Ok(udp_info());
+        };
+        let info = udp_info();
+        let flow = FlowKey::new(src, udp.get_source(), dst, udp.get_destination());
+        ctx.flow = Some(flow);
+
+        let payload = udp.payload();
+        let detection = self.classifier.classify(&flow, ctx.registry(), payload, ctx.captured_at);
+        let protocol = detection.as_ref().map(Detection::protocol);
+        let record = ctx.registry().note_flow(Transport::Udp, &flow, None, ctx.number, protocol, ctx.captured_at);
+        ctx.client_flow = Some(record.client);
+        let Some(detection) = detection else {
+            return Ok(info);
+        };
+        ctx.add_fields(detection.fields());
+
+        let mut dissection = ctx.dissect_with(detection.dissector.as_ref(), payload).unwrap_or(info);
+        if detection.new {
+            dissection.info.push_str(&detection.format_note());
+        }
+        Ok(dissection)
+    }
+}
+
+fn endpoint(address: Option<IpAddr>, port: u16) -> String {
+    match address {
+        Some(IpAddr::V6(address)) => format!("[{}]:{}", address, port),
+        Some(address) => format!("{}:{}", address, port),
+        None => port.to_string(),
+    }
+}
@@ -1,5 +1,6 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
-use anansi_core::AnansiFacade;
+use anansi_core::{AnansiFacade, DhcpLease, PacketCapture, PacketInfo};
+use serde::Serialize;
 
 #[tauri::command]
 async fn get_interfaces() -> Result<Vec<String>, String> {
@@ -9,10 +10,28 @@ async fn get_interfaces() -> Result<Vec<String>, String> {
         .map_err(|e| e.to_string())
 }
 
+/// A decoded capture file with the tables built from it
+#[derive(Serialize)]
+struct Capture {
+    packets: Vec<PacketInfo>,
+    dhcp_leases: Vec<DhcpLease>,
+}
+
+/// Decodes a capture file; the packets carry their protocol tree
+#[tauri::command]
+async fn read_capture(path: String) -> Result<Capture, String> {
+    let facade = AnansiFacade::new(false);
+    facade.load_tls_secrets_from_capture(&path).map_err(|e| e.to_string())?;
+    let mut packets = Vec::new();
+    PacketCapture::read_file(&path, None, |packet| packets.push(facade.process_packet(packet)))
+        .map_err(|e| e.to_string())?;
+    Ok(Capture { packets, dhcp_leases: facade.dhcp_leases() })
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
-        .invoke_handler(tauri::generate_handler![get_interfaces])
+        .invoke_handler(tauri::generate_handler![get_interfaces, read_capture])
         .run(tauri::generate_context!())
         .expect("Error while running tauri application!");
 }